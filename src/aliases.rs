@@ -0,0 +1,227 @@
+use crate::model::*;
+use regex::{Regex, RegexBuilder};
+
+/// Account name prefix or regular expression of an alias.
+enum AliasMatch {
+    Name(String),
+    Regex(Regex),
+}
+
+/// Aliases in effect, most recently defined first, with their replacement.
+#[derive(Default)]
+struct Aliases(Vec<(AliasMatch, String)>);
+
+impl Aliases {
+    /// Adds an alias. One whose regular expression is invalid is left out.
+    fn define(&mut self, alias: &AccountAlias) {
+        let alias_match = match alias.pattern() {
+            Some(pattern) => match pattern_regex(pattern) {
+                Some(regex) => AliasMatch::Regex(regex),
+                None => return,
+            },
+            None => AliasMatch::Name(alias.alias.clone()),
+        };
+        let replacement = match alias_match {
+            AliasMatch::Name(_) => alias.account.clone(),
+            AliasMatch::Regex(_) => regex_replacement(&alias.account),
+        };
+        self.0.insert(0, (alias_match, replacement));
+    }
+
+    /// Replaces an alias matching the whole account name or its leading
+    /// components, e.g. `Checking` in `Checking:Joint`, or every match of
+    /// an alias regular expression.
+    fn resolve(&self, account: &mut String) {
+        for (alias, target) in &self.0 {
+            match alias {
+                AliasMatch::Name(name) => {
+                    let rest = match account.strip_prefix(name.as_str()) {
+                        Some(rest) if rest.is_empty() || rest.starts_with(':') => rest,
+                        _ => continue,
+                    };
+                    *account = format!("{}{}", target, rest);
+                }
+                AliasMatch::Regex(regex) => {
+                    if !regex.is_match(account) {
+                        continue;
+                    }
+                    *account = regex.replace_all(account, target.as_str()).into_owned();
+                }
+            }
+            return;
+        }
+    }
+}
+
+/// `capture` directives in effect, in file order. Captures whose pattern
+/// is invalid are left out.
+#[derive(Default)]
+struct Captures(Vec<(String, Regex)>);
+
+impl Captures {
+    fn define(&mut self, capture: &AccountCapture) {
+        if let Some(regex) = pattern_regex(&capture.pattern) {
+            self.0.push((capture.account.clone(), regex));
+        }
+    }
+
+    /// Replaces the account with the target of the first capture whose
+    /// pattern matches it.
+    fn resolve(&self, account: &mut String) {
+        if let Some((target, _)) = self.0.iter().find(|(_, regex)| regex.is_match(account)) {
+            account.clone_from(target);
+        }
+    }
+}
+
+/// Regular expression matching account names ignoring case, as ledger
+/// does, or `None` if `pattern` is invalid.
+fn pattern_regex(pattern: &str) -> Option<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .ok()
+}
+
+/// Replacement of a regular expression alias, with hledger's `\1` group
+/// references written the way the regex crate expects them.
+fn regex_replacement(replacement: &str) -> String {
+    let mut result = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(digit)) if digit.is_ascii_digit() => {
+                result.push_str(&format!("${{{}}}", digit));
+                chars.next();
+            }
+            ('$', _) => result.push_str("$$"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+fn resolve_postings(aliases: &Aliases, captures: &Captures, postings: &mut [Posting]) {
+    for posting in postings {
+        aliases.resolve(&mut posting.account);
+        captures.resolve(&mut posting.account);
+    }
+}
+
+impl Ledger {
+    /// Rewrites posting accounts according to the aliases in effect at each
+    /// transaction, in file order.
+    ///
+    /// Aliases come from `alias ALIAS=ACCOUNT` directives and from the
+    /// `alias` subdirective of `account` declarations; `end aliases` drops
+    /// all of them. Only the most recently defined matching alias applies.
+    /// An `alias /REGEX/=REPLACEMENT` directive replaces every match of the
+    /// regular expression in the account name, with `\1` standing for the
+    /// first group, as in hledger.
+    ///
+    /// The account is then replaced with that of the first `capture`
+    /// directive whose pattern matches it.
+    ///
+    /// Patterns are regular expressions in the syntax of the `regex` crate,
+    /// matched ignoring case. One that is not valid is ignored.
+    ///
+    /// The alias and capture directives themselves are kept.
+    pub fn resolve_aliases(&mut self) {
+        let mut aliases = Aliases::default();
+        let mut captures = Captures::default();
+        for item in &mut self.items {
+            match item {
+                LedgerItem::AccountAlias(alias) => aliases.define(alias),
+                LedgerItem::AccountDeclaration(declaration) => {
+                    for subdirective in &declaration.subdirectives {
+                        if let AccountSubdirective::Alias(alias) = subdirective {
+                            aliases.define(&AccountAlias {
+                                alias: alias.clone(),
+                                account: declaration.name.clone(),
+                            });
+                        }
+                    }
+                }
+                LedgerItem::AccountCapture(capture) => captures.define(capture),
+                LedgerItem::EndAliases => aliases = Aliases::default(),
+                LedgerItem::Transaction(transaction) => {
+                    resolve_postings(&aliases, &captures, &mut transaction.postings)
+                }
+                LedgerItem::PeriodicTransaction(transaction) => {
+                    resolve_postings(&aliases, &captures, &mut transaction.postings)
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn resolve_aliases_test() {
+        let mut ledger = parse(
+            r#"2024-01-01 Before
+  Checking  $1
+  Equity
+alias Checking = Assets:Bank:Checking
+account Expenses:Food
+  alias food
+2024-01-02 Shop
+  food  $5
+  Checking:Joint
+  CheckingOld
+end aliases
+capture Expenses:Deductible  ^expenses:medical
+capture Expenses:Travel  ^expenses:(bus|train)$
+alias /^(assets):bank\b/=\1:Checking
+2024-01-03 After
+  Checking  $1
+  Equity
+  Expenses:Medical:Dentist  $20
+  Expenses:Transport  $2
+  Expenses:Train  $1
+  Assets:Bank  $-20
+  Assets:Bankrupt  $-4
+"#,
+        )
+        .unwrap();
+        ledger.resolve_aliases();
+
+        let accounts: Vec<Vec<&str>> = ledger
+            .transactions()
+            .map(|t| t.postings.iter().map(|p| p.account.as_str()).collect())
+            .collect();
+        assert_eq!(
+            accounts,
+            vec![
+                vec!["Checking", "Equity"],
+                vec!["Expenses:Food", "Assets:Bank:Checking:Joint", "CheckingOld"],
+                vec![
+                    "Checking",
+                    "Equity",
+                    "Expenses:Deductible",
+                    "Expenses:Transport",
+                    "Expenses:Travel",
+                    "Assets:Checking",
+                    "Assets:Bankrupt"
+                ],
+            ]
+        );
+        assert!(ledger
+            .to_string()
+            .contains("alias Checking=Assets:Bank:Checking\n"));
+        assert_eq!(
+            ledger.items[5].to_string(),
+            "capture Expenses:Deductible  ^expenses:medical\n"
+        );
+        assert_eq!(
+            ledger.items[7].to_string(),
+            "alias /^(assets):bank\\b/=\\1:Checking\n"
+        );
+        assert!(parse("capture Expenses:Deductible expensive\n").is_err());
+        assert!(parse("capture  \n").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use crate::balancing::BalanceError;
+use crate::model::*;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+/// Balances by account, then by commodity name.
+type Balances = BTreeMap<String, BTreeMap<String, Amount>>;
+
+/// Adds `amount` to the balance of `account`, failing on overflow.
+fn add(balances: &mut Balances, account: &str, amount: &Amount) -> Option<()> {
+    let amounts = balances.entry(account.to_owned()).or_default();
+    match amounts.get_mut(&amount.commodity.name) {
+        Some(sum) => sum.quantity = sum.quantity.checked_add(amount.quantity)?,
+        None => {
+            amounts.insert(amount.commodity.name.clone(), amount.clone());
+        }
+    }
+    Some(())
+}
+
+/// Balance of `account`, and of its subaccounts if `inclusive`, summing the
+/// balances before the transaction and the postings so far in it.
+fn balance_of(
+    balances: &Balances,
+    pending: &Balances,
+    account: &str,
+    inclusive: bool,
+) -> Option<BTreeMap<String, Amount>> {
+    let mut sum = Balances::new();
+    for map in [balances, pending] {
+        let accounts = map.iter().filter(|(name, _)| {
+            *name == account
+                || (inclusive
+                    && name
+                        .strip_prefix(account)
+                        .is_some_and(|rest| rest.starts_with(':')))
+        });
+        for (_, amounts) in accounts {
+            for amount in amounts.values() {
+                add(&mut sum, account, amount)?;
+            }
+        }
+    }
+    Some(sum.remove(account).unwrap_or_default())
+}
+
+/// Amounts to post so that `current` becomes the asserted balance.
+fn assigned_amounts(
+    current: BTreeMap<String, Amount>,
+    balance: &Balance,
+    kind: BalanceKind,
+) -> Option<Vec<Amount>> {
+    let target = match balance {
+        Balance::Amount(amount) => Some(amount),
+        Balance::Zero => None,
+    };
+    let sole = target.is_none() || matches!(kind, BalanceKind::Sole | BalanceKind::SoleInclusive);
+
+    let mut amounts = Vec::new();
+    if let Some(target) = target {
+        let quantity = current
+            .get(&target.commodity.name)
+            .map_or(Decimal::ZERO, |amount| amount.quantity);
+        amounts.push(Amount {
+            quantity: target.quantity.checked_sub(quantity)?,
+            commodity: target.commodity.clone(),
+        });
+    }
+    if sole {
+        let others = current.into_values().filter(|amount| {
+            !amount.quantity.is_zero()
+                && target.is_none_or(|target| target.commodity.name != amount.commodity.name)
+        });
+        amounts.extend(others.map(|amount| Amount {
+            quantity: -amount.quantity,
+            commodity: amount.commodity,
+        }));
+    }
+    if amounts.is_empty() {
+        amounts.push(Amount {
+            quantity: Decimal::ZERO,
+            commodity: Commodity {
+                name: String::new(),
+                position: CommodityPosition::Left,
+            },
+        });
+    }
+    Some(amounts)
+}
+
+/// Fills in the balance assignments of `transaction`, failing on overflow.
+fn assign(transaction: &mut Transaction, balances: &Balances) -> Option<()> {
+    let mut pending = Balances::new();
+    let mut index = 0;
+    while index < transaction.postings.len() {
+        let posting = &transaction.postings[index];
+        if let (true, Some(balance)) = (posting.is_balance_assignment(), &posting.balance) {
+            let inclusive = matches!(
+                posting.balance_kind,
+                BalanceKind::SingleInclusive | BalanceKind::SoleInclusive
+            );
+            let current = balance_of(balances, &pending, &posting.account, inclusive)?;
+            let template = posting.clone();
+            let filled: Vec<Posting> = assigned_amounts(current, balance, posting.balance_kind)?
+                .into_iter()
+                .enumerate()
+                .map(|(i, amount)| Posting {
+                    amount: Some(PostingAmount::from(amount)),
+                    balance: if i == 0 {
+                        template.balance.clone()
+                    } else {
+                        None
+                    },
+                    ..template.clone()
+                })
+                .collect();
+            let count = filled.len();
+            transaction.postings.splice(index..index + 1, filled);
+            for posting in &transaction.postings[index..index + count] {
+                if let Some(ref amount) = posting.amount {
+                    add(&mut pending, &posting.account, &amount.amount)?;
+                }
+            }
+            index += count;
+        } else {
+            if let Some(ref amount) = posting.amount {
+                add(&mut pending, &posting.account, &amount.amount)?;
+            }
+            index += 1;
+        }
+    }
+    Some(())
+}
+
+impl Posting {
+    /// Whether the posting is a balance assignment (`= $100` without an
+    /// amount), whose amount is whatever brings the account to that balance,
+    /// rather than a balance assertion checked against its amount.
+    pub fn is_balance_assignment(&self) -> bool {
+        self.amount.is_none() && self.amount_expr.is_none() && self.balance.is_some()
+    }
+}
+
+impl Ledger {
+    /// Fills in the amounts of balance assignments from the running balances
+    /// of their accounts, in file order, and infers elided amounts as
+    /// [`Ledger::infer_elided_amounts`] does. Returns the item indices of the
+    /// transactions that could not be balanced. After an overflow, the
+    /// running balances of the accounts involved are no longer reliable.
+    ///
+    /// An assignment sets the balance in its commodity; `==` assignments and
+    /// `= 0` also clear the other commodities, adding one posting per
+    /// commodity. `=*` and `==*` assignments count the balances of
+    /// subaccounts too.
+    pub fn apply_balance_assignments(&mut self) -> Vec<(usize, BalanceError)> {
+        let mut balances = Balances::new();
+        let mut bucket = None;
+        let mut errors = Vec::new();
+        for (index, item) in self.items.iter_mut().enumerate() {
+            match item {
+                LedgerItem::Bucket(account) => bucket = Some(account.clone()),
+                LedgerItem::Transaction(transaction) => {
+                    let result = match assign(transaction, &balances) {
+                        Some(()) => transaction.infer_elided_amount_with_bucket(bucket.as_deref()),
+                        None => Err(BalanceError::Overflow),
+                    };
+                    let added =
+                        transaction
+                            .postings
+                            .iter()
+                            .try_for_each(|posting| match posting.amount {
+                                Some(ref amount) => {
+                                    add(&mut balances, &posting.account, &amount.amount)
+                                }
+                                None => Some(()),
+                            });
+                    match (result, added) {
+                        (Err(err), _) => errors.push((index, err)),
+                        (Ok(()), None) => errors.push((index, BalanceError::Overflow)),
+                        (Ok(()), Some(())) => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn apply_balance_assignments_test() {
+        let mut ledger = parse(
+            r#"2024-01-01 Opening
+  Assets:Bank  $100
+  Assets:Bank:Savings  $50
+  Assets:Wallet  10 EUR
+  Equity
+2024-01-31 Reconcile
+  Assets:Bank  = $80
+  Expenses:Fees
+2024-02-01 Move
+  Assets:Wallet  $5
+  Assets:Wallet  == $5
+  Assets:Bank  $-5
+  Equity
+2024-02-02 Close
+  Assets:Bank  =* 0
+  Equity
+"#,
+        )
+        .unwrap();
+        let reconcile = ledger.transactions().nth(1).unwrap();
+        assert!(reconcile.postings[0].is_balance_assignment());
+        assert!(!reconcile.postings[1].is_balance_assignment());
+        assert!(ledger.apply_balance_assignments().is_empty());
+
+        let transactions: Vec<String> = ledger.transactions().map(|t| t.to_string()).collect();
+        assert_eq!(
+            transactions[1],
+            "2024-01-31 Reconcile\n  Assets:Bank  $-20 = $80\n  Expenses:Fees  $20"
+        );
+        assert_eq!(
+            transactions[2],
+            "2024-02-01 Move\n  Assets:Wallet  $5\n  Assets:Wallet  $0 == $5\n  Assets:Wallet  -10 EUR\n  Assets:Bank  $-5\n  Equity  10 EUR"
+        );
+        assert_eq!(
+            transactions[3],
+            "2024-02-02 Close\n  Assets:Bank  $-125 =* 0\n  Equity  $125"
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use crate::commodities::CommodityAliases;
+use crate::model::*;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Reason a transaction could not be balanced.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BalanceError {
+    /// Postings don't sum to zero and there is no posting to put the rest on.
+    Unbalanced(Vec<Amount>),
+    /// More than one posting has neither an amount nor a balance.
+    MultipleElidedAmounts,
+    /// A cost or sum is beyond the range of a decimal.
+    Overflow,
+    /// The amount expression of the posting to this account could not be
+    /// evaluated, e.g. because it uses an undefined variable.
+    UnevaluatedExpression(String),
+}
+
+impl fmt::Display for BalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BalanceError::Unbalanced(amounts) => {
+                write!(f, "transaction does not balance, remainder: ")?;
+                for (i, amount) in amounts.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", amount)?;
+                }
+                Ok(())
+            }
+            BalanceError::MultipleElidedAmounts => {
+                write!(f, "only one posting may have its amount elided")
+            }
+            BalanceError::Overflow => write!(f, "arithmetic overflow"),
+            BalanceError::UnevaluatedExpression(account) => {
+                write!(f, "cannot evaluate the amount expression of {}", account)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BalanceError {}
+
+impl Transaction {
+    /// Sums the real and balanced virtual postings per commodity and returns
+    /// the commodities that don't add up to zero.
+    ///
+    /// Postings are counted at their cost: `@`/`@@` prices take precedence
+    /// over lot prices, as in ledger. Costs and sums beyond the range of a
+    /// decimal saturate; [`Transaction::checked_unbalanced_amounts`] reports
+    /// them instead.
+    pub fn unbalanced_amounts(&self) -> Vec<Amount> {
+        self.unbalanced_amounts_with_aliases(&CommodityAliases::default())
+    }
+
+    /// Like [`Transaction::unbalanced_amounts`], but sums the commodities
+    /// that `aliases` makes the same together. Each remainder is in the
+    /// commodity as first written in the transaction.
+    pub fn unbalanced_amounts_with_aliases(&self, aliases: &CommodityAliases) -> Vec<Amount> {
+        self.cost_sums(
+            aliases,
+            |a, b| Some(a.saturating_mul(b)),
+            |a, b| Some(a.saturating_add(b)),
+        )
+        .unwrap_or_default()
+    }
+
+    /// Like [`Transaction::unbalanced_amounts`], but fails with
+    /// [`BalanceError::Overflow`] when a cost or sum overflows.
+    pub fn checked_unbalanced_amounts(&self) -> Result<Vec<Amount>, BalanceError> {
+        self.checked_cost_sums(&CommodityAliases::default())
+    }
+
+    fn checked_cost_sums(&self, aliases: &CommodityAliases) -> Result<Vec<Amount>, BalanceError> {
+        self.cost_sums(aliases, Decimal::checked_mul, Decimal::checked_add)
+            .ok_or(BalanceError::Overflow)
+    }
+
+    fn cost_sums(
+        &self,
+        aliases: &CommodityAliases,
+        mul: fn(Decimal, Decimal) -> Option<Decimal>,
+        add: fn(Decimal, Decimal) -> Option<Decimal>,
+    ) -> Option<Vec<Amount>> {
+        let mut sums: BTreeMap<String, Amount> = BTreeMap::new();
+
+        for posting in &self.postings {
+            if posting.reality == Reality::UnbalancedVirtual {
+                continue;
+            }
+            if let Some(ref amount) = posting.amount {
+                let cost = posting_cost(amount, mul)?;
+                match sums.get_mut(aliases.resolve(&cost.commodity.name)) {
+                    Some(sum) => sum.quantity = add(sum.quantity, cost.quantity)?,
+                    None => {
+                        sums.insert(aliases.resolve(&cost.commodity.name).to_owned(), cost);
+                    }
+                }
+            }
+        }
+
+        Some(
+            sums.into_values()
+                .filter(|amount| !amount.quantity.is_zero())
+                .collect(),
+        )
+    }
+
+    /// Fills in the posting whose amount was left out so that the transaction
+    /// balances. If several commodities remain, the posting is repeated once
+    /// per commodity, as ledger does.
+    ///
+    /// Postings that only have a balance (`= AMOUNT`) depend on running
+    /// account balances, so transactions containing them are not checked;
+    /// [`Ledger::apply_balance_assignments`] fills them in first.
+    ///
+    /// A posting whose amount expression could not be evaluated is not
+    /// elided: it fails with [`BalanceError::UnevaluatedExpression`].
+    pub fn infer_elided_amount(&mut self) -> Result<(), BalanceError> {
+        self.infer_elided_amount_with_aliases(&CommodityAliases::default())
+    }
+
+    /// Like [`Transaction::infer_elided_amount`], but balances the
+    /// commodities that `aliases` makes the same against each other.
+    pub fn infer_elided_amount_with_aliases(
+        &mut self,
+        aliases: &CommodityAliases,
+    ) -> Result<(), BalanceError> {
+        if let Some(posting) = self
+            .postings
+            .iter()
+            .find(|posting| posting.amount.is_none() && posting.amount_expr.is_some())
+        {
+            return Err(BalanceError::UnevaluatedExpression(posting.account.clone()));
+        }
+        if self
+            .postings
+            .iter()
+            .any(|posting| posting.amount.is_none() && posting.balance.is_some())
+        {
+            return Ok(());
+        }
+
+        let elided: Vec<usize> = self
+            .postings
+            .iter()
+            .enumerate()
+            .filter(|(_, posting)| {
+                posting.amount.is_none()
+                    && posting.amount_expr.is_none()
+                    && posting.reality != Reality::UnbalancedVirtual
+            })
+            .map(|(i, _)| i)
+            .collect();
+
+        let remainder = self.checked_cost_sums(aliases)?;
+        match elided.as_slice() {
+            [] if remainder.is_empty() => Ok(()),
+            [] => Err(BalanceError::Unbalanced(remainder)),
+            [index] => {
+                let template = self.postings[*index].clone();
+                let filled: Vec<Posting> = remainder
+                    .into_iter()
+                    .map(|amount| Posting {
+                        amount: Some(PostingAmount::from(Amount {
+                            quantity: amount.quantity * Decimal::NEGATIVE_ONE,
+                            commodity: amount.commodity,
+                        })),
+                        ..template.clone()
+                    })
+                    .collect();
+                if !filled.is_empty() {
+                    self.postings.splice(*index..*index + 1, filled);
+                }
+                Ok(())
+            }
+            _ => Err(BalanceError::MultipleElidedAmounts),
+        }
+    }
+
+    /// Like [`Transaction::infer_elided_amount`], but a transaction with a
+    /// single posting is first given an elided posting to `bucket`, as the
+    /// `bucket` directive does in ledger.
+    pub fn infer_elided_amount_with_bucket(
+        &mut self,
+        bucket: Option<&str>,
+    ) -> Result<(), BalanceError> {
+        self.infer_in_bucket(bucket, &CommodityAliases::default())
+    }
+
+    fn infer_in_bucket(
+        &mut self,
+        bucket: Option<&str>,
+        aliases: &CommodityAliases,
+    ) -> Result<(), BalanceError> {
+        if let (Some(bucket), [posting]) = (bucket, self.postings.as_slice()) {
+            if posting.amount.is_some() {
+                self.postings.push(Posting::new(bucket, None));
+            }
+        }
+        self.infer_elided_amount_with_aliases(aliases)
+    }
+}
+
+impl Ledger {
+    /// Runs [`Transaction::infer_elided_amount_with_bucket`] on every
+    /// transaction with the account of the last `bucket` directive before it,
+    /// returning the item indices of the transactions that failed.
+    ///
+    /// Commodities are balanced against their aliases from
+    /// [`Ledger::commodity_aliases`].
+    pub fn infer_elided_amounts(&mut self) -> Vec<(usize, BalanceError)> {
+        let aliases = self.commodity_aliases();
+        self.infer_elided_amounts_with_aliases(&aliases)
+    }
+
+    /// Like [`Ledger::infer_elided_amounts`], with the given commodity
+    /// aliases instead of the declared ones.
+    pub fn infer_elided_amounts_with_aliases(
+        &mut self,
+        aliases: &CommodityAliases,
+    ) -> Vec<(usize, BalanceError)> {
+        let mut bucket = None;
+        let mut errors = Vec::new();
+        for (index, item) in self.items.iter_mut().enumerate() {
+            match item {
+                LedgerItem::Bucket(account) => bucket = Some(account.clone()),
+                LedgerItem::Transaction(transaction) => {
+                    if let Err(err) = transaction.infer_in_bucket(bucket.as_deref(), aliases) {
+                        errors.push((index, err));
+                    }
+                }
+                _ => {}
+            }
+        }
+        errors
+    }
+}
+
+fn posting_cost(
+    amount: &PostingAmount,
+    mul: fn(Decimal, Decimal) -> Option<Decimal>,
+) -> Option<Amount> {
+    let quantity = amount.amount.quantity;
+    Some(match amount.price.as_ref().or(amount.lot_price.as_ref()) {
+        Some(Price::Unit(price)) => Amount {
+            quantity: mul(quantity, price.quantity)?,
+            commodity: price.commodity.clone(),
+        },
+        Some(Price::Total(price)) => Amount {
+            quantity: if quantity.is_sign_negative() {
+                -price.quantity.abs()
+            } else {
+                price.quantity.abs()
+            },
+            commodity: price.commodity.clone(),
+        },
+        None => amount.amount.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn transaction(input: &str) -> Transaction {
+        parse(input).unwrap().transactions().next().unwrap().clone()
+    }
+
+    #[test]
+    fn unbalanced_amounts_test() {
+        let t = transaction(
+            "2024-01-02 Buy\n  Assets:Broker  2 AAPL @ $150\n  (Budget)  $-1\n  Assets:Cash  $-290\n",
+        );
+        assert_eq!(t.unbalanced_amounts().len(), 1);
+        assert_eq!(t.unbalanced_amounts()[0].to_string(), "$10");
+
+        let t =
+            transaction("2024-01-02 Buy\n  Assets:Broker  2 AAPL {$150}\n  Assets:Cash  $-300\n");
+        assert_eq!(t.unbalanced_amounts(), vec![]);
+
+        let mut t = transaction(
+            "2024-01-02 Buy\n  Assets:Broker  2 AAPL @ $79228162514264337593543950335\n  Assets:Cash\n",
+        );
+        assert_eq!(
+            t.unbalanced_amounts()[0].to_string(),
+            "$79228162514264337593543950335"
+        );
+        assert_eq!(t.checked_unbalanced_amounts(), Err(BalanceError::Overflow));
+        assert_eq!(t.infer_elided_amount(), Err(BalanceError::Overflow));
+    }
+
+    #[test]
+    fn infer_elided_amount_test() {
+        let mut t = transaction(
+            "2024-01-02 Shop\n  Expenses:Food  $20\n  Expenses:Fun  10 EUR\n  ; note\n  Assets:Cash\n",
+        );
+        t.infer_elided_amount().unwrap();
+        assert_eq!(
+            t.to_string(),
+            "2024-01-02 Shop\n  Expenses:Food  $20\n  Expenses:Fun  10 EUR\n  ; note\n  Assets:Cash  $-20\n  Assets:Cash  -10 EUR"
+        );
+
+        let mut t = transaction("2024-01-02 Shop\n  Expenses:Food  $20\n  Assets:Cash  $-19\n");
+        assert_eq!(
+            t.infer_elided_amount().unwrap_err().to_string(),
+            "transaction does not balance, remainder: $1"
+        );
+
+        let mut t =
+            transaction("2024-01-02 Shop\n  Expenses:Food  $20\n  Assets:Cash\n  Assets:Bank\n");
+        assert_eq!(
+            t.infer_elided_amount(),
+            Err(BalanceError::MultipleElidedAmounts)
+        );
+
+        let mut t = transaction("2024-01-02 Shop\n  Expenses:Food  $20\n  Assets:Cash  = $100\n");
+        assert_eq!(t.infer_elided_amount(), Ok(()));
+
+        let input = "2024-01-02 Shop\n  Expenses:Food  ($5 * foo)\n  Assets:Cash  $-10\n";
+        let mut t = transaction(input);
+        assert_eq!(
+            t.infer_elided_amount(),
+            Err(BalanceError::UnevaluatedExpression(
+                "Expenses:Food".to_owned()
+            ))
+        );
+        assert_eq!(t.to_string(), input.trim_end());
+    }
+
+    #[test]
+    fn infer_elided_amounts_test() {
+        let mut ledger = parse(
+            r#"2024-01-01 Shop
+  Expenses:Food  $5
+bucket Assets:Checking
+2024-01-02 Shop
+  Expenses:Food  $20
+2024-01-03 Shop
+  Expenses:Food  $20
+  Assets:Cash  $-19
+"#,
+        )
+        .unwrap();
+
+        let errors = ledger.infer_elided_amounts();
+        assert_eq!(
+            errors.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            [0, 3]
+        );
+        assert_eq!(
+            ledger.transactions().nth(1).unwrap().to_string(),
+            "2024-01-02 Shop\n  Expenses:Food  $20\n  Assets:Checking  $-20"
+        );
+    }
+
+    #[test]
+    fn commodity_aliases_balancing_test() {
+        let t = transaction("2024-01-02 Shop\n  Expenses:Food  20 USD\n  Assets:Cash  $-20\n");
+        assert_eq!(t.unbalanced_amounts().len(), 2);
+        let aliases = CommodityAliases::default().with_alias("USD", "$");
+        assert_eq!(t.unbalanced_amounts_with_aliases(&aliases), vec![]);
+
+        let mut ledger = parse(
+            r#"commodity $
+  alias USD
+2024-01-02 Shop
+  Expenses:Food  20 USD
+  Expenses:Fun  $5
+  Assets:Cash
+"#,
+        )
+        .unwrap();
+        assert_eq!(ledger.infer_elided_amounts(), vec![]);
+        assert_eq!(
+            ledger.transactions().next().unwrap().to_string(),
+            "2024-01-02 Shop\n  Expenses:Food  20 USD\n  Expenses:Fun  $5\n  Assets:Cash  -25 USD"
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use crate::model::*;
+use std::collections::BTreeMap;
+
+///
+/// Other names of commodities, such as `USD` for `$`, so that amounts in
+/// either are treated as the same commodity without rewriting them.
+///
+/// [`Ledger::commodity_aliases`] reads them from the `alias` subdirectives
+/// of `commodity` declarations; more can be added at runtime.
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::CommodityAliases;
+///
+/// let aliases = CommodityAliases::default().with_alias("USD", "$");
+/// assert_eq!(aliases.resolve("USD"), "$");
+/// assert_eq!(aliases.resolve("EUR"), "EUR");
+/// ```
+///
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct CommodityAliases(BTreeMap<String, String>);
+
+impl CommodityAliases {
+    /// Makes `alias` another name of `commodity`, replacing an earlier
+    /// alias of the same name.
+    pub fn define(&mut self, alias: &str, commodity: &str) {
+        if alias != commodity {
+            self.0.insert(alias.to_owned(), commodity.to_owned());
+        }
+    }
+
+    pub fn with_alias(mut self, alias: &str, commodity: &str) -> Self {
+        self.define(alias, commodity);
+        self
+    }
+
+    /// Name a commodity is treated as: the commodity an alias stands for,
+    /// or the name itself.
+    pub fn resolve<'a>(&'a self, commodity: &'a str) -> &'a str {
+        self.0.get(commodity).map_or(commodity, String::as_str)
+    }
+}
+
+impl Ledger {
+    /// Commodity aliases declared with the `alias` subdirective of
+    /// `commodity` declarations, wherever they are in the journal.
+    pub fn commodity_aliases(&self) -> CommodityAliases {
+        let mut aliases = CommodityAliases::default();
+        for item in &self.items {
+            if let LedgerItem::CommodityDeclaration(declaration) = item {
+                for subdirective in &declaration.subdirectives {
+                    if let CommoditySubdirective::Alias(alias) = subdirective {
+                        aliases.define(alias, &declaration.name);
+                    }
+                }
+            }
+        }
+        aliases
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn commodity_aliases_test() {
+        let ledger =
+            parse("commodity $\n  alias USD\n  alias US$\ncommodity EUR\n  note euro\n").unwrap();
+        let aliases = ledger.commodity_aliases();
+        assert_eq!(aliases.resolve("USD"), "$");
+        assert_eq!(aliases.resolve("US$"), "$");
+        assert_eq!(aliases.resolve("$"), "$");
+        assert_eq!(aliases.resolve("EUR"), "EUR");
+
+        let aliases = aliases.with_alias("€", "EUR");
+        assert_eq!(aliases.resolve("€"), "EUR");
+    }
+}
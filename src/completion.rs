@@ -0,0 +1,190 @@
+use crate::model::*;
+use crate::tags::parse_tags;
+use std::collections::BTreeMap;
+
+///
+/// Frequency-ranked names used in a ledger, for autocompletion in editors
+/// and entry forms.
+///
+/// Build it with [`Ledger::completion_data`] and keep it up to date by adding
+/// new items as they are entered.
+///
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct CompletionData {
+    payees: BTreeMap<String, usize>,
+    accounts: BTreeMap<String, usize>,
+    commodities: BTreeMap<String, usize>,
+    tags: BTreeMap<String, BTreeMap<Option<String>, usize>>,
+}
+
+impl CompletionData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_ledger(&mut self, ledger: &Ledger) {
+        for item in &ledger.items {
+            self.add_item(item);
+        }
+    }
+
+    pub fn add_item(&mut self, item: &LedgerItem) {
+        match item {
+            LedgerItem::Transaction(transaction) => self.add_transaction(transaction),
+            LedgerItem::PeriodicTransaction(transaction) => {
+                self.add_comment(&transaction.comment);
+                for posting in &transaction.postings {
+                    self.add_posting(posting);
+                }
+            }
+            LedgerItem::CommodityPrice(price) => {
+                count(&mut self.commodities, &price.commodity_name);
+                count(&mut self.commodities, &price.amount.commodity.name);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn add_transaction(&mut self, transaction: &Transaction) {
+        if !transaction.description.is_empty() {
+            count(&mut self.payees, &transaction.description);
+        }
+        self.add_comment(&transaction.comment);
+        for posting in &transaction.postings {
+            self.add_posting(posting);
+        }
+    }
+
+    fn add_posting(&mut self, posting: &Posting) {
+        count(&mut self.accounts, &posting.account);
+        if let Some(ref amount) = posting.amount {
+            count(&mut self.commodities, &amount.amount.commodity.name);
+            for price in [&amount.lot_price, &amount.price].into_iter().flatten() {
+                match price {
+                    Price::Unit(amount) | Price::Total(amount) => {
+                        count(&mut self.commodities, &amount.commodity.name)
+                    }
+                }
+            }
+        }
+        self.add_comment(&posting.comment);
+    }
+
+    fn add_comment(&mut self, comment: &Option<String>) {
+        if let Some(ref comment) = comment {
+            for (name, value) in parse_tags(comment) {
+                *self.tags.entry(name).or_default().entry(value).or_default() += 1;
+            }
+        }
+    }
+
+    /// Transaction descriptions, most frequent first.
+    pub fn payees(&self) -> Vec<&str> {
+        ranked(self.payees.iter().map(|(name, n)| (name.as_str(), *n)))
+    }
+
+    /// Posting account names, most frequent first.
+    pub fn accounts(&self) -> Vec<&str> {
+        ranked(self.accounts.iter().map(|(name, n)| (name.as_str(), *n)))
+    }
+
+    /// Commodities of amounts, prices and price directives, most frequent first.
+    pub fn commodities(&self) -> Vec<&str> {
+        ranked(self.commodities.iter().map(|(name, n)| (name.as_str(), *n)))
+    }
+
+    /// Tag names found in comments, most frequent first.
+    pub fn tags(&self) -> Vec<&str> {
+        ranked(
+            self.tags
+                .iter()
+                .map(|(name, values)| (name.as_str(), values.values().sum())),
+        )
+    }
+
+    /// Values used with the given tag, most frequent first.
+    pub fn tag_values(&self, tag: &str) -> Vec<&str> {
+        match self.tags.get(tag) {
+            Some(values) => ranked(
+                values
+                    .iter()
+                    .filter_map(|(value, n)| value.as_deref().map(|value| (value, *n))),
+            ),
+            None => Vec::new(),
+        }
+    }
+}
+
+fn count(counts: &mut BTreeMap<String, usize>, name: &str) {
+    match counts.get_mut(name) {
+        Some(n) => *n += 1,
+        None => {
+            counts.insert(name.to_owned(), 1);
+        }
+    }
+}
+
+/// Sorts by descending frequency; ties keep alphabetical order.
+fn ranked<'a>(counts: impl Iterator<Item = (&'a str, usize)>) -> Vec<&'a str> {
+    let mut counts: Vec<_> = counts.collect();
+    counts.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+    counts.into_iter().map(|(name, _)| name).collect()
+}
+
+impl Ledger {
+    /// Collects payees, accounts, commodities and tags for autocompletion.
+    pub fn completion_data(&self) -> CompletionData {
+        let mut data = CompletionData::new();
+        data.add_ledger(self);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn completion_data_test() {
+        let ledger = parse(
+            r#"2024-01-02 Shop
+  ; :food:
+  Expenses:Food  $20
+  Assets:Cash
+2024-01-03 Cinema
+  ; Trip: Paris
+  Expenses:Fun  10 EUR @ $1.10
+  Assets:Cash
+2024-01-04 Shop
+  Expenses:Food  $5
+  ; Trip: Rome
+  Assets:Cash
+P 2024-01-05 00:00:00 AAPL $190
+"#,
+        )
+        .unwrap();
+
+        let mut data = ledger.completion_data();
+        assert_eq!(data.payees(), vec!["Shop", "Cinema"]);
+        assert_eq!(
+            data.accounts(),
+            vec!["Assets:Cash", "Expenses:Food", "Expenses:Fun"]
+        );
+        assert_eq!(data.commodities(), vec!["$", "AAPL", "EUR"]);
+        assert_eq!(data.tags(), vec!["Trip", "food"]);
+        assert_eq!(data.tag_values("Trip"), vec!["Paris", "Rome"]);
+        assert_eq!(data.tag_values("food"), Vec::<&str>::new());
+
+        let more = parse(
+            r#"2024-01-06 Cinema
+  Expenses:Fun  $10
+  ; Trip: Rome
+  Assets:Bank
+"#,
+        )
+        .unwrap();
+        data.add_ledger(&more);
+        assert_eq!(data.payees(), vec!["Cinema", "Shop"]);
+        assert_eq!(data.tag_values("Trip"), vec!["Rome", "Paris"]);
+    }
+}
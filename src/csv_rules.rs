@@ -0,0 +1,284 @@
+use crate::parser;
+use crate::{ParseError, ParserSettings};
+
+/// Settings of a rules file that are not field assignments. Any other
+/// `NAME VALUE` line assigns a value to the transaction field `NAME`.
+pub(crate) const CSV_SETTINGS: [&str; 8] = [
+    "separator",
+    "date-format",
+    "decimal-mark",
+    "newest-first",
+    "intra-day-reversed",
+    "timezone",
+    "encoding",
+    "balance-type",
+];
+
+///
+/// hledger CSV rules file (`*.csv.rules`), describing how to turn the
+/// records of a CSV file into transactions.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CsvRules {
+    /// Rules in file order.
+    pub rules: Vec<CsvRule>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum CsvRule {
+    /// `skip [N]`: number of header records to skip, 1 by default.
+    Skip(usize),
+    /// `fields NAME, NAME, ...`: names of the CSV columns, some of which
+    /// may be empty.
+    Fields(Vec<String>),
+    /// `NAME [VALUE]` for one of the settings such as `separator` or
+    /// `date-format`. Flags such as `newest-first` have no value.
+    Setting { name: String, value: Option<String> },
+    /// Top-level field assignment, applied to every record.
+    Assignment(CsvAssignment),
+    /// `if` block, applied to the records it matches.
+    If(CsvConditionalBlock),
+    /// `include FILE`.
+    Include(String),
+}
+
+///
+/// `FIELD VALUE` line setting a transaction field, such as `account1` or
+/// `amount`. `%N` and `%NAME` in `value` refer to the columns of the record,
+/// see [`CsvRules::interpolate`].
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CsvAssignment {
+    pub field: String,
+    pub value: String,
+}
+
+///
+/// Pattern an `if` block tests the record against.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CsvMatcher {
+    /// Column tested (`%NAME PATTERN`), or `None` to test the whole record.
+    pub field: Option<String>,
+    /// Case-insensitive regular expression, as written.
+    pub pattern: String,
+    /// `!`: the matcher holds when the pattern does not match.
+    pub negated: bool,
+    /// `&`: the matcher is and-ed with the previous one instead of or-ed.
+    pub and: bool,
+}
+
+///
+/// `if` block: matchers, one per line or on the `if` line, followed by
+/// indented rules.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CsvConditionalBlock {
+    pub matchers: Vec<CsvMatcher>,
+    pub assignments: Vec<CsvAssignment>,
+    /// `skip [N]`: records to skip, starting with the matching one.
+    pub skip: Option<usize>,
+    /// `end`: skip the matching record and all that follow.
+    pub end: bool,
+}
+
+impl CsvRules {
+    /// Column names given by the last `fields` rule.
+    pub fn fields(&self) -> Option<&[String]> {
+        self.rules.iter().rev().find_map(|rule| match rule {
+            CsvRule::Fields(fields) => Some(fields.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// Value of the last occurrence of a setting. Flags have an empty value.
+    pub fn setting(&self, name: &str) -> Option<&str> {
+        self.rules.iter().rev().find_map(|rule| match rule {
+            CsvRule::Setting {
+                name: setting,
+                value,
+            } if setting == name => Some(value.as_deref().unwrap_or_default()),
+            _ => None,
+        })
+    }
+
+    /// Replaces `%N` (1-based column number) and `%NAME` (column named by
+    /// [`CsvRules::fields`]) in `template` with the columns of `record`.
+    /// References to missing columns are replaced with nothing; a `%` not
+    /// followed by a name is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let rules = ledger_parser::parse_csv_rules("fields date, payee, amount\n").unwrap();
+    /// assert_eq!(
+    ///     rules.interpolate("%payee (%1)", &["2024-01-02", "Shop", "-5"]),
+    ///     "Shop (2024-01-02)"
+    /// );
+    /// ```
+    pub fn interpolate(&self, template: &str, record: &[&str]) -> String {
+        let fields = self.fields().unwrap_or_default();
+        let mut result = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('%') {
+            result.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let end = after
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+                .unwrap_or(after.len());
+            let name = &after[..end];
+            if name.is_empty() {
+                result.push('%');
+            } else {
+                let column = match name.parse::<usize>() {
+                    Ok(number) => number.checked_sub(1),
+                    Err(_) => fields.iter().position(|field| field == name),
+                };
+                result.push_str(column.and_then(|i| record.get(i)).unwrap_or(&""));
+            }
+            rest = &after[end..];
+        }
+        result.push_str(rest);
+        result
+    }
+}
+
+/// Parses an hledger CSV rules file. Empty lines and lines starting with
+/// `#`, `;` or `*` are skipped. `if` tables are not supported.
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::*;
+///
+/// let rules = parse_csv_rules(
+///     "skip 1\nfields date, description, amount\naccount1 Assets:Bank\nif COFFEE\n  account2 Expenses:Coffee\n",
+/// )
+/// .unwrap();
+/// assert_eq!(rules.rules[0], CsvRule::Skip(1));
+/// assert!(matches!(&rules.rules[3], CsvRule::If(block) if block.assignments[0].value == "Expenses:Coffee"));
+/// ```
+pub fn parse_csv_rules(input: &str) -> Result<CsvRules, ParseError> {
+    parser::parse_fragment(input, &ParserSettings::default(), parser::parse_csv_rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn parse_csv_rules_test() {
+        let rules = parse_csv_rules(
+            r#"# Bank export
+skip
+separator ;
+newest-first
+fields date, , description, amount-in, amount-out
+date-format %d/%m/%Y
+currency $
+account1 assets:bank:checking
+
+if
+%description (TAXES|IRS)
+& !%amount-out ^0
+  account2 expenses:taxes
+  comment  federal
+if groceries
+  ; weekly shopping
+  account2 expenses:food
+  skip
+if ^Balance
+  end
+include common.rules
+"#,
+        )
+        .unwrap();
+        let assignment = |field: &str, value: &str| CsvAssignment {
+            field: field.to_owned(),
+            value: value.to_owned(),
+        };
+        assert_eq!(
+            rules.rules,
+            vec![
+                CsvRule::Skip(1),
+                CsvRule::Setting {
+                    name: "separator".to_owned(),
+                    value: Some(";".to_owned()),
+                },
+                CsvRule::Setting {
+                    name: "newest-first".to_owned(),
+                    value: None,
+                },
+                CsvRule::Fields(
+                    ["date", "", "description", "amount-in", "amount-out"]
+                        .map(str::to_owned)
+                        .to_vec()
+                ),
+                CsvRule::Setting {
+                    name: "date-format".to_owned(),
+                    value: Some("%d/%m/%Y".to_owned()),
+                },
+                CsvRule::Assignment(assignment("currency", "$")),
+                CsvRule::Assignment(assignment("account1", "assets:bank:checking")),
+                CsvRule::If(CsvConditionalBlock {
+                    matchers: vec![
+                        CsvMatcher {
+                            field: Some("description".to_owned()),
+                            pattern: "(TAXES|IRS)".to_owned(),
+                            negated: false,
+                            and: false,
+                        },
+                        CsvMatcher {
+                            field: Some("amount-out".to_owned()),
+                            pattern: "^0".to_owned(),
+                            negated: true,
+                            and: true,
+                        },
+                    ],
+                    assignments: vec![
+                        assignment("account2", "expenses:taxes"),
+                        assignment("comment", "federal"),
+                    ],
+                    skip: None,
+                    end: false,
+                }),
+                CsvRule::If(CsvConditionalBlock {
+                    matchers: vec![CsvMatcher {
+                        field: None,
+                        pattern: "groceries".to_owned(),
+                        negated: false,
+                        and: false,
+                    }],
+                    assignments: vec![assignment("account2", "expenses:food")],
+                    skip: Some(1),
+                    end: false,
+                }),
+                CsvRule::If(CsvConditionalBlock {
+                    matchers: vec![CsvMatcher {
+                        field: None,
+                        pattern: "^Balance".to_owned(),
+                        negated: false,
+                        and: false,
+                    }],
+                    assignments: vec![],
+                    skip: None,
+                    end: true,
+                }),
+                CsvRule::Include("common.rules".to_owned()),
+            ]
+        );
+        assert_eq!(rules.setting("newest-first"), Some(""));
+        assert_eq!(rules.setting("separator"), Some(";"));
+        assert_eq!(rules.setting("encoding"), None);
+        assert_eq!(
+            rules.interpolate(
+                "%description %4%5 %9 100%",
+                &["01/02/2024", "x", "Shop", "", "-5"]
+            ),
+            "Shop -5  100%"
+        );
+
+        assert!(parse_csv_rules("if\n  account2 expenses:food\n").is_err());
+        assert!(parse_csv_rules("if,account2\nfoo,bar\n").is_err());
+    }
+}
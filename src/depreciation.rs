@@ -0,0 +1,199 @@
+use crate::model::*;
+use crate::periodic::occurrence;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+/// Tag naming the depreciated asset account on generated depreciation
+/// transactions.
+pub const DEPRECIATION_TAG: &str = "depreciation";
+
+/// How the depreciable cost is spread over the periods.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DepreciationMethod {
+    /// The same amount every period.
+    StraightLine,
+    /// The given fraction of the remaining book value every period, e.g.
+    /// `0.4` for 40%. The last period writes the asset down to its salvage
+    /// value.
+    DecliningBalance(Decimal),
+}
+
+///
+/// Depreciation of an asset bought for `cost` on `start_date`, written off
+/// over `periods` periods.
+///
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct DepreciationSchedule {
+    pub asset_account: String,
+    /// Account debited with the depreciation, `Expenses:Depreciation` by
+    /// default.
+    pub expense_account: String,
+    pub cost: Amount,
+    /// Book value left at the end of the schedule, zero by default.
+    pub salvage_value: Decimal,
+    pub start_date: NaiveDate,
+    /// Length of one period, monthly by default.
+    pub period: Period,
+    pub periods: u32,
+    pub method: DepreciationMethod,
+}
+
+impl DepreciationSchedule {
+    pub fn new(asset_account: &str, cost: Amount, start_date: NaiveDate, periods: u32) -> Self {
+        Self {
+            asset_account: asset_account.to_owned(),
+            expense_account: "Expenses:Depreciation".to_owned(),
+            cost,
+            salvage_value: Decimal::ZERO,
+            start_date,
+            period: Period::Monthly,
+            periods,
+            method: DepreciationMethod::StraightLine,
+        }
+    }
+
+    pub fn with_expense_account(mut self, expense_account: &str) -> Self {
+        self.expense_account = expense_account.to_owned();
+        self
+    }
+
+    pub fn with_salvage_value(mut self, salvage_value: Decimal) -> Self {
+        self.salvage_value = salvage_value;
+        self
+    }
+
+    pub fn with_period(mut self, period: Period) -> Self {
+        self.period = period;
+        self
+    }
+
+    pub fn with_method(mut self, method: DepreciationMethod) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// Generates one transaction per period, the first one period after the
+    /// start date, moving the depreciation from the asset account to the
+    /// expense account. Amounts are rounded to the precision of the cost and
+    /// add up to the cost less the salvage value.
+    ///
+    /// Each transaction is tagged with `depreciation: ASSET_ACCOUNT`.
+    pub fn generate(&self) -> Vec<Transaction> {
+        let scale = self.cost.quantity.scale();
+        let depreciable = self.cost.quantity - self.salvage_value;
+        if self.periods == 0 || depreciable <= Decimal::ZERO {
+            return Vec::new();
+        }
+
+        let quantities: Vec<Decimal> = match self.method {
+            DepreciationMethod::StraightLine => {
+                let depreciable = Amount {
+                    quantity: depreciable,
+                    commodity: self.cost.commodity.clone(),
+                };
+                match depreciable.split(self.periods as usize, scale) {
+                    Some(parts) => parts.into_iter().map(|part| part.quantity).collect(),
+                    None => return Vec::new(),
+                }
+            }
+            DepreciationMethod::DecliningBalance(rate) => {
+                let mut book_value = self.cost.quantity;
+                let mut quantities = Vec::new();
+                for index in 1..=self.periods {
+                    let remaining = book_value - self.salvage_value;
+                    let quantity = if index == self.periods {
+                        remaining
+                    } else {
+                        book_value
+                            .checked_mul(rate)
+                            .map_or(remaining, |quantity| quantity.round_dp(scale))
+                            .clamp(Decimal::ZERO, remaining)
+                    };
+                    book_value -= quantity;
+                    quantities.push(quantity);
+                }
+                quantities
+            }
+        };
+
+        let mut transactions = Vec::with_capacity(quantities.len());
+        for (index, quantity) in (1..).zip(quantities) {
+            let date = match occurrence(self.period, self.start_date, index) {
+                Some(date) => date,
+                None => break,
+            };
+            transactions.push(self.transaction_on(date, quantity));
+        }
+        transactions
+    }
+
+    fn transaction_on(&self, date: NaiveDate, quantity: Decimal) -> Transaction {
+        let posting = |account: &str, quantity: Decimal| {
+            let amount = Amount {
+                quantity,
+                commodity: self.cost.commodity.clone(),
+            };
+            Posting::new(account, Some(amount))
+        };
+
+        let mut transaction = Transaction::new(date, "Depreciation").with_postings([
+            posting(&self.expense_account, quantity),
+            posting(&self.asset_account, -quantity),
+        ]);
+        transaction.add_tag(DEPRECIATION_TAG, Some(&self.asset_account));
+        transaction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::amount_from_str;
+    use crate::*;
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn quantities(transactions: &[Transaction]) -> Vec<String> {
+        transactions
+            .iter()
+            .map(|t| t.postings[0].amount.as_ref().unwrap().amount.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn straight_line_test() {
+        let cost = amount_from_str("$1000.00").unwrap();
+        let schedule = DepreciationSchedule::new("Assets:Laptop", cost, date(2024, 1, 31), 3);
+        let transactions = schedule.generate();
+        assert_eq!(quantities(&transactions), ["$333.34", "$333.33", "$333.33"]);
+        assert_eq!(
+            transactions[1].to_string(),
+            "2024-03-31 Depreciation\n  ; depreciation: Assets:Laptop\n  Expenses:Depreciation  $333.33\n  Assets:Laptop  $-333.33"
+        );
+        assert!(transactions
+            .iter()
+            .all(|t| t.unbalanced_amounts().is_empty()));
+    }
+
+    #[test]
+    fn declining_balance_test() {
+        let cost = amount_from_str("1000 EUR").unwrap();
+        let schedule = DepreciationSchedule::new("Assets:Van", cost, date(2024, 1, 1), 3)
+            .with_period(Period::Yearly)
+            .with_salvage_value(Decimal::new(100, 0))
+            .with_method(DepreciationMethod::DecliningBalance(Decimal::new(4, 1)));
+        let transactions = schedule.generate();
+        assert_eq!(quantities(&transactions), ["400 EUR", "240 EUR", "260 EUR"]);
+        assert_eq!(transactions[2].date, date(2027, 1, 1));
+
+        let schedule = schedule.with_method(DepreciationMethod::DecliningBalance(Decimal::ONE));
+        assert_eq!(
+            quantities(&schedule.generate()),
+            ["900 EUR", "0 EUR", "0 EUR"]
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use crate::model::*;
+
+///
+/// Single item difference reported by [`Ledger::dry_run`].
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ItemChange {
+    /// Index of the item in `Ledger::items`.
+    pub index: usize,
+    /// Item before the transform, `None` if it was added.
+    pub before: Option<LedgerItem>,
+    /// Item after the transform, `None` if it was removed.
+    pub after: Option<LedgerItem>,
+}
+
+impl Ledger {
+    /// Runs a mutating transform on a copy of the ledger and reports which
+    /// items it would change, leaving `self` untouched.
+    ///
+    /// Items are compared by index, so a transform that inserts or removes
+    /// items reports every following item as changed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let ledger = ledger_parser::parse(r#"2024-01-02 Exchange
+    ///   Assets:Eur  100 EUR @ $1.10
+    ///   Assets:Usd
+    /// "#).unwrap();
+    ///
+    /// let changes = ledger.dry_run(|ledger| ledger.add_trading_postings("Trading"));
+    /// assert_eq!(changes.len(), 1);
+    /// assert_eq!(changes[0].index, 0);
+    /// ```
+    pub fn dry_run<F>(&self, transform: F) -> Vec<ItemChange>
+    where
+        F: FnOnce(&mut Ledger),
+    {
+        let mut transformed = self.clone();
+        transform(&mut transformed);
+
+        let len = self.items.len().max(transformed.items.len());
+        (0..len)
+            .filter_map(|index| {
+                let before = self.items.get(index);
+                let after = transformed.items.get(index);
+                if before == after {
+                    None
+                } else {
+                    Some(ItemChange {
+                        index,
+                        before: before.cloned(),
+                        after: after.cloned(),
+                    })
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn dry_run_test() {
+        let ledger = parse(
+            r#"; comment
+2024-01-02 Exchange
+  Assets:Eur  100 EUR @ $1.10
+  Assets:Usd
+"#,
+        )
+        .unwrap();
+
+        let changes = ledger.dry_run(|ledger| ledger.add_trading_postings("Trading"));
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].index, 1);
+        assert_eq!(changes[0].before.as_ref(), ledger.items.get(1));
+        match changes[0].after {
+            Some(LedgerItem::Transaction(ref transaction)) => {
+                assert_eq!(transaction.postings.len(), 4)
+            }
+            _ => panic!("expected a transaction"),
+        }
+
+        let changes = ledger.dry_run(|ledger| {
+            ledger.items.remove(0);
+        });
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].index, 1);
+        assert_eq!(changes[1].after, None);
+
+        assert_eq!(ledger.dry_run(|_| {}), vec![]);
+    }
+}
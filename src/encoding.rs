@@ -0,0 +1,341 @@
+use crate::{ParseError, ParseWarning, ParseWarningKind, ParserSettings};
+use std::borrow::Cow;
+
+/// Single-byte encoding of journals that are not UTF-8, see
+/// [`ParserSettings::with_legacy_encoding`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LegacyEncoding {
+    /// ISO-8859-1, each byte being the code point of the same value.
+    Latin1,
+    /// Windows-1252, Latin-1 with printable characters such as `€` instead
+    /// of most C1 control characters.
+    Windows1252,
+}
+
+/// Characters of Windows-1252 bytes `0x80` to `0x9F`. Unassigned bytes map
+/// to the C1 control character of the same value, as in the WHATWG
+/// encoding standard.
+const WINDOWS_1252_C1: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+
+impl LegacyEncoding {
+    fn char(self, byte: u8) -> char {
+        match (self, byte) {
+            (LegacyEncoding::Windows1252, 0x80..=0x9f) => WINDOWS_1252_C1[usize::from(byte - 0x80)],
+            _ => char::from(byte),
+        }
+    }
+}
+
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+const UTF16LE_BOM: &[u8] = b"\xff\xfe";
+const UTF16BE_BOM: &[u8] = b"\xfe\xff";
+
+/// Bytes shown on each side of a bad byte in error previews.
+const PREVIEW_CONTEXT: usize = 16;
+
+/// Control characters other than tabs and line breaks, which have no place
+/// in a journal.
+fn is_forbidden_control(c: char) -> bool {
+    c.is_control() && !matches!(c, '\t' | '\n' | '\r')
+}
+
+/// Text around byte `offset`, decoded lossily with control characters
+/// escaped.
+fn preview(input: &[u8], offset: usize) -> String {
+    let start = offset.saturating_sub(PREVIEW_CONTEXT);
+    let end = (offset + PREVIEW_CONTEXT).min(input.len());
+    String::from_utf8_lossy(&input[start..end])
+        .chars()
+        .map(|c| {
+            if c.is_control() {
+                c.escape_default().to_string()
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    lossy: bool,
+    output: String,
+    line: usize,
+    warnings: Vec<ParseWarning>,
+    /// Whether the last character read is a `\r`, not written yet.
+    carriage_return: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(input: &'a [u8], lossy: bool) -> Self {
+        Decoder {
+            input,
+            lossy,
+            output: String::with_capacity(input.len()),
+            line: 1,
+            warnings: Vec::new(),
+            carriage_return: false,
+        }
+    }
+
+    /// Writes a pending `\r`, as a line feed unless it starts a `\r\n`
+    /// line break, so that old Mac line breaks read like the others.
+    fn flush_carriage_return(&mut self, next: Option<char>) {
+        if std::mem::take(&mut self.carriage_return) {
+            if next == Some('\n') {
+                self.output.push('\r');
+            } else {
+                self.output.push('\n');
+                self.line += 1;
+            }
+        }
+    }
+
+    fn finish(mut self) -> (Cow<'a, str>, Vec<ParseWarning>) {
+        self.flush_carriage_return(None);
+        (Cow::Owned(self.output), self.warnings)
+    }
+
+    fn replace(&mut self, kind: ParseWarningKind) {
+        self.flush_carriage_return(None);
+        self.output.push(char::REPLACEMENT_CHARACTER);
+        self.warnings.push(ParseWarning {
+            line: self.line,
+            kind,
+        });
+    }
+
+    /// Appends valid text starting at byte `offset` of the input.
+    fn push_str(&mut self, text: &str, offset: usize) -> Result<(), ParseError> {
+        for (index, c) in text.char_indices() {
+            self.flush_carriage_return(Some(c));
+            if c == '\r' {
+                self.carriage_return = true;
+            } else if is_forbidden_control(c) {
+                let offset = offset + index;
+                if !self.lossy {
+                    return Err(ParseError::ControlCharacter {
+                        offset,
+                        preview: preview(self.input, offset),
+                    });
+                }
+                self.replace(ParseWarningKind::ControlCharacter { offset });
+            } else {
+                if c == '\n' {
+                    self.line += 1;
+                }
+                self.output.push(c);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes journal bytes, rejecting invalid UTF-8 and control characters,
+/// or with `lossy` replacing them by U+FFFD and reporting each one as a
+/// warning. A `\r` line break not followed by `\n` is read as `\n`.
+///
+/// A UTF-8 byte order mark is dropped, and a UTF-16 one makes the rest of
+/// the input read as UTF-16. Input that is not valid UTF-8 is read in the
+/// legacy encoding of the settings, if any.
+pub(crate) fn decode<'a>(
+    input: &'a [u8],
+    settings: &ParserSettings,
+) -> Result<(Cow<'a, str>, Vec<ParseWarning>), ParseError> {
+    let lossy = settings.lossy_input;
+    if input.starts_with(UTF16LE_BOM) {
+        return decode_utf16(input, lossy, u16::from_le_bytes);
+    }
+    if input.starts_with(UTF16BE_BOM) {
+        return decode_utf16(input, lossy, u16::from_be_bytes);
+    }
+    let start = if input.starts_with(UTF8_BOM) {
+        UTF8_BOM.len()
+    } else {
+        0
+    };
+    let utf8 = std::str::from_utf8(&input[start..]);
+    if let Ok(text) = utf8 {
+        let lone_carriage_return = text.split('\r').skip(1).any(|rest| !rest.starts_with('\n'));
+        if !lone_carriage_return && !text.chars().any(is_forbidden_control) {
+            return Ok((Cow::Borrowed(text), Vec::new()));
+        }
+    }
+
+    let mut decoder = Decoder::new(input, lossy);
+    if let (Err(_), Some(encoding)) = (utf8, settings.legacy_encoding) {
+        for (offset, &byte) in input.iter().enumerate() {
+            decoder.push_str(encoding.char(byte).encode_utf8(&mut [0; 4]), offset)?;
+        }
+        return Ok(decoder.finish());
+    }
+    let mut offset = start;
+    while offset < input.len() {
+        let rest = &input[offset..];
+        let (valid, invalid) = match std::str::from_utf8(rest) {
+            Ok(text) => (text, None),
+            Err(error) => {
+                let valid = std::str::from_utf8(&rest[..error.valid_up_to()]).unwrap_or_default();
+                let length = error.error_len().unwrap_or(rest.len() - valid.len());
+                (valid, Some(length))
+            }
+        };
+        decoder.push_str(valid, offset)?;
+        offset += valid.len();
+
+        if let Some(length) = invalid {
+            if !lossy {
+                return Err(ParseError::InvalidUtf8 {
+                    offset,
+                    preview: preview(input, offset),
+                });
+            }
+            decoder.replace(ParseWarningKind::InvalidUtf8 { offset });
+            offset += length;
+        }
+    }
+    Ok(decoder.finish())
+}
+
+/// Decodes UTF-16 input starting with a byte order mark, reading code units
+/// with `unit`.
+fn decode_utf16(
+    input: &[u8],
+    lossy: bool,
+    unit: fn([u8; 2]) -> u16,
+) -> Result<(Cow<'_, str>, Vec<ParseWarning>), ParseError> {
+    let mut decoder = Decoder::new(input, lossy);
+    let units = input[2..]
+        .chunks_exact(2)
+        .map(|pair| unit([pair[0], pair[1]]));
+    let mut offset = 2;
+    for result in char::decode_utf16(units) {
+        let length = result.as_ref().map_or(2, |c| c.len_utf16() * 2);
+        match result {
+            Ok(c) => decoder.push_str(c.encode_utf8(&mut [0; 4]), offset)?,
+            Err(_) if !lossy => return Err(ParseError::InvalidUtf16 { offset }),
+            Err(_) => decoder.replace(ParseWarningKind::InvalidUtf16 { offset }),
+        }
+        offset += length;
+    }
+    if input.len() % 2 == 1 {
+        let offset = input.len() - 1;
+        if !lossy {
+            return Err(ParseError::InvalidUtf16 { offset });
+        }
+        decoder.replace(ParseWarningKind::InvalidUtf16 { offset });
+    }
+    Ok(decoder.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn decode_test() {
+        let input = b"2024-01-02 Caf\xc3\xa9\n  Expenses:Food  $5 ; \xff\x1b\n  Assets:Cash\n";
+        let error = parse_bytes(input, &ParserSettings::default()).unwrap_err();
+        assert!(matches!(error, ParseError::InvalidUtf8 { offset: 39, .. }));
+        assert_eq!(
+            error.to_string(),
+            "invalid UTF-8 at byte 39 near \"nses:Food  $5 ; \u{fffd}\\u{1b}\\n  Assets:Cash\""
+        );
+
+        let error = parse("2024-01-02 Shop\x0c\n").unwrap_err();
+        assert!(matches!(
+            error,
+            ParseError::ControlCharacter { offset: 15, .. }
+        ));
+
+        let settings = ParserSettings::default().with_lossy_input(true);
+        let (ledger, warnings) = parse_bytes(input, &settings).unwrap();
+        assert_eq!(
+            ledger.transactions().next().unwrap().postings[0]
+                .comment
+                .as_deref(),
+            Some("\u{fffd}\u{fffd}")
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                ParseWarning {
+                    line: 2,
+                    kind: ParseWarningKind::InvalidUtf8 { offset: 39 },
+                },
+                ParseWarning {
+                    line: 2,
+                    kind: ParseWarningKind::ControlCharacter { offset: 40 },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_bom_and_legacy_encoding_test() {
+        let journal = "2024-01-02 Café\n  Expenses:Food  €5\n  Assets:Cash\n";
+        let settings = ParserSettings::default();
+        let expected = parse(journal).unwrap();
+        let ledger = |input: &[u8], settings: &ParserSettings| {
+            parse_bytes(input, settings).map(|(ledger, _)| ledger)
+        };
+
+        let utf8 = [b"\xef\xbb\xbf", journal.as_bytes()].concat();
+        assert_eq!(ledger(&utf8, &settings).unwrap(), expected);
+        assert_eq!(
+            parse(std::str::from_utf8(&utf8).unwrap()).unwrap(),
+            expected
+        );
+
+        let utf16le: Vec<u8> = [0xfeff]
+            .into_iter()
+            .chain(journal.encode_utf16())
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        assert_eq!(ledger(&utf16le, &settings).unwrap(), expected);
+        let utf16be: Vec<u8> = [0xfeff]
+            .into_iter()
+            .chain(journal.encode_utf16())
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        assert_eq!(ledger(&utf16be, &settings).unwrap(), expected);
+
+        let mut broken = utf16le[..utf16le.len() - 2].to_vec();
+        broken.extend([0x00, 0xd8, b'\n']);
+        assert!(matches!(
+            ledger(&broken, &settings),
+            Err(ParseError::InvalidUtf16 { offset }) if offset == broken.len() - 3
+        ));
+        let lossy = ParserSettings::default().with_lossy_input(true);
+        let (_, warnings) = parse_bytes(&broken, &lossy).unwrap();
+        assert_eq!(
+            warnings
+                .iter()
+                .map(|warning| &warning.kind)
+                .collect::<Vec<_>>(),
+            [
+                &ParseWarningKind::InvalidUtf16 {
+                    offset: broken.len() - 3
+                },
+                &ParseWarningKind::InvalidUtf16 {
+                    offset: broken.len() - 1
+                },
+            ]
+        );
+
+        let windows_1252 = b"2024-01-02 Caf\xe9\n  Expenses:Food  \x805\n  Assets:Cash\n";
+        assert!(ledger(windows_1252, &settings).is_err());
+        let cp1252 = ParserSettings::default().with_legacy_encoding(LegacyEncoding::Windows1252);
+        assert_eq!(ledger(windows_1252, &cp1252).unwrap(), expected);
+        let latin1 = ParserSettings::default().with_legacy_encoding(LegacyEncoding::Latin1);
+        assert!(matches!(
+            ledger(windows_1252, &latin1),
+            Err(ParseError::ControlCharacter { offset: 33, .. })
+        ));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::balancing::BalanceError;
+use crate::model::*;
+use crate::tags::comment_tags;
+use chrono::NaiveDate;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Non-fatal issue noticed while filling in an [`EntryDraft`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum EntryWarning {
+    /// The account has not been used in the ledger the draft was created from.
+    UnknownAccount(String),
+}
+
+impl fmt::Display for EntryWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EntryWarning::UnknownAccount(account) => write!(f, "unknown account: {}", account),
+        }
+    }
+}
+
+/// Reason an [`EntryDraft`] could not be turned into a transaction.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum EntryError {
+    MissingDate,
+    /// A transaction needs at least two postings.
+    TooFewPostings,
+    Balance(BalanceError),
+}
+
+impl fmt::Display for EntryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EntryError::MissingDate => write!(f, "transaction date is missing"),
+            EntryError::TooFewPostings => write!(f, "transaction needs at least two postings"),
+            EntryError::Balance(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for EntryError {}
+
+///
+/// Transaction being entered step by step, as in `ledger entry` or
+/// `hledger add`.
+///
+/// Each step validates its input, and [`EntryDraft::finish`] fills in the
+/// elided amount of the last posting before producing the transaction.
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::*;
+///
+/// let ledger = parse("2024-01-01 Shop\n  Expenses:Food  $5\n  Assets:Cash\n").unwrap();
+///
+/// let mut draft = EntryDraft::for_ledger(&ledger);
+/// draft.set_date(chrono::NaiveDate::from_ymd_opt(2024, 1, 2).unwrap());
+/// draft.set_description("Shop");
+/// let amount = Amount {
+///     quantity: rust_decimal::Decimal::new(1250, 2),
+///     commodity: Commodity {
+///         name: "$".to_owned(),
+///         position: CommodityPosition::Left,
+///     },
+/// };
+/// draft.add_posting("Expenses:Food", Some(amount));
+/// let warnings = draft.add_posting("Assets:Wallet", None);
+/// assert_eq!(warnings, vec![EntryWarning::UnknownAccount("Assets:Wallet".to_owned())]);
+///
+/// let transaction = draft.finish().unwrap();
+/// assert_eq!(transaction.postings[1].to_string(), "Assets:Wallet  $-12.50");
+/// ```
+///
+#[derive(Debug, Default, Clone)]
+pub struct EntryDraft {
+    date: Option<NaiveDate>,
+    status: Option<TransactionStatus>,
+    code: Option<String>,
+    description: String,
+    comment: Option<String>,
+    postings: Vec<Posting>,
+    known_accounts: BTreeSet<String>,
+}
+
+impl EntryDraft {
+    /// Creates a draft that doesn't warn about any account.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a draft that warns about accounts not used in `ledger`.
+    pub fn for_ledger(ledger: &Ledger) -> Self {
+        let known_accounts = ledger
+            .transactions()
+            .flat_map(|transaction| &transaction.postings)
+            .map(|posting| posting.account.clone())
+            .collect();
+        Self {
+            known_accounts,
+            ..Self::default()
+        }
+    }
+
+    pub fn set_date(&mut self, date: NaiveDate) {
+        self.date = Some(date);
+    }
+
+    pub fn set_status(&mut self, status: Option<TransactionStatus>) {
+        self.status = status;
+    }
+
+    pub fn set_code(&mut self, code: Option<&str>) {
+        self.code = code.map(str::to_owned);
+    }
+
+    pub fn set_description(&mut self, description: &str) {
+        self.description = description.trim().to_owned();
+    }
+
+    pub fn set_comment(&mut self, comment: Option<&str>) {
+        self.comment = comment.map(str::to_owned);
+    }
+
+    /// Adds a posting, returning warnings about it. Leave `amount` empty on
+    /// the last posting to have it balanced automatically.
+    pub fn add_posting(&mut self, account: &str, amount: Option<Amount>) -> Vec<EntryWarning> {
+        let account = account.trim();
+        let mut warnings = Vec::new();
+        if !self.known_accounts.is_empty() && !self.known_accounts.contains(account) {
+            warnings.push(EntryWarning::UnknownAccount(account.to_owned()));
+        }
+
+        self.postings.push(Posting::new(account, amount));
+        warnings
+    }
+
+    /// Removes the last posting, e.g. to re-enter it.
+    pub fn remove_last_posting(&mut self) -> Option<Posting> {
+        self.postings.pop()
+    }
+
+    pub fn postings(&self) -> &[Posting] {
+        &self.postings
+    }
+
+    /// Amounts still needed to balance the postings entered so far.
+    pub fn remaining(&self) -> Vec<Amount> {
+        self.to_transaction(NaiveDate::MIN)
+            .unbalanced_amounts()
+            .into_iter()
+            .map(|amount| Amount {
+                quantity: -amount.quantity,
+                commodity: amount.commodity,
+            })
+            .collect()
+    }
+
+    /// Validates the draft and returns the balanced transaction.
+    pub fn finish(self) -> Result<Transaction, EntryError> {
+        let date = self.date.ok_or(EntryError::MissingDate)?;
+        if self.postings.len() < 2 {
+            return Err(EntryError::TooFewPostings);
+        }
+
+        let mut transaction = self.to_transaction(date);
+        transaction
+            .infer_elided_amount()
+            .map_err(EntryError::Balance)?;
+        Ok(transaction)
+    }
+
+    fn to_transaction(&self, date: NaiveDate) -> Transaction {
+        Transaction {
+            comment: self.comment.clone(),
+            status: self.status,
+            code: self.code.clone(),
+            tags: comment_tags(self.comment.as_deref()),
+            ..Transaction::new(date, &self.description).with_postings(self.postings.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    fn usd(quantity: i64) -> Option<Amount> {
+        Some(Amount {
+            quantity: Decimal::new(quantity, 0),
+            commodity: Commodity {
+                name: "$".to_owned(),
+                position: CommodityPosition::Left,
+            },
+        })
+    }
+
+    #[test]
+    fn entry_draft_test() {
+        let mut draft = EntryDraft::new();
+        assert_eq!(draft.clone().finish(), Err(EntryError::MissingDate));
+
+        draft.set_date(NaiveDate::from_ymd_opt(2024, 1, 2).unwrap());
+        draft.set_status(Some(TransactionStatus::Cleared));
+        draft.set_description(" Shop ");
+        assert_eq!(draft.add_posting("Expenses:Food", usd(20)), vec![]);
+        assert_eq!(draft.clone().finish(), Err(EntryError::TooFewPostings));
+        assert_eq!(draft.remaining()[0].to_string(), "$-20");
+
+        draft.add_posting("Assets:Cash", usd(-15));
+        assert!(matches!(
+            draft.clone().finish(),
+            Err(EntryError::Balance(BalanceError::Unbalanced(_)))
+        ));
+
+        draft.remove_last_posting();
+        draft.add_posting("Assets:Cash", None);
+        assert_eq!(
+            draft.finish().unwrap().to_string(),
+            "2024-01-02 * Shop\n  Expenses:Food  $20\n  Assets:Cash  $-20"
+        );
+    }
+}
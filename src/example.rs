@@ -0,0 +1,202 @@
+use crate::model::*;
+use chrono::{Datelike, Days, Months, NaiveDate};
+use rust_decimal::Decimal;
+
+const EXPENSE_CATEGORIES: [&str; 10] = [
+    "Groceries",
+    "Dining",
+    "Transport",
+    "Utilities",
+    "Entertainment",
+    "Health",
+    "Clothing",
+    "Gifts",
+    "Travel",
+    "Education",
+];
+
+///
+/// Shape of the journal generated by [`Ledger::example`].
+///
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct ExampleProfile {
+    pub start_date: NaiveDate,
+    pub years: u32,
+    /// Number of expense accounts besides rent. Groceries is always one.
+    pub expense_accounts: usize,
+    /// Seed of the pseudo-random noise; the same seed gives the same journal.
+    pub seed: u64,
+}
+
+impl ExampleProfile {
+    pub fn with_start_date(mut self, start_date: NaiveDate) -> Self {
+        self.start_date = start_date;
+        self
+    }
+
+    pub fn with_years(mut self, years: u32) -> Self {
+        self.years = years;
+        self
+    }
+
+    pub fn with_expense_accounts(mut self, expense_accounts: usize) -> Self {
+        self.expense_accounts = expense_accounts;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+impl Default for ExampleProfile {
+    fn default() -> Self {
+        Self {
+            start_date: NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(),
+            years: 1,
+            expense_accounts: 5,
+            seed: 0,
+        }
+    }
+}
+
+/// Small xorshift generator, so examples don't depend on a random crate.
+struct Noise(u64);
+
+impl Noise {
+    fn new(seed: u64) -> Self {
+        Self(seed ^ 0x9e37_79b9_7f4a_7c15 | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Value in `[low, high)`.
+    fn range(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next() % (high - low)
+    }
+}
+
+fn expense_account(index: usize) -> String {
+    match EXPENSE_CATEGORIES.get(index) {
+        Some(category) => format!("Expenses:{}", category),
+        None => format!("Expenses:Other:{}", index - EXPENSE_CATEGORIES.len() + 1),
+    }
+}
+
+fn dollars(cents: i64) -> Amount {
+    Amount {
+        quantity: Decimal::new(cents, 2),
+        commodity: Commodity {
+            name: "$".to_owned(),
+            position: CommodityPosition::Left,
+        },
+    }
+}
+
+fn transaction(date: NaiveDate, description: &str, postings: Vec<Posting>) -> LedgerItem {
+    LedgerItem::Transaction(
+        Transaction::new(date, description)
+            .with_status(TransactionStatus::Cleared)
+            .with_postings(postings),
+    )
+}
+
+fn expense(date: NaiveDate, description: &str, account: &str, cents: i64) -> LedgerItem {
+    let postings = vec![
+        Posting::new(account, Some(dollars(cents))),
+        Posting::new("Assets:Checking", None),
+    ];
+    transaction(date, description, postings)
+}
+
+impl Ledger {
+    /// Generates a realistic synthetic journal: a monthly salary and rent,
+    /// weekly groceries and occasional spending on the other expense
+    /// accounts, all with some noise. Useful for benchmarks, demos and test
+    /// fixtures.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ledger_parser::*;
+    ///
+    /// let ledger = Ledger::example(&ExampleProfile::default().with_years(2));
+    /// assert_eq!(ledger, parse(&ledger.to_string()).unwrap());
+    /// ```
+    pub fn example(profile: &ExampleProfile) -> Ledger {
+        let mut noise = Noise::new(profile.seed);
+        let end_date = profile
+            .start_date
+            .checked_add_months(Months::new(profile.years.saturating_mul(12)))
+            .unwrap_or(NaiveDate::MAX);
+        let accounts: Vec<String> = (0..profile.expense_accounts.max(1))
+            .map(expense_account)
+            .collect();
+
+        let mut items = Vec::new();
+        let mut date = profile.start_date;
+        while date < end_date {
+            if date.day() == 1 {
+                let salary = 300_000 + noise.range(0, 20_000) as i64;
+                let postings = vec![
+                    Posting::new("Assets:Checking", Some(dollars(salary))),
+                    Posting::new("Income:Salary", None),
+                ];
+                items.push(transaction(date, "Employer", postings));
+                items.push(expense(date, "Landlord", "Expenses:Rent", 120_000));
+            }
+
+            if date.weekday() == chrono::Weekday::Sat {
+                let cents = noise.range(4_000, 12_000) as i64;
+                items.push(expense(date, "Supermarket", &accounts[0], cents));
+                for account in &accounts[1..] {
+                    if noise.range(0, 10) < 3 {
+                        let cents = noise.range(500, 15_000) as i64;
+                        items.push(expense(date, "Shop", account, cents));
+                    }
+                }
+            }
+
+            date = match date.checked_add_days(Days::new(1)) {
+                Some(date) => date,
+                None => break,
+            };
+        }
+
+        Ledger { items }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn example_test() {
+        let profile = ExampleProfile::default()
+            .with_expense_accounts(12)
+            .with_seed(7);
+        let ledger = Ledger::example(&profile);
+        assert_eq!(ledger, Ledger::example(&profile));
+        assert_eq!(ledger, parse(&ledger.to_string()).unwrap());
+
+        let salaries = ledger
+            .transactions()
+            .filter(|t| t.description == "Employer")
+            .count();
+        assert_eq!(salaries, 12);
+        assert!(ledger
+            .transactions()
+            .any(|t| t.postings[0].account == "Expenses:Other:2"));
+        assert!(ledger
+            .transactions()
+            .all(|t| t.clone().infer_elided_amount().is_ok()));
+    }
+}
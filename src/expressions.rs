@@ -0,0 +1,241 @@
+use crate::model::*;
+use crate::parser::expression_from_str;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Reason a value expression could not be evaluated.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ExprError {
+    UnknownVariable(String),
+    /// The function doesn't exist or got the wrong number of arguments.
+    UnknownFunction(String),
+    /// The operation is not defined for amounts in these two commodities.
+    CommodityMismatch(String, String),
+    DivisionByZero,
+    Overflow,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExprError::UnknownVariable(name) => write!(f, "unknown variable: {}", name),
+            ExprError::UnknownFunction(name) => write!(f, "unknown function: {}", name),
+            ExprError::CommodityMismatch(left, right) => {
+                write!(f, "cannot combine commodities {} and {}", left, right)
+            }
+            ExprError::DivisionByZero => write!(f, "division by zero"),
+            ExprError::Overflow => write!(f, "arithmetic overflow"),
+        }
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+fn number(quantity: Decimal) -> Amount {
+    Amount {
+        quantity,
+        commodity: Commodity {
+            name: String::new(),
+            position: CommodityPosition::Left,
+        },
+    }
+}
+
+/// Commodity of the result of adding or subtracting `left` and `right`.
+fn common_commodity(left: &Amount, right: &Amount) -> Result<Commodity, ExprError> {
+    if right.commodity.name.is_empty() || left.commodity.name == right.commodity.name {
+        Ok(left.commodity.clone())
+    } else if left.commodity.name.is_empty() {
+        Ok(right.commodity.clone())
+    } else {
+        Err(ExprError::CommodityMismatch(
+            left.commodity.name.clone(),
+            right.commodity.name.clone(),
+        ))
+    }
+}
+
+impl AmountExpr {
+    /// Evaluates the expression with the arithmetic operators and the
+    /// `abs`, `floor`, `ceiling` and `round` functions. Numbers are returned
+    /// as amounts with an empty commodity name.
+    ///
+    /// Variables are looked up in `variables` without their leading `$`.
+    /// Amounts in different commodities can't be added or multiplied
+    /// together, and can only be divided by an amount in the same
+    /// commodity, which gives a number.
+    pub fn evaluate(&self, variables: &BTreeMap<String, Amount>) -> Result<Amount, ExprError> {
+        let binary = |left: &AmountExpr, right: &AmountExpr| {
+            Ok::<_, ExprError>((left.evaluate(variables)?, right.evaluate(variables)?))
+        };
+
+        match self {
+            AmountExpr::Amount(amount) => Ok(amount.clone()),
+            AmountExpr::Number(quantity) => Ok(number(*quantity)),
+            AmountExpr::Variable(name) => {
+                let name = name.strip_prefix('$').unwrap_or(name);
+                variables
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| ExprError::UnknownVariable(name.to_owned()))
+            }
+            AmountExpr::Neg(expr) => {
+                let amount = expr.evaluate(variables)?;
+                Ok(Amount {
+                    quantity: -amount.quantity,
+                    ..amount
+                })
+            }
+            AmountExpr::Add(left, right) | AmountExpr::Sub(left, right) => {
+                let (left, right) = binary(left, right)?;
+                let quantity = match self {
+                    AmountExpr::Add(..) => left.quantity.checked_add(right.quantity),
+                    _ => left.quantity.checked_sub(right.quantity),
+                };
+                Ok(Amount {
+                    quantity: quantity.ok_or(ExprError::Overflow)?,
+                    commodity: common_commodity(&left, &right)?,
+                })
+            }
+            AmountExpr::Mul(left, right) => {
+                let (left, right) = binary(left, right)?;
+                if !left.commodity.name.is_empty() && !right.commodity.name.is_empty() {
+                    return Err(ExprError::CommodityMismatch(
+                        left.commodity.name,
+                        right.commodity.name,
+                    ));
+                }
+                Ok(Amount {
+                    quantity: left
+                        .quantity
+                        .checked_mul(right.quantity)
+                        .ok_or(ExprError::Overflow)?,
+                    commodity: common_commodity(&left, &right)?,
+                })
+            }
+            AmountExpr::Div(left, right) => {
+                let (left, right) = binary(left, right)?;
+                if right.quantity.is_zero() {
+                    return Err(ExprError::DivisionByZero);
+                }
+                let commodity = if right.commodity.name.is_empty() {
+                    left.commodity
+                } else if left.commodity.name == right.commodity.name {
+                    number(Decimal::ZERO).commodity
+                } else {
+                    return Err(ExprError::CommodityMismatch(
+                        left.commodity.name,
+                        right.commodity.name,
+                    ));
+                };
+                Ok(Amount {
+                    quantity: left
+                        .quantity
+                        .checked_div(right.quantity)
+                        .ok_or(ExprError::Overflow)?,
+                    commodity,
+                })
+            }
+            AmountExpr::Call(name, arguments) => {
+                let function: fn(Decimal) -> Decimal = match name.as_str() {
+                    "abs" => |quantity| quantity.abs(),
+                    "floor" => |quantity| quantity.floor(),
+                    "ceiling" => |quantity| quantity.ceil(),
+                    "round" => |quantity| quantity.round(),
+                    _ => return Err(ExprError::UnknownFunction(name.clone())),
+                };
+                match arguments.as_slice() {
+                    [argument] => {
+                        let amount = argument.evaluate(variables)?;
+                        Ok(Amount {
+                            quantity: function(amount.quantity),
+                            ..amount
+                        })
+                    }
+                    _ => Err(ExprError::UnknownFunction(name.clone())),
+                }
+            }
+        }
+    }
+}
+
+impl Ledger {
+    /// Values of the variables set with `define`, evaluated in file order so
+    /// that a definition can use the ones before it. Definitions that can't
+    /// be evaluated are left out.
+    pub fn variables(&self) -> BTreeMap<String, Amount> {
+        let mut variables = BTreeMap::new();
+        for item in &self.items {
+            if let LedgerItem::Definition(definition) = item {
+                define(&mut variables, definition);
+            }
+        }
+        variables
+    }
+}
+
+/// Evaluates `definition` and stores its value in `variables`.
+pub(crate) fn define(variables: &mut BTreeMap<String, Amount>, definition: &Definition) {
+    let value =
+        expression_from_str(&definition.value).and_then(|expr| expr.evaluate(variables).ok());
+    match value {
+        Some(value) => variables.insert(definition.name.clone(), value),
+        None => variables.remove(&definition.name),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::expression_from_str;
+    use crate::*;
+    use std::collections::BTreeMap;
+
+    fn evaluate(input: &str) -> Result<String, ExprError> {
+        let variables = parse("define rate=1.5\ndefine fee=$2 * rate\n")
+            .unwrap()
+            .variables();
+        expression_from_str(input)
+            .unwrap()
+            .evaluate(&variables)
+            .map(|amount| amount.to_string())
+    }
+
+    #[test]
+    fn evaluate_test() {
+        assert_eq!(evaluate("$10.00 * 3"), Ok("$30.00".to_owned()));
+        assert_eq!(
+            evaluate("2 * floor(-$1.5) + 1 EUR / 4"),
+            Err(ExprError::CommodityMismatch(
+                "$".to_owned(),
+                "EUR".to_owned()
+            ))
+        );
+        assert_eq!(evaluate("(10 EUR - 4 EUR) / 2 EUR"), Ok("3".to_owned()));
+        assert_eq!(evaluate("$fee + rate"), Ok("$4.5".to_owned()));
+        assert_eq!(evaluate("round(-($1.4))"), Ok("$-1".to_owned()));
+        assert_eq!(
+            evaluate("$1 * $2"),
+            Err(ExprError::CommodityMismatch("$".to_owned(), "$".to_owned()))
+        );
+        assert_eq!(evaluate("$1 / 0"), Err(ExprError::DivisionByZero));
+        assert_eq!(
+            evaluate("amount"),
+            Err(ExprError::UnknownVariable("amount".to_owned()))
+        );
+        assert_eq!(
+            evaluate("sqrt(4)"),
+            Err(ExprError::UnknownFunction("sqrt".to_owned()))
+        );
+        assert_eq!(
+            AmountExpr::Number(rust_decimal::Decimal::ONE).evaluate(&BTreeMap::new()),
+            Ok(Amount {
+                quantity: rust_decimal::Decimal::ONE,
+                commodity: Commodity {
+                    name: String::new(),
+                    position: CommodityPosition::Left
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,504 @@
+use crate::model::*;
+use crate::{parse_bytes, ParseError, ParserSettings};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Reason a ledger and the files it includes could not be loaded.
+#[derive(Debug)]
+pub enum IncludeError {
+    /// The file could not be read.
+    Io { path: PathBuf, error: io::Error },
+    /// The file could not be parsed.
+    Parse { path: PathBuf, error: ParseError },
+    /// The file includes itself, directly or through other files.
+    Cycle(PathBuf),
+}
+
+impl fmt::Display for IncludeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IncludeError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            IncludeError::Parse { path, error } => write!(f, "{}: {}", path.display(), error),
+            IncludeError::Cycle(path) => write!(f, "{}: file includes itself", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for IncludeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IncludeError::Io { error, .. } => Some(error),
+            IncludeError::Parse { error, .. } => Some(error),
+            IncludeError::Cycle(_) => None,
+        }
+    }
+}
+
+/// Whether `name` matches the glob `pattern`: `*` matches any run of
+/// characters, `?` any one character and `[abc]`, `[a-z]` or `[!abc]` one
+/// character of a set.
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| glob_match(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && glob_match(rest, &name[1..]),
+        Some(('[', rest)) => {
+            let Some(end) = rest.iter().skip(1).position(|&c| c == ']').map(|i| i + 1) else {
+                return name.first() == Some(&'[') && glob_match(rest, &name[1..]);
+            };
+            let Some((&c, name)) = name.split_first() else {
+                return false;
+            };
+            let (negated, set) = match rest[..end].split_first() {
+                Some(('!' | '^', set)) => (true, set),
+                _ => (false, &rest[..end]),
+            };
+            let mut matched = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == '-' {
+                    matched |= (set[i]..=set[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= set[i] == c;
+                    i += 1;
+                }
+            }
+            matched != negated && glob_match(&rest[end + 1..], name)
+        }
+        Some((&p, rest)) => name.first() == Some(&p) && glob_match(rest, &name[1..]),
+    }
+}
+
+fn has_wildcards(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+/// Entries of `dir` sorted by name, or none if it is not a directory.
+fn sorted_entries(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound || !dir.is_dir() => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        names.push((
+            entry.file_name().to_string_lossy().into_owned(),
+            entry.path(),
+        ));
+    }
+    names.sort();
+    Ok(names)
+}
+
+fn expand(dir: PathBuf, components: &[&str], files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let Some((&component, rest)) = components.split_first() else {
+        if dir.is_file() {
+            files.push(dir);
+        }
+        return Ok(());
+    };
+    if component == "**" {
+        expand(dir.clone(), rest, files)?;
+        for (name, path) in sorted_entries(&dir)? {
+            if !name.starts_with('.') && path.is_dir() {
+                expand(path, components, files)?;
+            }
+        }
+    } else if has_wildcards(component) {
+        let pattern: Vec<char> = component.chars().collect();
+        for (name, path) in sorted_entries(&dir)? {
+            let hidden = name.starts_with('.') && !component.starts_with('.');
+            if !hidden && glob_match(&pattern, &name.chars().collect::<Vec<_>>()) {
+                expand(path, rest, files)?;
+            }
+        }
+    } else {
+        expand(dir.join(component), rest, files)?;
+    }
+    Ok(())
+}
+
+/// Resolves the path or glob pattern of an `include` directive to the
+/// files it names. Relative paths are resolved against the directory of
+/// `including_file`, and `~/` against the home directory.
+///
+/// A path without wildcards is returned as is, whether or not the file
+/// exists. A pattern returns the files matching it, in path order: `*`,
+/// `?` and `[...]` match within a path component and `**` matches any
+/// number of directories. Wildcards don't match names starting with `.`.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::{Path, PathBuf};
+///
+/// let files = ledger_parser::expand_include("2024.journal", Path::new("books/main.journal")).unwrap();
+/// assert_eq!(files, vec![PathBuf::from("books/2024.journal")]);
+/// ```
+pub fn expand_include(pattern: &str, including_file: &Path) -> io::Result<Vec<PathBuf>> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let path = match (pattern.strip_prefix("~/"), home) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => including_file
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(pattern),
+    };
+    if !has_wildcards(pattern) {
+        return Ok(vec![path]);
+    }
+
+    let mut root = PathBuf::new();
+    let mut components = Vec::new();
+    for component in path.components() {
+        let component = component.as_os_str().to_str().unwrap_or_default();
+        if components.is_empty() && !has_wildcards(component) && component != "**" {
+            root.push(component);
+        } else {
+            components.push(component);
+        }
+    }
+    let mut files = Vec::new();
+    expand(root, &components, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn load(path: &Path, settings: &ParserSettings) -> Result<Ledger, IncludeError> {
+    let input = std::fs::read(path).map_err(|error| IncludeError::Io {
+        path: path.to_owned(),
+        error,
+    })?;
+    parse_bytes(&input, settings)
+        .map(|(ledger, _)| ledger)
+        .map_err(|error| IncludeError::Parse {
+            path: path.to_owned(),
+            error,
+        })
+}
+
+/// Name of an included file in its include markers: the path relative to
+/// the directory of the including file when it is inside it.
+fn include_name(file: &Path, including_file: &Path) -> String {
+    let dir = including_file.parent().unwrap_or_else(|| Path::new(""));
+    file.strip_prefix(dir)
+        .unwrap_or(file)
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl Ledger {
+    /// Parses the file at `path` and the files it includes, as
+    /// [`Ledger::resolve_includes`] does. Warnings are dropped.
+    pub fn from_path(path: &Path, settings: &ParserSettings) -> Result<Ledger, IncludeError> {
+        let mut ledger = load(path, settings)?;
+        ledger.resolve_includes(path, settings)?;
+        Ok(ledger)
+    }
+
+    /// Replaces each `include` directive with the items of the files it
+    /// names, between [`LedgerItem::IncludeBegin`] and
+    /// [`LedgerItem::IncludeEnd`] markers, loading the files they include in
+    /// turn. `path` is the file the ledger was read from; paths and glob
+    /// patterns are resolved by [`expand_include`]. Fails on the first file
+    /// that can't be read or parsed, or that includes itself.
+    ///
+    /// Each file is parsed on its own, so directives such as `Y` or `alias`
+    /// don't carry over into included files. The markers of a file matched
+    /// by a glob name the file rather than the pattern, and a glob leaves out
+    /// the file that contains it. [`Ledger::included_files`] gives the ledger of each file.
+    pub fn resolve_includes(
+        &mut self,
+        path: &Path,
+        settings: &ParserSettings,
+    ) -> Result<(), IncludeError> {
+        let canonical = path.canonicalize().map_err(|error| IncludeError::Io {
+            path: path.to_owned(),
+            error,
+        })?;
+        self.resolve_includes_from(path, settings, &mut vec![canonical])
+    }
+
+    /// Resolves includes with `stack` holding the canonical paths of the
+    /// files being loaded, this one last.
+    fn resolve_includes_from(
+        &mut self,
+        path: &Path,
+        settings: &ParserSettings,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<(), IncludeError> {
+        let mut items = Vec::with_capacity(self.items.len());
+        for item in self.items.drain(..) {
+            let LedgerItem::Include(pattern) = item else {
+                items.push(item);
+                continue;
+            };
+            let files = expand_include(&pattern, path).map_err(|error| IncludeError::Io {
+                path: path.to_owned(),
+                error,
+            })?;
+            for file in files {
+                let canonical = file.canonicalize().map_err(|error| IncludeError::Io {
+                    path: file.clone(),
+                    error,
+                })?;
+                if has_wildcards(&pattern) && stack.last() == Some(&canonical) {
+                    continue;
+                }
+                if stack.contains(&canonical) {
+                    return Err(IncludeError::Cycle(file));
+                }
+                let mut included = load(&file, settings)?;
+                stack.push(canonical);
+                included.resolve_includes_from(&file, settings, stack)?;
+                stack.pop();
+
+                let name = include_name(&file, path);
+                items.push(LedgerItem::IncludeBegin(name.clone()));
+                items.append(&mut included.items);
+                items.push(LedgerItem::IncludeEnd(name));
+            }
+        }
+        self.items = items;
+        Ok(())
+    }
+
+    /// Puts back the `include` directives in place of the included items
+    /// marked with [`LedgerItem::IncludeBegin`] and [`LedgerItem::IncludeEnd`],
+    /// giving the main file as it was written.
+    pub fn restore_includes(&mut self) {
+        let mut depth = 0usize;
+        let mut items = Vec::with_capacity(self.items.len());
+        for item in self.items.drain(..) {
+            match item {
+                LedgerItem::IncludeBegin(path) => {
+                    if depth == 0 {
+                        items.push(LedgerItem::Include(path));
+                    }
+                    depth += 1;
+                }
+                LedgerItem::IncludeEnd(_) => depth = depth.saturating_sub(1),
+                item if depth == 0 => items.push(item),
+                _ => {}
+            }
+        }
+        self.items = items;
+    }
+
+    /// Returns the items of each included file, with the files it includes
+    /// itself restored to `include` directives. Files are listed in the order
+    /// their inclusion starts.
+    ///
+    /// Serializing the main file after [`Ledger::restore_includes`] and each
+    /// of these reproduces the original multi-file layout.
+    pub fn included_files(&self) -> Vec<(String, Ledger)> {
+        let mut files: Vec<(String, Ledger)> = Vec::new();
+        // Indexes into `files` of the includes currently open
+        let mut open: Vec<usize> = Vec::new();
+        for item in &self.items {
+            let item = match item {
+                LedgerItem::IncludeBegin(path) => {
+                    let item = LedgerItem::Include(path.clone());
+                    if let Some(&parent) = open.last() {
+                        files[parent].1.items.push(item);
+                    }
+                    open.push(files.len());
+                    files.push((path.clone(), Ledger { items: Vec::new() }));
+                    continue;
+                }
+                LedgerItem::IncludeEnd(_) => {
+                    open.pop();
+                    continue;
+                }
+                item => item,
+            };
+            if let Some(&current) = open.last() {
+                files[current].1.items.push(item.clone());
+            }
+        }
+        files
+    }
+
+    /// Removes the include markers, leaving the included items in place.
+    pub fn remove_include_markers(&mut self) {
+        self.items.retain(|item| {
+            !matches!(
+                item,
+                LedgerItem::IncludeBegin(_) | LedgerItem::IncludeEnd(_)
+            )
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn merged() -> Ledger {
+        let mut ledger = parse("; main\ninclude a.ledger\n").unwrap();
+        let a = parse("; a\ninclude b.ledger\n; a end\n").unwrap();
+        let b = parse("; b\n").unwrap();
+
+        let mut items = vec![ledger.items[0].clone()];
+        items.push(LedgerItem::IncludeBegin("a.ledger".to_owned()));
+        items.push(a.items[0].clone());
+        items.push(LedgerItem::IncludeBegin("b.ledger".to_owned()));
+        items.extend(b.items);
+        items.push(LedgerItem::IncludeEnd("b.ledger".to_owned()));
+        items.push(a.items[2].clone());
+        items.push(LedgerItem::IncludeEnd("a.ledger".to_owned()));
+        ledger.items = items;
+        ledger
+    }
+
+    #[test]
+    fn include_layout_test() {
+        let ledger = merged();
+        assert_eq!(ledger.to_string(), "; main\n; a\n; b\n; a end\n");
+
+        let files = ledger.included_files();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, "a.ledger");
+        assert_eq!(files[0].1.to_string(), "; a\ninclude b.ledger\n; a end\n");
+        assert_eq!(files[1].0, "b.ledger");
+        assert_eq!(files[1].1.to_string(), "; b\n");
+
+        let mut main = ledger.clone();
+        main.restore_includes();
+        assert_eq!(main.to_string(), "; main\ninclude a.ledger\n");
+
+        let mut flat = ledger;
+        flat.remove_include_markers();
+        assert_eq!(flat.items.len(), 4);
+    }
+
+    #[test]
+    fn expand_include_test() {
+        let dir =
+            std::env::temp_dir().join(format!("ledger-parser-includes-{}", std::process::id()));
+        for file in [
+            "accounts/assets.journal",
+            "accounts/expenses.journal",
+            "accounts/notes.txt",
+            "accounts/.hidden.journal",
+            "years/2023/q1.journal",
+            "years/2024/q1.journal",
+            "years/2024/old/q4.journal",
+            "years/2025b/q1.journal",
+        ] {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let main = dir.join("main.journal");
+        let expand = |pattern| {
+            expand_include(pattern, &main)
+                .unwrap()
+                .into_iter()
+                .map(|path| {
+                    path.strip_prefix(&dir)
+                        .unwrap()
+                        .to_string_lossy()
+                        .replace('\\', "/")
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            expand("accounts/*.journal"),
+            ["accounts/assets.journal", "accounts/expenses.journal"]
+        );
+        assert_eq!(
+            expand("accounts/[!a]*"),
+            ["accounts/expenses.journal", "accounts/notes.txt"]
+        );
+        assert_eq!(
+            expand("years/202[0-4]/q?.journal"),
+            ["years/2023/q1.journal", "years/2024/q1.journal"]
+        );
+        assert_eq!(
+            expand("years/**/*.journal"),
+            [
+                "years/2023/q1.journal",
+                "years/2024/old/q4.journal",
+                "years/2024/q1.journal",
+                "years/2025b/q1.journal"
+            ]
+        );
+        assert_eq!(expand("missing/*.journal"), Vec::<String>::new());
+        assert_eq!(expand("missing.journal"), ["missing.journal"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn from_path_test() {
+        let dir =
+            std::env::temp_dir().join(format!("ledger-parser-resolve-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("accounts")).unwrap();
+        let write = |file: &str, text: &str| std::fs::write(dir.join(file), text).unwrap();
+        write(
+            "main.journal",
+            "; main\ninclude accounts/*.journal\n; end\n",
+        );
+        write("accounts/a.journal", "; a\ninclude ../shared.journal\n");
+        write("accounts/b.journal", "; b\n");
+        write("shared.journal", "; shared\n");
+
+        let settings = ParserSettings::default();
+        let ledger = Ledger::from_path(&dir.join("main.journal"), &settings).unwrap();
+        assert_eq!(ledger.to_string(), "; main\n; a\n; shared\n; b\n; end\n");
+        let files: Vec<(String, String)> = ledger
+            .included_files()
+            .into_iter()
+            .map(|(name, ledger)| (name.replace('\\', "/"), ledger.to_string()))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("accounts/a.journal", "; a\ninclude ../shared.journal\n"),
+                ("../shared.journal", "; shared\n"),
+                ("accounts/b.journal", "; b\n"),
+            ]
+            .map(|(name, text)| (name.to_owned(), text.to_owned()))
+        );
+
+        write("shared.journal", "include main.journal\n");
+        assert!(matches!(
+            Ledger::from_path(&dir.join("main.journal"), &settings),
+            Err(IncludeError::Cycle(path)) if path.ends_with("main.journal")
+        ));
+        write("shared.journal", "include missing.journal\n");
+        assert!(matches!(
+            Ledger::from_path(&dir.join("main.journal"), &settings),
+            Err(IncludeError::Io { path, .. }) if path.ends_with("missing.journal")
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_glob_skips_including_file_test() {
+        let dir = std::env::temp_dir().join(format!("ledger-parser-glob-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |file: &str, text: &str| std::fs::write(dir.join(file), text).unwrap();
+        write("main.journal", "; main\ninclude *.journal\n");
+        write("other.journal", "; other\n");
+
+        let settings = ParserSettings::default();
+        let ledger = Ledger::from_path(&dir.join("main.journal"), &settings).unwrap();
+        assert_eq!(ledger.to_string(), "; main\n; other\n");
+
+        write("main.journal", "include main.journal\n");
+        assert!(matches!(
+            Ledger::from_path(&dir.join("main.journal"), &settings),
+            Err(IncludeError::Cycle(path)) if path.ends_with("main.journal")
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
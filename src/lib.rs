@@ -4,16 +4,22 @@
 //!
 //! Supported elements:
 //!
-//! - Line comments (starting with: ``; # % | *``)
+//! - Line comments (starting with: ``; # % | *``), with ``;~`` and ``;>`` hint lines kept
+//!   apart
 //!
 //! - Inline comments (starting with ``;``)
 //!
+//! - Block comments (``comment`` to ``end comment``) and ``test`` to ``end test`` blocks, kept
+//!   as written
+//!
 //! - Transaction headers with format (minimum two spaces or one tab between `DESC` and `NOTE`):
 //!
 //!   ```ledger-cli,ignore
 //!   DATE[=EDATE] [*|!] [(CODE)] DESC  [; NOTE]
 //!   ```
 //!
+//!     - Other status flags can be enabled with [`ParserSettings::with_status_flags`]
+//!
 //! - Transaction postings with format (minimum two spaces or one tab between `ACCOUNT` and `AMOUNT`):
 //!
 //!   ```ledger-cli,ignore
@@ -22,35 +28,227 @@
 //!
 //!     - Virtual accounts are supported
 //!
-//! - `AMOUNT` can be combined with lot and commodity prices ({}, {{}}, @, @@)
+//!     - Balance assertions can also be written `==` (sole commodity), `=*` and `==*`
+//!       (including subaccounts)
+//!
+//! - `AMOUNT` can be combined with lot and commodity prices ({}, {{}}, @, @@), fixated with {=} or {{=}}, and a
+//!   lot date ([DATE]) and note ((NOTE)), in any order
+//!
+//! - `AMOUNT` can be a value expression in parentheses, such as `($10.00 * 3)`
+//!   ([`ParserSettings::with_parenthesized_negatives`] reads `($1,200.00)` as a negative
+//!   amount instead)
 //!
 //! - Commodity prices with format:
 //!
 //!   ```ledger-cli,ignore
-//!   P DATE SYMBOL PRICE
+//!   P DATE [TIME] SYMBOL PRICE
+//!   ```
+//!
+//! - Automated transactions, with account (`/REGEX/`), payee (`@REGEX`), tag
+//!   (`%TAG[=VALUE]`) and `expr` terms combined with `and`, `or` and `not`:
+//!
+//!   ```ledger-cli,ignore
+//!   = QUERY  [; NOTE]
 //!   ```
-//! - Command directives: `include`
+//! - Command directives:
+//!   - `include`, with glob patterns (`include accounts/*.journal`), loaded recursively by
+//!     `Ledger::from_path` and `Ledger::resolve_includes`
+//!   - `account` with `note`, `alias`, `payee`, `check`, `assert` and `default` subdirectives
+//!   - `commodity` with `note`, `format`, `alias`, `nomarket` and `default` subdirectives
+//!   - `payee` with `alias` and `uuid` subdirectives
+//!   - `tag` with `check` and `assert` subdirectives
+//!   - `alias`, including hledger's `alias /REGEX/=REPLACEMENT`, and `end aliases`
+//!   - `capture ACCOUNT  PATTERN`
+//!   - `apply tag` and `end apply tag`
+//!   - `Y`/`year`, setting the year of dates written without one (`03/15`)
+//!   - `decimal-mark`, setting the decimal mark of later quantities
+//!   - `D`, setting the commodity of amounts written without one
+//!   - `C` commodity conversions
+//!   - `N`, excluding a commodity from market valuation
+//!   - `bucket`/`A`
+//!   - `define`
+//!   - `event DATE DESCRIPTION` and `note TEXT`
+//!   - `check EXPR`, `assert EXPR`, `eval EXPR` and `expr EXPR`, with the value
+//!     expression kept as written
+//!
+//! - Month-name transaction dates (`2024 Jan 05`, `05 Jan 2024`), enabled with
+//!   `ParserSettings::with_month_names` and written back in the same language
+//!   with `SerializerSettings::with_month_names`
+//!
+//! - Digit group separators in quantities (`1,234,567.89`, `1 234 567.89`), set
+//!   with `ParserSettings::with_digit_group_separators`
+//!
+//! - Comma decimal marks (`1.234,56 EUR`), set with `ParserSettings::with_decimal_mark`,
+//!   the `decimal-mark` directive, or per commodity by its `commodity` or `D` format
+//!
+//! - Quantities in scientific notation (`1.5e-4 BTC`)
+//!
+//! - Timeclock files (`i DATE TIME ACCOUNT`, `o DATE TIME`), parsed with `parse_timeclock`
+//!   and turned into transactions of hours with `timeclock_transactions`
+//!
+//! - Timedot files (`DATE` lines followed by `ACCOUNT  ....` or `ACCOUNT  1.5h`), parsed into
+//!   transactions of hours with `parse_timedot`
+//!
+//! - hledger CSV rules files (`fields`, `skip`, settings, field assignments and `if` blocks),
+//!   parsed into a `CsvRules` model with `parse_csv_rules`
+//!
+//! Parsing is deterministic: items, postings and everything derived from them
+//! are kept in file order, and any maps returned by this crate are ordered
+//! (`BTreeMap`) so their iteration order does not change between runs.
+//!
+//! Large journals can be parsed lazily with [`parse_iter`], which yields one item at a
+//! time instead of building the whole [`Ledger`].
+//! [`parse_with_spans`] also returns where each item and posting is in the source, for
+//! editors and linters.
+//! [`parse_with_recovery`] skips the items that fail to parse and returns an error for
+//! each, instead of stopping at the first one.
+//! [`parse_lossless`] keeps the source text of each item, so that a journal can be edited
+//! and written back byte for byte except for the items that changed.
 
 mod model;
 pub use model::*;
 
+mod aliases;
+
+mod assignments;
+
 mod serializer;
 pub use serializer::*;
 
+mod balancing;
+pub use balancing::*;
+
+mod commodities;
+pub use commodities::*;
+
+mod completion;
+pub use completion::*;
+
+mod depreciation;
+pub use depreciation::*;
+
+mod csv_rules;
+pub use csv_rules::*;
+
+mod dry_run;
+pub use dry_run::*;
+
+mod entry;
+pub use entry::*;
+
+mod example;
+pub use example::*;
+
+mod expressions;
+pub use expressions::*;
+
+mod encoding;
+pub use encoding::LegacyEncoding;
+
+mod includes;
+pub use includes::*;
+
+mod lifecycle;
+pub use lifecycle::*;
+
+mod lossless;
+pub use lossless::*;
+
+mod normalize;
+pub use normalize::*;
+
 mod parser;
+pub use parser::{ParserSettings, ENGLISH_MONTH_NAMES};
+
+mod payroll;
+pub use payroll::*;
+
+mod periodic;
+pub use periodic::*;
+
+mod prices;
+pub use prices::*;
+
+#[cfg(feature = "snapshot")]
+mod snapshot;
 
-use nom::{error::convert_error, Finish};
+mod spans;
+pub use spans::*;
+
+mod splitting;
+
+mod streaming;
+pub use streaming::*;
+
+mod styles;
+pub use styles::*;
+
+mod tags;
+
+mod timeclock;
+pub use timeclock::*;
+
+mod timedot;
+pub use timedot::*;
+
+mod trading;
+
+mod transfers;
+pub use transfers::*;
+
+mod views;
+pub use views::*;
+
+use nom::error::{ContextError, ParseError as _};
+use nom::error::{ErrorKind, VerboseError, VerboseErrorKind};
 use std::fmt;
 
+/// Reason the input could not be parsed.
+///
+/// Overflowing numbers, impossible dates and nesting deeper than the parser
+/// supports end up as errors, not panics. Should a bug in the parser still
+/// panic, the panic is caught and reported as [`ParseError::Internal`],
+/// unless the crate is built with `panic = "abort"`.
+#[non_exhaustive]
 #[derive(Debug)]
 pub enum ParseError {
+    /// Error described only by a message.
     String(String),
+    /// Input that doesn't follow the syntax.
+    Syntax(SyntaxError),
+    /// Bytes that are not valid UTF-8, at a byte offset of the input.
+    /// `preview` is the text around them, decoded lossily.
+    InvalidUtf8 { offset: usize, preview: String },
+    /// Control character other than a tab or line break, at a byte offset
+    /// of the input. `preview` is the text around it, with control
+    /// characters escaped.
+    ControlCharacter { offset: usize, preview: String },
+    /// Unpaired surrogate or odd trailing byte in input starting with a
+    /// UTF-16 byte order mark, at a byte offset of the input.
+    InvalidUtf16 { offset: usize },
+    /// The parser panicked, with this message. This is a bug.
+    Internal(String),
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             ParseError::String(ref err) => err.fmt(f),
+            ParseError::Syntax(ref err) => err.fmt(f),
+            ParseError::InvalidUtf8 {
+                offset,
+                ref preview,
+            } => write!(f, "invalid UTF-8 at byte {} near \"{}\"", offset, preview),
+            ParseError::ControlCharacter {
+                offset,
+                ref preview,
+            } => write!(
+                f,
+                "control character at byte {} near \"{}\"",
+                offset, preview
+            ),
+            ParseError::InvalidUtf16 { offset } => write!(f, "invalid UTF-16 at byte {}", offset),
+            ParseError::Internal(ref message) => write!(f, "internal parser error: {}", message),
         }
     }
 }
@@ -59,6 +257,178 @@ impl std::error::Error for ParseError {
     fn description(&self) -> &str {
         match *self {
             ParseError::String(ref err) => err,
+            ParseError::Syntax(ref err) => &err.expected,
+            ParseError::InvalidUtf8 { .. } => "invalid UTF-8",
+            ParseError::ControlCharacter { .. } => "control character",
+            ParseError::InvalidUtf16 { .. } => "invalid UTF-16",
+            ParseError::Internal(ref message) => message,
+        }
+    }
+}
+
+/// Location of a syntax error and what the parser expected there.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SyntaxError {
+    /// Line number, starting from 1.
+    pub line: usize,
+    /// Column in characters, starting from 1.
+    pub column: usize,
+    /// The line the error is on, without its line break.
+    pub snippet: String,
+    /// What would have been valid there, such as `':'` or `end of input`.
+    pub expected: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "line {}, column {}: expected {}",
+            self.line, self.column, self.expected
+        )?;
+        writeln!(f, "{}", self.snippet)?;
+        write!(f, "{:>1$}", "^", self.column)
+    }
+}
+
+/// Describes what the parser expected from the kind of a nom error.
+fn expected(kind: &VerboseErrorKind) -> String {
+    let text = match kind {
+        VerboseErrorKind::Context(context) => context,
+        VerboseErrorKind::Char(c) => return format!("'{}'", c),
+        VerboseErrorKind::Nom(ErrorKind::Eof) => "end of input",
+        VerboseErrorKind::Nom(ErrorKind::TooLarge) => "less nesting or fewer operators",
+        VerboseErrorKind::Nom(ErrorKind::Digit) => "a digit",
+        VerboseErrorKind::Nom(ErrorKind::Space) => "a space",
+        VerboseErrorKind::Nom(ErrorKind::CrLf) => "a line break",
+        VerboseErrorKind::Nom(ErrorKind::MapOpt | ErrorKind::MapRes | ErrorKind::Verify) => {
+            "a valid value"
+        }
+        VerboseErrorKind::Nom(kind) => return kind.description().to_lowercase(),
+    };
+    text.to_owned()
+}
+
+/// Turns a nom error into a [`SyntaxError`] located in `input`: the first
+/// context given by the parsers says what was expected, or else the
+/// innermost error.
+pub(crate) fn syntax_error(input: &str, error: VerboseError<&str>) -> ParseError {
+    let Some(&(rest, ref kind)) = error.errors.first() else {
+        return ParseError::String("syntax error".to_owned());
+    };
+    let kind = error
+        .errors
+        .iter()
+        .map(|(_, kind)| kind)
+        .find(|kind| matches!(kind, VerboseErrorKind::Context(_)))
+        .unwrap_or(kind);
+    let offset = input.len() - rest.len();
+    let line_start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = input[offset..]
+        .find('\n')
+        .map_or(input.len(), |i| offset + i);
+    ParseError::Syntax(SyntaxError {
+        line: input[..offset].matches('\n').count() + 1,
+        column: input[line_start..offset].chars().count() + 1,
+        snippet: input[line_start..line_end]
+            .trim_end_matches('\r')
+            .to_owned(),
+        expected: expected(kind),
+    })
+}
+
+/// Turns the error returned by a parser of `input` into a [`ParseError`].
+/// Input that ends too early is a syntax error at its end.
+pub(crate) fn nom_error(input: &str, error: nom::Err<VerboseError<&str>>) -> ParseError {
+    match error {
+        nom::Err::Error(error) | nom::Err::Failure(error) => syntax_error(input, error),
+        nom::Err::Incomplete(_) => {
+            let end = &input[input.len()..];
+            let error = VerboseError::from_error_kind(end, ErrorKind::Complete);
+            syntax_error(input, VerboseError::add_context(end, "more input", error))
+        }
+    }
+}
+
+/// Runs `parse`, reporting a panic as [`ParseError::Internal`] instead of
+/// unwinding into the caller.
+///
+/// This is only a safety net: it does nothing under `panic = "abort"`, so
+/// inputs found to panic are fixed where they panic and kept as regression
+/// tests, and `generated_input_test` checks that none of its inputs get
+/// here.
+pub(crate) fn catch_panic<T>(
+    parse: impl FnOnce() -> Result<T, ParseError>,
+) -> Result<T, ParseError> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(parse)).unwrap_or_else(|payload| {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => (*message).to_owned(),
+                Err(_) => "unknown panic".to_owned(),
+            },
+        };
+        Err(ParseError::Internal(message))
+    })
+}
+
+/// Input that parsed but is probably not what the author meant.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseWarning {
+    /// Line number, starting from 1.
+    pub line: usize,
+    pub kind: ParseWarningKind,
+}
+
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ParseWarningKind {
+    /// Quantity such as `1,000` or `1.000` whose separator could be either
+    /// a decimal mark or a digit group separator. Not reported after a
+    /// `decimal-mark` directive.
+    AmbiguousQuantity(String),
+    /// Account name ending with what looks like an amount separated by a
+    /// single space. Amounts need two spaces or a tab before them.
+    SingleSpaceBeforeAmount { account: String },
+    /// Invalid UTF-8 at a byte offset of the input, replaced by U+FFFD
+    /// because of [`ParserSettings::with_lossy_input`].
+    InvalidUtf8 { offset: usize },
+    /// Control character at a byte offset of the input, replaced by U+FFFD
+    /// because of [`ParserSettings::with_lossy_input`].
+    ControlCharacter { offset: usize },
+    /// Invalid UTF-16 at a byte offset of the input, replaced by U+FFFD
+    /// because of [`ParserSettings::with_lossy_input`].
+    InvalidUtf16 { offset: usize },
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl fmt::Display for ParseWarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseWarningKind::AmbiguousQuantity(quantity) => write!(
+                f,
+                "ambiguous quantity {}: separator may be a decimal mark or a digit group separator",
+                quantity
+            ),
+            ParseWarningKind::SingleSpaceBeforeAmount { account } => write!(
+                f,
+                "account \"{}\" ends with an amount separated by a single space",
+                account
+            ),
+            ParseWarningKind::InvalidUtf8 { offset } => {
+                write!(f, "invalid UTF-8 at byte {} replaced", offset)
+            }
+            ParseWarningKind::ControlCharacter { offset } => {
+                write!(f, "control character at byte {} replaced", offset)
+            }
+            ParseWarningKind::InvalidUtf16 { offset } => {
+                write!(f, "invalid UTF-16 at byte {} replaced", offset)
+            }
         }
     }
 }
@@ -76,9 +446,179 @@ impl std::error::Error for ParseError {
 ///   TEST:Account 345  -$1.20"#);
 /// ```
 pub fn parse(input: &str) -> Result<Ledger, ParseError> {
-    let result = parser::parse_ledger(input);
-    match result.finish() {
-        Ok((_, result)) => Ok(result),
-        Err(error) => Err(ParseError::String(convert_error(input, error))),
-    }
+    parse_with_warnings(input).map(|(ledger, _)| ledger)
+}
+
+/// Parses ledger-cli source like [`parse`], also returning warnings about
+/// input that is valid but likely a mistake.
+///
+/// # Examples
+///
+/// ```
+/// let (_, warnings) = ledger_parser::parse_with_warnings(r#"2018-10-01 Description
+///   Expenses:Rent  $1,000
+///   Assets:Cash $20
+///   Assets:Bank"#).unwrap();
+/// assert_eq!(warnings.len(), 2);
+/// assert_eq!(warnings[1].line, 3);
+/// ```
+pub fn parse_with_warnings(input: &str) -> Result<(Ledger, Vec<ParseWarning>), ParseError> {
+    parse_with_settings(input, &ParserSettings::default())
+}
+
+/// Parses ledger-cli source like [`parse_with_warnings`], accepting the
+/// optional syntax enabled in `settings`.
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::*;
+///
+/// let settings = ParserSettings::default().with_month_names(&ENGLISH_MONTH_NAMES);
+/// let (ledger, _) = parse_with_settings(r#"2018 Oct 01 Description
+///   Assets:Cash  $10
+///   Income:Salary"#, &settings).unwrap();
+/// assert_eq!(ledger.transactions().next().unwrap().date_style, DateStyle::YearMonthNameDay);
+/// ```
+pub fn parse_with_settings(
+    input: &str,
+    settings: &ParserSettings,
+) -> Result<(Ledger, Vec<ParseWarning>), ParseError> {
+    parse_bytes(input.as_bytes(), settings)
+}
+
+/// Parses ledger-cli source read as bytes, like [`parse_with_settings`].
+///
+/// A UTF-8 byte order mark is skipped, and input starting with a UTF-16
+/// byte order mark is read as UTF-16. Other input is read as UTF-8, or in
+/// the encoding set with [`ParserSettings::with_legacy_encoding`] if it is
+/// not valid UTF-8.
+///
+/// Invalid UTF-8 and control characters other than tabs and line breaks
+/// fail with [`ParseError::InvalidUtf8`] and
+/// [`ParseError::ControlCharacter`], or with
+/// [`ParserSettings::with_lossy_input`] are replaced by U+FFFD and reported
+/// as warnings. So is invalid UTF-16, with [`ParseError::InvalidUtf16`].
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::*;
+///
+/// let input = b"2018-10-01 Caf\xe9\n  Assets:Cash  $10\n  Income:Salary\n";
+/// assert!(matches!(
+///     parse_bytes(input, &ParserSettings::default()),
+///     Err(ParseError::InvalidUtf8 { offset: 14, .. })
+/// ));
+///
+/// let settings = ParserSettings::default().with_lossy_input(true);
+/// let (ledger, warnings) = parse_bytes(input, &settings).unwrap();
+/// assert_eq!(ledger.transactions().next().unwrap().description, "Caf\u{fffd}");
+/// assert_eq!(warnings[0].kind, ParseWarningKind::InvalidUtf8 { offset: 14 });
+/// ```
+pub fn parse_bytes(
+    input: &[u8],
+    settings: &ParserSettings,
+) -> Result<(Ledger, Vec<ParseWarning>), ParseError> {
+    catch_panic(|| {
+        let (input, mut warnings) = encoding::decode(input, settings)?;
+        let ctx = parser::Context::new(settings.clone());
+        match parser::parse_ledger(&input, &ctx) {
+            Ok((_, result)) => {
+                warnings.extend(ctx.take_warnings(&input));
+                warnings.sort_by_key(|warning| warning.line);
+                Ok((result, warnings))
+            }
+            Err(error) => Err(nom_error(&input, error)),
+        }
+    })
+}
+
+/// Parses a date on its own, written `2018-10-01`, `2018/10/01` or
+/// `2018.10.01`.
+///
+/// # Examples
+///
+/// ```
+/// let date = ledger_parser::parse_date("2018/10/01").unwrap();
+/// assert_eq!(date, chrono::NaiveDate::from_ymd_opt(2018, 10, 1).unwrap());
+/// assert!(ledger_parser::parse_date("2018-10-32").is_err());
+/// ```
+pub fn parse_date(input: &str) -> Result<chrono::NaiveDate, ParseError> {
+    parser::parse_fragment(input.trim_start(), &ParserSettings::default(), |i, _| {
+        parser::parse_date(i)
+    })
+}
+
+/// Parses an amount on its own, such as `$1,000.00` or `-5 AAPL`, using the
+/// digit group separators and decimal mark of `settings`.
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::*;
+///
+/// let settings = ParserSettings::default().with_decimal_mark(',');
+/// let amount = parse_amount("1.234,50 EUR", &settings).unwrap();
+/// assert_eq!(amount.quantity, rust_decimal::Decimal::new(123450, 2));
+/// assert_eq!(amount.commodity.name, "EUR");
+/// ```
+pub fn parse_amount(input: &str, settings: &ParserSettings) -> Result<Amount, ParseError> {
+    parser::parse_fragment(input.trim_start(), settings, parser::parse_amount)
+}
+
+/// Parses a single posting line, with or without its indentation.
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::*;
+///
+/// let posting = parse_posting("Assets:Cash  $10  ; cash", &ParserSettings::default()).unwrap();
+/// assert_eq!(posting.account, "Assets:Cash");
+/// assert_eq!(posting.comment.as_deref(), Some("cash"));
+/// ```
+pub fn parse_posting(input: &str, settings: &ParserSettings) -> Result<Posting, ParseError> {
+    let input = format!("  {}", input.trim_start());
+    parser::parse_fragment(&input, settings, parser::parse_posting)
+}
+
+/// Parses a single transaction with its postings. Elided amounts are kept
+/// as they are written.
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::*;
+///
+/// let transaction = parse_transaction(
+///     "2018-10-01 Shop\n  Expenses:Food  $10\n  Assets:Cash\n",
+///     &ParserSettings::default(),
+/// )
+/// .unwrap();
+/// assert_eq!(transaction.postings.len(), 2);
+/// assert!(transaction.postings[1].amount.is_none());
+/// ```
+pub fn parse_transaction(
+    input: &str,
+    settings: &ParserSettings,
+) -> Result<Transaction, ParseError> {
+    parser::parse_fragment(input.trim_start(), settings, parser::parse_transaction)
+}
+
+/// Parses a timeclock file: `i DATE TIME ACCOUNT  [DESCRIPTION]` lines
+/// clocking in and `o DATE TIME` (or `O`, cleared) lines clocking out, with
+/// times written `HH:MM` or `HH:MM:SS`. Empty and comment lines are
+/// skipped. [`timeclock_transactions`] turns the entries into transactions.
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::*;
+///
+/// let entries = parse_timeclock("i 2024-01-02 09:00 Project:Client\no 2024-01-02 17:00\n").unwrap();
+/// assert!(matches!(&entries[0], TimeclockEntry::In { account, .. } if account == "Project:Client"));
+/// ```
+pub fn parse_timeclock(input: &str) -> Result<Vec<TimeclockEntry>, ParseError> {
+    parser::parse_fragment(input, &ParserSettings::default(), parser::parse_timeclock)
 }
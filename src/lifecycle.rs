@@ -0,0 +1,196 @@
+use crate::model::*;
+use crate::tags::{comment_tags, tag_date};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+/// Tag of an `account` declaration giving the date the account opens.
+pub const OPENED_TAG: &str = "opened";
+
+/// Tag of an `account` declaration giving the last date the account can be
+/// posted to.
+pub const CLOSED_TAG: &str = "closed";
+
+///
+/// Posting to an account outside its lifetime, found by
+/// [`Ledger::inactive_account_postings`].
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct InactiveAccountPosting {
+    pub account: String,
+    /// Date of the posting.
+    pub date: NaiveDate,
+    /// Index of the transaction in [`Ledger::items`].
+    pub item: usize,
+}
+
+/// Opening (`true`) and closing (`false`) dates of an account, sorted.
+type Lifetime = Vec<(NaiveDate, bool)>;
+
+fn lifetimes(ledger: &Ledger) -> BTreeMap<&str, Lifetime> {
+    let mut lifetimes: BTreeMap<&str, Lifetime> = BTreeMap::new();
+    for item in &ledger.items {
+        if let LedgerItem::AccountDeclaration(declaration) = item {
+            let tags = comment_tags(declaration.comment.as_deref());
+            let dates = [(OPENED_TAG, true), (CLOSED_TAG, false)]
+                .into_iter()
+                .filter_map(|(tag, opens)| Some((tag_date(&tags, tag)?, opens)));
+            lifetimes
+                .entry(declaration.name.as_str())
+                .or_default()
+                .extend(dates);
+        }
+    }
+    for lifetime in lifetimes.values_mut() {
+        lifetime.sort();
+    }
+    lifetimes
+}
+
+/// Whether an account can be posted to on `date`. An account is open from
+/// its opening date through its closing date, and an account whose first
+/// date is a closing one is open until then.
+fn is_open(lifetime: &Lifetime, date: NaiveDate) -> bool {
+    let last = lifetime
+        .iter()
+        .rfind(|&&(change, opens)| if opens { change <= date } else { change < date });
+    match last {
+        Some(&(_, opens)) => opens,
+        None => lifetime.first().is_none_or(|&(_, opens)| !opens),
+    }
+}
+
+impl Ledger {
+    /// Lists the postings made to an account before it opens or after it
+    /// closes, in file order.
+    ///
+    /// Lifetimes are declared with `opened:` and `closed:` tags on `account`
+    /// directives, e.g. `account Assets:Savings  ; opened: 2020-01-01,
+    /// closed: 2023-12-31`. An account can be declared again with a later
+    /// `opened:` date to reopen it. Accounts without either tag are always
+    /// open.
+    pub fn inactive_account_postings(&self) -> Vec<InactiveAccountPosting> {
+        let lifetimes = lifetimes(self);
+        let mut inactive = Vec::new();
+        for (index, item) in self.items.iter().enumerate() {
+            if let LedgerItem::Transaction(transaction) = item {
+                for posting in &transaction.postings {
+                    let date = posting.date.unwrap_or(transaction.date);
+                    let open = lifetimes
+                        .get(posting.account.as_str())
+                        .is_none_or(|lifetime| is_open(lifetime, date));
+                    if !open {
+                        inactive.push(InactiveAccountPosting {
+                            account: posting.account.clone(),
+                            date,
+                            item: index,
+                        });
+                    }
+                }
+            }
+        }
+        inactive
+    }
+
+    /// Lists the open accounts that could be closed: their balance is zero in
+    /// every commodity and they have no posting on or after `inactive_since`.
+    /// Accounts are sorted by name.
+    ///
+    /// Elided amounts are inferred to compute balances; transactions whose
+    /// amounts can't be inferred are skipped.
+    pub fn closable_accounts(&self, inactive_since: NaiveDate) -> Vec<String> {
+        let lifetimes = lifetimes(self);
+        let mut balances: BTreeMap<String, BTreeMap<String, Decimal>> = BTreeMap::new();
+        let mut last_used: BTreeMap<String, NaiveDate> = BTreeMap::new();
+        for transaction in self.transactions() {
+            let mut transaction = transaction.clone();
+            if transaction.infer_elided_amount().is_err() {
+                continue;
+            }
+            for posting in &transaction.postings {
+                let date = posting.date.unwrap_or(transaction.date);
+                last_used
+                    .entry(posting.account.clone())
+                    .and_modify(|last| *last = (*last).max(date))
+                    .or_insert(date);
+                let balance = balances.entry(posting.account.clone()).or_default();
+                if let Some(ref amount) = posting.amount {
+                    let sum = balance
+                        .entry(amount.amount.commodity.name.clone())
+                        .or_default();
+                    *sum = sum.saturating_add(amount.amount.quantity);
+                }
+            }
+        }
+
+        balances
+            .into_iter()
+            .filter(|(account, balance)| {
+                balance.values().all(Decimal::is_zero)
+                    && last_used[account] < inactive_since
+                    && lifetimes
+                        .get(account.as_str())
+                        .is_none_or(|lifetime| is_open(lifetime, inactive_since))
+            })
+            .map(|(account, _)| account)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use chrono::NaiveDate;
+
+    const INPUT: &str = r#"account Assets:Savings  ; opened: 2023-01-01, closed: 2023-06-30
+account Assets:Savings  ; opened: 2024-01-01
+account Assets:Old  ; closed: 2023-03-31
+2022-12-31 Deposit
+  Assets:Savings  $100
+  Assets:Checking
+2023-06-30 Withdraw
+  Assets:Savings  $-100
+  Assets:Checking
+2023-07-01 Fee
+  Assets:Savings  $1  ; date: 2024-01-02
+  Assets:Old  $-1
+2023-08-01 Card
+  Liabilities:Card  $20
+  Assets:Checking
+2023-09-01 Card
+  Liabilities:Card  $-20
+  Assets:Checking
+"#;
+
+    #[test]
+    fn inactive_account_postings_test() {
+        let ledger = parse(INPUT).unwrap();
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(
+            ledger.inactive_account_postings(),
+            vec![
+                InactiveAccountPosting {
+                    account: "Assets:Savings".to_owned(),
+                    date: date(2022, 12, 31),
+                    item: 3,
+                },
+                InactiveAccountPosting {
+                    account: "Assets:Old".to_owned(),
+                    date: date(2023, 7, 1),
+                    item: 5,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn closable_accounts_test() {
+        let ledger = parse(INPUT).unwrap();
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(
+            ledger.closable_accounts(date(2024, 1, 1)),
+            vec!["Assets:Checking".to_owned(), "Liabilities:Card".to_owned()]
+        );
+        assert!(ledger.closable_accounts(date(2023, 9, 1)).is_empty());
+    }
+}
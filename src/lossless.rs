@@ -0,0 +1,181 @@
+use crate::model::*;
+use crate::serializer::follow_decimal_mark;
+use crate::{parse_iter, ParseError, ParserSettings, Serializer, SerializerSettings};
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+
+///
+/// Ledger that keeps the source text of each item, so that writing it back
+/// gives the parsed input byte for byte: whitespace, column alignment,
+/// comment markers and number formatting included. Created by
+/// [`parse_lossless`].
+///
+/// Items are the unit of editing: an item that was changed, or added, is
+/// written by the serializer while the others keep their text.
+///
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct LosslessLedger {
+    pub items: Vec<LosslessItem>,
+}
+
+///
+/// [`LedgerItem`] along with the text it was parsed from, if it has not
+/// been changed since.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LosslessItem {
+    item: LedgerItem,
+    source: Option<String>,
+}
+
+impl LosslessItem {
+    /// Item without source text, written by the serializer.
+    pub fn new(item: LedgerItem) -> Self {
+        LosslessItem { item, source: None }
+    }
+
+    pub fn item(&self) -> &LedgerItem {
+        &self.item
+    }
+
+    /// Gives mutable access to the item, dropping its source text so that
+    /// it is serialized from now on.
+    pub fn item_mut(&mut self) -> &mut LedgerItem {
+        self.source = None;
+        &mut self.item
+    }
+
+    /// Text the item was parsed from, line break included, unless it was
+    /// changed since.
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    pub fn into_item(self) -> LedgerItem {
+        self.item
+    }
+}
+
+impl From<LosslessLedger> for Ledger {
+    fn from(ledger: LosslessLedger) -> Self {
+        Ledger {
+            items: ledger
+                .items
+                .into_iter()
+                .map(LosslessItem::into_item)
+                .collect(),
+        }
+    }
+}
+
+impl Serializer for LosslessLedger {
+    /// Writes the source text of the unchanged items, and serializes the
+    /// others with `settings`, following `decimal-mark` directives like
+    /// [`Ledger`] does.
+    fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        let mut settings = Cow::Borrowed(settings);
+        for item in &self.items {
+            match &item.source {
+                Some(source) => writer.write_all(source.as_bytes())?,
+                None => item.item.write(writer, &settings)?,
+            }
+            follow_decimal_mark(&mut settings, &item.item);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for LosslessLedger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
+/// Parses ledger-cli source like [`crate::parse_with_settings`], keeping
+/// the text of each item so that the ledger can be edited and written back
+/// without reformatting the rest of the file.
+///
+/// The text is that of the input, or of the decoded text if
+/// [`ParserSettings::with_lossy_input`] replaced invalid bytes.
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::*;
+///
+/// let input = "2018-10-01   Shop\n    Expenses:Food        $10.00\n    Assets:Cash\n\n2018-10-02 Bus\n  Expenses:Travel  $2\n  Assets:Cash\n";
+/// let mut ledger = parse_lossless(input, &ParserSettings::default()).unwrap();
+/// assert_eq!(ledger.to_string(), input);
+///
+/// if let LedgerItem::Transaction(transaction) = ledger.items[2].item_mut() {
+///     transaction.set_description("Train");
+/// }
+/// assert_eq!(
+///     ledger.to_string(),
+///     "2018-10-01   Shop\n    Expenses:Food        $10.00\n    Assets:Cash\n\n2018-10-02 Train\n  Expenses:Travel  $2\n  Assets:Cash\n"
+/// );
+/// ```
+pub fn parse_lossless(
+    input: &str,
+    settings: &ParserSettings,
+) -> Result<LosslessLedger, ParseError> {
+    let mut items = Vec::new();
+    let mut iter = parse_iter(input, settings);
+    while let Some(result) = iter.next_with_range() {
+        let (item, range) = result?;
+        items.push(LosslessItem {
+            item,
+            source: Some(iter.item_source(range)),
+        });
+    }
+    Ok(LosslessLedger { items })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn parse_lossless_test() {
+        let input = "; header\r\n\r\n2024-01-02 * (12) Shop  ; note\r\n    Expenses:Food      $1,000.50  ; food\r\n\tAssets:Cash\r\n\r\n# other comment\r\nP 2024-01-02 00:00:00 EUR   $1.10\r\ncomment\r\nfree text\r\nend comment\r\n~ monthly\r\n  Expenses:Rent  $500\r\n  Assets:Bank";
+        let mut ledger = parse_lossless(input, &ParserSettings::default()).unwrap();
+        assert_eq!(ledger.to_string(), input);
+        assert_eq!(Ledger::from(ledger.clone()), parse(input).unwrap());
+        assert_eq!(ledger.items[1].source(), Some("\r\n"));
+
+        let with_bom = "\u{feff}; header\n2024-01-02 Shop\n  Expenses:Food  $5\n  Assets:Cash\n";
+        let lossless = parse_lossless(with_bom, &ParserSettings::default()).unwrap();
+        assert_eq!(lossless.items[0].source(), Some("\u{feff}; header\n"));
+        assert_eq!(lossless.to_string(), with_bom);
+
+        let old_mac = "; header\r2024-01-02 Shop\r  Expenses:Food  $5\r  Assets:Cash\r";
+        let lossless = parse_lossless(old_mac, &ParserSettings::default()).unwrap();
+        assert_eq!(lossless.items[0].source(), Some("; header\r"));
+        assert_eq!(lossless.to_string(), old_mac);
+
+        ledger.items[0] = LosslessItem::new(LedgerItem::LineComment("edited".to_owned()));
+        ledger.items.insert(
+            1,
+            LosslessItem::new(LedgerItem::Include("other.ledger".to_owned())),
+        );
+        ledger.items[3].item_mut();
+        assert_eq!(ledger.items[3].source(), None);
+        let settings = SerializerSettings::default().with_eol("\r\n");
+        assert_eq!(
+            ledger.to_string_pretty(&settings),
+            input.replace(
+                "; header\r\n\r\n2024-01-02 * (12) Shop  ; note\r\n    Expenses:Food      $1,000.50  ; food\r\n\tAssets:Cash\r\n",
+                "; edited\r\ninclude other.ledger\r\n\r\n2024-01-02 * (12) Shop\r\n  ; note\r\n  Expenses:Food  $1000.50\r\n  ; food\r\n  Assets:Cash\r\n"
+            )
+        );
+    }
+}
@@ -1,16 +1,139 @@
 use crate::serializer::*;
+use crate::tags::parse_tags;
 use chrono::{NaiveDate, NaiveDateTime};
 use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 
 ///
 /// Main document. Contains transactions and/or commodity prices.
 ///
+/// The model is plain owned data without interior mutability, so a parsed
+/// ledger is `Send + Sync` and can be shared between threads in an `Arc`.
+///
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Ledger {
     pub items: Vec<LedgerItem>,
 }
 
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Ledger>();
+    assert_send_sync::<LedgerItem>();
+    assert_send_sync::<Transaction>();
+    assert_send_sync::<PeriodicTransaction>();
+    assert_send_sync::<AutomatedTransaction>();
+    assert_send_sync::<Posting>();
+    assert_send_sync::<CommodityPrice>();
+    assert_send_sync::<AccountDeclaration>();
+    assert_send_sync::<CommodityDeclaration>();
+    assert_send_sync::<PayeeDeclaration>();
+    assert_send_sync::<TagDeclaration>();
+    assert_send_sync::<CommodityConversion>();
+    assert_send_sync::<Definition>();
+    assert_send_sync::<Event>();
+    assert_send_sync::<CommentHint>();
+};
+
+impl Ledger {
+    /// Creates an empty ledger with room for `capacity` items, for building
+    /// large ledgers programmatically.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Ledger {
+            items: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Appends an item in amortized O(1).
+    pub fn push(&mut self, item: LedgerItem) {
+        self.items.push(item);
+    }
+
+    /// Releases the spare capacity of the items and of every transaction's
+    /// postings once building is done. Runs in O(items + postings).
+    pub fn shrink_to_fit(&mut self) {
+        self.items.shrink_to_fit();
+        for item in &mut self.items {
+            match item {
+                LedgerItem::Transaction(transaction) => transaction.postings.shrink_to_fit(),
+                LedgerItem::PeriodicTransaction(transaction) => {
+                    transaction.postings.shrink_to_fit()
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Iterates over the transactions in file order.
+    pub fn transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.items.iter().filter_map(|item| match item {
+            LedgerItem::Transaction(transaction) => Some(transaction),
+            _ => None,
+        })
+    }
+
+    /// Iterates over the periodic transactions in file order.
+    pub fn periodic_transactions(&self) -> impl Iterator<Item = &PeriodicTransaction> {
+        self.items.iter().filter_map(|item| match item {
+            LedgerItem::PeriodicTransaction(transaction) => Some(transaction),
+            _ => None,
+        })
+    }
+
+    /// Iterates over the events in file order.
+    pub fn events(&self) -> impl Iterator<Item = &Event> {
+        self.items.iter().filter_map(|item| match item {
+            LedgerItem::Event(event) => Some(event),
+            _ => None,
+        })
+    }
+
+    /// Iterates over the commodity prices in file order.
+    pub fn commodity_prices(&self) -> impl Iterator<Item = &CommodityPrice> {
+        self.items.iter().filter_map(|item| match item {
+            LedgerItem::CommodityPrice(price) => Some(price),
+            _ => None,
+        })
+    }
+
+    /// Variables set with `define`, by name. A later definition replaces an
+    /// earlier one.
+    pub fn definitions(&self) -> BTreeMap<String, String> {
+        self.items
+            .iter()
+            .filter_map(|item| match item {
+                LedgerItem::Definition(definition) => {
+                    Some((definition.name.clone(), definition.value.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Commodities to leave out of market valuation, marked with the `N`
+    /// directive or the `nomarket` subdirective of `commodity`.
+    pub fn no_market_commodities(&self) -> BTreeSet<String> {
+        let mut commodities = BTreeSet::new();
+        for item in &self.items {
+            match item {
+                LedgerItem::NoMarket(commodity) => {
+                    commodities.insert(commodity.clone());
+                }
+                LedgerItem::CommodityDeclaration(declaration)
+                    if declaration
+                        .subdirectives
+                        .contains(&CommoditySubdirective::NoMarket) =>
+                {
+                    commodities.insert(declaration.name.clone());
+                }
+                _ => {}
+            }
+        }
+        commodities
+    }
+}
+
 impl fmt::Display for Ledger {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -24,13 +147,71 @@ impl fmt::Display for Ledger {
 
 #[non_exhaustive]
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LedgerItem {
     EmptyLine,
     LineComment(String),
     Transaction(Transaction),
     CommodityPrice(CommodityPrice),
+    /// `include` directive, with the path or glob pattern as written. See
+    /// [`crate::expand_include`].
     Include(String),
     PeriodicTransaction(PeriodicTransaction),
+    AutomatedTransaction(AutomatedTransaction),
+    /// Start of the items loaded from an included file, replacing its
+    /// `include` directive. Serializes to nothing.
+    IncludeBegin(String),
+    /// End of the items loaded from an included file. Serializes to nothing.
+    IncludeEnd(String),
+    AccountDeclaration(AccountDeclaration),
+    CommodityDeclaration(CommodityDeclaration),
+    PayeeDeclaration(PayeeDeclaration),
+    TagDeclaration(TagDeclaration),
+    AccountAlias(AccountAlias),
+    /// `end aliases`, dropping the aliases defined so far.
+    EndAliases,
+    AccountCapture(AccountCapture),
+    /// `apply tag TAG`, starting a block whose transactions get the tag.
+    ApplyTag(String),
+    /// `end apply tag`, closing the innermost `apply tag` block.
+    EndApplyTag,
+    /// `Y`/`year` directive, setting the year of later dates written
+    /// without one.
+    Year(i32),
+    /// `decimal-mark` directive, setting the decimal mark (`.` or `,`) of
+    /// later quantities whose commodity format doesn't declare one.
+    DecimalMark(char),
+    /// `D` directive with an amount written in the default commodity's
+    /// format, as in `D $1,000.00`. Later amounts without a commodity are
+    /// read in it.
+    DefaultCommodity(String),
+    CommodityConversion(CommodityConversion),
+    /// `N COMMODITY`, excluding the commodity from market valuation.
+    NoMarket(String),
+    /// `bucket ACCOUNT` (or `A ACCOUNT`), the account balancing later
+    /// transactions that have a single posting.
+    Bucket(String),
+    Definition(Definition),
+    Event(Event),
+    /// `note TEXT`, a free-standing note.
+    Note(String),
+    /// `check EXPR`, a value expression that should hold for the journal,
+    /// warning when false. The expression is kept as written.
+    Check(String),
+    /// `assert EXPR`, like `check` but failing when false.
+    Assert(String),
+    /// `eval EXPR`, a value expression evaluated for its side effects. The
+    /// expression is kept as written.
+    Eval(String),
+    /// `expr EXPR`, the same as `eval`.
+    Expr(String),
+    CommentHint(CommentHint),
+    /// Text between a `comment` line and its `end comment` line, kept as
+    /// written, line endings included.
+    BlockComment(String),
+    /// Text following `test` up to its `end test` line, kept as written:
+    /// the arguments of the `test` line, then the lines of the block.
+    TestBlock(String),
 }
 
 impl fmt::Display for LedgerItem {
@@ -45,6 +226,7 @@ impl fmt::Display for LedgerItem {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Period {
     Daily,
     Weekly,
@@ -57,7 +239,19 @@ pub enum Period {
     EveryNYears(u32),
 }
 
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PeriodicTransaction {
     pub period: Period,
     pub comment: Option<String>,
@@ -66,18 +260,179 @@ pub struct PeriodicTransaction {
     pub postings: Vec<Posting>,
 }
 
+///
+/// Automated transaction (`= QUERY`), whose postings ledger adds to every
+/// transaction with a posting matching the query.
+///
+/// Posting amounts without a commodity multiply the matched posting's
+/// amount. They are stored with an empty commodity name.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AutomatedTransaction {
+    pub query: Query,
+    pub comment: Option<String>,
+    pub postings: Vec<Posting>,
+}
+
+///
+/// Query selecting postings, as used by automated transactions.
+///
+/// Terms next to each other are alternatives, as in ledger, and are written
+/// back joined with `or`.
+///
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Query {
+    /// Regular expression matching the account name, `/REGEX/` or a bare
+    /// word.
+    Account(String),
+    /// `@REGEX`, matching the payee.
+    Payee(String),
+    /// `%TAG` or `%TAG=VALUE`.
+    Tag(String, Option<String>),
+    /// `expr 'EXPRESSION'`, a value expression kept as written.
+    Expression(String),
+    Not(Box<Query>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
 ///
 /// Transaction.
 ///
+#[non_exhaustive]
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Transaction {
     pub comment: Option<String>,
     pub date: NaiveDate,
     pub effective_date: Option<NaiveDate>,
+    /// How the dates were written, kept when serializing.
+    pub date_style: DateStyle,
     pub status: Option<TransactionStatus>,
     pub code: Option<String>,
     pub description: String,
     pub postings: Vec<Posting>,
+    /// Tags declared in `comment` (`:tag1:tag2:` or `name: value`). They are
+    /// parsed from the comment, which is what gets serialized.
+    pub tags: Vec<(String, Option<String>)>,
+}
+
+impl Transaction {
+    /// Transaction on `date` without status, code, comment or postings.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ledger_parser::*;
+    ///
+    /// let dollars = |quantity| Amount {
+    ///     quantity: rust_decimal::Decimal::new(quantity, 0),
+    ///     commodity: Commodity {
+    ///         name: "$".to_owned(),
+    ///         position: CommodityPosition::Left,
+    ///     },
+    /// };
+    /// let date = chrono::NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+    /// let transaction = Transaction::new(date, "Shop")
+    ///     .with_status(TransactionStatus::Cleared)
+    ///     .with_comment("weekly")
+    ///     .with_postings([
+    ///         Posting::new("Expenses:Food", Some(dollars(10))),
+    ///         Posting::new("Assets:Cash", None),
+    ///     ]);
+    /// assert_eq!(
+    ///     transaction.to_string(),
+    ///     "2024-01-02 * Shop\n  ; weekly\n  Expenses:Food  $10\n  Assets:Cash"
+    /// );
+    /// ```
+    pub fn new(date: NaiveDate, description: &str) -> Self {
+        Transaction {
+            comment: None,
+            date,
+            effective_date: None,
+            date_style: DateStyle::Numeric,
+            status: None,
+            code: None,
+            description: description.to_owned(),
+            postings: Vec::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn with_status(mut self, status: TransactionStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_code(mut self, code: &str) -> Self {
+        self.code = Some(code.to_owned());
+        self
+    }
+
+    /// Replaces the comment, updating `tags`.
+    pub fn with_comment(mut self, comment: &str) -> Self {
+        self.comment = Some(comment.to_owned());
+        self.tags = parse_tags(comment);
+        self
+    }
+
+    /// Appends postings, like [`Transaction::extend_postings`].
+    pub fn with_postings<I>(mut self, postings: I) -> Self
+    where
+        I: IntoIterator<Item = Posting>,
+    {
+        self.extend_postings(postings);
+        self
+    }
+
+    /// Replaces the description, and with it the payee and note.
+    pub fn set_description(&mut self, description: &str) {
+        self.description = description.to_owned();
+    }
+
+    /// Payee of a `Payee | note` description, trimmed. `None` when the
+    /// description has no `|`.
+    pub fn payee(&self) -> Option<&str> {
+        let (payee, _) = self.description.split_once('|')?;
+        Some(payee.trim())
+    }
+
+    /// Note of a `Payee | note` description, the text after the first `|`,
+    /// trimmed.
+    pub fn note(&self) -> Option<&str> {
+        let (_, note) = self.description.split_once('|')?;
+        Some(note.trim())
+    }
+
+    /// Reserves room for at least `additional` more postings.
+    pub fn reserve_postings(&mut self, additional: usize) {
+        self.postings.reserve(additional);
+    }
+
+    /// Appends postings, reserving room for all of them up front when the
+    /// iterator knows its length. Amortized O(1) per posting.
+    pub fn extend_postings<I>(&mut self, postings: I)
+    where
+        I: IntoIterator<Item = Posting>,
+    {
+        let postings = postings.into_iter();
+        self.postings.reserve(postings.size_hint().0);
+        self.postings.extend(postings);
+    }
 }
 
 impl fmt::Display for Transaction {
@@ -91,10 +446,31 @@ impl fmt::Display for Transaction {
     }
 }
 
+///
+/// Way of writing a transaction date.
+///
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DateStyle {
+    /// `2024-01-05`
+    #[default]
+    Numeric,
+    /// `2024 Jan 05`
+    YearMonthNameDay,
+    /// `05 Jan 2024`
+    DayMonthNameYear,
+    /// `01/05`, with the year taken from the preceding `Y` directive.
+    MonthDay,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TransactionStatus {
     Pending,
     Cleared,
+    /// Flag other than `!` and `*`, among
+    /// [`crate::ParserSettings::with_status_flags`].
+    Custom(char),
 }
 
 impl fmt::Display for TransactionStatus {
@@ -108,14 +484,51 @@ impl fmt::Display for TransactionStatus {
     }
 }
 
+#[non_exhaustive]
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Posting {
     pub account: String,
     pub reality: Reality,
     pub amount: Option<PostingAmount>,
+    /// Value expression the amount was written as, such as `($10.00 * 3)`.
+    /// `amount` holds its value when it could be evaluated while parsing;
+    /// the expression is what gets serialized.
+    pub amount_expr: Option<AmountExpr>,
     pub balance: Option<Balance>,
+    /// Operator of the balance assertion, `=` unless written otherwise.
+    pub balance_kind: BalanceKind,
     pub status: Option<TransactionStatus>,
+    /// Date overriding the transaction date, from a `date:` tag. When
+    /// serializing, it replaces a tag that disagrees with it.
+    pub date: Option<NaiveDate>,
+    /// Effective date overriding the transaction's, from a `date2:` tag.
+    pub effective_date: Option<NaiveDate>,
     pub comment: Option<String>,
+    /// Tags declared in `comment` (`:tag1:tag2:` or `name: value`). They are
+    /// parsed from the comment, which is what gets serialized.
+    pub tags: Vec<(String, Option<String>)>,
+}
+
+impl Posting {
+    /// Real posting of `amount` to `account`, without balance assertion,
+    /// status or comment. A posting without an amount has it inferred from
+    /// the others, see [`Transaction::infer_elided_amount`].
+    pub fn new(account: &str, amount: Option<Amount>) -> Self {
+        Posting {
+            account: account.to_owned(),
+            reality: Reality::Real,
+            amount: amount.map(PostingAmount::from),
+            amount_expr: None,
+            balance: None,
+            balance_kind: BalanceKind::Single,
+            status: None,
+            date: None,
+            effective_date: None,
+            comment: None,
+            tags: Vec::new(),
+        }
+    }
 }
 
 impl fmt::Display for Posting {
@@ -130,19 +543,76 @@ impl fmt::Display for Posting {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Reality {
     Real,
     BalancedVirtual,
     UnbalancedVirtual,
 }
 
+///
+/// Value expression, as written in parentheses in place of an amount.
+///
+#[non_exhaustive]
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AmountExpr {
+    Amount(Amount),
+    /// Number without a commodity.
+    Number(Decimal),
+    /// Variable, such as `amount` or one set with `define`. A leading `$`
+    /// is kept as written.
+    Variable(String),
+    Neg(Box<AmountExpr>),
+    Add(Box<AmountExpr>, Box<AmountExpr>),
+    Sub(Box<AmountExpr>, Box<AmountExpr>),
+    Mul(Box<AmountExpr>, Box<AmountExpr>),
+    Div(Box<AmountExpr>, Box<AmountExpr>),
+    /// Function call, such as `floor(amount)`.
+    Call(String, Vec<AmountExpr>),
+}
+
+impl fmt::Display for AmountExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PostingAmount {
     pub amount: Amount,
     pub lot_price: Option<Price>,
+    /// Whether the lot price is fixated (`{=$10.00}`), pinning the lot's
+    /// valuation to it.
+    pub lot_price_fixed: bool,
+    /// Acquisition date of the lot, written as `[2023-05-01]`.
+    pub lot_date: Option<NaiveDate>,
+    /// Note naming the lot, written as `(first purchase)`.
+    pub lot_note: Option<String>,
     pub price: Option<Price>,
 }
 
+impl From<Amount> for PostingAmount {
+    /// Amount without lot annotations or price.
+    fn from(amount: Amount) -> Self {
+        PostingAmount {
+            amount,
+            lot_price: None,
+            lot_price_fixed: false,
+            lot_date: None,
+            lot_note: None,
+            price: None,
+        }
+    }
+}
+
 impl fmt::Display for PostingAmount {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -155,6 +625,7 @@ impl fmt::Display for PostingAmount {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Amount {
     pub quantity: Decimal,
     pub commodity: Commodity,
@@ -172,24 +643,54 @@ impl fmt::Display for Amount {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Commodity {
     pub name: String,
     pub position: CommodityPosition,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CommodityPosition {
     Left,
     Right,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Price {
     Unit(Amount),
     Total(Amount),
 }
 
+/// Scope of a balance assertion, as in hledger.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BalanceKind {
+    /// `=`: the balance in the asserted commodity, ignoring others.
+    Single,
+    /// `==`: the whole balance, which has no other commodity.
+    Sole,
+    /// `=*`: like [`BalanceKind::Single`], including subaccounts.
+    SingleInclusive,
+    /// `==*`: like [`BalanceKind::Sole`], including subaccounts.
+    SoleInclusive,
+}
+
+impl BalanceKind {
+    /// Operator introducing the assertion, e.g. `==*`.
+    pub fn operator(self) -> &'static str {
+        match self {
+            BalanceKind::Single => "=",
+            BalanceKind::Sole => "==",
+            BalanceKind::SingleInclusive => "=*",
+            BalanceKind::SoleInclusive => "==*",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Balance {
     Zero,
     Amount(Amount),
@@ -210,8 +711,13 @@ impl fmt::Display for Balance {
 /// Commodity price.
 ///
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CommodityPrice {
+    /// Time of the price, midnight if the directive gives only a date.
     pub datetime: NaiveDateTime,
+    /// Whether the directive gives a time. Prices without one are written
+    /// with their date only.
+    pub has_time: bool,
     pub commodity_name: String,
     pub amount: Amount,
 }
@@ -227,12 +733,368 @@ impl fmt::Display for CommodityPrice {
     }
 }
 
+///
+/// Account declared with the `account` directive.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccountDeclaration {
+    pub name: String,
+    pub comment: Option<String>,
+    pub subdirectives: Vec<AccountSubdirective>,
+}
+
+impl fmt::Display for AccountDeclaration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
+/// Indented line following an `account` directive.
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AccountSubdirective {
+    Note(String),
+    /// Alternative name that refers to the account.
+    Alias(String),
+    /// Payee regex; postings without an account for matching payees use this one.
+    Payee(String),
+    /// Value expression checked on postings, warning when false.
+    Check(String),
+    /// Value expression checked on postings, failing when false.
+    Assert(String),
+    /// Marks the account as the default for unbalanced postings.
+    Default,
+}
+
+///
+/// Commodity declared with the `commodity` directive.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommodityDeclaration {
+    /// Commodity symbol, or a sample amount such as `$1,000.00` in the
+    /// one-line form.
+    pub name: String,
+    pub comment: Option<String>,
+    pub subdirectives: Vec<CommoditySubdirective>,
+}
+
+impl fmt::Display for CommodityDeclaration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
+/// Indented line following a `commodity` directive.
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CommoditySubdirective {
+    Note(String),
+    /// Sample amount showing how the commodity is displayed, e.g. `$1,000.00`.
+    Format(String),
+    Alias(String),
+    /// Prices of the commodity are not downloaded.
+    NoMarket,
+    /// Marks the commodity as the default for amounts without one.
+    Default,
+}
+
+///
+/// Payee declared with the `payee` directive.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PayeeDeclaration {
+    pub name: String,
+    pub comment: Option<String>,
+    pub subdirectives: Vec<PayeeSubdirective>,
+}
+
+impl fmt::Display for PayeeDeclaration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
+/// Indented line following a `payee` directive.
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PayeeSubdirective {
+    /// Regex matching transaction descriptions that refer to this payee.
+    Alias(String),
+    /// Transaction UUID that refers to this payee.
+    Uuid(String),
+}
+
+///
+/// Tag declared with the `tag` directive.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TagDeclaration {
+    pub name: String,
+    pub comment: Option<String>,
+    pub subdirectives: Vec<TagSubdirective>,
+}
+
+impl fmt::Display for TagDeclaration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
+/// Indented line following a `tag` directive.
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TagSubdirective {
+    /// Value expression checked on the tag's values, warning when false.
+    Check(String),
+    /// Value expression checked on the tag's values, failing when false.
+    Assert(String),
+}
+
+///
+/// Account alias defined with `alias ALIAS=ACCOUNT`.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccountAlias {
+    pub alias: String,
+    pub account: String,
+}
+
+impl AccountAlias {
+    /// Regular expression of an `alias /REGEX/=REPLACEMENT` directive, as
+    /// hledger supports, or `None` for an alias of an account name.
+    pub fn pattern(&self) -> Option<&str> {
+        self.alias
+            .strip_prefix('/')?
+            .strip_suffix('/')
+            .filter(|pattern| !pattern.is_empty())
+    }
+}
+
+impl fmt::Display for AccountAlias {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
+///
+/// `capture ACCOUNT  PATTERN`, moving the postings of accounts matching
+/// `pattern` to `account`. See [`Ledger::resolve_aliases`].
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccountCapture {
+    pub account: String,
+    /// Regular expression matched against account names, as written.
+    pub pattern: String,
+}
+
+impl fmt::Display for AccountCapture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
+///
+/// Unit conversion defined with `C AMOUNT = AMOUNT`, as in
+/// `C 1.00 Kb = 1024 bytes`.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommodityConversion {
+    pub from: Amount,
+    pub to: Amount,
+}
+
+impl fmt::Display for CommodityConversion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
+///
+/// Variable defined with `define NAME=VALUE`. The value is kept as written.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Definition {
+    pub name: String,
+    pub value: String,
+}
+
+impl fmt::Display for Definition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
+///
+/// Dated event written as `event DATE DESCRIPTION`, such as
+/// `event 2024-03-01 Moved to Berlin`.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Event {
+    pub date: NaiveDate,
+    pub description: String,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
+///
+/// Comment line starting with `;~` or `;>`, which tools use for hints. Kept
+/// apart from plain comments so they are not lost or confused with them.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommentHint {
+    /// `~` or `>`.
+    pub marker: char,
+    pub text: String,
+}
+
+impl fmt::Display for CommentHint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.to_string_pretty(&SerializerSettings::default())
+        )?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use chrono::NaiveDate;
     use rust_decimal::Decimal;
 
+    #[test]
+    fn shared_ledger_read_access() {
+        let ledger = std::sync::Arc::new(
+            crate::parse(
+                r#"2018-10-01 Payee
+  TEST:A  $1.20
+  TEST:B
+P 2017-11-12 12:00:00 mBH 5.00 PLN
+"#,
+            )
+            .unwrap(),
+        );
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let ledger = ledger.clone();
+                std::thread::spawn(move || {
+                    (
+                        ledger.transactions().count(),
+                        ledger.commodity_prices().count(),
+                        ledger.periodic_transactions().count(),
+                    )
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), (1, 1, 0));
+        }
+    }
+
+    #[test]
+    fn stable_ordering() {
+        let input = r#"2018-10-02 Second
+  TEST:B  $1
+  TEST:A
+P 2017-11-12 12:00:00 mBH 5.00 PLN
+2018-10-01 First
+  TEST:D  $2
+  TEST:C
+P 2017-11-11 12:00:00 ABC 1.00 PLN
+"#;
+        let ledger = crate::parse(input).unwrap();
+        assert_eq!(ledger, crate::parse(input).unwrap());
+        assert_eq!(ledger.to_string(), input);
+        assert_eq!(
+            ledger
+                .transactions()
+                .map(|t| t.description.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Second", "First"]
+        );
+        assert_eq!(
+            ledger
+                .transactions()
+                .flat_map(|t| t.postings.iter().map(|p| p.account.as_str()))
+                .collect::<Vec<_>>(),
+            vec!["TEST:B", "TEST:A", "TEST:D", "TEST:C"]
+        );
+        assert_eq!(
+            ledger
+                .commodity_prices()
+                .map(|p| p.commodity_name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["mBH", "ABC"]
+        );
+    }
+
     #[test]
     fn display_transaction_status() {
         assert_eq!(format!("{}", TransactionStatus::Pending), "!");
@@ -274,7 +1136,11 @@ mod tests {
         let actual = format!(
             "{}",
             CommodityPrice {
-                datetime: NaiveDate::from_ymd(2017, 11, 12).and_hms(12, 00, 00),
+                datetime: NaiveDate::from_ymd_opt(2017, 11, 12)
+                    .unwrap()
+                    .and_hms_opt(12, 0, 0)
+                    .unwrap(),
+                has_time: true,
                 commodity_name: "mBH".to_owned(),
                 amount: Amount {
                     quantity: Decimal::new(500, 2),
@@ -324,8 +1190,12 @@ mod tests {
                             }
                         },
                         lot_price: None,
+                        lot_price_fixed: false,
+                        lot_date: None,
+                        lot_note: None,
                         price: None,
                     }),
+                    amount_expr: None,
                     balance: Some(Balance::Amount(Amount {
                         quantity: Decimal::new(5000, 2),
                         commodity: Commodity {
@@ -333,8 +1203,12 @@ mod tests {
                             position: CommodityPosition::Left,
                         }
                     })),
+                    balance_kind: BalanceKind::Single,
                     status: Some(TransactionStatus::Cleared),
+                    date: None,
+                    effective_date: None,
                     comment: Some("asdf".to_owned()),
+                    tags: Vec::new(),
                 }
             ),
             "* Assets:Checking  USD42.00 = USD50.00\n  ; asdf"
@@ -347,8 +1221,9 @@ mod tests {
             "{}",
             Transaction {
                 comment: Some("Comment Line 1\nComment Line 2".to_owned()),
-                date: NaiveDate::from_ymd(2018, 10, 01),
-                effective_date: Some(NaiveDate::from_ymd(2018, 10, 14)),
+                date: NaiveDate::from_ymd_opt(2018, 10, 1).unwrap(),
+                effective_date: Some(NaiveDate::from_ymd_opt(2018, 10, 14).unwrap()),
+                date_style: DateStyle::Numeric,
                 status: Some(TransactionStatus::Pending),
                 code: Some("123".to_owned()),
                 description: "Marek Ogarek".to_owned(),
@@ -365,11 +1240,19 @@ mod tests {
                                 }
                             },
                             lot_price: None,
+                            lot_price_fixed: false,
+                            lot_date: None,
+                            lot_note: None,
                             price: None
                         }),
+                        amount_expr: None,
                         balance: None,
+                        balance_kind: BalanceKind::Single,
                         status: None,
-                        comment: Some("dd".to_owned())
+                        date: None,
+                        effective_date: None,
+                        comment: Some("dd".to_owned()),
+                        tags: Vec::new(),
                     },
                     Posting {
                         account: "TEST:ABC 123".to_owned(),
@@ -383,13 +1266,22 @@ mod tests {
                                 }
                             },
                             lot_price: None,
+                            lot_price_fixed: false,
+                            lot_date: None,
+                            lot_note: None,
                             price: None
                         }),
+                        amount_expr: None,
                         balance: None,
+                        balance_kind: BalanceKind::Single,
                         status: None,
-                        comment: None
+                        date: None,
+                        effective_date: None,
+                        comment: None,
+                        tags: Vec::new(),
                     }
-                ]
+                ],
+                tags: Vec::new(),
             },
         );
         let expected = r#"2018-10-01=2018-10-14 ! (123) Marek Ogarek
@@ -409,8 +1301,9 @@ mod tests {
                 items: vec![
                     LedgerItem::Transaction(Transaction {
                         comment: Some("Comment Line 1\nComment Line 2".to_owned()),
-                        date: NaiveDate::from_ymd(2018, 10, 01),
-                        effective_date: Some(NaiveDate::from_ymd(2018, 10, 14)),
+                        date: NaiveDate::from_ymd_opt(2018, 10, 1).unwrap(),
+                        effective_date: Some(NaiveDate::from_ymd_opt(2018, 10, 14).unwrap()),
+                        date_style: DateStyle::Numeric,
                         status: Some(TransactionStatus::Pending),
                         code: Some("123".to_owned()),
                         description: "Marek Ogarek".to_owned(),
@@ -427,11 +1320,19 @@ mod tests {
                                         }
                                     },
                                     lot_price: None,
+                                    lot_price_fixed: false,
+                                    lot_date: None,
+                                    lot_note: None,
                                     price: None
                                 }),
+                                amount_expr: None,
                                 balance: None,
+                                balance_kind: BalanceKind::Single,
                                 status: None,
-                                comment: Some("dd".to_owned())
+                                date: None,
+                                effective_date: None,
+                                comment: Some("dd".to_owned()),
+                                tags: Vec::new(),
                             },
                             Posting {
                                 account: "TEST:ABC 123".to_owned(),
@@ -445,19 +1346,29 @@ mod tests {
                                         }
                                     },
                                     lot_price: None,
+                                    lot_price_fixed: false,
+                                    lot_date: None,
+                                    lot_note: None,
                                     price: None
                                 }),
+                                amount_expr: None,
                                 balance: None,
+                                balance_kind: BalanceKind::Single,
                                 status: None,
-                                comment: None
+                                date: None,
+                                effective_date: None,
+                                comment: None,
+                                tags: Vec::new(),
                             }
-                        ]
+                        ],
+                        tags: Vec::new(),
                     }),
                     LedgerItem::EmptyLine,
                     LedgerItem::Transaction(Transaction {
                         comment: None,
-                        date: NaiveDate::from_ymd(2018, 10, 01),
-                        effective_date: Some(NaiveDate::from_ymd(2018, 10, 14)),
+                        date: NaiveDate::from_ymd_opt(2018, 10, 1).unwrap(),
+                        effective_date: Some(NaiveDate::from_ymd_opt(2018, 10, 14).unwrap()),
+                        date_style: DateStyle::Numeric,
                         status: Some(TransactionStatus::Pending),
                         code: Some("123".to_owned()),
                         description: "Marek Ogarek".to_owned(),
@@ -480,6 +1391,9 @@ mod tests {
                                             position: CommodityPosition::Right
                                         }
                                     })),
+                                    lot_price_fixed: false,
+                                    lot_date: None,
+                                    lot_note: None,
                                     price: Some(Price::Unit(Amount {
                                         quantity: Decimal::new(600, 2),
                                         commodity: Commodity {
@@ -488,9 +1402,14 @@ mod tests {
                                         }
                                     }))
                                 }),
+                                amount_expr: None,
                                 balance: None,
+                                balance_kind: BalanceKind::Single,
                                 status: None,
-                                comment: None
+                                date: None,
+                                effective_date: None,
+                                comment: None,
+                                tags: Vec::new(),
                             },
                             Posting {
                                 account: "TEST:ABC 123".to_owned(),
@@ -510,6 +1429,9 @@ mod tests {
                                             position: CommodityPosition::Right
                                         }
                                     })),
+                                    lot_price_fixed: false,
+                                    lot_date: None,
+                                    lot_note: None,
                                     price: Some(Price::Total(Amount {
                                         quantity: Decimal::new(600, 2),
                                         commodity: Commodity {
@@ -518,15 +1440,25 @@ mod tests {
                                         }
                                     }))
                                 }),
+                                amount_expr: None,
                                 balance: None,
+                                balance_kind: BalanceKind::Single,
                                 status: None,
-                                comment: None
+                                date: None,
+                                effective_date: None,
+                                comment: None,
+                                tags: Vec::new(),
                             }
-                        ]
+                        ],
+                        tags: Vec::new(),
                     }),
                     LedgerItem::EmptyLine,
                     LedgerItem::CommodityPrice(CommodityPrice {
-                        datetime: NaiveDate::from_ymd(2017, 11, 12).and_hms(12, 00, 00),
+                        datetime: NaiveDate::from_ymd_opt(2017, 11, 12)
+                            .unwrap()
+                            .and_hms_opt(12, 0, 0)
+                            .unwrap(),
+                        has_time: true,
                         commodity_name: "mBH".to_owned(),
                         amount: Amount {
                             quantity: Decimal::new(500, 2),
@@ -554,4 +1486,26 @@ P 2017-11-12 12:00:00 mBH 5.00 PLN
 "#;
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn bulk_construction() {
+        let template =
+            crate::parse("2024-01-02 Shop\n  Expenses:Food  $5\n  Assets:Cash\n").unwrap();
+        let transaction = template.transactions().next().unwrap().clone();
+
+        let mut ledger = Ledger::with_capacity(100);
+        assert!(ledger.items.capacity() >= 100);
+        for _ in 0..10 {
+            let mut transaction = transaction.clone();
+            transaction.reserve_postings(8);
+            transaction.extend_postings(transaction.postings.clone());
+            ledger.push(LedgerItem::Transaction(transaction));
+        }
+        ledger.shrink_to_fit();
+        assert_eq!(ledger.items.capacity(), 10);
+        for transaction in ledger.transactions() {
+            assert_eq!(transaction.postings.len(), 4);
+            assert_eq!(transaction.postings.capacity(), 4);
+        }
+    }
 }
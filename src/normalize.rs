@@ -0,0 +1,99 @@
+use crate::model::*;
+use std::cmp::Ordering;
+
+/// Order of postings inside a transaction, see [`Ledger::sort_postings`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PostingOrder {
+    /// Alphabetically by account name.
+    Account,
+    /// Positive amounts (debits) first, then negative amounts (credits), then
+    /// postings without an amount.
+    DebitsFirst,
+}
+
+impl PostingOrder {
+    fn compare(self, a: &Posting, b: &Posting) -> Ordering {
+        match self {
+            PostingOrder::Account => a.account.cmp(&b.account),
+            PostingOrder::DebitsFirst => debit_rank(a).cmp(&debit_rank(b)),
+        }
+    }
+}
+
+fn debit_rank(posting: &Posting) -> u8 {
+    match posting.amount {
+        Some(ref amount) if amount.amount.quantity.is_sign_negative() => 1,
+        Some(_) => 0,
+        None => 2,
+    }
+}
+
+impl Ledger {
+    /// Sorts the postings of every transaction and periodic transaction.
+    ///
+    /// The sort is stable and posting comments move together with their
+    /// posting.
+    pub fn sort_postings(&mut self, order: PostingOrder) {
+        for item in &mut self.items {
+            match item {
+                LedgerItem::Transaction(transaction) => transaction.sort_postings(order),
+                LedgerItem::PeriodicTransaction(transaction) => transaction.sort_postings(order),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Transaction {
+    pub fn sort_postings(&mut self, order: PostingOrder) {
+        self.postings.sort_by(|a, b| order.compare(a, b));
+    }
+}
+
+impl PeriodicTransaction {
+    pub fn sort_postings(&mut self, order: PostingOrder) {
+        self.postings.sort_by(|a, b| order.compare(a, b));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn sort_postings_test() {
+        let input = r#"2024-01-02 Shop
+  Liabilities:Card  $-30
+  ; paid by card
+  Expenses:Food  $20
+  Assets:Cash
+  Expenses:Drinks  $10
+"#;
+
+        let mut ledger = parse(input).unwrap();
+        ledger.sort_postings(PostingOrder::Account);
+        assert_eq!(
+            ledger.to_string(),
+            r#"2024-01-02 Shop
+  Assets:Cash
+  Expenses:Drinks  $10
+  Expenses:Food  $20
+  Liabilities:Card  $-30
+  ; paid by card
+"#
+        );
+
+        let mut ledger = parse(input).unwrap();
+        ledger.sort_postings(PostingOrder::DebitsFirst);
+        assert_eq!(
+            ledger.to_string(),
+            r#"2024-01-02 Shop
+  Expenses:Food  $20
+  Expenses:Drinks  $10
+  Liabilities:Card  $-30
+  ; paid by card
+  Assets:Cash
+"#
+        );
+    }
+}
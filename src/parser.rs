@@ -1,25 +1,394 @@
-use chrono::{NaiveDate, NaiveDateTime};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
 use nom::{
     branch::alt,
     bytes::complete::{is_not, tag, take_while, take_while1, take_while_m_n},
-    character::complete::{char, digit0, digit1, line_ending, not_line_ending, space0, space1},
-    combinator::{eof, map, map_opt, map_res, opt, peek, recognize, value, verify},
-    error::VerboseError,
-    multi::{fold_many1, many0, many1},
+    character::complete::{
+        char, digit0, digit1, line_ending, none_of, not_line_ending, one_of, satisfy, space0,
+        space1,
+    },
+    combinator::{
+        consumed, cut, eof, map, map_opt, map_res, not, opt, peek, recognize, value, verify,
+    },
+    error::{context, ContextError, ErrorKind, ParseError, VerboseError},
+    multi::{fold_many0, fold_many1, many0, many1, separated_list0},
     sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
-    AsChar, Err, IResult, Needed, Parser,
+    AsChar, Err, IResult, Parser,
 };
 use rust_decimal::Decimal;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
+use crate::csv_rules::{
+    CsvAssignment, CsvConditionalBlock, CsvMatcher, CsvRule, CsvRules, CSV_SETTINGS,
+};
+use crate::expressions::define;
 use crate::model::*;
+use crate::tags::{comment_tags, tag_date};
+use crate::timeclock::TimeclockEntry;
+use crate::timedot::{timedot_transaction, unit_hours};
+use crate::{LegacyEncoding, ParseWarning, ParseWarningKind};
 
 type LedgerParseResult<'a, T> = IResult<&'a str, T, VerboseError<&'a str>>;
 
+/// English month names, for [`ParserSettings::with_month_names`].
+pub const ENGLISH_MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Options enabling syntax that is not accepted by default.
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct ParserSettings {
+    /// Month names, starting with January, accepted in transaction dates
+    /// such as `2024 Jan 05` or `05 January 2024`, ignoring case. A name
+    /// also matches its first three letters unless another name starts
+    /// with them too, such as `Juin` and `Juillet`. Empty disables these
+    /// dates.
+    pub month_names: Vec<String>,
+    /// Character separating account name components in the input, such as
+    /// `.` or `/`. Account names are normalized to use `:`.
+    pub account_separator: char,
+    /// Applies Unicode NFC normalization to account and payee names, so
+    /// that names written with composed and decomposed characters match.
+    #[cfg(feature = "unicode")]
+    pub normalize_names: bool,
+    /// Lowercases account and payee names.
+    pub fold_case: bool,
+    /// Characters accepted between groups of three digits in quantities,
+    /// such as `,` in `1,234,567.89` or a space in `1 234 567.89`. They are
+    /// dropped when parsing. `,` by default. A separator equal to the
+    /// decimal mark stands for the other one of `.` and `,`.
+    pub digit_group_separators: Vec<char>,
+    /// Replaces invalid UTF-8 and control characters by U+FFFD, with a
+    /// warning for each, instead of failing.
+    pub lossy_input: bool,
+    /// Decimal mark of quantities, `.` by default. Commodities whose
+    /// `commodity` or `D` format clearly uses the other mark, such as
+    /// `1.000,00 EUR`, are read with that one instead.
+    pub decimal_mark: char,
+    /// Reads a posting amount in parentheses, such as `($1,200.00)`, as a
+    /// negative amount instead of a value expression.
+    pub parenthesized_negatives: bool,
+    /// Spaces in a row ending the account name of a posting, 2 by default
+    /// as in ledger. A tab always ends it. With 1, `Assets:Cash $5` is read
+    /// as an account and an amount, and account names cannot hold spaces.
+    pub separator_spaces: usize,
+    /// Encoding of input that is not valid UTF-8 and has no byte order
+    /// mark, such as journals exported by old banking software. Such input
+    /// is invalid if `None`, the default.
+    pub legacy_encoding: Option<LegacyEncoding>,
+    /// Characters read as [`TransactionStatus::Custom`] flags in place of
+    /// `*` and `!`, such as `?` in `2024-01-02 ? Shop`. None by default.
+    pub status_flags: Vec<char>,
+}
+
+impl ParserSettings {
+    pub fn with_month_names(mut self, month_names: &[&str; 12]) -> Self {
+        self.month_names = month_names.iter().map(|&name| name.to_owned()).collect();
+        self
+    }
+
+    pub fn with_account_separator(mut self, account_separator: char) -> Self {
+        self.account_separator = account_separator;
+        self
+    }
+
+    #[cfg(feature = "unicode")]
+    pub fn with_normalize_names(mut self, normalize_names: bool) -> Self {
+        self.normalize_names = normalize_names;
+        self
+    }
+
+    pub fn with_fold_case(mut self, fold_case: bool) -> Self {
+        self.fold_case = fold_case;
+        self
+    }
+
+    pub fn with_digit_group_separators(mut self, separators: &[char]) -> Self {
+        self.digit_group_separators = separators.to_vec();
+        self
+    }
+
+    pub fn with_lossy_input(mut self, lossy_input: bool) -> Self {
+        self.lossy_input = lossy_input;
+        self
+    }
+
+    /// Sets the decimal mark, e.g. `,` for `1.234,56 EUR`. With the default
+    /// separators, `.` then separates digit groups.
+    pub fn with_decimal_mark(mut self, decimal_mark: char) -> Self {
+        self.decimal_mark = decimal_mark;
+        self
+    }
+
+    pub fn with_parenthesized_negatives(mut self, parenthesized_negatives: bool) -> Self {
+        self.parenthesized_negatives = parenthesized_negatives;
+        self
+    }
+
+    pub fn with_separator_spaces(mut self, separator_spaces: usize) -> Self {
+        self.separator_spaces = separator_spaces;
+        self
+    }
+
+    pub fn with_legacy_encoding(mut self, legacy_encoding: LegacyEncoding) -> Self {
+        self.legacy_encoding = Some(legacy_encoding);
+        self
+    }
+
+    pub fn with_status_flags(mut self, status_flags: &[char]) -> Self {
+        self.status_flags = status_flags.to_vec();
+        self
+    }
+}
+
+impl Default for ParserSettings {
+    fn default() -> Self {
+        Self {
+            month_names: Vec::new(),
+            account_separator: ':',
+            #[cfg(feature = "unicode")]
+            normalize_names: false,
+            fold_case: false,
+            digit_group_separators: vec![','],
+            lossy_input: false,
+            decimal_mark: '.',
+            parenthesized_negatives: false,
+            separator_spaces: 2,
+            legacy_encoding: None,
+            status_flags: Vec::new(),
+        }
+    }
+}
+
+/// Deepest nesting of parentheses and prefix operators in value expressions
+/// and queries.
+const MAX_NESTING: usize = 64;
+
+/// Most binary operators in one value expression.
+const MAX_OPERATORS: usize = 1024;
+
+/// Failure for input beyond [`MAX_NESTING`] or [`MAX_OPERATORS`], with a
+/// context so that it is reported rather than the items around it.
+fn too_large(input: &str) -> Err<VerboseError<&str>> {
+    let error = VerboseError::from_error_kind(input, ErrorKind::TooLarge);
+    Err::Failure(VerboseError::add_context(
+        input,
+        "less nesting or fewer operators",
+        error,
+    ))
+}
+
+/// State shared by the parsers while reading one journal.
+#[derive(Default)]
+pub struct Context {
+    settings: ParserSettings,
+    /// Warnings along with the length of the input left when they were found.
+    warnings: RefCell<Vec<(usize, ParseWarningKind)>>,
+    /// Year set by the last `Y` directive, for dates written without one.
+    year: Cell<Option<i32>>,
+    /// Commodity set by the last `D` directive, for amounts written without
+    /// one.
+    default_commodity: RefCell<Option<Commodity>>,
+    /// Variables set with `define` so far.
+    variables: RefCell<BTreeMap<String, Amount>>,
+    /// Decimal marks of the commodities whose format declares one.
+    decimal_marks: RefCell<BTreeMap<String, char>>,
+    /// Decimal mark set by the last `decimal-mark` directive.
+    default_decimal_mark: Cell<Option<char>>,
+    /// Nesting depth of the expression or query being parsed.
+    depth: Cell<usize>,
+    /// Binary operators read so far in the expression being parsed.
+    operators: Cell<usize>,
+    /// Input left at the start and end of each posting parsed, when
+    /// recorded.
+    posting_spans: RefCell<Option<Vec<(usize, usize)>>>,
+}
+
+impl Context {
+    pub fn new(settings: ParserSettings) -> Self {
+        Self {
+            settings,
+            ..Self::default()
+        }
+    }
+
+    /// Runs `parser` one nesting level deeper, failing beyond
+    /// [`MAX_NESTING`] levels rather than overflowing the stack.
+    fn nested<'a, T, F>(&self, input: &'a str, parser: F) -> LedgerParseResult<'a, T>
+    where
+        F: FnOnce(&'a str) -> LedgerParseResult<'a, T>,
+    {
+        let depth = self.depth.get();
+        if depth >= MAX_NESTING {
+            return Err(too_large(input));
+        }
+        self.depth.set(depth + 1);
+        let result = parser(input);
+        self.depth.set(depth);
+        result
+    }
+
+    fn account_name(&self, name: &str) -> String {
+        self.name(&name.replace(self.settings.account_separator, ":"))
+    }
+
+    /// Account or payee name, normalized according to the settings.
+    fn name(&self, name: &str) -> String {
+        #[cfg(feature = "unicode")]
+        let name = &if self.settings.normalize_names {
+            unicode_normalization::UnicodeNormalization::nfc(name).collect()
+        } else {
+            name.to_owned()
+        };
+
+        if self.settings.fold_case {
+            name.to_lowercase()
+        } else {
+            name.to_owned()
+        }
+    }
+
+    fn decimal_mark(&self, commodity: &str) -> char {
+        self.decimal_marks
+            .borrow()
+            .get(commodity)
+            .copied()
+            .or(self.default_decimal_mark.get())
+            .unwrap_or(self.settings.decimal_mark)
+    }
+
+    /// Remembers the decimal mark shown by a commodity format, returning its
+    /// commodity.
+    fn declare_format(&self, format: &str) -> Option<Commodity> {
+        let (amount, decimal_mark) = match self.default_decimal_mark.get() {
+            Some(mark) => format_from_str(format, &self.settings.clone().with_decimal_mark(mark)),
+            None => format_from_str(format, &self.settings),
+        }?;
+        if let Some(decimal_mark) = decimal_mark {
+            self.decimal_marks
+                .borrow_mut()
+                .insert(amount.commodity.name.clone(), decimal_mark);
+        }
+        Some(amount.commodity)
+    }
+
+    fn is_digit_group_separator(&self, c: char, decimal_mark: char) -> bool {
+        self.settings
+            .digit_group_separators
+            .iter()
+            .any(|&separator| match separator == decimal_mark {
+                true => c == other_decimal_mark(decimal_mark),
+                false => c == separator,
+            })
+    }
+
+    fn warn(&self, input: &str, kind: ParseWarningKind) {
+        self.warnings.borrow_mut().push((input.len(), kind));
+    }
+
+    /// Starts recording where postings start and end.
+    pub fn record_posting_spans(&self) {
+        self.posting_spans.replace(Some(Vec::new()));
+    }
+
+    /// Returns where the postings parsed since the last call start and end,
+    /// as the length of the input left at each point.
+    pub fn take_posting_spans(&self) -> Vec<(usize, usize)> {
+        self.posting_spans
+            .borrow_mut()
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Returns the collected warnings with line numbers within `source`.
+    pub fn take_warnings(&self, source: &str) -> Vec<ParseWarning> {
+        self.warnings
+            .take()
+            .into_iter()
+            .map(|(remaining, kind)| ParseWarning {
+                line: source[..source.len() - remaining].matches('\n').count() + 1,
+                kind,
+            })
+            .collect()
+    }
+}
+
+fn other_decimal_mark(decimal_mark: char) -> char {
+    if decimal_mark == ',' {
+        '.'
+    } else {
+        ','
+    }
+}
+
+/// Decimal mark used by a commodity format, if the format shows it: `,` in
+/// `1.000,00 EUR` or `1,5 EUR`, but none in `1,000 EUR` or `1 EUR`.
+fn format_decimal_mark(format: &str) -> Option<char> {
+    let (position, last) = format
+        .char_indices()
+        .rfind(|&(_, c)| c == '.' || c == ',')?;
+    let before = &format[..position];
+    if before.contains(last) {
+        Some(other_decimal_mark(last))
+    } else if before.contains(other_decimal_mark(last)) {
+        Some(last)
+    } else if format[position + 1..]
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .count()
+        == 3
+    {
+        None
+    } else {
+        Some(last)
+    }
+}
+
 fn is_commodity_char(c: char) -> bool {
     !"0123456789{}[]()~`!@#%^&*-=+\\'\",./? ;\t\r\n".contains(c)
 }
 
+/// Length of the unquoted commodity name at the start of `input`. Digits
+/// and `.` may follow a letter inside the name, as in `BRK.B`. Trailing
+/// digits, as in `EUR2`, belong to the name only with `trailing_digits`,
+/// since the quantity of an amount can follow its commodity directly.
+pub(crate) fn bare_commodity_len(input: &str, trailing_digits: bool) -> usize {
+    let is_inner = |c: char| c.is_ascii_digit() || c == '.';
+    let mut len = 0;
+    loop {
+        let rest = &input[len..];
+        len += rest.len() - rest.trim_start_matches(is_commodity_char).len();
+        if len == 0 || !input[..len].ends_with(char::is_alphabetic) {
+            break;
+        }
+        let rest = &input[len..];
+        let after = rest.trim_start_matches(is_inner);
+        if after.len() < rest.len() && after.starts_with(is_commodity_char) {
+            len += rest.len() - after.len();
+        } else {
+            if trailing_digits {
+                len += rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            }
+            break;
+        }
+    }
+    len
+}
+
 fn join_comments(inline_comment: Option<&str>, line_comments: Vec<&str>) -> Option<String> {
     if let Some(inline) = inline_comment {
         let mut full: String = inline.to_owned();
@@ -35,7 +404,7 @@ fn join_comments(inline_comment: Option<&str>, line_comments: Vec<&str>) -> Opti
     }
 }
 
-fn eol_or_eof(input: &str) -> LedgerParseResult<&str> {
+fn eol_or_eof(input: &str) -> LedgerParseResult<'_, &str> {
     alt((line_ending, eof))(input)
 }
 
@@ -43,7 +412,7 @@ fn number_n<'a>(n: usize) -> impl FnMut(&'a str) -> IResult<&'a str, i32, Verbos
     map_res(take_while_m_n(n, n, AsChar::is_dec_digit), i32::from_str)
 }
 
-fn parse_date_internal(input: &str) -> LedgerParseResult<(i32, i32, i32)> {
+fn parse_date_internal(input: &str) -> LedgerParseResult<'_, (i32, i32, i32)> {
     tuple((
         terminated(number_n(4), alt((tag("-"), tag("/"), tag(".")))),
         terminated(number_n(2), alt((tag("-"), tag("/"), tag(".")))),
@@ -51,7 +420,7 @@ fn parse_date_internal(input: &str) -> LedgerParseResult<(i32, i32, i32)> {
     ))(input)
 }
 
-fn parse_time_internal(input: &str) -> LedgerParseResult<(i32, i32, i32)> {
+fn parse_time_internal(input: &str) -> LedgerParseResult<'_, (i32, i32, i32)> {
     tuple((
         terminated(number_n(2), tag(":")),
         terminated(number_n(2), tag(":")),
@@ -59,19 +428,67 @@ fn parse_time_internal(input: &str) -> LedgerParseResult<(i32, i32, i32)> {
     ))(input)
 }
 
-fn parse_datetime_internal(input: &str) -> LedgerParseResult<(i32, i32, i32, i32, i32, i32)> {
+fn parse_datetime_internal(input: &str) -> LedgerParseResult<'_, (i32, i32, i32, i32, i32, i32)> {
     separated_pair(parse_date_internal, space1, parse_time_internal)
         .map(|(date, time)| (date.0, date.1, date.2, time.0, time.1, time.2))
         .parse(input)
 }
 
-fn parse_date(input: &str) -> LedgerParseResult<NaiveDate> {
+pub(crate) fn parse_date(input: &str) -> LedgerParseResult<'_, NaiveDate> {
     map_opt(parse_date_internal, |value| {
         NaiveDate::from_ymd_opt(value.0, value.1 as u32, value.2 as u32)
     })(input)
 }
 
-fn parse_datetime(input: &str) -> LedgerParseResult<NaiveDateTime> {
+fn parse_month_name<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, u32> {
+    map_opt(take_while1(char::is_alphabetic), |word: &str| {
+        let word = word.to_lowercase();
+        let names: Vec<String> = ctx
+            .settings
+            .month_names
+            .iter()
+            .map(|name| name.to_lowercase())
+            .collect();
+        let position = names.iter().position(|name| *name == word).or_else(|| {
+            // An abbreviation must be of a single month
+            let mut months = names
+                .iter()
+                .enumerate()
+                .filter(|(_, name)| name.chars().take(3).collect::<String>() == word);
+            let (position, _) = months.next()?;
+            months.next().is_none().then_some(position)
+        })?;
+        Some(position as u32 + 1)
+    })(input)
+}
+
+fn parse_transaction_date<'a>(
+    input: &'a str,
+    ctx: &Context,
+) -> LedgerParseResult<'a, (NaiveDate, DateStyle)> {
+    let day = || map_res(take_while_m_n(1, 2, AsChar::is_dec_digit), u32::from_str);
+    let month = |i| parse_month_name(i, ctx);
+    alt((
+        parse_date.map(|date| (date, DateStyle::Numeric)),
+        map_opt(
+            tuple((number_n(4), space1, month, space1, day())),
+            |(year, _, month, _, day)| NaiveDate::from_ymd_opt(year, month, day),
+        )
+        .map(|date| (date, DateStyle::YearMonthNameDay)),
+        map_opt(
+            tuple((day(), space1, month, space1, number_n(4))),
+            |(day, _, month, _, year)| NaiveDate::from_ymd_opt(year, month, day),
+        )
+        .map(|date| (date, DateStyle::DayMonthNameYear)),
+        map_opt(
+            separated_pair(day(), one_of("-/."), day()),
+            |(month, day)| NaiveDate::from_ymd_opt(ctx.year.get()?, month, day),
+        )
+        .map(|date| (date, DateStyle::MonthDay)),
+    ))(input)
+}
+
+fn parse_datetime(input: &str) -> LedgerParseResult<'_, NaiveDateTime> {
     map_opt(
         parse_datetime_internal,
         |value| match NaiveDate::from_ymd_opt(value.0, value.1 as u32, value.2 as u32) {
@@ -81,43 +498,70 @@ fn parse_datetime(input: &str) -> LedgerParseResult<NaiveDateTime> {
     )(input)
 }
 
-fn parse_quantity(input: &str) -> LedgerParseResult<Decimal> {
-    map_res(
+fn parse_quantity<'a>(
+    input: &'a str,
+    ctx: &Context,
+    decimal_mark: char,
+) -> LedgerParseResult<'a, Decimal> {
+    let (rest, (text, quantity)) = consumed(map_res(
         tuple((
             opt(tag("-")),
             alt((
                 pair(
                     take_while_m_n(1, 3, AsChar::is_dec_digit),
                     many1(preceded(
-                        tag(","),
+                        satisfy(|c| ctx.is_digit_group_separator(c, decimal_mark)),
                         take_while_m_n(3, 3, AsChar::is_dec_digit).map(str::to_owned),
                     )),
                 )
                 .map(|(leading, rest)| format!("{}{}", leading, rest.join(""))),
                 digit0.map(str::to_owned),
             )),
-            opt(recognize(preceded(tag("."), digit1))),
+            opt(preceded(char(decimal_mark), digit1)),
+            opt(recognize(tuple((one_of("eE"), opt(one_of("+-")), digit1)))),
         ))
-        .map(|(sign, decimal, fractional)| {
-            format!(
-                "{}{}{}",
-                sign.unwrap_or(""),
-                decimal,
-                fractional.unwrap_or("")
-            )
+        .map(|(sign, decimal, fractional, exponent)| {
+            let mut quantity = format!("{}{}", sign.unwrap_or(""), decimal);
+            if let Some(fractional) = fractional {
+                quantity = format!("{}.{}", quantity, fractional);
+            }
+            (quantity, exponent)
         }),
-        |s: String| Decimal::from_str(&s),
-    )(input)
+        |(quantity, exponent): (String, Option<&str>)| match exponent {
+            Some(exponent) => Decimal::from_scientific(&format!("{}{}", quantity, exponent)),
+            None => Decimal::from_str(&quantity),
+        },
+    ))(input)?;
+
+    if ctx.default_decimal_mark.get().is_none() && is_ambiguous_quantity(text) {
+        ctx.warn(input, ParseWarningKind::AmbiguousQuantity(text.to_owned()));
+    }
+    Ok((rest, quantity))
+}
+
+/// Whether a single `,` or `.` followed by three digits could be either a
+/// decimal mark or a digit group separator, as in `1,000` or `1.000`.
+fn is_ambiguous_quantity(text: &str) -> bool {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    match digits.split_once([',', '.']) {
+        Some((integer, fraction)) => {
+            (1..=3).contains(&integer.len())
+                && fraction.len() == 3
+                && fraction.chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
 }
 
-fn string_fragment(input: &str) -> LedgerParseResult<&str> {
+fn string_fragment(input: &str) -> LedgerParseResult<'_, &str> {
     alt((
         verify(is_not("\\\""), |s: &str| !s.is_empty()),
         value("\"", tag("\\\"")),
+        tag("\\"),
     ))(input)
 }
 
-fn string_between_quotes(input: &str) -> LedgerParseResult<String> {
+fn string_between_quotes(input: &str) -> LedgerParseResult<'_, String> {
     let string_contents = fold_many1(string_fragment, String::new, |mut string, fragment| {
         string.push_str(fragment);
         string
@@ -126,109 +570,372 @@ fn string_between_quotes(input: &str) -> LedgerParseResult<String> {
     delimited(char('"'), string_contents, char('"'))(input)
 }
 
-fn commodity_without_quotes(input: &str) -> LedgerParseResult<String> {
-    take_while1(is_commodity_char)
-        .map(str::to_owned)
-        .parse(input)
+fn commodity_without_quotes(
+    trailing_digits: bool,
+) -> impl Fn(&str) -> LedgerParseResult<'_, String> {
+    move |input| match bare_commodity_len(input, trailing_digits) {
+        0 => Err(Err::Error(VerboseError::from_error_kind(
+            input,
+            ErrorKind::TakeWhile1,
+        ))),
+        len => Ok((&input[len..], input[..len].to_owned())),
+    }
+}
+
+/// Parses a commodity written before a quantity.
+fn parse_commodity(input: &str) -> LedgerParseResult<'_, String> {
+    alt((string_between_quotes, commodity_without_quotes(false)))(input)
 }
 
-fn parse_commodity(input: &str) -> LedgerParseResult<String> {
-    alt((string_between_quotes, commodity_without_quotes))(input)
+/// Parses a commodity not followed by a quantity, such as one written after
+/// the quantity of an amount.
+fn parse_commodity_name(input: &str) -> LedgerParseResult<'_, String> {
+    alt((string_between_quotes, commodity_without_quotes(true)))(input)
 }
 
-fn parse_amount(input: &str) -> LedgerParseResult<Amount> {
+pub(crate) fn parse_amount<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, Amount> {
     alt((
-        tuple((
-            opt(terminated(tag("-"), space0)),
-            terminated(parse_commodity, space0),
-            parse_quantity,
-        ))
-        .map(|(neg_opt, name, quantity)| Amount {
-            quantity: if neg_opt.is_some() {
-                quantity * Decimal::new(-1, 0)
-            } else {
-                quantity
-            },
-            commodity: Commodity {
-                name,
-                position: CommodityPosition::Left,
-            },
-        }),
-        pair(terminated(parse_quantity, space0), parse_commodity).map(|(quantity, name)| Amount {
-            quantity,
-            commodity: Commodity {
-                name,
-                position: CommodityPosition::Right,
-            },
-        }),
+        |i| {
+            let (i, neg_opt) = opt(terminated(tag("-"), space0))(i)?;
+            let (i, name) = terminated(parse_commodity, space0)(i)?;
+            let (i, quantity) = parse_quantity(i, ctx, ctx.decimal_mark(&name))?;
+            Ok((
+                i,
+                Amount {
+                    quantity: if neg_opt.is_some() {
+                        quantity * Decimal::new(-1, 0)
+                    } else {
+                        quantity
+                    },
+                    commodity: Commodity {
+                        name,
+                        position: CommodityPosition::Left,
+                    },
+                },
+            ))
+        },
+        |i| {
+            // The commodity decides the decimal mark, so look ahead for it
+            let (_, name) = peek(preceded(
+                pair(
+                    take_while(|c: char| {
+                        c.is_ascii_digit()
+                            || "-.,".contains(c)
+                            || ctx.settings.digit_group_separators.contains(&c)
+                    }),
+                    pair(
+                        opt(tuple((one_of("eE"), opt(one_of("+-")), digit1))),
+                        space0,
+                    ),
+                ),
+                parse_commodity_name,
+            ))(i)?;
+            let decimal_mark = ctx.decimal_mark(&name);
+            pair(
+                terminated(move |i| parse_quantity(i, ctx, decimal_mark), space0),
+                parse_commodity_name,
+            )
+            .map(|(quantity, name)| Amount {
+                quantity,
+                commodity: Commodity {
+                    name,
+                    position: CommodityPosition::Right,
+                },
+            })
+            .parse(i)
+        },
+        |i| match ctx.default_commodity.borrow().clone() {
+            Some(commodity) => {
+                parse_quantity(i, ctx, ctx.decimal_mark(&commodity.name)).map(|(i, quantity)| {
+                    (
+                        i,
+                        Amount {
+                            quantity,
+                            commodity,
+                        },
+                    )
+                })
+            }
+            None => Err(Err::Error(VerboseError::from_error_kind(
+                i,
+                ErrorKind::Verify,
+            ))),
+        },
     ))(input)
 }
 
-fn parse_posting_amount(input: &str) -> LedgerParseResult<PostingAmount> {
-    let (input, amount) = parse_amount(input)?;
-    let (input, lot_price) = opt(preceded(space0, parse_lot_price))(input)?;
-    let (input, price) = opt(preceded(space0, parse_price))(input)?;
-    Ok((
-        input,
-        PostingAmount {
-            amount,
-            lot_price,
-            price,
+fn identifier(input: &str) -> LedgerParseResult<'_, &str> {
+    recognize(pair(
+        take_while_m_n(1, 1, |c: char| c.is_alphabetic() || c == '_'),
+        take_while(|c: char| c.is_alphanumeric() || c == '_'),
+    ))(input)
+}
+
+fn parse_expression_factor<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, AmountExpr> {
+    ctx.nested(input, |input| expression_factor(input, ctx))
+}
+
+fn expression_factor<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, AmountExpr> {
+    let expression = |i| parse_expression(i, ctx);
+    alt((
+        delimited(pair(char('('), space0), expression, pair(space0, char(')'))),
+        pair(
+            identifier,
+            delimited(
+                pair(char('('), space0),
+                separated_list0(tuple((space0, char(','), space0)), expression),
+                pair(space0, char(')')),
+            ),
+        )
+        .map(|(name, arguments)| AmountExpr::Call(name.to_owned(), arguments)),
+        (|i| parse_amount(i, ctx)).map(AmountExpr::Amount),
+        map_res(
+            recognize(pair(digit1, opt(pair(char('.'), digit1)))),
+            Decimal::from_str,
+        )
+        .map(AmountExpr::Number),
+        preceded(pair(char('-'), space0), |i| parse_expression_factor(i, ctx))
+            .map(|expr| AmountExpr::Neg(Box::new(expr))),
+        recognize(pair(opt(char('$')), identifier))
+            .map(|name: &str| AmountExpr::Variable(name.to_owned())),
+    ))(input)
+}
+
+/// Parses one of the binary `operators`, failing beyond [`MAX_OPERATORS`]
+/// in the expression, which would make it too deep to evaluate.
+fn parse_operator<'a>(
+    input: &'a str,
+    ctx: &Context,
+    operators: &str,
+) -> LedgerParseResult<'a, char> {
+    let (rest, operator) = delimited(space0, one_of(operators), space0)(input)?;
+    let count = ctx.operators.get();
+    if count >= MAX_OPERATORS {
+        return Err(too_large(input));
+    }
+    ctx.operators.set(count + 1);
+    Ok((rest, operator))
+}
+
+fn parse_expression_term<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, AmountExpr> {
+    let factor = |i| parse_expression_factor(i, ctx);
+    let (input, first) = factor(input)?;
+    fold_many0(
+        pair(|i| parse_operator(i, ctx, "*/"), factor),
+        move || first.clone(),
+        |left, (operator, right)| match operator {
+            '*' => AmountExpr::Mul(Box::new(left), Box::new(right)),
+            _ => AmountExpr::Div(Box::new(left), Box::new(right)),
         },
-    ))
+    )(input)
+}
+
+fn parse_expression<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, AmountExpr> {
+    // Numbers in expressions are plain numbers, not in the default commodity
+    let default_commodity = ctx.default_commodity.take();
+    if ctx.depth.get() == 0 {
+        ctx.operators.set(0);
+    }
+    let result = (|input| {
+        let term = |i| parse_expression_term(i, ctx);
+        let (input, first) = term(input)?;
+        fold_many0(
+            pair(|i| parse_operator(i, ctx, "+-"), term),
+            move || first.clone(),
+            |left, (operator, right)| match operator {
+                '+' => AmountExpr::Add(Box::new(left), Box::new(right)),
+                _ => AmountExpr::Sub(Box::new(left), Box::new(right)),
+            },
+        )(input)
+    })(input);
+    ctx.default_commodity.replace(default_commodity);
+    result
+}
+
+/// Annotation following the quantity of a posting amount.
+enum LotAnnotation<'a> {
+    Price(Price, bool),
+    Date(NaiveDate),
+    Note(&'a str),
+}
+
+fn parse_lot_annotation<'a>(
+    input: &'a str,
+    ctx: &Context,
+) -> LedgerParseResult<'a, LotAnnotation<'a>> {
+    alt((
+        (|i| parse_fixed_lot_price(i, ctx)).map(|price| LotAnnotation::Price(price, true)),
+        (|i| parse_lot_price(i, ctx)).map(|price| LotAnnotation::Price(price, false)),
+        parse_lot_date.map(LotAnnotation::Date),
+        parse_lot_note.map(LotAnnotation::Note),
+    ))(input)
+}
+
+/// Parses an amount in parentheses, in accounting notation, as its negative.
+fn parse_parenthesized_amount<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, Amount> {
+    delimited(
+        pair(char('('), space0),
+        |i| parse_amount(i, ctx),
+        pair(space0, char(')')),
+    )
+    .map(|amount| Amount {
+        quantity: -amount.quantity,
+        ..amount
+    })
+    .parse(input)
+}
+
+/// Parses an amount followed by its lot price, lot date and lot note, each
+/// at most once and in any order, then its price.
+fn parse_posting_amount<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, PostingAmount> {
+    let (mut input, amount) = if ctx.settings.parenthesized_negatives {
+        alt((
+            |i| parse_parenthesized_amount(i, ctx),
+            |i| parse_amount(i, ctx),
+        ))(input)?
+    } else {
+        parse_amount(input, ctx)?
+    };
+    let mut posting_amount = PostingAmount::from(amount);
+    while let Ok((rest, annotation)) = preceded(space0, |i| parse_lot_annotation(i, ctx))(input) {
+        match annotation {
+            LotAnnotation::Price(price, fixed) if posting_amount.lot_price.is_none() => {
+                posting_amount.lot_price = Some(price);
+                posting_amount.lot_price_fixed = fixed;
+            }
+            LotAnnotation::Date(date) if posting_amount.lot_date.is_none() => {
+                posting_amount.lot_date = Some(date);
+            }
+            LotAnnotation::Note(note) if posting_amount.lot_note.is_none() => {
+                posting_amount.lot_note = Some(note.to_owned());
+            }
+            _ => break,
+        }
+        input = rest;
+    }
+    let (input, price) = opt(preceded(space0, |i| parse_price(i, ctx)))(input)?;
+    posting_amount.price = price;
+    Ok((input, posting_amount))
+}
+
+fn parse_lot_date(input: &str) -> LedgerParseResult<'_, NaiveDate> {
+    delimited(pair(char('['), space0), parse_date, pair(space0, char(']')))(input)
 }
 
-fn parse_lot_price(input: &str) -> LedgerParseResult<Price> {
+fn parse_lot_note(input: &str) -> LedgerParseResult<'_, &str> {
+    delimited(
+        char('('),
+        verify(is_not("()\r\n").map(str::trim), |s: &str| !s.is_empty()),
+        char(')'),
+    )(input)
+}
+
+fn parse_lot_price<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, Price> {
     alt((
         delimited(
             pair(tag("{{"), space0),
-            parse_amount,
+            |i| parse_amount(i, ctx),
+            pair(space0, tag("}}")),
+        )
+        .map(Price::Total),
+        delimited(
+            pair(tag("{"), space0),
+            |i| parse_amount(i, ctx),
+            pair(space0, tag("}")),
+        )
+        .map(Price::Unit),
+    ))(input)
+}
+
+/// Parses a fixated lot price, `{=AMOUNT}` or `{{=AMOUNT}}`.
+fn parse_fixed_lot_price<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, Price> {
+    alt((
+        delimited(
+            tuple((tag("{{"), space0, char('='), space0)),
+            |i| parse_amount(i, ctx),
             pair(space0, tag("}}")),
         )
         .map(Price::Total),
-        delimited(pair(tag("{"), space0), parse_amount, pair(space0, tag("}"))).map(Price::Unit),
+        delimited(
+            tuple((tag("{"), space0, char('='), space0)),
+            |i| parse_amount(i, ctx),
+            pair(space0, tag("}")),
+        )
+        .map(Price::Unit),
+    ))(input)
+}
+
+fn parse_price<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, Price> {
+    alt((
+        preceded(pair(tag("@@"), space0), |i| parse_amount(i, ctx)).map(Price::Total),
+        preceded(pair(tag("@"), space0), |i| parse_amount(i, ctx)).map(Price::Unit),
     ))(input)
 }
 
-fn parse_price(input: &str) -> LedgerParseResult<Price> {
+fn parse_balance_kind(input: &str) -> LedgerParseResult<'_, BalanceKind> {
     alt((
-        preceded(pair(tag("@@"), space0), parse_amount).map(Price::Total),
-        preceded(pair(tag("@"), space0), parse_amount).map(Price::Unit),
+        value(BalanceKind::SoleInclusive, tag("==*")),
+        value(BalanceKind::Sole, tag("==")),
+        value(BalanceKind::SingleInclusive, tag("=*")),
+        value(BalanceKind::Single, tag("=")),
     ))(input)
 }
 
-fn parse_balance(input: &str) -> LedgerParseResult<Balance> {
+fn parse_balance<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, Balance> {
     alt((
-        parse_amount.map(Balance::Amount),
+        (|i| parse_amount(i, ctx)).map(Balance::Amount),
         value(Balance::Zero, tag("0")),
     ))(input)
 }
 
-fn parse_commodity_price(input: &str) -> LedgerParseResult<CommodityPrice> {
+fn parse_commodity_price<'a>(
+    input: &'a str,
+    ctx: &Context,
+) -> LedgerParseResult<'a, CommodityPrice> {
     let (input, _) = tag("P")(input)?;
-    let (input, datetime) = preceded(space1, parse_datetime)(input)?;
-    let (input, commodity_name) = preceded(space1, parse_commodity)(input)?;
-    let (input, amount) = preceded(space1, parse_amount)(input)?;
+    let (input, (datetime, has_time)) = preceded(
+        space1,
+        alt((
+            parse_datetime.map(|datetime| (datetime, true)),
+            parse_date.map(|date| (date.and_time(NaiveTime::MIN), false)),
+        )),
+    )(input)?;
+    let (input, commodity_name) = preceded(space1, parse_commodity_name)(input)?;
+    let (input, amount) = preceded(space1, |i| parse_amount(i, ctx))(input)?;
     let (input, _) = alt((preceded(space0, parse_inline_comment), eol_or_eof))(input)?;
 
     Ok((
         input,
         CommodityPrice {
             datetime,
+            has_time,
             commodity_name,
             amount,
         },
     ))
 }
 
-fn parse_empty_line(input: &str) -> LedgerParseResult<&str> {
+fn parse_commodity_conversion<'a>(
+    input: &'a str,
+    ctx: &Context,
+) -> LedgerParseResult<'a, CommodityConversion> {
+    let (input, _) = tag("C")(input)?;
+    let (input, from) = preceded(space1, |i| parse_amount(i, ctx))(input)?;
+    let (input, to) =
+        preceded(tuple((space0, char('='), space0)), |i| parse_amount(i, ctx))(input)?;
+    let (input, _) = alt((preceded(space0, parse_inline_comment), eol_or_eof))(input)?;
+
+    Ok((input, CommodityConversion { from, to }))
+}
+
+fn parse_empty_line(input: &str) -> LedgerParseResult<'_, &str> {
     alt((
         terminated(space0, line_ending),
         terminated(space1, eof), // Must consume something or many0 errors to prevent infinite loop
     ))(input)
 }
 
-fn parse_line_comment(input: &str) -> LedgerParseResult<&str> {
+fn parse_line_comment(input: &str) -> LedgerParseResult<'_, &str> {
     let (input, _) = delimited(
         space0,
         alt((char(';'), char('#'), char('%'), char('|'), char('*'))),
@@ -237,12 +944,49 @@ fn parse_line_comment(input: &str) -> LedgerParseResult<&str> {
     terminated(not_line_ending.map(str::trim_end), eol_or_eof)(input)
 }
 
-fn parse_inline_comment(input: &str) -> LedgerParseResult<&str> {
+fn parse_comment_hint(input: &str) -> LedgerParseResult<'_, CommentHint> {
+    let (input, marker) = preceded(pair(space0, char(';')), one_of("~>"))(input)?;
+    let (input, text) = preceded(space0, terminated(not_line_ending, eol_or_eof))(input)?;
+    Ok((
+        input,
+        CommentHint {
+            marker,
+            text: text.trim_end().to_owned(),
+        },
+    ))
+}
+
+/// Parses a block opened by a `KEYWORD` line and closed by an `end KEYWORD`
+/// line, or by the end of the input. Returns the text between the keyword
+/// and the closing line.
+fn parse_block<'a>(input: &'a str, keyword: &str) -> LedgerParseResult<'a, &'a str> {
+    let (body, _) = terminated(tag(keyword), peek(alt((space1, eol_or_eof))))(input)?;
+    // The first line holds the rest of the opening line
+    let (mut input, _) = terminated(not_line_ending, eol_or_eof)(body)?;
+    while !input.is_empty() {
+        let text = &body[..body.len() - input.len()];
+        if let Ok((input, _)) = tuple((tag("end"), space1, tag(keyword), space0, eol_or_eof))(input)
+        {
+            return Ok((input, text));
+        }
+        (input, _) = terminated(not_line_ending, eol_or_eof)(input)?;
+    }
+    Ok((input, body))
+}
+
+fn parse_block_comment(input: &str) -> LedgerParseResult<'_, &str> {
+    let (input, text) = parse_block(input, "comment")?;
+    // Nothing may follow `comment` on its line
+    let (body, _) = pair(space0, eol_or_eof)(text)?;
+    Ok((input, body))
+}
+
+fn parse_inline_comment(input: &str) -> LedgerParseResult<'_, &str> {
     let (input, _) = terminated(tag(";"), space0)(input)?;
     terminated(not_line_ending.map(str::trim_end), eol_or_eof)(input)
 }
 
-fn parse_include_file(input: &str) -> LedgerParseResult<&str> {
+fn parse_include_file(input: &str) -> LedgerParseResult<'_, &str> {
     let (input, _) = delimited(space0, tag("include"), space1)(input)?;
     verify(
         terminated(not_line_ending, eol_or_eof).map(str::trim_end),
@@ -250,43 +994,45 @@ fn parse_include_file(input: &str) -> LedgerParseResult<&str> {
     )(input)
 }
 
-fn take_until_hard_separator(input: &str) -> LedgerParseResult<&str> {
-    let mut second_space = false;
-    for (pos, c) in input.char_indices() {
-        if c == '\t' || c == '\r' || c == '\n' {
-            if pos > 0 {
-                let (found, rest) = if second_space {
-                    input.split_at(pos - 1)
-                } else {
-                    input.split_at(pos)
-                };
-                return Ok((rest, found));
-            } else {
-                return Err(Err::Incomplete(Needed::new(1)));
-            }
-        }
-
-        if c == ' ' {
-            if second_space {
-                let (found, rest) = input.split_at(pos - 1);
-                return Ok((rest, found));
-            } else {
-                second_space = true;
+/// Takes the text up to a hard separator, that is a tab or `spaces` spaces
+/// in a row, or up to the end of the line, leaving out the blanks before
+/// it.
+fn take_until_separator(input: &str, spaces: usize) -> LedgerParseResult<'_, &str> {
+    let line = &input[..input.find(['\r', '\n']).unwrap_or(input.len())];
+    let mut end = line.len();
+    let mut run = 0;
+    for (pos, c) in line.char_indices() {
+        match c {
+            '\t' => {
+                end = pos;
+                break;
             }
-        } else {
-            second_space = false;
-
-            if pos == input.len() - 1 && pos > 0 {
-                return Ok(("", input));
+            ' ' => {
+                run += 1;
+                if run >= spaces.max(1) {
+                    end = pos + 1 - run;
+                    break;
+                }
             }
+            _ => run = 0,
         }
     }
+    let found = line[..end].trim_end_matches([' ', '\t']);
+    if found.is_empty() {
+        return Err(Err::Error(VerboseError::from_error_kind(
+            input,
+            ErrorKind::TakeUntil,
+        )));
+    }
+    Ok((&input[found.len()..], found))
+}
 
-    Err(Err::Incomplete(Needed::new(1)))
+fn take_until_hard_separator(input: &str) -> LedgerParseResult<'_, &str> {
+    take_until_separator(input, 2)
 }
 
-fn parse_account(input: &str) -> LedgerParseResult<(&str, Reality)> {
-    let (input, name) = take_until_hard_separator(input)?;
+fn parse_account<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, (&'a str, Reality)> {
+    let (input, name) = take_until_separator(input, ctx.settings.separator_spaces)?;
 
     if let Some(n1) = name.strip_prefix('[') {
         if let Some(n2) = n1.strip_suffix(']') {
@@ -303,47 +1049,113 @@ fn parse_account(input: &str) -> LedgerParseResult<(&str, Reality)> {
     Ok((input, (name, Reality::Real)))
 }
 
-fn parse_transaction_status(input: &str) -> LedgerParseResult<TransactionStatus> {
+fn parse_transaction_status<'a>(
+    input: &'a str,
+    ctx: &Context,
+) -> LedgerParseResult<'a, TransactionStatus> {
     alt((
         value(TransactionStatus::Cleared, char('*')),
         value(TransactionStatus::Pending, char('!')),
+        satisfy(|c| ctx.settings.status_flags.contains(&c)).map(TransactionStatus::Custom),
     ))(input)
 }
 
-fn parse_posting(input: &str) -> LedgerParseResult<Posting> {
+/// Parses a posting, recording where it is in the input.
+fn parse_recorded_posting<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, Posting> {
+    let (rest, posting) = parse_posting(input, ctx)?;
+    if let Some(spans) = ctx.posting_spans.borrow_mut().as_mut() {
+        spans.push((input.len(), rest.len()));
+    }
+    Ok((rest, posting))
+}
+
+pub(crate) fn parse_posting<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, Posting> {
     let (input, _) = space1(input)?;
-    let (input, status) = opt(parse_transaction_status)(input)?;
+    let (input, status) = opt(|i| parse_transaction_status(i, ctx))(input)?;
     let (input, _) = space0(input)?;
-    let (input, (account, reality)) = parse_account(input)?;
-    let (input, amount) = opt(preceded(space0, parse_posting_amount))(input)?;
-    let (input, balance) =
-        opt(preceded(delimited(space0, tag("="), space0), parse_balance))(input)?;
+    let (input, (account, reality)) = parse_account(input, ctx)?;
+    if let Some((_, last_word)) = account.rsplit_once(' ') {
+        if matches!(parse_amount(last_word, &Context::default()), Ok(("", _))) {
+            ctx.warn(
+                input,
+                ParseWarningKind::SingleSpaceBeforeAmount {
+                    account: account.to_owned(),
+                },
+            );
+        }
+    }
+    let negative = ctx.settings.parenthesized_negatives
+        && preceded(space0, |i| parse_parenthesized_amount(i, ctx))(input).is_ok();
+    let (input, amount_expr) = if negative {
+        (input, None)
+    } else {
+        opt(preceded(
+            space0,
+            delimited(
+                pair(char('('), space0),
+                |i| parse_expression(i, ctx),
+                pair(space0, char(')')),
+            ),
+        ))(input)?
+    };
+    let (input, amount) = match amount_expr {
+        Some(ref expr) => (
+            input,
+            expr.evaluate(&ctx.variables.borrow())
+                .ok()
+                .map(PostingAmount::from),
+        ),
+        None => opt(preceded(space0, |i| parse_posting_amount(i, ctx)))(input)?,
+    };
+    let (input, balance) = opt(pair(delimited(space0, parse_balance_kind, space0), |i| {
+        parse_balance(i, ctx)
+    }))(input)?;
+    // Past the account, the line must be a posting: report what is wrong
+    // with it where it is wrong
+    let expected = match (&balance, &amount_expr, &amount) {
+        (Some(_), _, _) => "a comment",
+        (None, None, Some(_)) => "a lot price, price, balance assertion or comment",
+        (None, _, _) => "an amount, balance assertion or comment",
+    };
+    let (balance_kind, balance) = match balance {
+        Some((kind, balance)) => (kind, Some(balance)),
+        None => (BalanceKind::Single, None),
+    };
     let (input, _) = space0(input)?;
-    let (input, inline_comment) =
-        alt((parse_inline_comment.map(Some), value(None, eol_or_eof)))(input)?;
+    let (input, inline_comment) = cut(context(
+        expected,
+        alt((parse_inline_comment.map(Some), value(None, eol_or_eof))),
+    ))(input)?;
     let (input, line_comments) = many0(parse_line_comment)(input)?;
+    let comment = join_comments(inline_comment, line_comments);
+    let tags = comment_tags(comment.as_deref());
 
     Ok((
         input,
         Posting {
-            account: account.to_owned(),
+            account: ctx.account_name(account),
             reality,
             amount,
+            amount_expr,
             balance,
+            balance_kind,
             status,
-            comment: join_comments(inline_comment, line_comments),
+            date: tag_date(&tags, "date"),
+            effective_date: tag_date(&tags, "date2"),
+            comment,
+            tags,
         },
     ))
 }
 
-fn parse_payee(input: &str) -> LedgerParseResult<&str> {
+fn parse_payee(input: &str) -> LedgerParseResult<'_, &str> {
     alt((
         terminated(take_until_hard_separator, peek(pair(space1, tag(";")))),
         not_line_ending,
     ))(input)
 }
 
-fn parse_period(input: &str) -> LedgerParseResult<Period> {
+fn parse_period(input: &str) -> LedgerParseResult<'_, Period> {
     alt((
         value(Period::Daily, tag("daily")),
         value(Period::Weekly, tag("weekly")),
@@ -367,7 +1179,7 @@ fn parse_period(input: &str) -> LedgerParseResult<Period> {
 fn every_n_parser<'a, F>(
     period_str: &'static str,
     variant: F,
-) -> impl Fn(&'a str) -> LedgerParseResult<Period>
+) -> impl Fn(&'a str) -> LedgerParseResult<'a, Period>
 where
     F: Fn(u32) -> Period,
 {
@@ -385,7 +1197,10 @@ where
     }
 }
 
-fn parse_periodic_transaction(input: &str) -> LedgerParseResult<PeriodicTransaction> {
+fn parse_periodic_transaction<'a>(
+    input: &'a str,
+    ctx: &Context,
+) -> LedgerParseResult<'a, PeriodicTransaction> {
     let (input, _) = tag("~")(input)?;
     let (input, _) = space1(input)?;
     let (input, period) = parse_period(input)?;
@@ -399,7 +1214,7 @@ fn parse_periodic_transaction(input: &str) -> LedgerParseResult<PeriodicTransact
     let (input, inline_comment) =
         alt((parse_inline_comment.map(Some), value(None, eol_or_eof)))(input)?;
     let (input, line_comments) = many0(parse_line_comment)(input)?;
-    let (input, postings) = many1(parse_posting)(input)?;
+    let (input, postings) = many1(|i| parse_recorded_posting(i, ctx))(input)?;
 
     Ok((
         input,
@@ -413,142 +1228,1077 @@ fn parse_periodic_transaction(input: &str) -> LedgerParseResult<PeriodicTransact
     ))
 }
 
-fn parse_transaction(input: &str) -> LedgerParseResult<Transaction> {
-    let (input, date) = parse_date(input)?;
-    let (input, effective_date) = opt(preceded(tag("="), parse_date))(input)?;
-    let (input, status) = opt(preceded(space1, parse_transaction_status))(input)?;
-    let (input, code) = opt(preceded(
-        space1,
-        delimited(char('('), is_not(")"), char(')')),
-    ))(input)?;
-    let (input, description) = preceded(space1, parse_payee)(input)?;
-    let (input, _) = space0(input)?;
-    let (input, inline_comment) =
-        alt((parse_inline_comment.map(Some), value(None, eol_or_eof)))(input)?;
-    let (input, line_comments) = many0(parse_line_comment)(input)?;
-    let (input, postings) = many1(parse_posting)(input)?;
+/// Word of a query: anything up to whitespace, a parenthesis or a comment.
+fn query_word(input: &str) -> LedgerParseResult<'_, &str> {
+    take_while1(|c: char| !c.is_whitespace() && !"();".contains(c))(input)
+}
 
-    Ok((
-        input,
-        Transaction {
+fn parse_query_term<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, Query> {
+    ctx.nested(input, |input| query_term(input, ctx))
+}
+
+fn query_term<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, Query> {
+    alt((
+        delimited(
+            pair(char('('), space0),
+            |i| parse_query(i, ctx),
+            pair(space0, char(')')),
+        ),
+        preceded(
+            alt((terminated(tag("not"), space1), terminated(tag("!"), space0))),
+            |i| parse_query_term(i, ctx),
+        )
+        .map(|query| Query::Not(Box::new(query))),
+        preceded(
+            pair(tag("expr"), space1),
+            delimited(char('\''), is_not("'\n"), char('\'')),
+        )
+        .map(|expression: &str| Query::Expression(expression.to_owned())),
+        preceded(char('@'), query_word).map(|payee| Query::Payee(payee.to_owned())),
+        preceded(
+            char('%'),
+            pair(
+                take_while1(|c: char| !c.is_whitespace() && !"()=;".contains(c)),
+                opt(preceded(char('='), query_word)),
+            ),
+        )
+        .map(|(name, value)| Query::Tag(name.to_owned(), value.map(str::to_owned))),
+        delimited(char('/'), is_not("/\n"), char('/'))
+            .map(|pattern: &str| Query::Account(pattern.to_owned())),
+        verify(query_word, |word: &str| {
+            !["and", "or", "not", "expr"].contains(&word)
+        })
+        .map(|pattern| Query::Account(pattern.to_owned())),
+    ))(input)
+}
+
+fn parse_query<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, Query> {
+    fn combine(first: Query, mut rest: Vec<Query>, f: fn(Vec<Query>) -> Query) -> Query {
+        if rest.is_empty() {
+            first
+        } else {
+            rest.insert(0, first);
+            f(rest)
+        }
+    }
+
+    let and = |input| {
+        let (input, first) = parse_query_term(input, ctx)?;
+        let (input, rest) = many0(preceded(
+            tuple((space1, alt((tag("and"), tag("&"))), space1)),
+            |i| parse_query_term(i, ctx),
+        ))(input)?;
+        Ok((input, combine(first, rest, Query::And)))
+    };
+    let (input, first) = and(input)?;
+    let (input, rest) = many0(preceded(
+        pair(space1, opt(terminated(alt((tag("or"), tag("|"))), space1))),
+        and,
+    ))(input)?;
+    Ok((input, combine(first, rest, Query::Or)))
+}
+
+fn parse_automated_transaction<'a>(
+    input: &'a str,
+    ctx: &Context,
+) -> LedgerParseResult<'a, AutomatedTransaction> {
+    let (input, _) = pair(tag("="), space0)(input)?;
+    let (input, query) = parse_query(input, ctx)?;
+    let (input, _) = space0(input)?;
+    let (input, inline_comment) =
+        alt((parse_inline_comment.map(Some), value(None, eol_or_eof)))(input)?;
+    let (input, line_comments) = many0(parse_line_comment)(input)?;
+
+    // Amounts without a commodity are multipliers
+    let multiplier = Commodity {
+        name: String::new(),
+        position: CommodityPosition::Left,
+    };
+    let default_commodity = ctx.default_commodity.replace(Some(multiplier));
+    let postings = many1(|i| parse_recorded_posting(i, ctx))(input);
+    ctx.default_commodity.replace(default_commodity);
+    let (input, postings) = postings?;
+
+    Ok((
+        input,
+        AutomatedTransaction {
+            query,
             comment: join_comments(inline_comment, line_comments),
+            postings,
+        },
+    ))
+}
+
+pub(crate) fn parse_transaction<'a>(
+    input: &'a str,
+    ctx: &Context,
+) -> LedgerParseResult<'a, Transaction> {
+    let (input, (date, date_style)) = parse_transaction_date(input, ctx)?;
+    let (input, effective_date) = opt(preceded(tag("="), |i| parse_transaction_date(i, ctx)))
+        .map(|date| date.map(|(date, _)| date))
+        .parse(input)?;
+    let (input, status) = opt(preceded(space1, |i| parse_transaction_status(i, ctx)))(input)?;
+    let (input, code) = opt(preceded(
+        space1,
+        delimited(char('('), is_not(")"), char(')')),
+    ))(input)?;
+    let (input, description) = alt((
+        // Comment right after the date, status or code, without description
+        value("", peek(pair(space1, char(';')))),
+        preceded(space1, parse_payee),
+    ))(input)?;
+    let (input, _) = space0(input)?;
+    let (input, inline_comment) =
+        alt((parse_inline_comment.map(Some), value(None, eol_or_eof)))(input)?;
+    let (input, line_comments) = many0(parse_line_comment)(input)?;
+    let (input, postings) = cut(context(
+        "a posting",
+        many1(|i| parse_recorded_posting(i, ctx)),
+    ))(input)?;
+    let comment = join_comments(inline_comment, line_comments);
+    let tags = comment_tags(comment.as_deref());
+
+    Ok((
+        input,
+        Transaction {
+            comment,
             date,
             effective_date,
+            date_style,
             status,
             code: code.map(str::to_owned),
-            description: description.to_owned(),
+            description: ctx.name(description),
             postings,
+            tags,
+        },
+    ))
+}
+
+/// Argument of a directive: the rest of the line, which must not be empty.
+fn directive_argument(input: &str) -> LedgerParseResult<'_, String> {
+    preceded(
+        space1,
+        verify(not_line_ending.map(str::trim_end), |s: &str| !s.is_empty()),
+    )
+    .map(str::to_owned)
+    .parse(input)
+}
+
+fn parse_account_subdirective(input: &str) -> LedgerParseResult<'_, AccountSubdirective> {
+    delimited(
+        space1,
+        alt((
+            preceded(tag("note"), directive_argument).map(AccountSubdirective::Note),
+            preceded(tag("alias"), directive_argument).map(AccountSubdirective::Alias),
+            preceded(tag("payee"), directive_argument).map(AccountSubdirective::Payee),
+            preceded(tag("check"), directive_argument).map(AccountSubdirective::Check),
+            preceded(tag("assert"), directive_argument).map(AccountSubdirective::Assert),
+            value(AccountSubdirective::Default, pair(tag("default"), space0)),
+        )),
+        eol_or_eof,
+    )(input)
+}
+
+fn parse_account_declaration<'a>(
+    input: &'a str,
+    ctx: &Context,
+) -> LedgerParseResult<'a, AccountDeclaration> {
+    let (input, _) = terminated(tag("account"), space1)(input)?;
+    let (input, name) = verify(parse_payee.map(str::trim_end), |s: &str| !s.is_empty())(input)?;
+    let (input, _) = space0(input)?;
+    let (input, comment) = alt((parse_inline_comment.map(Some), value(None, eol_or_eof)))(input)?;
+    let (input, subdirectives) = many0(parse_account_subdirective)(input)?;
+
+    Ok((
+        input,
+        AccountDeclaration {
+            name: ctx.account_name(name),
+            comment: comment.map(str::to_owned),
+            subdirectives,
+        },
+    ))
+}
+
+fn parse_commodity_subdirective(input: &str) -> LedgerParseResult<'_, CommoditySubdirective> {
+    delimited(
+        space1,
+        alt((
+            preceded(tag("note"), directive_argument).map(CommoditySubdirective::Note),
+            preceded(tag("format"), directive_argument).map(CommoditySubdirective::Format),
+            preceded(tag("alias"), directive_argument).map(CommoditySubdirective::Alias),
+            value(
+                CommoditySubdirective::NoMarket,
+                pair(tag("nomarket"), space0),
+            ),
+            value(CommoditySubdirective::Default, pair(tag("default"), space0)),
+        )),
+        eol_or_eof,
+    )(input)
+}
+
+fn parse_commodity_declaration(input: &str) -> LedgerParseResult<'_, CommodityDeclaration> {
+    let (input, _) = terminated(tag("commodity"), space1)(input)?;
+    let (input, name) = verify(parse_payee.map(str::trim_end), |s: &str| !s.is_empty())(input)?;
+    let (input, _) = space0(input)?;
+    let (input, comment) = alt((parse_inline_comment.map(Some), value(None, eol_or_eof)))(input)?;
+    let (input, subdirectives) = many0(parse_commodity_subdirective)(input)?;
+
+    Ok((
+        input,
+        CommodityDeclaration {
+            name: name.to_owned(),
+            comment: comment.map(str::to_owned),
+            subdirectives,
+        },
+    ))
+}
+
+fn parse_payee_subdirective(input: &str) -> LedgerParseResult<'_, PayeeSubdirective> {
+    delimited(
+        space1,
+        alt((
+            preceded(tag("alias"), directive_argument).map(PayeeSubdirective::Alias),
+            preceded(tag("uuid"), directive_argument).map(PayeeSubdirective::Uuid),
+        )),
+        eol_or_eof,
+    )(input)
+}
+
+fn parse_payee_declaration<'a>(
+    input: &'a str,
+    ctx: &Context,
+) -> LedgerParseResult<'a, PayeeDeclaration> {
+    let (input, _) = terminated(tag("payee"), space1)(input)?;
+    let (input, name) = verify(parse_payee.map(str::trim_end), |s: &str| !s.is_empty())(input)?;
+    let (input, _) = space0(input)?;
+    let (input, comment) = alt((parse_inline_comment.map(Some), value(None, eol_or_eof)))(input)?;
+    let (input, subdirectives) = many0(parse_payee_subdirective)(input)?;
+
+    Ok((
+        input,
+        PayeeDeclaration {
+            name: ctx.name(name),
+            comment: comment.map(str::to_owned),
+            subdirectives,
+        },
+    ))
+}
+
+fn parse_tag_subdirective(input: &str) -> LedgerParseResult<'_, TagSubdirective> {
+    delimited(
+        space1,
+        alt((
+            preceded(tag("check"), directive_argument).map(TagSubdirective::Check),
+            preceded(tag("assert"), directive_argument).map(TagSubdirective::Assert),
+        )),
+        eol_or_eof,
+    )(input)
+}
+
+fn parse_tag_declaration(input: &str) -> LedgerParseResult<'_, TagDeclaration> {
+    let (input, _) = terminated(tag("tag"), space1)(input)?;
+    let (input, name) = verify(parse_payee.map(str::trim_end), |s: &str| !s.is_empty())(input)?;
+    let (input, _) = space0(input)?;
+    let (input, comment) = alt((parse_inline_comment.map(Some), value(None, eol_or_eof)))(input)?;
+    let (input, subdirectives) = many0(parse_tag_subdirective)(input)?;
+
+    Ok((
+        input,
+        TagDeclaration {
+            name: name.to_owned(),
+            comment: comment.map(str::to_owned),
+            subdirectives,
+        },
+    ))
+}
+
+fn parse_account_alias<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, AccountAlias> {
+    let (input, _) = terminated(tag("alias"), space1)(input)?;
+    let (input, alias) =
+        verify(is_not("=\r\n").map(str::trim_end), |s: &str| !s.is_empty())(input)?;
+    let (input, _) = tag("=")(input)?;
+    let (input, account) = terminated(
+        verify(not_line_ending.map(str::trim), |s: &str| !s.is_empty()),
+        eol_or_eof,
+    )(input)?;
+
+    let mut alias = AccountAlias {
+        alias: alias.to_owned(),
+        account: ctx.account_name(account),
+    };
+    if alias.pattern().is_none() {
+        alias.alias = ctx.account_name(&alias.alias);
+    }
+    Ok((input, alias))
+}
+
+fn parse_account_capture<'a>(
+    input: &'a str,
+    ctx: &Context,
+) -> LedgerParseResult<'a, AccountCapture> {
+    let (input, _) = terminated(tag("capture"), space1)(input)?;
+    let (input, account) = preceded(peek(none_of("\r\n")), take_until_hard_separator)(input)?;
+    let (input, pattern) = delimited(
+        space1,
+        verify(not_line_ending.map(str::trim_end), |s: &str| !s.is_empty()),
+        eol_or_eof,
+    )(input)?;
+
+    Ok((
+        input,
+        AccountCapture {
+            account: ctx.account_name(account),
+            pattern: pattern.to_owned(),
+        },
+    ))
+}
+
+fn parse_end_aliases(input: &str) -> LedgerParseResult<'_, &str> {
+    terminated(
+        recognize(tuple((tag("end"), space1, tag("aliases")))),
+        pair(space0, eol_or_eof),
+    )(input)
+}
+
+fn parse_apply_tag(input: &str) -> LedgerParseResult<'_, &str> {
+    let (input, _) = tuple((tag("apply"), space1, tag("tag")))(input)?;
+    preceded(
+        space1,
+        terminated(
+            verify(not_line_ending.map(str::trim_end), |s: &str| !s.is_empty()),
+            eol_or_eof,
+        ),
+    )(input)
+}
+
+fn parse_year(input: &str) -> LedgerParseResult<'_, i32> {
+    let (input, _) = terminated(alt((tag("year"), tag("Y"))), space1)(input)?;
+    terminated(number_n(4), pair(space0, eol_or_eof))(input)
+}
+
+fn parse_default_commodity<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, String> {
+    let (input, _) = tag("D")(input)?;
+    terminated(
+        verify(directive_argument, |format: &str| {
+            format_from_str(format, &ctx.settings).is_some()
+        }),
+        eol_or_eof,
+    )(input)
+}
+
+fn parse_no_market(input: &str) -> LedgerParseResult<'_, String> {
+    let (input, _) = tag("N")(input)?;
+    terminated(
+        preceded(space1, parse_commodity_name),
+        pair(space0, eol_or_eof),
+    )(input)
+}
+
+fn parse_bucket<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, String> {
+    let (input, _) = alt((tag("bucket"), tag("A")))(input)?;
+    terminated(directive_argument, eol_or_eof)
+        .map(|account| ctx.account_name(&account))
+        .parse(input)
+}
+
+fn parse_definition(input: &str) -> LedgerParseResult<'_, Definition> {
+    let (input, _) = terminated(tag("define"), space1)(input)?;
+    let (input, name) = take_while1(|c: char| c.is_alphanumeric() || c == '_')(input)?;
+    let (input, _) = tuple((space0, char('='), space0))(input)?;
+    let (input, value) = terminated(
+        verify(not_line_ending.map(str::trim_end), |s: &str| !s.is_empty()),
+        eol_or_eof,
+    )(input)?;
+
+    Ok((
+        input,
+        Definition {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        },
+    ))
+}
+
+fn parse_event(input: &str) -> LedgerParseResult<'_, Event> {
+    let (input, _) = terminated(tag("event"), space1)(input)?;
+    let (input, date) = terminated(parse_date, space1)(input)?;
+    let (input, description) = terminated(
+        verify(not_line_ending.map(str::trim_end), |s: &str| !s.is_empty()),
+        eol_or_eof,
+    )(input)?;
+
+    Ok((
+        input,
+        Event {
+            date,
+            description: description.to_owned(),
         },
     ))
 }
 
-fn parse_ledger_item(input: &str) -> LedgerParseResult<LedgerItem> {
+fn parse_note(input: &str) -> LedgerParseResult<'_, &str> {
+    let (input, _) = terminated(tag("note"), space1)(input)?;
+    terminated(
+        verify(not_line_ending.map(str::trim_end), |s: &str| !s.is_empty()),
+        eol_or_eof,
+    )(input)
+}
+
+/// `check`, `assert`, `eval` and `expr` directives, with their value
+/// expression.
+fn parse_value_directive(input: &str) -> LedgerParseResult<'_, LedgerItem> {
     alt((
-        value(LedgerItem::EmptyLine, parse_empty_line),
-        parse_line_comment
-            .map(str::to_owned)
-            .map(LedgerItem::LineComment),
-        parse_transaction.map(LedgerItem::Transaction),
-        parse_commodity_price.map(LedgerItem::CommodityPrice),
-        parse_periodic_transaction.map(LedgerItem::PeriodicTransaction),
-        parse_include_file
-            .map(str::to_owned)
-            .map(LedgerItem::Include),
+        preceded(tag("check"), directive_argument).map(LedgerItem::Check),
+        preceded(tag("assert"), directive_argument).map(LedgerItem::Assert),
+        preceded(tag("eval"), directive_argument).map(LedgerItem::Eval),
+        preceded(tag("expr"), directive_argument).map(LedgerItem::Expr),
     ))(input)
 }
 
-pub fn parse_ledger(input: &str) -> LedgerParseResult<Ledger> {
-    let (input, items) = many0(parse_ledger_item)(input)?;
-    let (input, _) = eof(input)?;
+fn parse_decimal_mark(input: &str) -> LedgerParseResult<'_, char> {
+    delimited(
+        pair(tag("decimal-mark"), space1),
+        one_of(".,"),
+        pair(space0, eol_or_eof),
+    )(input)
+}
 
-    Ok((input, Ledger { items }))
+fn parse_end_apply_tag(input: &str) -> LedgerParseResult<'_, &str> {
+    terminated(
+        recognize(tuple((
+            tag("end"),
+            space1,
+            opt(pair(tag("apply"), space1)),
+            tag("tag"),
+        ))),
+        pair(space0, eol_or_eof),
+    )(input)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use nom::{
-        error::{ErrorKind, ParseError},
-        Err::Error,
+/// Time of a timeclock entry, `HH:MM` or `HH:MM:SS`.
+fn parse_clock_time(input: &str) -> LedgerParseResult<'_, NaiveTime> {
+    map_opt(
+        tuple((
+            terminated(number_n(2), tag(":")),
+            number_n(2),
+            opt(preceded(tag(":"), number_n(2))),
+        )),
+        |(hour, minute, second)| {
+            NaiveTime::from_hms_opt(hour as u32, minute as u32, second.unwrap_or(0) as u32)
+        },
+    )(input)
+}
+
+/// Rest of a timeclock line, split at the first two spaces or tab.
+fn parse_clock_text(input: &str) -> LedgerParseResult<'_, (&str, Option<&str>)> {
+    let (input, text) = terminated(not_line_ending.map(str::trim), eol_or_eof)(input)?;
+    let separator = [text.find("  "), text.find('\t')]
+        .into_iter()
+        .flatten()
+        .min();
+    let text = match separator {
+        Some(index) => (text[..index].trim_end(), Some(text[index..].trim_start())),
+        None => (text, None),
     };
+    Ok((input, text))
+}
 
-    #[test]
-    fn parse_date_test() {
-        assert_eq!(
-            parse_date("2017-03-24"),
-            Ok(("", NaiveDate::from_ymd(2017, 3, 24)))
-        );
-        assert_eq!(
-            parse_date("2017/03/24"),
-            Ok(("", NaiveDate::from_ymd(2017, 3, 24)))
-        );
-        assert_eq!(
-            parse_date("2017.03.24"),
-            Ok(("", NaiveDate::from_ymd(2017, 3, 24)))
-        );
-        assert_eq!(
-            parse_date("2017-13-24"),
-            Err(Error(ParseError::from_error_kind(
-                "2017-13-24",
-                ErrorKind::MapOpt
-            )))
-        );
-    }
+fn parse_clock_datetime(input: &str) -> LedgerParseResult<'_, NaiveDateTime> {
+    preceded(
+        space1,
+        separated_pair(parse_date, space1, parse_clock_time)
+            .map(|(date, time)| date.and_time(time)),
+    )(input)
+}
 
-    #[test]
-    fn parse_datetime_test() {
-        assert_eq!(
-            parse_datetime("2017-03-24 17:15:23"),
-            Ok(("", NaiveDate::from_ymd(2017, 3, 24).and_hms(17, 15, 23)))
-        );
-        assert_eq!(
-            parse_datetime("2017-13-24 22:11:22"),
-            Err(Error(ParseError::from_error_kind(
-                "2017-13-24 22:11:22",
-                ErrorKind::MapOpt
-            )))
-        );
-        assert_eq!(
-            parse_datetime("2017-03-24 25:11:22"),
-            Err(Error(ParseError::from_error_kind(
-                "2017-03-24 25:11:22",
-                ErrorKind::MapOpt
-            )))
-        );
-    }
+fn parse_clock_in(input: &str) -> LedgerParseResult<'_, TimeclockEntry> {
+    let (input, _) = char('i')(input)?;
+    let (input, datetime) = parse_clock_datetime(input)?;
+    let (input, (account, description)) = preceded(
+        space1,
+        verify(parse_clock_text, |(account, _): &(&str, _)| {
+            !account.is_empty()
+        }),
+    )(input)?;
+    Ok((
+        input,
+        TimeclockEntry::In {
+            datetime,
+            account: account.to_owned(),
+            description: description.map(str::to_owned),
+        },
+    ))
+}
 
-    #[test]
-    fn parse_quantity_test() {
-        assert_eq!(parse_quantity("1000"), Ok(("", Decimal::new(1000, 0))));
-        assert_eq!(parse_quantity("2.02"), Ok(("", Decimal::new(202, 2))));
-        assert_eq!(parse_quantity("-12.13"), Ok(("", Decimal::new(-1213, 2))));
-        assert_eq!(parse_quantity("0.1"), Ok(("", Decimal::new(1, 1))));
-        assert_eq!(parse_quantity("3"), Ok(("", Decimal::new(3, 0))));
-        assert_eq!(parse_quantity("1"), Ok(("", Decimal::new(1, 0))));
-        assert_eq!(parse_quantity("1,000"), Ok(("", Decimal::new(1000, 0))));
-        assert_eq!(
-            parse_quantity("12,456,132.14"),
-            Ok(("", Decimal::new(1245613214, 2)))
-        );
-    }
+fn parse_clock_out(input: &str) -> LedgerParseResult<'_, TimeclockEntry> {
+    let (input, kind) = one_of("oO")(input)?;
+    let (input, datetime) = parse_clock_datetime(input)?;
+    let (input, note) = alt((preceded(space1, not_line_ending.map(str::trim)), space0))(input)?;
+    let (input, _) = eol_or_eof(input)?;
+    Ok((
+        input,
+        TimeclockEntry::Out {
+            datetime,
+            cleared: kind == 'O',
+            note: Some(note.to_owned()).filter(|note| !note.is_empty()),
+        },
+    ))
+}
 
-    #[test]
-    fn parse_commodity_test() {
-        assert_eq!(
-            parse_commodity("\"ABC 123\""),
-            Ok(("", "ABC 123".to_owned()))
-        );
-        assert_eq!(parse_commodity("ABC "), Ok((" ", "ABC".to_owned())));
-        assert_eq!(parse_commodity("$1"), Ok(("1", "$".to_owned())));
-        assert_eq!(parse_commodity("€1"), Ok(("1", "€".to_owned())));
-        assert_eq!(parse_commodity("€ "), Ok((" ", "€".to_owned())));
-        assert_eq!(parse_commodity("€-1"), Ok(("-1", "€".to_owned())));
-    }
+/// Parses timeclock entries, skipping empty and comment lines.
+pub(crate) fn parse_timeclock<'a>(
+    input: &'a str,
+    _ctx: &Context,
+) -> LedgerParseResult<'a, Vec<TimeclockEntry>> {
+    preceded(
+        parse_skipped_lines,
+        many0(terminated(
+            alt((parse_clock_in, parse_clock_out)),
+            parse_skipped_lines,
+        )),
+    )(input)
+}
+
+/// Empty and comment lines of timeclock and timedot files.
+fn parse_skipped_lines(input: &str) -> LedgerParseResult<'_, ()> {
+    fold_many0(
+        alt((parse_empty_line, parse_line_comment)),
+        || (),
+        |_, _| (),
+    )(input)
+}
+
+/// Time spent in a timedot entry, in hours: dots worth a quarter of an hour
+/// each, or a quantity with an optional unit.
+fn parse_timedot_hours(input: &str) -> LedgerParseResult<'_, Decimal> {
+    alt((
+        map_opt(
+            pair(
+                recognize(pair(digit1, opt(pair(char('.'), digit1)))),
+                take_while(char::is_alphabetic),
+            ),
+            |(quantity, unit)| {
+                Decimal::from_str(quantity)
+                    .ok()?
+                    .checked_mul(unit_hours(unit)?)
+            },
+        ),
+        fold_many1(
+            terminated(char('.'), space0),
+            || Decimal::ZERO,
+            |hours, _| hours + Decimal::new(25, 2),
+        ),
+    ))(input)
+}
+
+fn parse_timedot_entry(input: &str) -> LedgerParseResult<'_, (&str, Decimal)> {
+    let (input, _) = pair(space0, not(parse_date))(input)?;
+    let (input, account) = recognize(many1(alt((
+        is_not(" \t\r\n"),
+        terminated(tag(" "), peek(is_not(" \t\r\n"))),
+    ))))(input)?;
+    let (input, _) = alt((tag("\t"), tag("  ")))(input)?;
+    let (input, hours) = preceded(space0, parse_timedot_hours)(input)?;
+    let (input, _) = pair(space0, eol_or_eof)(input)?;
+    Ok((input, (account, hours)))
+}
+
+fn parse_timedot_day(input: &str) -> LedgerParseResult<'_, Vec<LedgerItem>> {
+    let (input, date) = parse_date(input)?;
+    let (input, description) = terminated(
+        alt((preceded(space1, not_line_ending.map(str::trim)), space0)),
+        eol_or_eof,
+    )(input)?;
+    let (input, _) = parse_skipped_lines(input)?;
+    let (input, entries) = many0(terminated(parse_timedot_entry, parse_skipped_lines))(input)?;
+    let items = entries
+        .into_iter()
+        .map(|(account, hours)| timedot_transaction(date, description, account, hours))
+        .collect();
+    Ok((input, items))
+}
+
+/// Parses a timedot file into a ledger of one transaction per entry.
+pub(crate) fn parse_timedot<'a>(input: &'a str, _ctx: &Context) -> LedgerParseResult<'a, Ledger> {
+    let (input, _) = parse_skipped_lines(input)?;
+    let (input, days) = many0(parse_timedot_day)(input)?;
+    Ok((
+        input,
+        Ledger {
+            items: days.into_iter().flatten().collect(),
+        },
+    ))
+}
+
+/// Empty and comment lines of CSV rules files. `%` starts a field matcher
+/// there, not a comment.
+fn parse_csv_skipped_lines(input: &str) -> LedgerParseResult<'_, ()> {
+    fold_many0(
+        alt((
+            parse_empty_line,
+            recognize(tuple((space0, one_of("#;*"), not_line_ending, eol_or_eof))),
+        )),
+        || (),
+        |_, _| (),
+    )(input)
+}
+
+/// Rest of a CSV rules line, trimmed.
+fn parse_csv_value(input: &str) -> LedgerParseResult<'_, &str> {
+    terminated(not_line_ending.map(str::trim), eol_or_eof)(input)
+}
+
+fn parse_csv_skip(input: &str) -> LedgerParseResult<'_, usize> {
+    let (input, _) = tag("skip")(input)?;
+    let (input, count) = alt((
+        preceded(space1, map_res(digit1, usize::from_str)),
+        value(1, peek(alt((space0, eol_or_eof)))),
+    ))(input)?;
+    let (input, _) = pair(space0, eol_or_eof)(input)?;
+    Ok((input, count))
+}
+
+fn parse_csv_field_name(input: &str) -> LedgerParseResult<'_, &str> {
+    take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-')(input)
+}
+
+fn parse_csv_assignment(input: &str) -> LedgerParseResult<'_, CsvAssignment> {
+    let (input, field) = parse_csv_field_name(input)?;
+    let (input, value) = alt((preceded(space1, parse_csv_value), value("", eol_or_eof)))(input)?;
+    Ok((
+        input,
+        CsvAssignment {
+            field: field.to_owned(),
+            value: value.to_owned(),
+        },
+    ))
+}
+
+fn parse_csv_matcher(input: &str) -> LedgerParseResult<'_, CsvMatcher> {
+    let (input, and) = opt(terminated(char('&'), space0))(input)?;
+    let (input, negated) = opt(terminated(char('!'), space0))(input)?;
+    let (input, field) = opt(delimited(char('%'), parse_csv_field_name, space1))(input)?;
+    let (input, pattern) = verify(parse_csv_value, |pattern: &str| !pattern.is_empty())(input)?;
+    Ok((
+        input,
+        CsvMatcher {
+            field: field.map(str::to_owned),
+            pattern: pattern.to_owned(),
+            negated: negated.is_some(),
+            and: and.is_some(),
+        },
+    ))
+}
+
+fn parse_csv_conditional_block(input: &str) -> LedgerParseResult<'_, CsvConditionalBlock> {
+    let (input, _) = tag("if")(input)?;
+    let (input, first) = alt((
+        preceded(space1, parse_csv_matcher).map(Some),
+        value(None, pair(space0, eol_or_eof)),
+    ))(input)?;
+    let (mut input, others) =
+        many0(preceded(not(alt((space1, eol_or_eof))), parse_csv_matcher))(input)?;
+    let matchers: Vec<CsvMatcher> = first.into_iter().chain(others).collect();
+    if matchers.is_empty() {
+        return Err(Err::Error(VerboseError::from_error_kind(
+            input,
+            ErrorKind::Many1,
+        )));
+    }
+
+    let mut block = CsvConditionalBlock {
+        matchers,
+        assignments: Vec::new(),
+        skip: None,
+        end: false,
+    };
+    loop {
+        (input, _) = parse_csv_skipped_lines(input)?;
+        let Ok((rest, _)) = space1::<_, VerboseError<&str>>(input) else {
+            break;
+        };
+        if let Ok((rest, count)) = parse_csv_skip(rest) {
+            block.skip = Some(count);
+            input = rest;
+        } else if let Ok((rest, _)) = pair(tag("end"), pair(space0, eol_or_eof))(rest) {
+            block.end = true;
+            input = rest;
+        } else {
+            let (rest, assignment) = parse_csv_assignment(rest)?;
+            block.assignments.push(assignment);
+            input = rest;
+        }
+    }
+    Ok((input, block))
+}
+
+fn parse_csv_rule(input: &str) -> LedgerParseResult<'_, CsvRule> {
+    alt((
+        parse_csv_skip.map(CsvRule::Skip),
+        preceded(
+            pair(tag("fields"), space1),
+            parse_csv_value.map(|fields| {
+                CsvRule::Fields(
+                    fields
+                        .split(',')
+                        .map(|field| field.trim().to_owned())
+                        .collect(),
+                )
+            }),
+        ),
+        parse_include_file.map(|file| CsvRule::Include(file.to_owned())),
+        parse_csv_conditional_block.map(CsvRule::If),
+        parse_csv_assignment.map(|assignment| {
+            if CSV_SETTINGS.contains(&assignment.field.as_str()) {
+                CsvRule::Setting {
+                    name: assignment.field,
+                    value: Some(assignment.value).filter(|value| !value.is_empty()),
+                }
+            } else {
+                CsvRule::Assignment(assignment)
+            }
+        }),
+    ))(input)
+}
+
+/// Parses an hledger CSV rules file.
+pub(crate) fn parse_csv_rules<'a>(
+    input: &'a str,
+    _ctx: &Context,
+) -> LedgerParseResult<'a, CsvRules> {
+    preceded(
+        parse_csv_skipped_lines,
+        many0(terminated(parse_csv_rule, parse_csv_skipped_lines)),
+    )
+    .map(|rules| CsvRules { rules })
+    .parse(input)
+}
+
+/// Parses the whole of `input` with `parser`, allowing whitespace around it.
+/// Warnings are dropped.
+pub(crate) fn parse_fragment<T, F>(
+    input: &str,
+    settings: &ParserSettings,
+    mut parser: F,
+) -> Result<T, crate::ParseError>
+where
+    F: for<'a> FnMut(&'a str, &Context) -> LedgerParseResult<'a, T>,
+{
+    crate::catch_panic(|| {
+        let ctx = Context::new(settings.clone());
+        let input = input.trim_end();
+        let result = terminated(|i| parser(i, &ctx), eof)(input);
+        match result {
+            Ok((_, value)) => Ok(value),
+            Err(error) => Err(crate::nom_error(input, error)),
+        }
+    })
+}
+
+/// Parses the whole of `input` as a value expression, without the
+/// surrounding parentheses.
+pub(crate) fn expression_from_str(input: &str) -> Option<AmountExpr> {
+    match parse_expression(input.trim(), &Context::default()) {
+        Ok(("", expr)) => Some(expr),
+        _ => None,
+    }
+}
+
+/// Parses the whole of `input` as an amount.
+#[cfg(test)]
+pub(crate) fn amount_from_str(input: &str) -> Option<Amount> {
+    match parse_amount(input.trim(), &Context::default()) {
+        Ok(("", amount)) => Some(amount),
+        _ => None,
+    }
+}
+
+/// Parses a commodity format such as `1.000,00 EUR`, along with the decimal
+/// mark it shows. Quantities that don't show one use the mark of `settings`.
+pub(crate) fn format_from_str(
+    format: &str,
+    settings: &ParserSettings,
+) -> Option<(Amount, Option<char>)> {
+    let decimal_mark = format_decimal_mark(format);
+    let ctx = Context::new(
+        settings
+            .clone()
+            .with_decimal_mark(decimal_mark.unwrap_or(settings.decimal_mark)),
+    );
+    match parse_amount(format.trim(), &ctx) {
+        Ok(("", amount)) => Some((amount, decimal_mark)),
+        _ => None,
+    }
+}
+
+fn parse_ledger_item<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, LedgerItem> {
+    alt((
+        value(LedgerItem::EmptyLine, parse_empty_line),
+        parse_comment_hint.map(LedgerItem::CommentHint),
+        parse_block_comment
+            .map(str::to_owned)
+            .map(LedgerItem::BlockComment),
+        (|i| parse_block(i, "test"))
+            .map(str::to_owned)
+            .map(LedgerItem::TestBlock),
+        parse_line_comment
+            .map(str::to_owned)
+            .map(LedgerItem::LineComment),
+        (|i| parse_transaction(i, ctx)).map(LedgerItem::Transaction),
+        (|i| parse_commodity_price(i, ctx)).map(LedgerItem::CommodityPrice),
+        (|i| parse_periodic_transaction(i, ctx)).map(LedgerItem::PeriodicTransaction),
+        (|i| parse_automated_transaction(i, ctx)).map(LedgerItem::AutomatedTransaction),
+        |i| parse_directive(i, ctx),
+    ))(input)
+}
+
+fn parse_directive<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, LedgerItem> {
+    alt((
+        (|i| parse_commodity_conversion(i, ctx)).map(LedgerItem::CommodityConversion),
+        parse_include_file
+            .map(str::to_owned)
+            .map(LedgerItem::Include),
+        (|i| parse_account_declaration(i, ctx)).map(LedgerItem::AccountDeclaration),
+        parse_commodity_declaration.map(LedgerItem::CommodityDeclaration),
+        (|i| parse_payee_declaration(i, ctx)).map(LedgerItem::PayeeDeclaration),
+        parse_tag_declaration.map(LedgerItem::TagDeclaration),
+        (|i| parse_account_alias(i, ctx)).map(LedgerItem::AccountAlias),
+        value(LedgerItem::EndAliases, parse_end_aliases),
+        (|i| parse_account_capture(i, ctx)).map(LedgerItem::AccountCapture),
+        parse_apply_tag.map(str::to_owned).map(LedgerItem::ApplyTag),
+        value(LedgerItem::EndApplyTag, parse_end_apply_tag),
+        parse_year.map(LedgerItem::Year),
+        parse_decimal_mark.map(LedgerItem::DecimalMark),
+        (|i| parse_default_commodity(i, ctx)).map(LedgerItem::DefaultCommodity),
+        parse_no_market.map(LedgerItem::NoMarket),
+        (|i| parse_bucket(i, ctx)).map(LedgerItem::Bucket),
+        parse_definition.map(LedgerItem::Definition),
+        parse_event.map(LedgerItem::Event),
+        parse_note.map(str::to_owned).map(LedgerItem::Note),
+        terminated(parse_value_directive, eol_or_eof),
+    ))(input)
+}
+
+/// Parses the next item, and applies the directives among `Y`,
+/// `decimal-mark`, `define`, `commodity` and `D` to the items that follow.
+pub(crate) fn parse_ledger_step<'a>(
+    input: &'a str,
+    ctx: &Context,
+) -> LedgerParseResult<'a, LedgerItem> {
+    // Drop warnings and postings from items that turn out not to parse
+    let warning_count = ctx.warnings.borrow().len();
+    let posting_count = ctx.posting_spans.borrow().as_ref().map_or(0, Vec::len);
+    match parse_ledger_item(input, ctx) {
+        Ok((rest, _)) if rest.len() == input.len() => Err(Err::Failure(
+            VerboseError::from_error_kind(input, ErrorKind::Many0),
+        )),
+        Ok((rest, item)) => {
+            match item {
+                LedgerItem::Year(year) => ctx.year.set(Some(year)),
+                LedgerItem::DecimalMark(mark) => ctx.default_decimal_mark.set(Some(mark)),
+                LedgerItem::Definition(ref definition) => {
+                    define(&mut ctx.variables.borrow_mut(), definition)
+                }
+                LedgerItem::CommodityDeclaration(ref declaration) => {
+                    ctx.declare_format(declaration.format());
+                }
+                LedgerItem::DefaultCommodity(ref format) => {
+                    *ctx.default_commodity.borrow_mut() = ctx.declare_format(format);
+                }
+                _ => {}
+            }
+            Ok((rest, item))
+        }
+        Err(err) => {
+            ctx.warnings.borrow_mut().truncate(warning_count);
+            if let Some(spans) = ctx.posting_spans.borrow_mut().as_mut() {
+                spans.truncate(posting_count);
+            }
+            Err(err)
+        }
+    }
+}
+
+pub fn parse_ledger<'a>(input: &'a str, ctx: &Context) -> LedgerParseResult<'a, Ledger> {
+    let mut items = Vec::new();
+    let mut input = input;
+    loop {
+        match parse_ledger_step(input, ctx) {
+            Ok((rest, item)) => {
+                items.push(item);
+                input = rest;
+            }
+            Err(Err::Error(_)) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    let (input, _) = context("a transaction, directive or comment", eof)(input)?;
+
+    Ok((input, Ledger { items }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nom::{
+        error::{ErrorKind, ParseError},
+        Err::Error,
+    };
+
+    #[test]
+    fn parse_date_test() {
+        assert_eq!(
+            parse_date("2017-03-24"),
+            Ok(("", NaiveDate::from_ymd_opt(2017, 3, 24).unwrap()))
+        );
+        assert_eq!(
+            parse_date("2017/03/24"),
+            Ok(("", NaiveDate::from_ymd_opt(2017, 3, 24).unwrap()))
+        );
+        assert_eq!(
+            parse_date("2017.03.24"),
+            Ok(("", NaiveDate::from_ymd_opt(2017, 3, 24).unwrap()))
+        );
+        assert_eq!(
+            parse_date("2017-13-24"),
+            Err(Error(ParseError::from_error_kind(
+                "2017-13-24",
+                ErrorKind::MapOpt
+            )))
+        );
+    }
+
+    #[test]
+    fn parse_datetime_test() {
+        assert_eq!(
+            parse_datetime("2017-03-24 17:15:23"),
+            Ok((
+                "",
+                NaiveDate::from_ymd_opt(2017, 3, 24)
+                    .unwrap()
+                    .and_hms_opt(17, 15, 23)
+                    .unwrap()
+            ))
+        );
+        assert_eq!(
+            parse_datetime("2017-13-24 22:11:22"),
+            Err(Error(ParseError::from_error_kind(
+                "2017-13-24 22:11:22",
+                ErrorKind::MapOpt
+            )))
+        );
+        assert_eq!(
+            parse_datetime("2017-03-24 25:11:22"),
+            Err(Error(ParseError::from_error_kind(
+                "2017-03-24 25:11:22",
+                ErrorKind::MapOpt
+            )))
+        );
+    }
+
+    #[test]
+    fn parse_quantity_test() {
+        assert_eq!(
+            parse_quantity("1000", &Context::default(), '.'),
+            Ok(("", Decimal::new(1000, 0)))
+        );
+        assert_eq!(
+            parse_quantity("2.02", &Context::default(), '.'),
+            Ok(("", Decimal::new(202, 2)))
+        );
+        assert_eq!(
+            parse_quantity("-12.13", &Context::default(), '.'),
+            Ok(("", Decimal::new(-1213, 2)))
+        );
+        assert_eq!(
+            parse_quantity("0.1", &Context::default(), '.'),
+            Ok(("", Decimal::new(1, 1)))
+        );
+        assert_eq!(
+            parse_quantity("3", &Context::default(), '.'),
+            Ok(("", Decimal::new(3, 0)))
+        );
+        assert_eq!(
+            parse_quantity("1", &Context::default(), '.'),
+            Ok(("", Decimal::new(1, 0)))
+        );
+        assert_eq!(
+            parse_quantity("1,000", &Context::default(), '.'),
+            Ok(("", Decimal::new(1000, 0)))
+        );
+        assert_eq!(
+            parse_quantity("12,456,132.14", &Context::default(), '.'),
+            Ok(("", Decimal::new(1245613214, 2)))
+        );
+        assert_eq!(
+            parse_quantity("1.5e-4", &Context::default(), '.'),
+            Ok(("", Decimal::new(15, 5)))
+        );
+        assert_eq!(
+            parse_quantity("-2E+3 BTC", &Context::default(), '.'),
+            Ok((" BTC", Decimal::new(-2000, 0)))
+        );
+        assert_eq!(
+            parse_quantity("10EUR", &Context::default(), '.'),
+            Ok(("EUR", Decimal::new(10, 0)))
+        );
+
+        let ctx = Context::new(ParserSettings::default().with_digit_group_separators(&[' ', '\'']));
+        assert_eq!(
+            parse_quantity("1 234 567.89", &ctx, '.'),
+            Ok(("", Decimal::new(123456789, 2)))
+        );
+        assert_eq!(
+            parse_quantity("-1'234 EUR", &ctx, '.'),
+            Ok((" EUR", Decimal::new(-1234, 0)))
+        );
+        assert_eq!(
+            parse_quantity("1,234", &ctx, '.'),
+            Ok((",234", Decimal::new(1, 0)))
+        );
+    }
+
+    #[test]
+    fn parse_commodity_test() {
+        assert_eq!(
+            parse_commodity("\"ABC 123\""),
+            Ok(("", "ABC 123".to_owned()))
+        );
+        assert_eq!(parse_commodity("ABC "), Ok((" ", "ABC".to_owned())));
+        assert_eq!(parse_commodity("$1"), Ok(("1", "$".to_owned())));
+        assert_eq!(parse_commodity("€1"), Ok(("1", "€".to_owned())));
+        assert_eq!(parse_commodity("€ "), Ok((" ", "€".to_owned())));
+        assert_eq!(parse_commodity("€-1"), Ok(("-1", "€".to_owned())));
+        assert_eq!(parse_commodity("BRK.B 10"), Ok((" 10", "BRK.B".to_owned())));
+        assert_eq!(parse_commodity("EUR2"), Ok(("2", "EUR".to_owned())));
+        assert_eq!(parse_commodity("$1.5k"), Ok(("1.5k", "$".to_owned())));
+        assert_eq!(parse_commodity_name("EUR2 "), Ok((" ", "EUR2".to_owned())));
+        assert_eq!(parse_commodity_name("BRK. "), Ok((". ", "BRK".to_owned())));
+        assert_eq!(
+            parse_commodity_name(r#""10\"% C\sh:a.r,e""#),
+            Ok(("", r#"10"% C\sh:a.r,e"#.to_owned()))
+        );
+
+        let ctx = Context::default();
+        let (_, amount) = parse_amount("10 EUR2", &ctx).unwrap();
+        assert_eq!(amount.commodity.name, "EUR2");
+        assert_eq!(amount.to_string(), "10 EUR2");
+        let (_, amount) = parse_amount("BRK.B 5", &ctx).unwrap();
+        assert_eq!(amount.to_string(), "BRK.B 5");
+        let amount = Amount {
+            commodity: Commodity {
+                name: "EUR2".to_owned(),
+                position: CommodityPosition::Left,
+            },
+            ..amount
+        };
+        assert_eq!(amount.to_string(), "\"EUR2\"5");
+    }
 
     #[test]
     fn parse_amount_test() {
         assert_eq!(
-            parse_amount("$1.20"),
+            parse_amount("$1.20", &Context::default()),
             Ok((
                 "",
                 Amount {
@@ -561,7 +2311,7 @@ mod tests {
             ))
         );
         assert_eq!(
-            parse_amount("$-1.20"),
+            parse_amount("$-1.20", &Context::default()),
             Ok((
                 "",
                 Amount {
@@ -574,7 +2324,7 @@ mod tests {
             ))
         );
         assert_eq!(
-            parse_amount("-$1.20 "),
+            parse_amount("-$1.20 ", &Context::default()),
             Ok((
                 " ",
                 Amount {
@@ -587,7 +2337,7 @@ mod tests {
             ))
         );
         assert_eq!(
-            parse_amount("- $ 1.20"),
+            parse_amount("- $ 1.20", &Context::default()),
             Ok((
                 "",
                 Amount {
@@ -600,987 +2350,2487 @@ mod tests {
             ))
         );
         assert_eq!(
-            parse_amount("1.20USD"),
+            parse_amount("1.20USD", &Context::default()),
+            Ok((
+                "",
+                Amount {
+                    quantity: Decimal::new(120, 2),
+                    commodity: Commodity {
+                        name: "USD".to_owned(),
+                        position: CommodityPosition::Right
+                    }
+                }
+            ))
+        );
+        assert_eq!(
+            parse_amount("1.20USD ", &Context::default()),
+            Ok((
+                " ",
+                Amount {
+                    quantity: Decimal::new(120, 2),
+                    commodity: Commodity {
+                        name: "USD".to_owned(),
+                        position: CommodityPosition::Right
+                    }
+                }
+            ))
+        );
+        assert_eq!(
+            parse_amount("-1.20 USD", &Context::default()),
+            Ok((
+                "",
+                Amount {
+                    quantity: Decimal::new(-120, 2),
+                    commodity: Commodity {
+                        name: "USD".to_owned(),
+                        position: CommodityPosition::Right
+                    }
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_lot_price_test() {
+        assert_eq!(
+            parse_lot_price("{$1.20}", &Context::default()),
+            Ok((
+                "",
+                Price::Unit(Amount {
+                    quantity: Decimal::new(120, 2),
+                    commodity: Commodity {
+                        name: "$".to_owned(),
+                        position: CommodityPosition::Left
+                    }
+                })
+            ))
+        );
+        assert_eq!(
+            parse_lot_price("{ $1.20 }", &Context::default()),
+            Ok((
+                "",
+                Price::Unit(Amount {
+                    quantity: Decimal::new(120, 2),
+                    commodity: Commodity {
+                        name: "$".to_owned(),
+                        position: CommodityPosition::Left
+                    }
+                })
+            ))
+        );
+        assert_eq!(
+            parse_lot_price("{1.20PLN}", &Context::default()),
+            Ok((
+                "",
+                Price::Unit(Amount {
+                    quantity: Decimal::new(120, 2),
+                    commodity: Commodity {
+                        name: "PLN".to_owned(),
+                        position: CommodityPosition::Right
+                    }
+                })
+            ))
+        );
+        assert_eq!(
+            parse_lot_price("{ 1.20 PLN } ", &Context::default()),
+            Ok((
+                " ",
+                Price::Unit(Amount {
+                    quantity: Decimal::new(120, 2),
+                    commodity: Commodity {
+                        name: "PLN".to_owned(),
+                        position: CommodityPosition::Right
+                    }
+                })
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_price_test() {
+        assert_eq!(
+            parse_price("@$1.20", &Context::default()),
+            Ok((
+                "",
+                Price::Unit(Amount {
+                    quantity: Decimal::new(120, 2),
+                    commodity: Commodity {
+                        name: "$".to_owned(),
+                        position: CommodityPosition::Left
+                    }
+                })
+            ))
+        );
+        assert_eq!(
+            parse_price("@ $1.20", &Context::default()),
+            Ok((
+                "",
+                Price::Unit(Amount {
+                    quantity: Decimal::new(120, 2),
+                    commodity: Commodity {
+                        name: "$".to_owned(),
+                        position: CommodityPosition::Left
+                    }
+                })
+            ))
+        );
+        assert_eq!(
+            parse_price("@@1.20PLN", &Context::default()),
+            Ok((
+                "",
+                Price::Total(Amount {
+                    quantity: Decimal::new(120, 2),
+                    commodity: Commodity {
+                        name: "PLN".to_owned(),
+                        position: CommodityPosition::Right
+                    }
+                })
+            ))
+        );
+        assert_eq!(
+            parse_price("@@ 1.20 PLN ", &Context::default()),
+            Ok((
+                " ",
+                Price::Total(Amount {
+                    quantity: Decimal::new(120, 2),
+                    commodity: Commodity {
+                        name: "PLN".to_owned(),
+                        position: CommodityPosition::Right
+                    }
+                })
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_posting_amount_test() {
+        assert_eq!(
+            parse_posting_amount("$1.20", &Context::default()),
+            Ok((
+                "",
+                PostingAmount {
+                    amount: Amount {
+                        quantity: Decimal::new(120, 2),
+                        commodity: Commodity {
+                            name: "$".to_owned(),
+                            position: CommodityPosition::Left
+                        }
+                    },
+                    lot_price: None,
+                    lot_price_fixed: false,
+                    lot_date: None,
+                    lot_note: None,
+                    price: None
+                }
+            ))
+        );
+        assert_eq!(
+            parse_posting_amount("$1.20 @ 5.00 PLN", &Context::default()),
+            Ok((
+                "",
+                PostingAmount {
+                    amount: Amount {
+                        quantity: Decimal::new(120, 2),
+                        commodity: Commodity {
+                            name: "$".to_owned(),
+                            position: CommodityPosition::Left
+                        }
+                    },
+                    lot_price: None,
+                    lot_price_fixed: false,
+                    lot_date: None,
+                    lot_note: None,
+                    price: Some(Price::Unit(Amount {
+                        quantity: Decimal::new(500, 2),
+                        commodity: Commodity {
+                            name: "PLN".to_owned(),
+                            position: CommodityPosition::Right
+                        }
+                    }))
+                }
+            ))
+        );
+        assert_eq!(
+            parse_posting_amount("$1.20 {5.00 PLN}", &Context::default()),
+            Ok((
+                "",
+                PostingAmount {
+                    amount: Amount {
+                        quantity: Decimal::new(120, 2),
+                        commodity: Commodity {
+                            name: "$".to_owned(),
+                            position: CommodityPosition::Left
+                        }
+                    },
+                    lot_price: Some(Price::Unit(Amount {
+                        quantity: Decimal::new(500, 2),
+                        commodity: Commodity {
+                            name: "PLN".to_owned(),
+                            position: CommodityPosition::Right
+                        }
+                    })),
+                    lot_price_fixed: false,
+                    lot_date: None,
+                    lot_note: None,
+                    price: None,
+                }
+            ))
+        );
+        assert_eq!(
+            parse_posting_amount("$1.20 {{5.00 PLN}} @@6.0PLN ", &Context::default()),
+            Ok((
+                " ",
+                PostingAmount {
+                    amount: Amount {
+                        quantity: Decimal::new(120, 2),
+                        commodity: Commodity {
+                            name: "$".to_owned(),
+                            position: CommodityPosition::Left
+                        }
+                    },
+                    lot_price: Some(Price::Total(Amount {
+                        quantity: Decimal::new(500, 2),
+                        commodity: Commodity {
+                            name: "PLN".to_owned(),
+                            position: CommodityPosition::Right
+                        }
+                    })),
+                    lot_price_fixed: false,
+                    lot_date: None,
+                    lot_note: None,
+                    price: Some(Price::Total(Amount {
+                        quantity: Decimal::new(600, 2),
+                        commodity: Commodity {
+                            name: "PLN".to_owned(),
+                            position: CommodityPosition::Right
+                        }
+                    })),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_balance_test() {
+        assert_eq!(
+            parse_balance("$1.20", &Context::default()),
+            Ok((
+                "",
+                Balance::Amount(Amount {
+                    quantity: Decimal::new(120, 2),
+                    commodity: Commodity {
+                        name: "$".to_owned(),
+                        position: CommodityPosition::Left
+                    }
+                })
+            ))
+        );
+        assert_eq!(
+            parse_balance("0 PLN", &Context::default()),
+            Ok((
+                "",
+                Balance::Amount(Amount {
+                    quantity: Decimal::new(0, 0),
+                    commodity: Commodity {
+                        name: "PLN".to_owned(),
+                        position: CommodityPosition::Right
+                    }
+                })
+            ))
+        );
+        assert_eq!(
+            parse_balance("0", &Context::default()),
+            Ok(("", Balance::Zero))
+        );
+    }
+
+    #[test]
+    fn parse_commodity_price_test() {
+        assert_eq!(
+            parse_commodity_price("P 2017-11-12 12:00:00 mBH 5.00 PLN", &Context::default()),
+            Ok((
+                "",
+                CommodityPrice {
+                    datetime: NaiveDate::from_ymd_opt(2017, 11, 12)
+                        .unwrap()
+                        .and_hms_opt(12, 0, 0)
+                        .unwrap(),
+                    has_time: true,
+                    commodity_name: "mBH".to_owned(),
+                    amount: Amount {
+                        quantity: Decimal::new(500, 2),
+                        commodity: Commodity {
+                            name: "PLN".to_owned(),
+                            position: CommodityPosition::Right
+                        }
+                    }
+                }
+            ))
+        );
+
+        let (_, price) =
+            parse_commodity_price("P 2024-01-02 AAPL $192.53", &Context::default()).unwrap();
+        assert_eq!(
+            price.datetime,
+            NaiveDate::from_ymd_opt(2024, 1, 2)
+                .unwrap()
+                .and_time(NaiveTime::MIN)
+        );
+        assert!(!price.has_time);
+        assert_eq!(price.to_string(), "P 2024-01-02 AAPL $192.53");
+
+        let input = r#"P 2024-01-02 00:00:00 "S&P 500" 4700 "US \"D\"""#;
+        let (_, price) = parse_commodity_price(input, &Context::default()).unwrap();
+        assert_eq!(price.commodity_name, "S&P 500");
+        assert_eq!(price.amount.commodity.name, "US \"D\"");
+        assert_eq!(price.to_string(), input);
+    }
+
+    #[test]
+    fn parse_account_test() {
+        assert_eq!(
+            parse_account("TEST:ABC 123  ", &Context::default()),
+            Ok(("  ", ("TEST:ABC 123", Reality::Real)))
+        );
+        assert_eq!(
+            parse_account("TEST:ABC 123\t", &Context::default()),
+            Ok(("\t", ("TEST:ABC 123", Reality::Real)))
+        );
+        assert_eq!(
+            parse_account("TEST:ABC 123", &Context::default()),
+            Ok(("", ("TEST:ABC 123", Reality::Real)))
+        );
+        assert_eq!(
+            parse_account("[TEST:ABC 123]", &Context::default()),
+            Ok(("", ("TEST:ABC 123", Reality::BalancedVirtual)))
+        );
+        assert_eq!(
+            parse_account("(TEST:ABC 123)", &Context::default()),
+            Ok(("", ("TEST:ABC 123", Reality::UnbalancedVirtual)))
+        );
+    }
+
+    #[test]
+    fn parse_transaction_status_test() {
+        let ctx = Context::default();
+        assert_eq!(
+            parse_transaction_status("!", &ctx),
+            Ok(("", TransactionStatus::Pending))
+        );
+        assert_eq!(
+            parse_transaction_status("*", &ctx),
+            Ok(("", TransactionStatus::Cleared))
+        );
+        assert!(parse_transaction_status("?", &ctx).is_err());
+
+        let settings = ParserSettings::default().with_status_flags(&['?', 'R']);
+        let ctx = Context::new(settings.clone());
+        assert_eq!(
+            parse_transaction_status("?", &ctx),
+            Ok(("", TransactionStatus::Custom('?')))
+        );
+        let input = "2024-01-02 ? (7) Shop\n  R Expenses:Food  $5\n  Assets:Cash\n";
+        let (ledger, _) = crate::parse_with_settings(input, &settings).unwrap();
+        let transaction = ledger.transactions().next().unwrap();
+        assert_eq!(transaction.status, Some(TransactionStatus::Custom('?')));
+        assert_eq!(transaction.description, "Shop");
+        assert_eq!(
+            transaction.postings[0].status,
+            Some(TransactionStatus::Custom('R'))
+        );
+        assert_eq!(ledger.to_string(), input);
+        assert_eq!(
+            crate::parse("2024-01-02 ? Shop\n  Expenses:Food  $5\n  Assets:Cash\n")
+                .unwrap()
+                .transactions()
+                .next()
+                .unwrap()
+                .description,
+            "? Shop"
+        );
+    }
+
+    #[test]
+    fn parse_posting_test() {
+        assert_eq!(
+            parse_posting(" TEST:ABC 123  $1.20", &Context::default()),
+            Ok((
+                "",
+                Posting {
+                    account: "TEST:ABC 123".to_owned(),
+                    reality: Reality::Real,
+                    amount: Some(PostingAmount {
+                        amount: Amount {
+                            quantity: Decimal::new(120, 2),
+                            commodity: Commodity {
+                                name: "$".to_owned(),
+                                position: CommodityPosition::Left
+                            }
+                        },
+                        lot_price: None,
+                        lot_price_fixed: false,
+                        lot_date: None,
+                        lot_note: None,
+                        price: None
+                    }),
+                    amount_expr: None,
+                    balance: None,
+                    balance_kind: BalanceKind::Single,
+                    status: None,
+                    date: None,
+                    effective_date: None,
+                    comment: None,
+                    tags: Vec::new(),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_posting(
+                " ! TEST:ABC 123  $1.20;test\n;comment line 2",
+                &Context::default()
+            ),
+            Ok((
+                "",
+                Posting {
+                    account: "TEST:ABC 123".to_owned(),
+                    reality: Reality::Real,
+                    amount: Some(PostingAmount {
+                        amount: Amount {
+                            quantity: Decimal::new(120, 2),
+                            commodity: Commodity {
+                                name: "$".to_owned(),
+                                position: CommodityPosition::Left
+                            }
+                        },
+                        lot_price: None,
+                        lot_price_fixed: false,
+                        lot_date: None,
+                        lot_note: None,
+                        price: None
+                    }),
+                    amount_expr: None,
+                    balance: None,
+                    balance_kind: BalanceKind::Single,
+                    status: Some(TransactionStatus::Pending),
+                    date: None,
+                    effective_date: None,
+                    comment: Some("test\ncomment line 2".to_owned()),
+                    tags: Vec::new(),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_posting(" ! TEST:ABC 123;test\n;comment", &Context::default()),
+            Ok((
+                "",
+                Posting {
+                    account: "TEST:ABC 123;test".to_owned(),
+                    reality: Reality::Real,
+                    amount: None,
+                    amount_expr: None,
+                    balance: None,
+                    balance_kind: BalanceKind::Single,
+                    status: Some(TransactionStatus::Pending),
+                    date: None,
+                    effective_date: None,
+                    comment: Some("comment".to_owned()),
+                    tags: Vec::new(),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_posting(
+                " ! TEST:ABC 123  ;test\n;comment line 2",
+                &Context::default()
+            ),
+            Ok((
+                "",
+                Posting {
+                    account: "TEST:ABC 123".to_owned(),
+                    reality: Reality::Real,
+                    amount: None,
+                    amount_expr: None,
+                    balance: None,
+                    balance_kind: BalanceKind::Single,
+                    status: Some(TransactionStatus::Pending),
+                    date: None,
+                    effective_date: None,
+                    comment: Some("test\ncomment line 2".to_owned()),
+                    tags: Vec::new(),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_posting(
+                " ! TEST:ABC 123   ;  test     \n       ;        comment line 2     ",
+                &Context::default()
+            ),
+            Ok((
+                "",
+                Posting {
+                    account: "TEST:ABC 123".to_owned(),
+                    reality: Reality::Real,
+                    amount: None,
+                    amount_expr: None,
+                    balance: None,
+                    balance_kind: BalanceKind::Single,
+                    status: Some(TransactionStatus::Pending),
+                    date: None,
+                    effective_date: None,
+                    comment: Some("test\ncomment line 2".to_owned()),
+                    tags: Vec::new(),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_posting(" TEST:ABC 123  $1.20 = $2.40 ;comment", &Context::default()),
+            Ok((
+                "",
+                Posting {
+                    account: "TEST:ABC 123".to_owned(),
+                    reality: Reality::Real,
+                    amount: Some(PostingAmount {
+                        amount: Amount {
+                            quantity: Decimal::new(120, 2),
+                            commodity: Commodity {
+                                name: "$".to_owned(),
+                                position: CommodityPosition::Left
+                            }
+                        },
+                        lot_price: None,
+                        lot_price_fixed: false,
+                        lot_date: None,
+                        lot_note: None,
+                        price: None
+                    }),
+                    amount_expr: None,
+                    balance: Some(Balance::Amount(Amount {
+                        quantity: Decimal::new(240, 2),
+                        commodity: Commodity {
+                            name: "$".to_owned(),
+                            position: CommodityPosition::Left
+                        }
+                    })),
+                    balance_kind: BalanceKind::Single,
+                    status: None,
+                    date: None,
+                    effective_date: None,
+                    comment: Some("comment".to_owned()),
+                    tags: Vec::new(),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_posting(" TEST:ABC 123", &Context::default()),
+            Ok((
+                "",
+                Posting {
+                    account: "TEST:ABC 123".to_owned(),
+                    reality: Reality::Real,
+                    amount: None,
+                    amount_expr: None,
+                    balance: None,
+                    balance_kind: BalanceKind::Single,
+                    status: None,
+                    date: None,
+                    effective_date: None,
+                    comment: None,
+                    tags: Vec::new(),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_posting(" TEST:ABC 123   ; 456", &Context::default()),
+            Ok((
+                "",
+                Posting {
+                    account: "TEST:ABC 123".to_owned(),
+                    reality: Reality::Real,
+                    amount: None,
+                    amount_expr: None,
+                    balance: None,
+                    balance_kind: BalanceKind::Single,
+                    status: None,
+                    date: None,
+                    effective_date: None,
+                    comment: Some("456".to_owned()),
+                    tags: Vec::new(),
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn pares_period_test() {
+        assert_eq!(parse_period("daily"), Ok(("", Period::Daily)));
+        assert_eq!(parse_period("weekly"), Ok(("", Period::Weekly)));
+        assert_eq!(parse_period("monthly"), Ok(("", Period::Monthly)));
+        assert_eq!(parse_period("yearly"), Ok(("", Period::Yearly)));
+        assert_eq!(parse_period("every day"), Ok(("", Period::EveryNDays(1))));
+        assert_eq!(parse_period("every week"), Ok(("", Period::EveryNWeeks(1))));
+        assert_eq!(
+            parse_period("every month"),
+            Ok(("", Period::EveryNMonths(1)))
+        );
+        assert_eq!(parse_period("every year"), Ok(("", Period::EveryNYears(1))));
+        assert_eq!(parse_period("biweekly"), Ok(("", Period::EveryNWeeks(2))));
+        assert_eq!(parse_period("bimonthly"), Ok(("", Period::EveryNMonths(2))));
+        assert_eq!(
+            parse_period("every 3 days"),
+            Ok(("", Period::EveryNDays(3)))
+        );
+        assert_eq!(
+            parse_period("every 12 weeks"),
+            Ok(("", Period::EveryNWeeks(12)))
+        );
+        assert_eq!(
+            parse_period("every 23 months"),
+            Ok(("", Period::EveryNMonths(23)))
+        );
+        assert_eq!(
+            parse_period("every 2 years"),
+            Ok(("", Period::EveryNYears(2)))
+        );
+        assert_eq!(
+            parse_period("2023-01-01"),
+            Ok((
+                "",
+                Period::Date(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap())
+            ))
+        )
+    }
+
+    #[test]
+    fn parse_periodic_transactions_test() {
+        assert_eq!(
+            parse_periodic_transaction(
+                r#"~ monthly 
+ TEST:ABC 123  $1.20 ; Posting comment
+                     ; over two lines
+ TEST:ABC 123  $1.20"#,
+                &Context::default()
+            ),
+            Ok((
+                "",
+                PeriodicTransaction {
+                    period: Period::Monthly,
+                    comment: None,
+                    start_date: None,
+                    end_date: None,
+                    postings: vec![
+                        Posting {
+                            account: "TEST:ABC 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(120, 2),
+                                    commodity: Commodity {
+                                        name: "$".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: Some("Posting comment\nover two lines".to_owned()),
+                            tags: Vec::new(),
+                        },
+                        Posting {
+                            account: "TEST:ABC 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(120, 2),
+                                    commodity: Commodity {
+                                        name: "$".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: None,
+                            tags: Vec::new(),
+                        }
+                    ],
+                }
+            ))
+        );
+
+        assert_eq!(
+            parse_periodic_transaction(
+                r#"~ every 3 months 
+ TEST:ABC 123  $1.20 ; Posting comment
+                     ; over two lines
+ TEST:ABC 123  $1.20"#,
+                &Context::default()
+            ),
+            Ok((
+                "",
+                PeriodicTransaction {
+                    period: Period::EveryNMonths(3),
+                    comment: None,
+                    start_date: None,
+                    end_date: None,
+                    postings: vec![
+                        Posting {
+                            account: "TEST:ABC 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(120, 2),
+                                    commodity: Commodity {
+                                        name: "$".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: Some("Posting comment\nover two lines".to_owned()),
+                            tags: Vec::new(),
+                        },
+                        Posting {
+                            account: "TEST:ABC 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(120, 2),
+                                    commodity: Commodity {
+                                        name: "$".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: None,
+                            tags: Vec::new(),
+                        }
+                    ],
+                }
+            ))
+        );
+
+        assert_eq!(
+            parse_periodic_transaction(
+                r#"~ every 3 months from 2023-01-01 to 2023-02-01
+ TEST:ABC 123  $1.20 ; Posting comment
+                     ; over two lines
+ TEST:ABC 123  $1.20"#,
+                &Context::default()
+            ),
+            Ok((
+                "",
+                PeriodicTransaction {
+                    period: Period::EveryNMonths(3),
+                    comment: None,
+                    start_date: Some(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap()),
+                    end_date: Some(NaiveDate::from_ymd_opt(2023, 2, 1).unwrap()),
+                    postings: vec![
+                        Posting {
+                            account: "TEST:ABC 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(120, 2),
+                                    commodity: Commodity {
+                                        name: "$".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: Some("Posting comment\nover two lines".to_owned()),
+                            tags: Vec::new(),
+                        },
+                        Posting {
+                            account: "TEST:ABC 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(120, 2),
+                                    commodity: Commodity {
+                                        name: "$".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: None,
+                            tags: Vec::new(),
+                        }
+                    ],
+                }
+            ))
+        );
+        assert_eq!(
+            parse_periodic_transaction(
+                r#"~ every 3 months from 2023-01-01 to 2023-02-01 ; Transaction comment
+ TEST:ABC 123  $1.20 ; Posting comment
+                     ; over two lines
+ TEST:ABC 123  $1.20"#,
+                &Context::default()
+            ),
+            Ok((
+                "",
+                PeriodicTransaction {
+                    period: Period::EveryNMonths(3),
+                    comment: Some("Transaction comment".to_owned()),
+                    start_date: Some(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap()),
+                    end_date: Some(NaiveDate::from_ymd_opt(2023, 2, 1).unwrap()),
+                    postings: vec![
+                        Posting {
+                            account: "TEST:ABC 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(120, 2),
+                                    commodity: Commodity {
+                                        name: "$".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: Some("Posting comment\nover two lines".to_owned()),
+                            tags: Vec::new(),
+                        },
+                        Posting {
+                            account: "TEST:ABC 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(120, 2),
+                                    commodity: Commodity {
+                                        name: "$".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: None,
+                            tags: Vec::new(),
+                        }
+                    ],
+                }
+            ))
+        )
+    }
+
+    #[test]
+    fn parse_transaction_test() {
+        assert_eq!(
+            parse_transaction(
+                r#"2018-10-01=2018-10-14 ! (123) Marek Ogarek  ; Transaction comment
+ TEST:ABC 123  $1.20 ; Posting comment
+                     ; over two lines
+ TEST:ABC 123  $1.20"#,
+                &Context::default()
+            ),
+            Ok((
+                "",
+                Transaction {
+                    comment: Some("Transaction comment".to_owned()),
+                    date: NaiveDate::from_ymd_opt(2018, 10, 1).unwrap(),
+                    effective_date: Some(NaiveDate::from_ymd_opt(2018, 10, 14).unwrap()),
+                    date_style: DateStyle::Numeric,
+                    status: Some(TransactionStatus::Pending),
+                    code: Some("123".to_owned()),
+                    description: "Marek Ogarek".to_owned(),
+                    postings: vec![
+                        Posting {
+                            account: "TEST:ABC 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(120, 2),
+                                    commodity: Commodity {
+                                        name: "$".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: Some("Posting comment\nover two lines".to_owned()),
+                            tags: Vec::new(),
+                        },
+                        Posting {
+                            account: "TEST:ABC 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(120, 2),
+                                    commodity: Commodity {
+                                        name: "$".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: None,
+                            tags: Vec::new(),
+                        }
+                    ],
+                    tags: Vec::new(),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_transaction(
+                r#"2018-10-01=2018-10-14 Marek Ogarek ; one space
+ TEST:ABC 123  $1.20 ; test
+ TEST:DEF 123  EUR-1.20
+ TEST:GHI 123
+ TEST:JKL 123  EUR-2.00"#,
+                &Context::default()
+            ),
+            Ok((
+                "",
+                Transaction {
+                    comment: None,
+                    date: NaiveDate::from_ymd_opt(2018, 10, 1).unwrap(),
+                    effective_date: Some(NaiveDate::from_ymd_opt(2018, 10, 14).unwrap()),
+                    date_style: DateStyle::Numeric,
+                    status: None,
+                    code: None,
+                    description: "Marek Ogarek ; one space".to_owned(),
+                    postings: vec![
+                        Posting {
+                            account: "TEST:ABC 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(120, 2),
+                                    commodity: Commodity {
+                                        name: "$".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: Some("test".to_owned()),
+                            tags: Vec::new(),
+                        },
+                        Posting {
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            account: "TEST:DEF 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(-120, 2),
+                                    commodity: Commodity {
+                                        name: "EUR".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: None,
+                            tags: Vec::new(),
+                        },
+                        Posting {
+                            account: "TEST:GHI 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: None,
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: None,
+                            tags: Vec::new(),
+                        },
+                        Posting {
+                            account: "TEST:JKL 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(-200, 2),
+                                    commodity: Commodity {
+                                        name: "EUR".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: None,
+                            tags: Vec::new(),
+                        },
+                    ],
+                    tags: Vec::new(),
+                }
+            ))
+        );
+        assert_eq!(
+            parse_transaction(
+                r#"2018-10-01=2018-10-14 ! (123) Marek Ogarek  two spaces
+ TEST:ABC 123  $1.20 ; test
+ TEST:DEF 123"#,
+                &Context::default()
+            ),
             Ok((
                 "",
-                Amount {
-                    quantity: Decimal::new(120, 2),
-                    commodity: Commodity {
-                        name: "USD".to_owned(),
-                        position: CommodityPosition::Right
-                    }
+                Transaction {
+                    comment: None,
+                    date: NaiveDate::from_ymd_opt(2018, 10, 1).unwrap(),
+                    effective_date: Some(NaiveDate::from_ymd_opt(2018, 10, 14).unwrap()),
+                    date_style: DateStyle::Numeric,
+                    status: Some(TransactionStatus::Pending),
+                    code: Some("123".to_owned()),
+                    description: "Marek Ogarek  two spaces".to_owned(),
+                    postings: vec![
+                        Posting {
+                            account: "TEST:ABC 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: Some(PostingAmount {
+                                amount: Amount {
+                                    quantity: Decimal::new(120, 2),
+                                    commodity: Commodity {
+                                        name: "$".to_owned(),
+                                        position: CommodityPosition::Left
+                                    }
+                                },
+                                lot_price: None,
+                                lot_price_fixed: false,
+                                lot_date: None,
+                                lot_note: None,
+                                price: None
+                            }),
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: Some("test".to_owned()),
+                            tags: Vec::new(),
+                        },
+                        Posting {
+                            account: "TEST:DEF 123".to_owned(),
+                            reality: Reality::Real,
+                            amount: None,
+                            amount_expr: None,
+                            balance: None,
+                            balance_kind: BalanceKind::Single,
+                            status: None,
+                            date: None,
+                            effective_date: None,
+                            comment: None,
+                            tags: Vec::new(),
+                        },
+                    ],
+                    tags: Vec::new(),
                 }
             ))
         );
+    }
+
+    #[test]
+    fn parse_include_test() {
         assert_eq!(
-            parse_amount("1.20USD "),
-            Ok((
-                " ",
-                Amount {
-                    quantity: Decimal::new(120, 2),
-                    commodity: Commodity {
-                        name: "USD".to_owned(),
-                        position: CommodityPosition::Right
-                    }
-                }
-            ))
+            parse_include_file(r#"include other_file.ledger"#),
+            Ok(("", "other_file.ledger"))
+        );
+    }
+
+    #[test]
+    fn parse_ledger_test() {
+        let res = parse_ledger(
+            r#"; Example 1
+
+include other_file.ledger
+
+P 2017-11-12 12:00:00 mBH 5.00 PLN
+
+; Comment
+2018-10-01=2018-10-14 ! (123) Marek Ogarek
+ TEST:ABC 123  $1.20
+ TEST:ABC 123  $1.20
+
+2018-10-01=2018-10-14 ! (123) Marek Ogarek
+ TEST:ABC 123  $1.20
+ TEST:ABC 123  $1.20
+"#,
+            &Context::default(),
+        )
+        .unwrap()
+        .1;
+        assert_eq!(res.items.len(), 10);
+        assert!(matches!(res.items[0], LedgerItem::LineComment(_)));
+        assert!(matches!(res.items[1], LedgerItem::EmptyLine));
+        assert!(matches!(res.items[2], LedgerItem::Include(_)));
+        assert!(matches!(res.items[3], LedgerItem::EmptyLine));
+        assert!(matches!(res.items[4], LedgerItem::CommodityPrice(_)));
+        assert!(matches!(res.items[5], LedgerItem::EmptyLine));
+        assert!(matches!(res.items[6], LedgerItem::LineComment(_)));
+        assert!(matches!(res.items[7], LedgerItem::Transaction(_)));
+        assert!(matches!(res.items[8], LedgerItem::EmptyLine));
+        assert!(matches!(res.items[9], LedgerItem::Transaction(_)));
+    }
+
+    #[test]
+    fn parse_warnings_test() {
+        let (ledger, warnings) = crate::parse_with_warnings(
+            r#"2018-10-01 Rent
+  Expenses:Rent  1.000 EUR
+  Assets:Bank  $1,000.00
+  Assets:Cash $-20
+2018-10-02 Shop
+  TEST:Account 123  $1,234
+  TEST:Account 345
+"#,
+        )
+        .unwrap();
+        assert_eq!(ledger.items.len(), 2);
+        assert_eq!(
+            warnings,
+            vec![
+                ParseWarning {
+                    line: 2,
+                    kind: ParseWarningKind::AmbiguousQuantity("1.000".to_owned()),
+                },
+                ParseWarning {
+                    line: 4,
+                    kind: ParseWarningKind::SingleSpaceBeforeAmount {
+                        account: "Assets:Cash $-20".to_owned(),
+                    },
+                },
+                ParseWarning {
+                    line: 6,
+                    kind: ParseWarningKind::AmbiguousQuantity("1,234".to_owned()),
+                },
+            ]
         );
         assert_eq!(
-            parse_amount("-1.20 USD"),
-            Ok((
-                "",
-                Amount {
-                    quantity: Decimal::new(-120, 2),
-                    commodity: Commodity {
-                        name: "USD".to_owned(),
-                        position: CommodityPosition::Right
-                    }
-                }
-            ))
+            warnings[1].to_string(),
+            "line 4: account \"Assets:Cash $-20\" ends with an amount separated by a single space"
         );
     }
 
     #[test]
-    fn parse_lot_price_test() {
+    fn parse_transaction_date_test() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 5).unwrap();
+        assert!(parse_transaction_date("2024 Jan 05", &Context::default()).is_err());
+
+        let ctx = Context::new(ParserSettings::default().with_month_names(&ENGLISH_MONTH_NAMES));
         assert_eq!(
-            parse_lot_price("{$1.20}"),
-            Ok((
-                "",
-                Price::Unit(Amount {
-                    quantity: Decimal::new(120, 2),
-                    commodity: Commodity {
-                        name: "$".to_owned(),
-                        position: CommodityPosition::Left
-                    }
-                })
-            ))
+            parse_transaction_date("2024-01-05", &ctx),
+            Ok(("", (date, DateStyle::Numeric)))
         );
         assert_eq!(
-            parse_lot_price("{ $1.20 }"),
-            Ok((
-                "",
-                Price::Unit(Amount {
-                    quantity: Decimal::new(120, 2),
-                    commodity: Commodity {
-                        name: "$".to_owned(),
-                        position: CommodityPosition::Left
-                    }
-                })
-            ))
+            parse_transaction_date("2024 Jan 05", &ctx),
+            Ok(("", (date, DateStyle::YearMonthNameDay)))
+        );
+        assert_eq!(
+            parse_transaction_date("5 january 2024 Shop", &ctx),
+            Ok((" Shop", (date, DateStyle::DayMonthNameYear)))
         );
+        assert!(parse_transaction_date("2024 Jnu 05", &ctx).is_err());
+        assert!(parse_transaction_date("2024 Feb 30", &ctx).is_err());
+
+        let ctx = Context::new(ParserSettings::default().with_month_names(&[
+            "Janvier",
+            "Février",
+            "Mars",
+            "Avril",
+            "Mai",
+            "Juin",
+            "Juillet",
+            "Août",
+            "Septembre",
+            "Octobre",
+            "Novembre",
+            "Décembre",
+        ]));
         assert_eq!(
-            parse_lot_price("{1.20PLN}"),
+            parse_transaction_date("05 FÉV 2024", &ctx),
             Ok((
                 "",
-                Price::Unit(Amount {
-                    quantity: Decimal::new(120, 2),
-                    commodity: Commodity {
-                        name: "PLN".to_owned(),
-                        position: CommodityPosition::Right
-                    }
-                })
+                (
+                    NaiveDate::from_ymd_opt(2024, 2, 5).unwrap(),
+                    DateStyle::DayMonthNameYear
+                )
             ))
         );
         assert_eq!(
-            parse_lot_price("{ 1.20 PLN } "),
-            Ok((
-                " ",
-                Price::Unit(Amount {
-                    quantity: Decimal::new(120, 2),
-                    commodity: Commodity {
-                        name: "PLN".to_owned(),
-                        position: CommodityPosition::Right
-                    }
-                })
-            ))
+            parse_transaction_date("05 juillet 2024", &ctx).map(|(_, (date, _))| date),
+            Ok(NaiveDate::from_ymd_opt(2024, 7, 5).unwrap())
         );
+        assert!(parse_transaction_date("05 Jui 2024", &ctx).is_err());
     }
 
     #[test]
-    fn parse_price_test() {
+    fn period_round_trip() {
+        for (period, text) in [
+            (Period::EveryNWeeks(1), "weekly"),
+            (Period::EveryNMonths(1), "monthly"),
+            (Period::EveryNDays(3), "every 3 days"),
+            (Period::EveryNWeeks(2), "every 2 weeks"),
+            (Period::EveryNMonths(3), "every 3 months"),
+            (Period::EveryNYears(2), "every 2 years"),
+        ] {
+            assert_eq!(period.to_string(), text);
+            assert_eq!(
+                parse_period(text).map(|(_, period)| period.to_string()),
+                Ok(text.to_owned())
+            );
+        }
+    }
+
+    #[test]
+    fn month_name_dates_round_trip() {
+        let settings = ParserSettings::default().with_month_names(&ENGLISH_MONTH_NAMES);
+        let input = "05 Jan 2024=10 Jan 2024 Shop\n  Expenses:Food  $5\n  Assets:Cash\n";
+        let (ledger, _) = crate::parse_with_settings(input, &settings).unwrap();
+        assert_eq!(ledger.to_string(), input);
+
+        let french = [
+            "Janvier",
+            "Février",
+            "Mars",
+            "Avril",
+            "Mai",
+            "Juin",
+            "Juillet",
+            "Août",
+            "Septembre",
+            "Octobre",
+            "Novembre",
+            "Décembre",
+        ];
+        let settings = ParserSettings::default().with_month_names(&french);
+        let input = "05 juin 2024=2 Fév 2025 Shop\n  Expenses:Food  $5\n  Assets:Cash\n";
+        let (ledger, _) = crate::parse_with_settings(input, &settings).unwrap();
+        let serializer_settings = crate::SerializerSettings::default().with_month_names(&french);
+        let output = crate::Serializer::to_string_pretty(&ledger, &serializer_settings);
         assert_eq!(
-            parse_price("@$1.20"),
-            Ok((
-                "",
-                Price::Unit(Amount {
-                    quantity: Decimal::new(120, 2),
-                    commodity: Commodity {
-                        name: "$".to_owned(),
-                        position: CommodityPosition::Left
-                    }
-                })
-            ))
+            output,
+            "05 Juin 2024=02 Fév 2025 Shop\n  Expenses:Food  $5\n  Assets:Cash\n"
         );
         assert_eq!(
-            parse_price("@ $1.20"),
-            Ok((
-                "",
-                Price::Unit(Amount {
-                    quantity: Decimal::new(120, 2),
-                    commodity: Commodity {
-                        name: "$".to_owned(),
-                        position: CommodityPosition::Left
-                    }
-                })
-            ))
+            crate::parse_with_settings(&output, &settings).unwrap().0,
+            ledger
         );
+    }
+
+    #[test]
+    fn parse_tags_test() {
+        let ledger = crate::parse(
+            r#"2024-01-02 Hotel  ; Trip: Paris
+  ; :business:
+  Expenses:Travel  EUR100  ; receipt: r1, :refund:
+  Assets:Cash
+"#,
+        )
+        .unwrap();
+        let transaction = ledger.transactions().next().unwrap();
+        let tag = |name: &str, value: Option<&str>| (name.to_owned(), value.map(str::to_owned));
         assert_eq!(
-            parse_price("@@1.20PLN"),
-            Ok((
-                "",
-                Price::Total(Amount {
-                    quantity: Decimal::new(120, 2),
-                    commodity: Commodity {
-                        name: "PLN".to_owned(),
-                        position: CommodityPosition::Right
-                    }
-                })
-            ))
+            transaction.tags,
+            vec![tag("Trip", Some("Paris")), tag("business", None)]
         );
         assert_eq!(
-            parse_price("@@ 1.20 PLN "),
-            Ok((
-                " ",
-                Price::Total(Amount {
-                    quantity: Decimal::new(120, 2),
-                    commodity: Commodity {
-                        name: "PLN".to_owned(),
-                        position: CommodityPosition::Right
-                    }
-                })
-            ))
+            transaction.postings[0].tags,
+            vec![tag("refund", None), tag("receipt", Some("r1"))]
         );
+        assert_eq!(transaction.postings[1].tags, vec![]);
     }
 
     #[test]
-    fn parse_posting_amount_test() {
+    fn parse_account_declaration_test() {
         assert_eq!(
-            parse_posting_amount("$1.20"),
+            parse_account_declaration("account Assets:Bank Account  ; main\n  note Checking\n  alias bank\n  payee ^ACME\n  check commodity == \"$\"\n  default\n", &Context::default()),
             Ok((
                 "",
-                PostingAmount {
-                    amount: Amount {
-                        quantity: Decimal::new(120, 2),
-                        commodity: Commodity {
-                            name: "$".to_owned(),
-                            position: CommodityPosition::Left
-                        }
-                    },
-                    lot_price: None,
-                    price: None
+                AccountDeclaration {
+                    name: "Assets:Bank Account".to_owned(),
+                    comment: Some("main".to_owned()),
+                    subdirectives: vec![
+                        AccountSubdirective::Note("Checking".to_owned()),
+                        AccountSubdirective::Alias("bank".to_owned()),
+                        AccountSubdirective::Payee("^ACME".to_owned()),
+                        AccountSubdirective::Check("commodity == \"$\"".to_owned()),
+                        AccountSubdirective::Default,
+                    ],
                 }
             ))
         );
         assert_eq!(
-            parse_posting_amount("$1.20 @ 5.00 PLN"),
+            parse_account_declaration("account Expenses", &Context::default()),
             Ok((
                 "",
-                PostingAmount {
-                    amount: Amount {
-                        quantity: Decimal::new(120, 2),
-                        commodity: Commodity {
-                            name: "$".to_owned(),
-                            position: CommodityPosition::Left
-                        }
-                    },
-                    lot_price: None,
-                    price: Some(Price::Unit(Amount {
-                        quantity: Decimal::new(500, 2),
-                        commodity: Commodity {
-                            name: "PLN".to_owned(),
-                            position: CommodityPosition::Right
-                        }
-                    }))
+                AccountDeclaration {
+                    name: "Expenses".to_owned(),
+                    comment: None,
+                    subdirectives: vec![],
                 }
             ))
         );
+        assert!(crate::parse("account Assets:Bank\n  frobnicate\n").is_err());
+
+        let input = "account Assets:Bank  ; main\n  note Checking\n  assert amount > 0\n2024-01-02 Shop\n  Expenses:Food  $5\n  Assets:Bank\n";
+        assert_eq!(crate::parse(input).unwrap().to_string(), input);
+    }
+
+    #[test]
+    fn parse_commodity_declaration_test() {
         assert_eq!(
-            parse_posting_amount("$1.20 {5.00 PLN}"),
+            parse_commodity_declaration("commodity $\n  note US dollar\n  format $1,000.00\n  alias USD\n  nomarket\n  default"),
             Ok((
                 "",
-                PostingAmount {
-                    amount: Amount {
-                        quantity: Decimal::new(120, 2),
-                        commodity: Commodity {
-                            name: "$".to_owned(),
-                            position: CommodityPosition::Left
-                        }
-                    },
-                    lot_price: Some(Price::Unit(Amount {
-                        quantity: Decimal::new(500, 2),
-                        commodity: Commodity {
-                            name: "PLN".to_owned(),
-                            position: CommodityPosition::Right
-                        }
-                    })),
-                    price: None,
+                CommodityDeclaration {
+                    name: "$".to_owned(),
+                    comment: None,
+                    subdirectives: vec![
+                        CommoditySubdirective::Note("US dollar".to_owned()),
+                        CommoditySubdirective::Format("$1,000.00".to_owned()),
+                        CommoditySubdirective::Alias("USD".to_owned()),
+                        CommoditySubdirective::NoMarket,
+                        CommoditySubdirective::Default,
+                    ],
                 }
             ))
         );
+
+        let input = "commodity 1.000,00 EUR  ; one-line form\ncommodity \"AAPL 2024\"\n  format 1.0 \"AAPL 2024\"\n";
+        assert_eq!(crate::parse(input).unwrap().to_string(), input);
+    }
+
+    #[test]
+    fn parse_payee_declaration_test() {
         assert_eq!(
-            parse_posting_amount("$1.20 {{5.00 PLN}} @@6.0PLN "),
+            parse_payee_declaration(
+                "payee Acme Corp  ; supplier\n  alias ^ACME\n  uuid 2a2e21d4\n",
+                &Context::default()
+            ),
             Ok((
-                " ",
-                PostingAmount {
-                    amount: Amount {
-                        quantity: Decimal::new(120, 2),
-                        commodity: Commodity {
-                            name: "$".to_owned(),
-                            position: CommodityPosition::Left
-                        }
-                    },
-                    lot_price: Some(Price::Total(Amount {
-                        quantity: Decimal::new(500, 2),
-                        commodity: Commodity {
-                            name: "PLN".to_owned(),
-                            position: CommodityPosition::Right
-                        }
-                    })),
-                    price: Some(Price::Total(Amount {
-                        quantity: Decimal::new(600, 2),
-                        commodity: Commodity {
-                            name: "PLN".to_owned(),
-                            position: CommodityPosition::Right
-                        }
-                    })),
+                "",
+                PayeeDeclaration {
+                    name: "Acme Corp".to_owned(),
+                    comment: Some("supplier".to_owned()),
+                    subdirectives: vec![
+                        PayeeSubdirective::Alias("^ACME".to_owned()),
+                        PayeeSubdirective::Uuid("2a2e21d4".to_owned()),
+                    ],
                 }
             ))
         );
+
+        let input = "payee Acme Corp  ; supplier\n  alias ^ACME\npayee Landlord\n";
+        assert_eq!(crate::parse(input).unwrap().to_string(), input);
     }
 
     #[test]
-    fn parse_balance_test() {
+    fn parse_tag_declaration_test() {
         assert_eq!(
-            parse_balance("$1.20"),
+            parse_tag_declaration("tag Receipt\n  check value =~ /pdf$/\n  assert value != \"\"\n"),
             Ok((
                 "",
-                Balance::Amount(Amount {
-                    quantity: Decimal::new(120, 2),
-                    commodity: Commodity {
-                        name: "$".to_owned(),
-                        position: CommodityPosition::Left
-                    }
-                })
+                TagDeclaration {
+                    name: "Receipt".to_owned(),
+                    comment: None,
+                    subdirectives: vec![
+                        TagSubdirective::Check("value =~ /pdf$/".to_owned()),
+                        TagSubdirective::Assert("value != \"\"".to_owned()),
+                    ],
+                }
             ))
         );
+
+        let input = "tag Receipt  ; scanned\n  check value =~ /pdf$/\ntag Trip\n";
+        assert_eq!(crate::parse(input).unwrap().to_string(), input);
+    }
+
+    #[test]
+    fn normalize_names_test() {
+        let input =
+            "payee Caf\u{e9}\n2024-01-02 Cafe\u{301}\n  Expenses:Caf\u{e9}  $5\n  Assets:CASH\n";
+        let settings = ParserSettings::default().with_fold_case(true);
+        let (ledger, _) = crate::parse_with_settings(input, &settings).unwrap();
+        let transaction = ledger.transactions().next().unwrap();
+        assert_eq!(transaction.description, "cafe\u{301}");
+        assert_eq!(transaction.postings[1].account, "assets:cash");
+
+        #[cfg(feature = "unicode")]
+        {
+            let settings = settings.with_normalize_names(true);
+            let (ledger, _) = crate::parse_with_settings(input, &settings).unwrap();
+            let transaction = ledger.transactions().next().unwrap();
+            assert_eq!(transaction.description, "caf\u{e9}");
+            assert_eq!(transaction.postings[0].account, "expenses:caf\u{e9}");
+            assert!(matches!(
+                &ledger.items[0],
+                LedgerItem::PayeeDeclaration(payee) if payee.name == "caf\u{e9}"
+            ));
+        }
+    }
+
+    #[test]
+    fn parse_year_test() {
+        assert_eq!(parse_year("Y 2024\n"), Ok(("", 2024)));
+        assert_eq!(parse_year("year 2023  "), Ok(("", 2023)));
+        assert!(parse_year("Y 24\n").is_err());
+
+        let ctx = Context::default();
+        assert!(parse_transaction_date("03/15", &ctx).is_err());
+        ctx.year.set(Some(2024));
         assert_eq!(
-            parse_balance("0 PLN"),
+            parse_transaction_date("3/15 Shop", &ctx),
             Ok((
-                "",
-                Balance::Amount(Amount {
-                    quantity: Decimal::new(0, 0),
-                    commodity: Commodity {
-                        name: "PLN".to_owned(),
-                        position: CommodityPosition::Right
-                    }
-                })
+                " Shop",
+                (
+                    NaiveDate::from_ymd_opt(2024, 3, 15).unwrap(),
+                    DateStyle::MonthDay
+                )
             ))
         );
-        assert_eq!(parse_balance("0"), Ok(("", Balance::Zero)));
+        assert!(parse_transaction_date("02/30", &ctx).is_err());
     }
 
     #[test]
-    fn parse_commodity_price_test() {
+    fn partial_dates_round_trip() {
+        let input = "Y 2024\n03/15=03/20 Shop\n  Expenses:Food  $5\n  Assets:Cash\nyear 2023\n12/31 Shop\n  Expenses:Food  $5\n  Assets:Cash\n";
+        let ledger = crate::parse(input).unwrap();
+        let dates: Vec<NaiveDate> = ledger.transactions().map(|t| t.date).collect();
+        assert_eq!(
+            dates,
+            vec![
+                NaiveDate::from_ymd_opt(2024, 3, 15).unwrap(),
+                NaiveDate::from_ymd_opt(2023, 12, 31).unwrap(),
+            ]
+        );
+        assert_eq!(ledger.to_string(), input.replace("year 2023", "Y 2023"));
+        assert!(crate::parse("03/15 Shop\n  Expenses:Food  $5\n  Assets:Cash\n").is_err());
+    }
+
+    #[test]
+    fn parse_default_commodity_test() {
+        assert_eq!(
+            parse_default_commodity("D 1.000 EUR  \n", &Context::default()),
+            Ok(("", "1.000 EUR".to_owned()))
+        );
+        assert!(parse_default_commodity("D EUR\n", &Context::default()).is_err());
+    }
+
+    #[test]
+    fn parse_commodity_conversion_test() {
         assert_eq!(
-            parse_commodity_price("P 2017-11-12 12:00:00 mBH 5.00 PLN"),
+            parse_commodity_conversion("C 1.00 Kb = 1024 bytes\n", &Context::default()),
             Ok((
                 "",
-                CommodityPrice {
-                    datetime: NaiveDate::from_ymd(2017, 11, 12).and_hms(12, 00, 00),
-                    commodity_name: "mBH".to_owned(),
-                    amount: Amount {
-                        quantity: Decimal::new(500, 2),
+                CommodityConversion {
+                    from: Amount {
+                        quantity: Decimal::new(100, 2),
                         commodity: Commodity {
-                            name: "PLN".to_owned(),
+                            name: "Kb".to_owned(),
+                            position: CommodityPosition::Right
+                        }
+                    },
+                    to: Amount {
+                        quantity: Decimal::new(1024, 0),
+                        commodity: Commodity {
+                            name: "bytes".to_owned(),
                             position: CommodityPosition::Right
                         }
                     }
                 }
             ))
         );
+
+        let input = "C 1.00 Mb = 1024 Kb\nC 1 h = 60 m\n";
+        assert_eq!(crate::parse(input).unwrap().to_string(), input);
     }
 
     #[test]
-    fn parse_account_test() {
-        assert_eq!(
-            parse_account("TEST:ABC 123  "),
-            Ok(("  ", ("TEST:ABC 123", Reality::Real)))
-        );
-        assert_eq!(
-            parse_account("TEST:ABC 123\t"),
-            Ok(("\t", ("TEST:ABC 123", Reality::Real)))
-        );
-        assert_eq!(
-            parse_account("TEST:ABC 123"),
-            Ok(("", ("TEST:ABC 123", Reality::Real)))
-        );
+    fn parse_no_market_test() {
+        assert_eq!(parse_no_market("N EUR \n"), Ok(("", "EUR".to_owned())));
         assert_eq!(
-            parse_account("[TEST:ABC 123]"),
-            Ok(("", ("TEST:ABC 123", Reality::BalancedVirtual)))
+            parse_no_market("N \"Gift Card\""),
+            Ok(("", "Gift Card".to_owned()))
         );
+        assert!(parse_no_market("N\n").is_err());
+
+        let ledger = crate::parse("N EUR\ncommodity AAPL\n  nomarket\ncommodity $\n").unwrap();
         assert_eq!(
-            parse_account("(TEST:ABC 123)"),
-            Ok(("", ("TEST:ABC 123", Reality::UnbalancedVirtual)))
+            ledger
+                .no_market_commodities()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            ["AAPL", "EUR"]
         );
+        assert!(ledger.to_string().starts_with("N EUR\n"));
     }
 
     #[test]
-    fn parse_transaction_status_test() {
+    fn parse_bucket_test() {
+        let ctx = Context::new(ParserSettings::default().with_account_separator('.'));
         assert_eq!(
-            parse_transaction_status("!"),
-            Ok(("", TransactionStatus::Pending))
+            parse_bucket("bucket Assets.Checking \n", &ctx),
+            Ok(("", "Assets:Checking".to_owned()))
         );
         assert_eq!(
-            parse_transaction_status("*"),
-            Ok(("", TransactionStatus::Cleared))
+            parse_bucket("A Assets:Cash", &Context::default()),
+            Ok(("", "Assets:Cash".to_owned()))
         );
+        assert!(parse_bucket("bucket\n", &Context::default()).is_err());
     }
 
     #[test]
-    fn parse_posting_test() {
+    fn parse_definition_test() {
         assert_eq!(
-            parse_posting(" TEST:ABC 123  $1.20"),
+            parse_definition("define exchange_rate = 1.1 \n"),
             Ok((
                 "",
-                Posting {
-                    account: "TEST:ABC 123".to_owned(),
-                    reality: Reality::Real,
-                    amount: Some(PostingAmount {
-                        amount: Amount {
-                            quantity: Decimal::new(120, 2),
-                            commodity: Commodity {
-                                name: "$".to_owned(),
-                                position: CommodityPosition::Left
-                            }
-                        },
-                        lot_price: None,
-                        price: None
-                    }),
-                    balance: None,
-                    status: None,
-                    comment: None,
+                Definition {
+                    name: "exchange_rate".to_owned(),
+                    value: "1.1".to_owned()
                 }
             ))
         );
+        assert!(parse_definition("define rate\n").is_err());
+
+        let ledger = crate::parse("define rate=1.1\ndefine fee=$2\ndefine rate=1.2\n").unwrap();
+        assert_eq!(ledger.definitions()["rate"], "1.2");
+        assert_eq!(ledger.definitions()["fee"], "$2");
+        assert!(ledger.to_string().starts_with("define rate=1.1\n"));
+    }
+
+    #[test]
+    fn parse_query_test() {
+        let account = |pattern: &str| Query::Account(pattern.to_owned());
+        assert_eq!(
+            parse_query("/^Expenses:Books/", &Context::default()),
+            Ok(("", account("^Expenses:Books")))
+        );
         assert_eq!(
-            parse_posting(" ! TEST:ABC 123  $1.20;test\n;comment line 2"),
+            parse_query("food dining  ; note", &Context::default()),
             Ok((
-                "",
-                Posting {
-                    account: "TEST:ABC 123".to_owned(),
-                    reality: Reality::Real,
-                    amount: Some(PostingAmount {
-                        amount: Amount {
-                            quantity: Decimal::new(120, 2),
-                            commodity: Commodity {
-                                name: "$".to_owned(),
-                                position: CommodityPosition::Left
-                            }
-                        },
-                        lot_price: None,
-                        price: None
-                    }),
-                    balance: None,
-                    status: Some(TransactionStatus::Pending),
-                    comment: Some("test\ncomment line 2".to_owned())
-                }
+                "  ; note",
+                Query::Or(vec![account("food"), account("dining")])
             ))
         );
         assert_eq!(
-            parse_posting(" ! TEST:ABC 123;test\n;comment"),
+            parse_query("expenses and not (@Acme | %trip=rome)", &Context::default()),
             Ok((
                 "",
-                Posting {
-                    account: "TEST:ABC 123;test".to_owned(),
-                    reality: Reality::Real,
-                    amount: None,
-                    balance: None,
-                    status: Some(TransactionStatus::Pending),
-                    comment: Some("comment".to_owned())
-                }
+                Query::And(vec![
+                    account("expenses"),
+                    Query::Not(Box::new(Query::Or(vec![
+                        Query::Payee("Acme".to_owned()),
+                        Query::Tag("trip".to_owned(), Some("rome".to_owned())),
+                    ]))),
+                ])
             ))
         );
         assert_eq!(
-            parse_posting(" ! TEST:ABC 123  ;test\n;comment line 2"),
+            parse_query("expr 'account =~ /Books/'", &Context::default()),
+            Ok(("", Query::Expression("account =~ /Books/".to_owned())))
+        );
+        assert!(parse_query("and", &Context::default()).is_err());
+    }
+
+    #[test]
+    fn parse_automated_transaction_test() {
+        let input = r#"= /^Expenses:Books/ or %gift and @Shop
+  ; automated
+  (Budget:Books)  -1
+  Assets:Tax  0.1
+  Liabilities:Fee  $1
+"#;
+        let ledger = crate::parse(input).unwrap();
+        let automated = match &ledger.items[0] {
+            LedgerItem::AutomatedTransaction(automated) => automated,
+            item => panic!("unexpected item {:?}", item),
+        };
+        assert_eq!(automated.comment.as_deref(), Some("automated"));
+        let multiplier = &automated.postings[1].amount.as_ref().unwrap().amount;
+        assert_eq!(multiplier.quantity, Decimal::new(1, 1));
+        assert_eq!(multiplier.commodity.name, "");
+        assert_eq!(ledger.to_string(), input);
+
+        // Multipliers don't leak out of automated transactions
+        assert!(crate::parse(&format!("{}2024-01-01 Shop\n  Expenses  1\n", input)).is_err());
+    }
+
+    #[test]
+    fn parse_expression_test() {
+        let ctx = Context::default();
+        let dollars = |quantity| {
+            AmountExpr::Amount(Amount {
+                quantity,
+                commodity: Commodity {
+                    name: "$".to_owned(),
+                    position: CommodityPosition::Left,
+                },
+            })
+        };
+        assert_eq!(
+            parse_expression("$10.00 * 3", &ctx),
             Ok((
                 "",
-                Posting {
-                    account: "TEST:ABC 123".to_owned(),
-                    reality: Reality::Real,
-                    amount: None,
-                    balance: None,
-                    status: Some(TransactionStatus::Pending),
-                    comment: Some("test\ncomment line 2".to_owned())
-                }
+                AmountExpr::Mul(
+                    Box::new(dollars(Decimal::new(1000, 2))),
+                    Box::new(AmountExpr::Number(Decimal::new(3, 0)))
+                )
             ))
         );
         assert_eq!(
-            parse_posting(" ! TEST:ABC 123   ;  test     \n       ;        comment line 2     "),
+            parse_expression("2 * floor($amount) - -$1)", &ctx),
             Ok((
-                "",
-                Posting {
-                    account: "TEST:ABC 123".to_owned(),
-                    reality: Reality::Real,
-                    amount: None,
-                    balance: None,
-                    status: Some(TransactionStatus::Pending),
-                    comment: Some("test\ncomment line 2".to_owned())
-                }
+                ")",
+                AmountExpr::Sub(
+                    Box::new(AmountExpr::Mul(
+                        Box::new(AmountExpr::Number(Decimal::new(2, 0))),
+                        Box::new(AmountExpr::Call(
+                            "floor".to_owned(),
+                            vec![AmountExpr::Variable("$amount".to_owned())]
+                        ))
+                    )),
+                    Box::new(dollars(Decimal::new(-1, 0)))
+                )
             ))
         );
+    }
+
+    #[test]
+    fn posting_expressions_test() {
+        let input = r#"define rate=1.1
+D $1.00
+2024-01-02 Shop
+  Expenses:Food  ($10.00 * 3)
+  Expenses:Fee  (2 * (rate - 1) * 5 EUR / 4)
+  Assets:Cash  (-30 - 2)
+= /^Expenses:Food/
+  (Budget:Food)  (amount * -1)
+"#;
+        let ledger = crate::parse(input).unwrap();
+        let postings = &ledger.transactions().next().unwrap().postings;
+        let amounts: Vec<String> = postings
+            .iter()
+            .map(|posting| posting.amount.as_ref().unwrap().amount.to_string())
+            .collect();
+        assert_eq!(amounts, ["$30.00", "0.250 EUR", "-32"]);
+        assert_eq!(ledger.to_string(), input);
+    }
+
+    #[test]
+    fn parenthesized_negatives_test() {
+        let input = "2024-01-02 Bank\n  Expenses:Fees  ( $1,200.00 ) @ 1.1 EUR\n  Expenses:Food  ($10.00 * 3)\n  Assets:Bank\n";
+        let settings = ParserSettings::default().with_parenthesized_negatives(true);
+        let (ledger, _) = crate::parse_with_settings(input, &settings).unwrap();
+        let postings = &ledger.transactions().next().unwrap().postings;
+        let amount = postings[0].amount.as_ref().unwrap();
+        assert_eq!(amount.amount.to_string(), "$-1200.00");
+        assert!(amount.price.is_some());
+        assert!(postings[0].amount_expr.is_none());
+        assert!(postings[1].amount_expr.is_some());
+
+        let ledger =
+            crate::parse("2024-01-02 Bank\n  Expenses:Fees  ($1,200.00)\n  Assets:Bank\n").unwrap();
+        let postings = &ledger.transactions().next().unwrap().postings;
+        assert!(postings[0].amount_expr.is_some());
+    }
+
+    #[test]
+    fn decimal_mark_test() {
+        let settings = ParserSettings::default().with_decimal_mark(',');
+        let ctx = Context::new(settings);
+        assert_eq!(
+            parse_quantity("-1.234.567,89", &ctx, ','),
+            Ok(("", Decimal::new(-123456789, 2)))
+        );
         assert_eq!(
-            parse_posting(" TEST:ABC 123  $1.20 = $2.40 ;comment"),
+            parse_amount("€12,5", &ctx),
             Ok((
                 "",
-                Posting {
-                    account: "TEST:ABC 123".to_owned(),
-                    reality: Reality::Real,
-                    amount: Some(PostingAmount {
-                        amount: Amount {
-                            quantity: Decimal::new(120, 2),
-                            commodity: Commodity {
-                                name: "$".to_owned(),
-                                position: CommodityPosition::Left
-                            }
-                        },
-                        lot_price: None,
-                        price: None
-                    }),
-                    balance: Some(Balance::Amount(Amount {
-                        quantity: Decimal::new(240, 2),
-                        commodity: Commodity {
-                            name: "$".to_owned(),
-                            position: CommodityPosition::Left
-                        }
-                    })),
-                    status: None,
-                    comment: Some("comment".to_owned())
+                Amount {
+                    quantity: Decimal::new(125, 1),
+                    commodity: Commodity {
+                        name: "€".to_owned(),
+                        position: CommodityPosition::Left,
+                    },
                 }
             ))
         );
+
+        assert_eq!(format_decimal_mark("1.000,00 EUR"), Some(','));
+        assert_eq!(format_decimal_mark("1,5 EUR"), Some(','));
+        assert_eq!(format_decimal_mark("1.000.000 EUR"), Some(','));
+        assert_eq!(format_decimal_mark("$1,000.00"), Some('.'));
+        assert_eq!(format_decimal_mark("1,000 EUR"), None);
+        assert_eq!(format_decimal_mark("EUR"), None);
+
+        let input = r#"commodity 1.000,00 EUR
+2024-01-02 Shop
+  Expenses:Food  1.234,50 EUR @ $1.10
+  Assets:Cash  $-1,357.95
+"#;
+        let ledger = crate::parse(input).unwrap();
+        let transaction = ledger.transactions().next().unwrap();
+        let amount = transaction.postings[0].amount.as_ref().unwrap();
+        assert_eq!(amount.amount.quantity, Decimal::new(123450, 2));
+        assert_eq!(
+            amount.price,
+            Some(Price::Unit(Amount {
+                quantity: Decimal::new(110, 2),
+                commodity: Commodity {
+                    name: "$".to_owned(),
+                    position: CommodityPosition::Left,
+                },
+            }))
+        );
+        assert!(
+            crate::parse("2024-01-02 Shop\n  Expenses:Food  1.234,50 EUR\n  Assets:Cash\n")
+                .is_err()
+        );
+
+        let settings = crate::SerializerSettings::default()
+            .with_commodity_styles(ledger.infer_commodity_styles());
+        let output = crate::Serializer::to_string_pretty(&ledger, &settings);
+        assert!(output.contains("  Expenses:Food  1234,50 EUR @ $1.10\n"));
+        assert_eq!(crate::parse(&output).unwrap(), ledger);
+    }
+
+    #[test]
+    fn parse_lot_date_test() {
+        let ctx = Context::default();
+        let (rest, amount) =
+            parse_posting_amount("10 AAPL {$150.00} [2023-05-01] @ $170", &ctx).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(amount.lot_date, NaiveDate::from_ymd_opt(2023, 5, 1));
+        assert!(amount.lot_price.is_some() && amount.price.is_some());
+        assert_eq!(
+            parse_posting_amount("-5 AAPL [ 2023/05/01 ]", &ctx)
+                .unwrap()
+                .1
+                .to_string(),
+            "-5 AAPL [2023-05-01]"
+        );
+        assert!(parse_posting_amount("10 AAPL [2023-02-30]", &ctx)
+            .is_ok_and(|(rest, _)| !rest.is_empty()));
+
+        let input = "2024-01-02 Sell\n  Assets:Broker  -10 AAPL {$150.00} [2023-05-01] @ $170.00\n  Assets:Cash\n";
+        assert_eq!(crate::parse(input).unwrap().to_string(), input);
+    }
+
+    #[test]
+    fn decimal_mark_directive_test() {
+        assert_eq!(parse_decimal_mark("decimal-mark , \n"), Ok(("", ',')));
+        assert!(parse_decimal_mark("decimal-mark ;\n").is_err());
+
+        let input = r#"2024-01-01 Before
+  Expenses:Food  1,000 EUR
+  Assets:Cash
+decimal-mark ,
+commodity 1.000 USD
+2024-01-02 After
+  Expenses:Food  1.234,50 EUR
+  Expenses:Fees  1,000 EUR
+  Expenses:Tax  2,5 USD
+  Assets:Cash
+"#;
+        let (ledger, warnings) =
+            crate::parse_with_settings(input, &ParserSettings::default()).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(ledger.items[1], LedgerItem::DecimalMark(','));
+        let quantities: Vec<Vec<Decimal>> = ledger
+            .transactions()
+            .map(|t| {
+                t.postings
+                    .iter()
+                    .filter_map(|p| p.amount.as_ref())
+                    .map(|a| a.amount.quantity)
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            quantities,
+            vec![
+                vec![Decimal::new(1000, 0)],
+                vec![
+                    Decimal::new(123450, 2),
+                    Decimal::new(1, 0),
+                    Decimal::new(25, 1)
+                ],
+            ]
+        );
+
+        let output = ledger.to_string();
+        assert!(output.contains("decimal-mark ,\n"));
+        assert!(output.contains("  Expenses:Food  1234,50 EUR\n"));
+        assert_eq!(crate::parse(&output).unwrap(), ledger);
+
+        let input = "2024-01-01 X\n  A  1.5 EUR\n  B\ndecimal-mark ,\n2024-01-01 X\n  A  1.234,56 EUR\n  B\n";
+        let ledger = crate::parse(input).unwrap();
+        let output = ledger.to_string();
+        assert_eq!(output, input.replace("1.234,56", "1234,56"));
+        assert_eq!(crate::parse(&output).unwrap(), ledger);
+        let mut lossless = crate::parse_lossless(input, &ParserSettings::default()).unwrap();
+        lossless.items[2].item_mut();
+        assert_eq!(lossless.to_string(), output);
+    }
+
+    #[test]
+    fn parse_event_and_note_test() {
         assert_eq!(
-            parse_posting(" TEST:ABC 123"),
+            parse_event("event 2024-03-01 Moved to Berlin  \n"),
             Ok((
                 "",
-                Posting {
-                    account: "TEST:ABC 123".to_owned(),
-                    reality: Reality::Real,
-                    amount: None,
-                    balance: None,
-                    status: None,
-                    comment: None
+                Event {
+                    date: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+                    description: "Moved to Berlin".to_owned(),
                 }
             ))
         );
+        assert!(parse_event("event Moved\n").is_err());
+        assert_eq!(parse_note("note Rent went up\n"), Ok(("", "Rent went up")));
         assert_eq!(
-            parse_posting(" TEST:ABC 123   ; 456"),
+            parse_comment_hint(";~ budget: monthly\n"),
             Ok((
                 "",
-                Posting {
-                    account: "TEST:ABC 123".to_owned(),
-                    reality: Reality::Real,
-                    amount: None,
-                    balance: None,
-                    status: None,
-                    comment: Some("456".to_owned()),
+                CommentHint {
+                    marker: '~',
+                    text: "budget: monthly".to_owned(),
                 }
             ))
         );
+
+        let input =
+            "event 2024-03-01 Moved to Berlin\nnote Rent went up\n;> check receipts\n;~\n; plain\n";
+        let ledger = crate::parse(input).unwrap();
+        assert_eq!(ledger.events().count(), 1);
+        assert_eq!(ledger.items[4], LedgerItem::LineComment("plain".to_owned()));
+        assert_eq!(ledger.to_string(), input);
+    }
+
+    #[test]
+    fn parse_value_directive_test() {
+        assert_eq!(
+            parse_value_directive("check account =~ /^Assets/  \n"),
+            Ok(("\n", LedgerItem::Check("account =~ /^Assets/".to_owned())))
+        );
+        assert!(parse_value_directive("check\n").is_err());
+
+        let input = "assert amount > 0\ncheck commodity == \"$\"\neval total = 0\nexpr foo(1, 2)\n";
+        let ledger = crate::parse(input).unwrap();
+        assert_eq!(ledger.items[0], LedgerItem::Assert("amount > 0".to_owned()));
+        assert_eq!(ledger.to_string(), input);
+        assert_eq!(ledger.items[3], LedgerItem::Expr("foo(1, 2)".to_owned()));
+        assert!(crate::parse("checking account\n").is_err());
+        assert!(crate::parse("evaluate x\n").is_err());
+    }
+
+    #[test]
+    fn parse_lot_note_test() {
+        let ctx = Context::default();
+        let (rest, amount) = parse_posting_amount(
+            "10 AAPL {$150.00} [2023-05-01] ( first purchase ) @ $170",
+            &ctx,
+        )
+        .unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(amount.lot_note.as_deref(), Some("first purchase"));
+        assert_eq!(
+            amount.to_string(),
+            "10 AAPL {$150.00} [2023-05-01] (first purchase) @ $170"
+        );
+        assert_eq!(parse_posting_amount("10 AAPL ()", &ctx).unwrap().0, " ()");
     }
 
     #[test]
-    fn pares_period_test() {
-        assert_eq!(parse_period("daily"), Ok(("", Period::Daily)));
-        assert_eq!(parse_period("weekly"), Ok(("", Period::Weekly)));
-        assert_eq!(parse_period("monthly"), Ok(("", Period::Monthly)));
-        assert_eq!(parse_period("yearly"), Ok(("", Period::Yearly)));
-        assert_eq!(parse_period("every day"), Ok(("", Period::EveryNDays(1))));
-        assert_eq!(parse_period("every week"), Ok(("", Period::EveryNWeeks(1))));
+    fn parse_fixed_lot_price_test() {
+        let ctx = Context::default();
+        let (rest, amount) = parse_posting_amount("10 AAPL {= $10.00} [2023-05-01]", &ctx).unwrap();
+        assert_eq!(rest, "");
+        assert!(amount.lot_price_fixed);
+        assert_eq!(amount.to_string(), "10 AAPL {=$10.00} [2023-05-01]");
+
+        let (_, amount) = parse_posting_amount("10 AAPL {{=$100}} @ $12", &ctx).unwrap();
+        assert!(amount.lot_price_fixed);
+        assert!(matches!(amount.lot_price, Some(Price::Total(_))));
+        assert_eq!(amount.to_string(), "10 AAPL {{=$100}} @ $12");
+
+        let (_, amount) = parse_posting_amount("10 AAPL {$10.00}", &ctx).unwrap();
+        assert!(!amount.lot_price_fixed);
+    }
+
+    #[test]
+    fn parse_lot_annotation_order_test() {
+        let ctx = Context::default();
+        let (rest, amount) = parse_posting_amount(
+            "10 AAPL (first purchase) [2023-05-01] {=$150.00} @ $170",
+            &ctx,
+        )
+        .unwrap();
+        assert_eq!(rest, "");
         assert_eq!(
-            parse_period("every month"),
-            Ok(("", Period::EveryNMonths(1)))
+            amount.to_string(),
+            "10 AAPL {=$150.00} [2023-05-01] (first purchase) @ $170"
         );
-        assert_eq!(parse_period("every year"), Ok(("", Period::EveryNYears(1))));
-        assert_eq!(parse_period("biweekly"), Ok(("", Period::EveryNWeeks(2))));
-        assert_eq!(parse_period("bimonthly"), Ok(("", Period::EveryNMonths(2))));
         assert_eq!(
-            parse_period("every 3 days"),
-            Ok(("", Period::EveryNDays(3)))
+            parse_posting_amount(&amount.to_string(), &ctx).unwrap().1,
+            amount
         );
         assert_eq!(
-            parse_period("every 12 weeks"),
-            Ok(("", Period::EveryNWeeks(12)))
+            parse_posting_amount("10 AAPL [2023-05-01] {$150.00}", &ctx)
+                .unwrap()
+                .1
+                .to_string(),
+            "10 AAPL {$150.00} [2023-05-01]"
         );
         assert_eq!(
-            parse_period("every 23 months"),
-            Ok(("", Period::EveryNMonths(23)))
+            parse_posting_amount("10 AAPL [2023-05-01] [2023-05-02]", &ctx)
+                .unwrap()
+                .0,
+            " [2023-05-02]"
         );
+    }
+
+    #[test]
+    fn parse_fragment_test() {
+        let settings = ParserSettings::default();
         assert_eq!(
-            parse_period("every 2 years"),
-            Ok(("", Period::EveryNYears(2)))
+            crate::parse_amount(" $1,000.50 ", &settings)
+                .unwrap()
+                .to_string(),
+            "$1000.50"
         );
+        assert!(crate::parse_amount("$10 @ $2", &settings).is_err());
+        assert!(crate::parse_date("2024-01-02 Shop").is_err());
         assert_eq!(
-            parse_period("2023-01-01"),
-            Ok(("", Period::Date(NaiveDate::from_ymd(2023, 01, 01))))
+            crate::parse_posting("  (Budget:Food)  $-5\n", &settings)
+                .unwrap()
+                .reality,
+            Reality::UnbalancedVirtual
+        );
+        assert!(crate::parse_transaction("2024-01-02 Shop\n", &settings).is_err());
+        assert!(crate::parse_transaction(
+            "2024-01-02 Shop\n  Expenses:Food  $5\n  Assets:Cash\n2024-01-03 Shop\n  Expenses:Food  $5\n  Assets:Cash\n",
+            &settings
         )
+        .is_err());
     }
 
     #[test]
-    fn parse_periodic_transactions_test() {
+    fn parse_balance_kind_test() {
+        let ctx = Context::default();
+        for (operator, kind) in [
+            ("=", BalanceKind::Single),
+            ("==", BalanceKind::Sole),
+            ("=*", BalanceKind::SingleInclusive),
+            ("==*", BalanceKind::SoleInclusive),
+        ] {
+            let input = format!("  Assets:Cash  $5 {} $100", operator);
+            let (_, posting) = parse_posting(&input, &ctx).unwrap();
+            assert_eq!(posting.balance_kind, kind);
+            assert_eq!(posting.to_string(), input.trim_start());
+        }
+
+        let input = "2024-01-02 Check\n  Assets:Cash  ==* 0\n  Equity\n";
+        assert_eq!(crate::parse(input).unwrap().to_string(), input);
+    }
+
+    #[test]
+    fn syntax_error_test() {
+        let error = crate::parse(
+            "; ok\n2024-01-02 Shop\n  Expenses:Food  $5\n  Assets:Cash\r\nbad line\r\n",
+        )
+        .unwrap_err();
+        let crate::ParseError::Syntax(ref syntax) = error else {
+            panic!("{:?}", error);
+        };
         assert_eq!(
-            parse_periodic_transaction(
-                r#"~ monthly 
- TEST:ABC 123  $1.20 ; Posting comment
-                     ; over two lines
- TEST:ABC 123  $1.20"#
-            ),
-            Ok((
-                "",
-                PeriodicTransaction {
-                    period: Period::Monthly,
-                    comment: None,
-                    start_date: None,
-                    end_date: None,
-                    postings: vec![
-                        Posting {
-                            account: "TEST:ABC 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(120, 2),
-                                    commodity: Commodity {
-                                        name: "$".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            balance: None,
-                            status: None,
-                            comment: Some("Posting comment\nover two lines".to_owned()),
-                        },
-                        Posting {
-                            account: "TEST:ABC 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(120, 2),
-                                    commodity: Commodity {
-                                        name: "$".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            balance: None,
-                            status: None,
-                            comment: None,
-                        }
-                    ],
-                }
-            ))
+            *syntax,
+            crate::SyntaxError {
+                line: 5,
+                column: 1,
+                snippet: "bad line".to_owned(),
+                expected: "a transaction, directive or comment".to_owned(),
+            }
         );
-
         assert_eq!(
-            parse_periodic_transaction(
-                r#"~ every 3 months 
- TEST:ABC 123  $1.20 ; Posting comment
-                     ; over two lines
- TEST:ABC 123  $1.20"#
-            ),
-            Ok((
-                "",
-                PeriodicTransaction {
-                    period: Period::EveryNMonths(3),
-                    comment: None,
-                    start_date: None,
-                    end_date: None,
-                    postings: vec![
-                        Posting {
-                            account: "TEST:ABC 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(120, 2),
-                                    commodity: Commodity {
-                                        name: "$".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            balance: None,
-                            status: None,
-                            comment: Some("Posting comment\nover two lines".to_owned()),
-                        },
-                        Posting {
-                            account: "TEST:ABC 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(120, 2),
-                                    commodity: Commodity {
-                                        name: "$".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            balance: None,
-                            status: None,
-                            comment: None,
-                        }
-                    ],
-                }
-            ))
+            error.to_string(),
+            "line 5, column 1: expected a transaction, directive or comment\nbad line\n^"
         );
 
-        assert_eq!(
-            parse_periodic_transaction(
-                r#"~ every 3 months from 2023-01-01 to 2023-02-01
- TEST:ABC 123  $1.20 ; Posting comment
-                     ; over two lines
- TEST:ABC 123  $1.20"#
+        for (input, position, expected) in [
+            (
+                "; ok\n2024-01-02 Shop\n  Expenses:Food  10 AAPL {bad}\n  Assets:Cash\n",
+                (3, 26),
+                "a lot price, price, balance assertion or comment",
             ),
-            Ok((
-                "",
-                PeriodicTransaction {
-                    period: Period::EveryNMonths(3),
-                    comment: None,
-                    start_date: Some(NaiveDate::from_ymd(2023, 01, 01)),
-                    end_date: Some(NaiveDate::from_ymd(2023, 02, 01)),
-                    postings: vec![
-                        Posting {
-                            account: "TEST:ABC 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(120, 2),
-                                    commodity: Commodity {
-                                        name: "$".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            balance: None,
-                            status: None,
-                            comment: Some("Posting comment\nover two lines".to_owned()),
-                        },
-                        Posting {
-                            account: "TEST:ABC 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(120, 2),
-                                    commodity: Commodity {
-                                        name: "$".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            balance: None,
-                            status: None,
-                            comment: None,
-                        }
-                    ],
-                }
-            ))
-        );
-        assert_eq!(
-            parse_periodic_transaction(
-                r#"~ every 3 months from 2023-01-01 to 2023-02-01 ; Transaction comment
- TEST:ABC 123  $1.20 ; Posting comment
-                     ; over two lines
- TEST:ABC 123  $1.20"#
+            (
+                "2024-01-02 Shop\n  Expenses:Food  1e99999 USD\n  Assets:Cash\n",
+                (2, 18),
+                "an amount, balance assertion or comment",
             ),
-            Ok((
-                "",
-                PeriodicTransaction {
-                    period: Period::EveryNMonths(3),
-                    comment: Some("Transaction comment".to_owned()),
-                    start_date: Some(NaiveDate::from_ymd(2023, 01, 01)),
-                    end_date: Some(NaiveDate::from_ymd(2023, 02, 01)),
-                    postings: vec![
-                        Posting {
-                            account: "TEST:ABC 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(120, 2),
-                                    commodity: Commodity {
-                                        name: "$".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            balance: None,
-                            status: None,
-                            comment: Some("Posting comment\nover two lines".to_owned()),
-                        },
-                        Posting {
-                            account: "TEST:ABC 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(120, 2),
-                                    commodity: Commodity {
-                                        name: "$".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            balance: None,
-                            status: None,
-                            comment: None,
-                        }
-                    ],
+            (
+                "2024-01-02 Shop\n  Expenses:Food  $5 = $5 x\n  Assets:Cash\n",
+                (2, 26),
+                "a comment",
+            ),
+            ("2024-01-02 Shop\n", (2, 1), "a posting"),
+        ] {
+            match crate::parse(input) {
+                Err(crate::ParseError::Syntax(syntax)) => {
+                    assert_eq!((syntax.line, syntax.column), position, "{:?}", input);
+                    assert_eq!(syntax.expected, expected);
                 }
-            ))
+                result => panic!("{:?}", result),
+            }
+        }
+
+        let deep = format!("2024-01-02 Shop\n  Ünk  {}$1\n", "(".repeat(100));
+        match crate::parse(&deep) {
+            Err(crate::ParseError::Syntax(syntax)) => {
+                assert_eq!((syntax.line, syntax.column), (2, 73));
+                assert_eq!(syntax.expected, "less nesting or fewer operators");
+            }
+            result => panic!("{:?}", result),
+        }
+
+        let error = crate::parse_amount("$10 @ $2", &ParserSettings::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 1, column 4: expected end of input\n$10 @ $2\n   ^"
+        );
+    }
+
+    #[test]
+    fn adversarial_input_test() {
+        let deep = format!(
+            "2024-01-02 Shop\n  Expenses:Food  {}$1{}\n  Assets:Cash\n",
+            "(".repeat(100_000),
+            ")".repeat(100_000)
+        );
+        assert!(crate::parse(&deep).is_err());
+        let nested = format!(
+            "{}$1{}",
+            "(".repeat(MAX_NESTING - 1),
+            ")".repeat(MAX_NESTING - 1)
+        );
+        assert!(expression_from_str(&nested).is_some());
+        assert!(expression_from_str(&format!("(({}))", nested)).is_none());
+
+        let long = format!("$1{}", " + 1".repeat(MAX_OPERATORS + 1));
+        assert!(expression_from_str(&long).is_none());
+        let negated = format!("{}$1", "-".repeat(100_000));
+        assert!(expression_from_str(&negated).is_none());
+
+        let query = format!("= {}/Food/\n  (Budget)  -1\n", "not ".repeat(100_000));
+        assert!(crate::parse(&query).is_err());
+
+        for input in [
+            "2024-01-02 Shop\n  Expenses:Food  1e999999999 EUR\n  Assets:Cash\n",
+            "2024-01-02 Shop\n  Expenses:Food  $99999999999999999999999999999999\n  Assets:Cash\n",
+            "99999-99-99 Shop\n  Expenses:Food  $1\n  Assets:Cash\n",
+            "Y 99999999999999999999\n",
+            "P 2024-01-01 25:00:00 AAPL $1\n",
+        ] {
+            assert!(crate::parse(input).is_err(), "{:?}", input);
+        }
+        // Whitespace-only lines where a posting is expected
+        for input in ["2024-01-01 X\n  \n", "2024-01-01 X\n  A  $1\n   \n  B\n"] {
+            assert!(
+                matches!(crate::parse(input), Err(crate::ParseError::Syntax(_))),
+                "{:?}",
+                input
+            );
+        }
+        match crate::nom_error("2024", Err::Incomplete(nom::Needed::new(1))) {
+            crate::ParseError::Syntax(syntax) => {
+                assert_eq!((syntax.line, syntax.column), (1, 5));
+                assert_eq!(syntax.expected, "more input");
+            }
+            error => panic!("{:?}", error),
+        }
+        let ledger = crate::parse(
+            "define big=$79228162514264337593543950335 * 2\n2024-01-02 Shop\n  Expenses:Food  ($79228162514264337593543950335 + 1)\n  Assets:Cash\n",
         )
+        .unwrap();
+        assert!(ledger.variables().is_empty());
+        assert!(ledger.transactions().next().unwrap().postings[0]
+            .amount
+            .is_none());
     }
 
+    /// Parses variations of a journal made by inserting, deleting and
+    /// copying text at pseudo-random places, checking that the parser
+    /// never panics.
     #[test]
-    fn parse_transaction_test() {
-        assert_eq!(
-            parse_transaction(
-                r#"2018-10-01=2018-10-14 ! (123) Marek Ogarek  ; Transaction comment
- TEST:ABC 123  $1.20 ; Posting comment
-                     ; over two lines
- TEST:ABC 123  $1.20"#
-            ),
-            Ok((
-                "",
-                Transaction {
-                    comment: Some("Transaction comment".to_owned()),
-                    date: NaiveDate::from_ymd(2018, 10, 1),
-                    effective_date: Some(NaiveDate::from_ymd(2018, 10, 14)),
-                    status: Some(TransactionStatus::Pending),
-                    code: Some("123".to_owned()),
-                    description: "Marek Ogarek".to_owned(),
-                    postings: vec![
-                        Posting {
-                            account: "TEST:ABC 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(120, 2),
-                                    commodity: Commodity {
-                                        name: "$".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            balance: None,
-                            status: None,
-                            comment: Some("Posting comment\nover two lines".to_owned()),
-                        },
-                        Posting {
-                            account: "TEST:ABC 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(120, 2),
-                                    commodity: Commodity {
-                                        name: "$".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            balance: None,
-                            status: None,
-                            comment: None,
-                        }
-                    ]
+    fn generated_input_test() {
+        const JOURNAL: &str = "Y 2024\ndefine big=$5 * 2\napply tag trip: Paris\n2024-05-01=2024-05-03 * (12) Hotel | night  ; booked date:2024-05-02, :work:\n  ; :travel: paid: yes\n  Expenses:Travel  EUR100 {=$1.10} [2024-01-01] (lot) @ $1.20  ; date: 2024-05-03\n  Expenses:Food  ($5 * 2 + big / 3 - -1)\n  [Budget]  -10 EUR @@ $11 = 0 EUR\n  Assets:Cash\nend apply tag\n~ every 2 weeks from 2024-01-01\n  Expenses:Rent  $500\n  Assets:Bank\n= /Food/ and @Shop\n  (Budget)  -1\nP 2024-01-02 00:00:00 EUR $1.10\ncommodity $1,000.00\n  format $1,000.00\ni 2024/01/02 10:00:00 Work  note\n";
+        const TOKENS: [&str; 20] = [
+            "\n",
+            "  ",
+            "\t",
+            ";",
+            "(",
+            ")",
+            "@",
+            "{",
+            "}",
+            "=",
+            "$",
+            "-",
+            ",",
+            ":",
+            "\r",
+            "[",
+            "\u{feff}",
+            "\u{17c}",
+            "79228162514264337593543950335",
+            "1e28",
+        ];
+
+        // xorshift64, for reproducible inputs
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        let mut random = |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+        let boundary = |text: &str, mut index: usize| {
+            while !text.is_char_boundary(index) {
+                index -= 1;
+            }
+            index
+        };
+        let settings = ParserSettings::default();
+        for _ in 0..2000 {
+            let mut input = JOURNAL.to_owned();
+            for _ in 0..1 + random(4) {
+                let start = boundary(&input, random(input.len() + 1));
+                let end = boundary(&input, (start + random(40)).min(input.len()));
+                match random(3) {
+                    0 => input.insert_str(start, TOKENS[random(TOKENS.len())]),
+                    1 => input.replace_range(start..end, ""),
+                    _ => {
+                        let copy = input[start..end].to_owned();
+                        let at = boundary(&input, random(input.len() + 1));
+                        input.insert_str(at, &copy);
+                    }
                 }
-            ))
+            }
+
+            let internal = |result: Result<(), crate::ParseError>| {
+                assert!(
+                    !matches!(result, Err(crate::ParseError::Internal(_))),
+                    "{:?}: {:?}",
+                    input,
+                    result
+                );
+            };
+            internal(
+                crate::parse_with_settings(&input, &settings).map(|(ledger, _)| {
+                    ledger.to_string();
+                }),
+            );
+            internal(crate::parse_lossless(&input, &settings).map(|ledger| {
+                ledger.to_string();
+            }));
+            internal(crate::parse_with_spans(&input, &settings).map(|_| ()));
+        }
+    }
+
+    #[test]
+    fn parse_payee_note_test() {
+        let ledger = crate::parse(
+            "2024-01-02 Acme Inc |  Office chairs | two\n  Expenses:Office  $300\n  Assets:Bank\n",
+        )
+        .unwrap();
+        let mut transaction = ledger.transactions().next().unwrap().clone();
+        assert_eq!(transaction.payee(), Some("Acme Inc"));
+        assert_eq!(transaction.note(), Some("Office chairs | two"));
+        assert_eq!(
+            ledger.to_string(),
+            "2024-01-02 Acme Inc |  Office chairs | two\n  Expenses:Office  $300\n  Assets:Bank\n"
         );
+
+        transaction.set_description("Acme Inc");
+        assert_eq!(transaction.payee(), None);
+        assert_eq!(transaction.note(), None);
+
+        let settings = ParserSettings::default().with_fold_case(true);
+        let (ledger, _) = crate::parse_with_settings(
+            "2024-01-02 Shop | Weekly\n  Expenses:Food  $5\n  Assets:Cash\n",
+            &settings,
+        )
+        .unwrap();
+        let transaction = ledger.transactions().next().unwrap();
         assert_eq!(
-            parse_transaction(
-                r#"2018-10-01=2018-10-14 Marek Ogarek ; one space
- TEST:ABC 123  $1.20 ; test
- TEST:DEF 123  EUR-1.20
- TEST:GHI 123
- TEST:JKL 123  EUR-2.00"#
-            ),
-            Ok((
-                "",
-                Transaction {
-                    comment: None,
-                    date: NaiveDate::from_ymd(2018, 10, 1),
-                    effective_date: Some(NaiveDate::from_ymd(2018, 10, 14)),
-                    status: None,
-                    code: None,
-                    description: "Marek Ogarek ; one space".to_owned(),
-                    postings: vec![
-                        Posting {
-                            account: "TEST:ABC 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(120, 2),
-                                    commodity: Commodity {
-                                        name: "$".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            balance: None,
-                            status: None,
-                            comment: Some("test".to_owned()),
-                        },
-                        Posting {
-                            balance: None,
-                            account: "TEST:DEF 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(-120, 2),
-                                    commodity: Commodity {
-                                        name: "EUR".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            status: None,
-                            comment: None,
-                        },
-                        Posting {
-                            account: "TEST:GHI 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: None,
-                            balance: None,
-                            status: None,
-                            comment: None,
-                        },
-                        Posting {
-                            account: "TEST:JKL 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(-200, 2),
-                                    commodity: Commodity {
-                                        name: "EUR".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            balance: None,
-                            status: None,
-                            comment: None,
-                        },
-                    ]
-                }
-            ))
+            (transaction.payee(), transaction.note()),
+            (Some("shop"), Some("weekly"))
         );
+    }
+
+    #[test]
+    fn separator_test() {
+        let input = "2024-01-02 Shop\n\tAssets:Cash \t$5\n  Expenses:Food Stuff\t \t$-5\n";
+        let ledger = crate::parse(input).unwrap();
+        let transaction = ledger.transactions().next().unwrap();
+        let accounts: Vec<&str> = transaction
+            .postings
+            .iter()
+            .map(|posting| posting.account.as_str())
+            .collect();
+        assert_eq!(accounts, ["Assets:Cash", "Expenses:Food Stuff"]);
+        let settings = crate::SerializerSettings::default().with_separator("\t");
         assert_eq!(
-            parse_transaction(
-                r#"2018-10-01=2018-10-14 ! (123) Marek Ogarek  two spaces
- TEST:ABC 123  $1.20 ; test
- TEST:DEF 123"#
-            ),
-            Ok((
-                "",
-                Transaction {
-                    comment: None,
-                    date: NaiveDate::from_ymd(2018, 10, 1),
-                    effective_date: Some(NaiveDate::from_ymd(2018, 10, 14)),
-                    status: Some(TransactionStatus::Pending),
-                    code: Some("123".to_owned()),
-                    description: "Marek Ogarek  two spaces".to_owned(),
-                    postings: vec![
-                        Posting {
-                            account: "TEST:ABC 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: Some(PostingAmount {
-                                amount: Amount {
-                                    quantity: Decimal::new(120, 2),
-                                    commodity: Commodity {
-                                        name: "$".to_owned(),
-                                        position: CommodityPosition::Left
-                                    }
-                                },
-                                lot_price: None,
-                                price: None
-                            }),
-                            balance: None,
-                            status: None,
-                            comment: Some("test".to_owned()),
-                        },
-                        Posting {
-                            account: "TEST:DEF 123".to_owned(),
-                            reality: Reality::Real,
-                            amount: None,
-                            balance: None,
-                            status: None,
-                            comment: None,
-                        },
-                    ]
-                }
-            ))
+            crate::Serializer::to_string_pretty(transaction, &settings),
+            "2024-01-02 Shop\n  Assets:Cash\t$5\n  Expenses:Food Stuff\t$-5"
+        );
+
+        let input = "2024-01-02 Shop\n  Assets:Cash $5\n  Expenses:Food   $-5 ; one\n";
+        let ledger = crate::parse(input).unwrap();
+        let transaction = ledger.transactions().next().unwrap();
+        assert_eq!(transaction.postings[0].account, "Assets:Cash $5");
+        let settings = ParserSettings::default().with_separator_spaces(1);
+        let (ledger, _) = crate::parse_with_settings(input, &settings).unwrap();
+        assert_eq!(
+            ledger.to_string(),
+            "2024-01-02 Shop\n  Assets:Cash  $5\n  Expenses:Food  $-5\n  ; one\n"
         );
     }
 
     #[test]
-    fn parse_include_test() {
+    fn parse_same_line_comments_test() {
+        let comments = |input: &str| {
+            let ledger = crate::parse(input).unwrap();
+            let transaction = ledger.transactions().next().unwrap().clone();
+            let postings: Vec<Option<String>> = transaction
+                .postings
+                .iter()
+                .map(|posting| posting.comment.clone())
+                .collect();
+            (transaction.description, transaction.comment, postings)
+        };
         assert_eq!(
-            parse_include_file(r#"include other_file.ledger"#),
-            Ok(("", "other_file.ledger"))
+            comments("2024-01-02 * Grocery  ; weekly shop\n  Assets:Cash  $-20.00  ; lunch\n  Expenses:Food\t; food\n"),
+            (
+                "Grocery".to_owned(),
+                Some("weekly shop".to_owned()),
+                vec![Some("lunch".to_owned()), Some("food".to_owned())]
+            )
+        );
+        assert_eq!(
+            comments("2024-01-02 * (12)\t; weekly shop\n  Assets:Cash  $-20.00 @ 1 EUR ; lunch\n  Expenses:Food\n"),
+            (
+                "".to_owned(),
+                Some("weekly shop".to_owned()),
+                vec![Some("lunch".to_owned()), None]
+            )
+        );
+        assert_eq!(
+            comments("2024-01-02  ; weekly shop\n  Assets:Cash  $-20.00\n  Expenses:Food\n").1,
+            Some("weekly shop".to_owned())
         );
     }
 
     #[test]
-    fn parse_ledger_test() {
-        let res = parse_ledger(
-            r#"; Example 1
+    fn parse_block_comment_test() {
+        assert_eq!(
+            parse_block_comment("comment\n; not a line comment\n\n2024-01-02 Not a transaction\nend comment\nY 2024\n"),
+            Ok(("Y 2024\n", "; not a line comment\n\n2024-01-02 Not a transaction\n"))
+        );
+        assert_eq!(parse_block_comment("comment  \nend comment"), Ok(("", "")));
+        assert!(parse_block_comment("comment out\nend comment\n").is_err());
+        assert!(parse_block_comment("commodity $\n").is_err());
+        assert_eq!(
+            parse_block("test reg Food\nFood  $5\nend test\n", "test"),
+            Ok(("", " reg Food\nFood  $5\n"))
+        );
 
-include other_file.ledger
+        let input = "comment\r\n  Old:Account  $5\r\nend comment\r\ntest bal\n  $5  Food\nend test\n; after\n";
+        let ledger = crate::parse(input).unwrap();
+        assert_eq!(
+            ledger.items[0],
+            LedgerItem::BlockComment("  Old:Account  $5\r\n".to_owned())
+        );
+        assert_eq!(ledger.items[2], LedgerItem::LineComment("after".to_owned()));
+        assert_eq!(
+            ledger.to_string(),
+            "comment\n  Old:Account  $5\nend comment\ntest bal\n  $5  Food\nend test\n; after\n"
+        );
 
-P 2017-11-12 12:00:00 mBH 5.00 PLN
+        let ledger = crate::parse("comment\nno end").unwrap();
+        assert_eq!(
+            ledger.items,
+            vec![LedgerItem::BlockComment("no end".to_owned())]
+        );
+        assert_eq!(ledger.to_string(), "comment\nno end\nend comment\n");
+    }
 
-; Comment
-2018-10-01=2018-10-14 ! (123) Marek Ogarek
- TEST:ABC 123  $1.20
- TEST:ABC 123  $1.20
+    #[test]
+    fn line_endings_test() {
+        let input = "; comment\nY 2024\n01/02 * Shop  ; note\n  ; more\n  Expenses:Food  $5\n  Assets:Cash\n\n~ monthly\n  Expenses:Rent  $500\n  Assets:Bank\ncomment\ntext\nend comment\nP 2024-01-02 EUR $1.10";
+        let expected = crate::parse(input).unwrap();
+        let mixed: String = input
+            .split_inclusive('\n')
+            .enumerate()
+            .map(|(i, line)| match i % 3 {
+                0 => line.replace('\n', "\r\n"),
+                1 => line.replace('\n', "\r"),
+                _ => line.to_owned(),
+            })
+            .collect();
+        for input in [
+            input.replace('\n', "\r\n"),
+            input.replace('\n', "\r"),
+            mixed,
+        ] {
+            let ledger = crate::parse(&input).unwrap();
+            let settings = crate::SerializerSettings::default().with_eol("\r\n");
+            assert_eq!(
+                crate::Serializer::to_string_pretty(&ledger, &settings),
+                crate::Serializer::to_string_pretty(&expected, &settings)
+            );
+        }
 
-2018-10-01=2018-10-14 ! (123) Marek Ogarek
- TEST:ABC 123  $1.20
- TEST:ABC 123  $1.20
-"#,
+        let (_, warnings) = crate::parse_with_warnings(
+            "\r\r2024-01-02 Shop\r  Assets:Cash\r  Expenses:Food  $1,000\r",
         )
-        .unwrap()
-        .1;
-        assert_eq!(res.items.len(), 10);
-        assert!(matches!(res.items[0], LedgerItem::LineComment(_)));
-        assert!(matches!(res.items[1], LedgerItem::EmptyLine));
-        assert!(matches!(res.items[2], LedgerItem::Include(_)));
-        assert!(matches!(res.items[3], LedgerItem::EmptyLine));
-        assert!(matches!(res.items[4], LedgerItem::CommodityPrice(_)));
-        assert!(matches!(res.items[5], LedgerItem::EmptyLine));
-        assert!(matches!(res.items[6], LedgerItem::LineComment(_)));
-        assert!(matches!(res.items[7], LedgerItem::Transaction(_)));
-        assert!(matches!(res.items[8], LedgerItem::EmptyLine));
-        assert!(matches!(res.items[9], LedgerItem::Transaction(_)));
+        .unwrap();
+        assert_eq!(warnings[0].line, 5);
+    }
+
+    #[test]
+    fn parse_line_comment_markers_test() {
+        let input = "; semicolon\n# hash\n% percent\n| bar\n* star\n2024-01-02 Shop\n  Expenses:Food  $5\n  Assets:Cash\n";
+        let ledger = crate::parse(input).unwrap();
+        assert_eq!(
+            ledger.items[..5],
+            ["semicolon", "hash", "percent", "bar", "star"]
+                .map(|comment| LedgerItem::LineComment(comment.to_owned()))
+        );
+        assert_eq!(ledger.transactions().count(), 1);
     }
 }
@@ -0,0 +1,196 @@
+use crate::balancing::BalanceError;
+use crate::model::*;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+///
+/// One payslip, as read from a payroll export. All quantities are positive
+/// and in `commodity`.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Payslip {
+    pub date: NaiveDate,
+    pub employer: String,
+    pub commodity: Commodity,
+    pub gross: Decimal,
+    /// Withheld taxes by name, e.g. `("Income", 850)`.
+    pub taxes: Vec<(String, Decimal)>,
+    /// Other deductions by name, e.g. `("Pension", 200)`.
+    pub deductions: Vec<(String, Decimal)>,
+    /// Amount paid out.
+    pub net: Decimal,
+}
+
+///
+/// Accounts the lines of a [`Payslip`] are posted to.
+///
+/// Taxes and deductions go to `TAX_ROOT:NAME` and `DEDUCTION_ROOT:NAME`
+/// unless [`PayrollAccounts::with_account`] maps their name elsewhere.
+///
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct PayrollAccounts {
+    /// `Income:Salary` by default.
+    pub gross: String,
+    /// `Assets:Checking` by default.
+    pub net: String,
+    /// `Expenses:Taxes` by default.
+    pub tax_root: String,
+    /// `Expenses:Deductions` by default.
+    pub deduction_root: String,
+    /// Accounts of individual taxes and deductions, by name.
+    pub accounts: BTreeMap<String, String>,
+}
+
+impl PayrollAccounts {
+    pub fn with_gross(mut self, account: &str) -> Self {
+        self.gross = account.to_owned();
+        self
+    }
+
+    pub fn with_net(mut self, account: &str) -> Self {
+        self.net = account.to_owned();
+        self
+    }
+
+    pub fn with_tax_root(mut self, account: &str) -> Self {
+        self.tax_root = account.to_owned();
+        self
+    }
+
+    pub fn with_deduction_root(mut self, account: &str) -> Self {
+        self.deduction_root = account.to_owned();
+        self
+    }
+
+    /// Posts the tax or deduction called `name` to `account`.
+    pub fn with_account(mut self, name: &str, account: &str) -> Self {
+        self.accounts.insert(name.to_owned(), account.to_owned());
+        self
+    }
+
+    fn account(&self, root: &str, name: &str) -> String {
+        match self.accounts.get(name) {
+            Some(account) => account.clone(),
+            None => format!("{}:{}", root, name),
+        }
+    }
+}
+
+impl Default for PayrollAccounts {
+    fn default() -> Self {
+        Self {
+            gross: "Income:Salary".to_owned(),
+            net: "Assets:Checking".to_owned(),
+            tax_root: "Expenses:Taxes".to_owned(),
+            deduction_root: "Expenses:Deductions".to_owned(),
+            accounts: BTreeMap::new(),
+        }
+    }
+}
+
+impl Payslip {
+    /// Builds the payroll transaction: the gross pay taken from income, one
+    /// posting per tax and deduction, and the net pay.
+    ///
+    /// Fails if the gross pay less taxes and deductions is not the net pay.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ledger_parser::*;
+    /// use rust_decimal::Decimal;
+    ///
+    /// let payslip = Payslip {
+    ///     date: chrono::NaiveDate::from_ymd_opt(2024, 1, 31).unwrap(),
+    ///     employer: "Acme".to_owned(),
+    ///     commodity: Commodity {
+    ///         name: "$".to_owned(),
+    ///         position: CommodityPosition::Left,
+    ///     },
+    ///     gross: Decimal::new(5000, 0),
+    ///     taxes: vec![("Income".to_owned(), Decimal::new(900, 0))],
+    ///     deductions: vec![("Pension".to_owned(), Decimal::new(250, 0))],
+    ///     net: Decimal::new(3850, 0),
+    /// };
+    /// let accounts = PayrollAccounts::default().with_account("Pension", "Assets:Pension");
+    /// assert_eq!(
+    ///     payslip.to_transaction(&accounts).unwrap().to_string(),
+    ///     "2024-01-31 Acme\n  Income:Salary  $-5000\n  Expenses:Taxes:Income  $900\n  Assets:Pension  $250\n  Assets:Checking  $3850"
+    /// );
+    /// ```
+    pub fn to_transaction(&self, accounts: &PayrollAccounts) -> Result<Transaction, BalanceError> {
+        let posting = |account: &str, quantity: Decimal| {
+            let amount = Amount {
+                quantity,
+                commodity: self.commodity.clone(),
+            };
+            Posting::new(account, Some(amount))
+        };
+
+        let mut postings = Vec::with_capacity(self.taxes.len() + self.deductions.len() + 2);
+        postings.push(posting(&accounts.gross, -self.gross));
+        for (name, quantity) in &self.taxes {
+            postings.push(posting(
+                &accounts.account(&accounts.tax_root, name),
+                *quantity,
+            ));
+        }
+        for (name, quantity) in &self.deductions {
+            postings.push(posting(
+                &accounts.account(&accounts.deduction_root, name),
+                *quantity,
+            ));
+        }
+        postings.push(posting(&accounts.net, self.net));
+
+        let transaction = Transaction::new(self.date, &self.employer).with_postings(postings);
+        let remainder = transaction.unbalanced_amounts();
+        if remainder.is_empty() {
+            Ok(transaction)
+        } else {
+            Err(BalanceError::Unbalanced(remainder))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use chrono::NaiveDate;
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn payslip_test() {
+        let mut payslip = Payslip {
+            date: NaiveDate::from_ymd_opt(2024, 1, 31).unwrap(),
+            employer: "Acme".to_owned(),
+            commodity: Commodity {
+                name: "EUR".to_owned(),
+                position: CommodityPosition::Right,
+            },
+            gross: Decimal::new(400000, 2),
+            taxes: vec![
+                ("Income".to_owned(), Decimal::new(60000, 2)),
+                ("Social".to_owned(), Decimal::new(35050, 2)),
+            ],
+            deductions: vec![],
+            net: Decimal::new(304950, 2),
+        };
+        let accounts = PayrollAccounts::default()
+            .with_net("Assets:Bank")
+            .with_tax_root("Expenses:Tax")
+            .with_account("Social", "Expenses:Social Security");
+        assert_eq!(
+            payslip.to_transaction(&accounts).unwrap().to_string(),
+            "2024-01-31 Acme\n  Income:Salary  -4000.00 EUR\n  Expenses:Tax:Income  600.00 EUR\n  Expenses:Social Security  350.50 EUR\n  Assets:Bank  3049.50 EUR"
+        );
+
+        payslip.net = Decimal::new(305000, 2);
+        assert_eq!(
+            payslip.to_transaction(&accounts).unwrap_err().to_string(),
+            "transaction does not balance, remainder: 0.50 EUR"
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use crate::model::*;
+use chrono::{Datelike, Days, Months, NaiveDate};
+
+/// Tag added to the comment of transactions generated from periodic
+/// transactions, as hledger does.
+pub const GENERATED_TRANSACTION_TAG: &str = "generated-transaction";
+
+impl PeriodicTransaction {
+    /// Generates the transactions this rule produces on dates in `[from, to)`.
+    ///
+    /// Occurrences are counted from the rule's start date or, without one,
+    /// from the start of the period containing `from` (Monday for weeks, the
+    /// 1st for months, January 1st for years). Each generated transaction is
+    /// tagged with `generated-transaction: ~ PERIOD`.
+    pub fn generate(&self, from: NaiveDate, to: NaiveDate) -> Vec<Transaction> {
+        let from = self.start_date.map_or(from, |start| start.max(from));
+        let to = self.end_date.map_or(to, |end| end.min(to));
+        let anchor = self
+            .start_date
+            .unwrap_or_else(|| period_start(self.period, from));
+
+        let mut transactions = Vec::new();
+        for index in 0.. {
+            let date = match occurrence(self.period, anchor, index) {
+                Some(date) if date < to => date,
+                _ => break,
+            };
+            if date >= from {
+                transactions.push(self.transaction_on(date));
+            }
+        }
+        transactions
+    }
+
+    fn transaction_on(&self, date: NaiveDate) -> Transaction {
+        let mut expression = format!("~ {}", self.period);
+        if let Some(start_date) = self.start_date {
+            expression.push_str(&format!(" from {}", start_date.format("%Y-%m-%d")));
+        }
+        if let Some(end_date) = self.end_date {
+            expression.push_str(&format!(" to {}", end_date.format("%Y-%m-%d")));
+        }
+        let tag = format!("{}: {}", GENERATED_TRANSACTION_TAG, expression);
+        let comment = match self.comment {
+            Some(ref comment) => format!("{}\n{}", comment, tag),
+            None => tag,
+        };
+
+        Transaction::new(date, "")
+            .with_comment(&comment)
+            .with_postings(self.postings.clone())
+    }
+}
+
+fn period_start(period: Period, date: NaiveDate) -> NaiveDate {
+    match period {
+        Period::Weekly | Period::EveryNWeeks(_) => {
+            date - Days::new(u64::from(date.weekday().num_days_from_monday()))
+        }
+        Period::Monthly | Period::EveryNMonths(_) => date.with_day(1).unwrap_or(date),
+        Period::Yearly | Period::EveryNYears(_) => date.with_ordinal(1).unwrap_or(date),
+        Period::Daily | Period::EveryNDays(_) | Period::Date(_) => date,
+    }
+}
+
+/// Date of the `index`-th occurrence counted from `anchor`, or `None` when
+/// there is none (or it is out of the supported date range).
+pub(crate) fn occurrence(period: Period, anchor: NaiveDate, index: u32) -> Option<NaiveDate> {
+    let days = |step: u32| {
+        if step == 0 {
+            return None;
+        }
+        anchor.checked_add_days(Days::new(u64::from(step) * u64::from(index)))
+    };
+    let months = |step: u32| {
+        if step == 0 {
+            return None;
+        }
+        anchor.checked_add_months(Months::new(step.checked_mul(index)?))
+    };
+
+    match period {
+        Period::Date(date) if index == 0 => Some(date),
+        Period::Date(_) => None,
+        Period::Daily => days(1),
+        Period::EveryNDays(n) => days(n),
+        Period::Weekly => days(7),
+        Period::EveryNWeeks(n) => days(n.checked_mul(7)?),
+        Period::Monthly => months(1),
+        Period::EveryNMonths(n) => months(n),
+        Period::Yearly => months(12),
+        Period::EveryNYears(n) => months(n.checked_mul(12)?),
+    }
+}
+
+impl Ledger {
+    /// Generates the transactions of all periodic transactions for dates in
+    /// `[from, to)`, ordered by date.
+    pub fn generate_periodic_transactions(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Vec<Transaction> {
+        let mut transactions: Vec<Transaction> = self
+            .periodic_transactions()
+            .flat_map(|periodic| periodic.generate(from, to))
+            .collect();
+        transactions.sort_by_key(|transaction| transaction.date);
+        transactions
+    }
+}
+
+impl Transaction {
+    /// Returns `true` for transactions generated from a periodic transaction,
+    /// so that projected data can be told apart from recorded data.
+    pub fn is_generated(&self) -> bool {
+        self.tags
+            .iter()
+            .any(|(name, _)| name == GENERATED_TRANSACTION_TAG)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use chrono::NaiveDate;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn dates(transactions: &[Transaction]) -> Vec<NaiveDate> {
+        transactions.iter().map(|t| t.date).collect()
+    }
+
+    #[test]
+    fn generate_periodic_transactions_test() {
+        let ledger = parse(
+            r#"~ monthly from 2024-01-31 to 2024-05-01 ; rent
+  Expenses:Rent  $500
+  Assets:Bank
+~ weekly
+  Expenses:Food  $50
+  Assets:Bank
+2024-01-02 Shop
+  Expenses:Food  $5
+  Assets:Cash
+"#,
+        )
+        .unwrap();
+
+        let generated = ledger.generate_periodic_transactions(date(2024, 2, 1), date(2024, 3, 1));
+        assert_eq!(
+            dates(&generated),
+            vec![
+                date(2024, 2, 5),
+                date(2024, 2, 12),
+                date(2024, 2, 19),
+                date(2024, 2, 26),
+                date(2024, 2, 29),
+            ]
+        );
+        assert!(generated.iter().all(Transaction::is_generated));
+        assert!(!ledger.transactions().any(Transaction::is_generated));
+        assert_eq!(
+            generated[4].to_string(),
+            r#"2024-02-29
+  ; rent
+  ; generated-transaction: ~ monthly from 2024-01-31 to 2024-05-01
+  Expenses:Rent  $500
+  Assets:Bank"#
+        );
+
+        let rent = ledger.periodic_transactions().next().unwrap();
+        assert_eq!(
+            dates(&rent.generate(date(2020, 1, 1), date(2030, 1, 1))),
+            vec![
+                date(2024, 1, 31),
+                date(2024, 2, 29),
+                date(2024, 3, 31),
+                date(2024, 4, 30),
+            ]
+        );
+    }
+
+    #[test]
+    fn generate_every_n_test() {
+        let periodic = |period| PeriodicTransaction {
+            period,
+            comment: None,
+            start_date: None,
+            end_date: None,
+            postings: vec![],
+        };
+        let generate =
+            |period| dates(&periodic(period).generate(date(2024, 1, 10), date(2025, 1, 1)));
+
+        assert_eq!(
+            generate(Period::EveryNMonths(5)),
+            vec![date(2024, 6, 1), date(2024, 11, 1)]
+        );
+        assert_eq!(generate(Period::EveryNYears(2)), vec![]);
+        assert_eq!(
+            generate(Period::Date(date(2024, 3, 1))),
+            vec![date(2024, 3, 1)]
+        );
+        assert_eq!(generate(Period::EveryNDays(0)), vec![]);
+        assert_eq!(generate(Period::EveryNDays(100)).len(), 4);
+    }
+}
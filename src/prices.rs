@@ -0,0 +1,174 @@
+use crate::commodities::CommodityAliases;
+use crate::model::*;
+use chrono::NaiveDate;
+use std::collections::{BTreeMap, BTreeSet};
+
+///
+/// Commodity whose price history doesn't match its use, found by
+/// [`Ledger::dangling_commodities`].
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DanglingCommodity {
+    /// Used in transactions but in no `P` directive.
+    Unpriced {
+        commodity: String,
+        /// Date of the last transaction using it.
+        last_used: NaiveDate,
+    },
+    /// Priced by `P` directives but used in no transaction.
+    Unused {
+        commodity: String,
+        /// Date of the last price.
+        last_priced: NaiveDate,
+    },
+}
+
+/// Records `date` as the last of `commodity`, under the name it stands for.
+fn see(
+    dates: &mut BTreeMap<String, NaiveDate>,
+    aliases: &CommodityAliases,
+    commodity: &str,
+    date: NaiveDate,
+) {
+    if commodity.is_empty() {
+        return;
+    }
+    dates
+        .entry(aliases.resolve(commodity).to_owned())
+        .and_modify(|last| *last = (*last).max(date))
+        .or_insert(date);
+}
+
+impl Ledger {
+    /// Lists the commodities used in transactions that no `P` directive
+    /// mentions, on either side, and the commodities priced by `P`
+    /// directives that no transaction uses, sorted by name.
+    ///
+    /// Commodities left out of market valuation with `N` or `nomarket` are
+    /// not reported as unpriced. A commodity and its aliases from
+    /// [`Ledger::commodity_aliases`] count as one, reported by the name
+    /// they stand for.
+    pub fn dangling_commodities(&self) -> Vec<DanglingCommodity> {
+        self.dangling_commodities_with_aliases(&self.commodity_aliases())
+    }
+
+    /// Like [`Ledger::dangling_commodities`], with the given commodity
+    /// aliases instead of the declared ones.
+    pub fn dangling_commodities_with_aliases(
+        &self,
+        aliases: &CommodityAliases,
+    ) -> Vec<DanglingCommodity> {
+        let mut used = BTreeMap::new();
+        let mut priced = BTreeMap::new();
+        let mut quoted = BTreeMap::new();
+        for item in &self.items {
+            match item {
+                LedgerItem::Transaction(transaction) => {
+                    for posting in &transaction.postings {
+                        let date = posting.date.unwrap_or(transaction.date);
+                        if let Some(ref amount) = posting.amount {
+                            see(&mut used, aliases, &amount.amount.commodity.name, date);
+                            for price in [&amount.lot_price, &amount.price].into_iter().flatten() {
+                                match price {
+                                    Price::Unit(price) | Price::Total(price) => {
+                                        see(&mut used, aliases, &price.commodity.name, date)
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                LedgerItem::CommodityPrice(price) => {
+                    let date = price.datetime.date();
+                    see(&mut priced, aliases, &price.commodity_name, date);
+                    see(&mut quoted, aliases, &price.amount.commodity.name, date);
+                }
+                _ => {}
+            }
+        }
+
+        let no_market: BTreeSet<String> = self
+            .no_market_commodities()
+            .iter()
+            .map(|commodity| aliases.resolve(commodity).to_owned())
+            .collect();
+        let unpriced = used
+            .iter()
+            .filter(|(commodity, _)| {
+                !priced.contains_key(*commodity)
+                    && !quoted.contains_key(*commodity)
+                    && !no_market.contains(*commodity)
+            })
+            .map(|(commodity, &last_used)| DanglingCommodity::Unpriced {
+                commodity: commodity.clone(),
+                last_used,
+            });
+        let unused = priced
+            .iter()
+            .filter(|(commodity, _)| !used.contains_key(*commodity))
+            .map(|(commodity, &last_priced)| DanglingCommodity::Unused {
+                commodity: commodity.clone(),
+                last_priced,
+            });
+
+        let mut dangling: Vec<DanglingCommodity> = unpriced.chain(unused).collect();
+        dangling.sort_by(|a, b| a.commodity().cmp(b.commodity()));
+        dangling
+    }
+}
+
+impl DanglingCommodity {
+    pub fn commodity(&self) -> &str {
+        match self {
+            DanglingCommodity::Unpriced { commodity, .. }
+            | DanglingCommodity::Unused { commodity, .. } => commodity,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn dangling_commodities_test() {
+        let ledger = parse(
+            r#"P 2024-01-01 00:00:00 AAPL $180
+P 2024-03-01 00:00:00 AAPL $170
+P 2024-02-01 00:00:00 TSLA $200
+N HOURS
+2024-01-05 Buy
+  Assets:Broker  10 AAPL @ $180
+  Assets:Broker  5 VTI {$220}
+  Assets:Cash
+2024-02-10 Sell
+  Assets:Broker  -2 VTI @ $230
+  Assets:Cash
+2024-03-01 Work
+  Assets:Time  8 HOURS
+  Income:Work
+"#,
+        )
+        .unwrap();
+        let date = |m, d| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+        assert_eq!(
+            ledger.dangling_commodities(),
+            vec![
+                DanglingCommodity::Unused {
+                    commodity: "TSLA".to_owned(),
+                    last_priced: date(2, 1),
+                },
+                DanglingCommodity::Unpriced {
+                    commodity: "VTI".to_owned(),
+                    last_used: date(2, 10),
+                },
+            ]
+        );
+        let aliases = CommodityAliases::default().with_alias("VTI", "TSLA");
+        assert_eq!(ledger.dangling_commodities_with_aliases(&aliases), vec![]);
+
+        let ledger = parse("commodity AAPL\n  alias Apple\nP 2024-01-01 Apple $180\n2024-01-05 Buy\n  Assets:Broker  10 AAPL\n  Assets:Cash  $-1800\n").unwrap();
+        assert_eq!(ledger.dangling_commodities(), vec![]);
+    }
+}
@@ -1,10 +1,77 @@
 use crate::model::*;
+use crate::parser::bare_commodity_len;
+use crate::styles::CommodityStyle;
+use crate::tags::dated_comment;
+use chrono::{Datelike, NaiveDate};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::io;
 
+/// How quantities are rounded when rendered at reduced precision.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RoundingMode {
+    /// Round to the nearest value, ties to the even neighbour (banker's rounding).
+    HalfEven,
+    /// Round to the nearest value, ties away from zero.
+    HalfUp,
+    /// Drop the extra digits (round towards zero).
+    Truncate,
+}
+
+impl RoundingMode {
+    /// Rounds `quantity` to `decimal_places` digits after the decimal mark.
+    pub fn round(self, quantity: Decimal, decimal_places: u32) -> Decimal {
+        let strategy = match self {
+            RoundingMode::HalfEven => RoundingStrategy::MidpointNearestEven,
+            RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::Truncate => RoundingStrategy::ToZero,
+        };
+        quantity.round_dp_with_strategy(decimal_places, strategy)
+    }
+}
+
+/// How quantities are written.
+///
+/// `Decimal`, `Scientific` and `Engineering` output can be parsed back;
+/// `SiSuffix` is meant for display of very large quantities.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum QuantityNotation {
+    /// Plain decimal number, e.g. `1234500`.
+    Decimal,
+    /// One digit before the decimal mark and an exponent, e.g. `1.2345e6`.
+    Scientific,
+    /// Exponent is a multiple of three, e.g. `1.2345e6` or `123.45e-6`.
+    Engineering,
+    /// SI suffix for thousands and above (`k`, `M`, `G`, `T`, `P`), e.g. `1.2345M`.
+    SiSuffix,
+}
+
 #[non_exhaustive]
+#[derive(Debug, Clone)]
 pub struct SerializerSettings {
     pub indent: String,
     pub eol: String,
+    /// Maximum number of decimal places to render. Quantities with more
+    /// digits are rounded using `rounding`; `None` keeps them as parsed.
+    pub precision: Option<u32>,
+    pub rounding: RoundingMode,
+    pub notation: QuantityNotation,
+    /// Per-commodity display styles. Amounts of a listed commodity are
+    /// written with its symbol position and exactly its number of decimal
+    /// places, taking precedence over `precision`.
+    pub commodity_styles: BTreeMap<String, CommodityStyle>,
+    /// Character written between account name components instead of `:`.
+    pub account_separator: char,
+    /// Decimal mark of quantities, `.` by default. A commodity style can
+    /// set its own.
+    pub decimal_mark: char,
+    /// Text between the account and the amount of a posting, such as a tab.
+    /// `indent` if `None`.
+    pub separator: Option<String>,
+    /// Month names, starting with January, of dates written with a month
+    /// name. English abbreviations if empty.
+    pub month_names: Vec<String>,
 }
 
 impl SerializerSettings {
@@ -17,6 +84,57 @@ impl SerializerSettings {
         self.eol = eol.to_owned();
         self
     }
+
+    pub fn with_precision(mut self, precision: u32) -> Self {
+        self.precision = Some(precision);
+        self
+    }
+
+    pub fn with_rounding(mut self, rounding: RoundingMode) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    pub fn with_notation(mut self, notation: QuantityNotation) -> Self {
+        self.notation = notation;
+        self
+    }
+
+    pub fn with_commodity_styles(mut self, styles: BTreeMap<String, CommodityStyle>) -> Self {
+        self.commodity_styles = styles;
+        self
+    }
+
+    pub fn with_account_separator(mut self, account_separator: char) -> Self {
+        self.account_separator = account_separator;
+        self
+    }
+
+    pub fn with_decimal_mark(mut self, decimal_mark: char) -> Self {
+        self.decimal_mark = decimal_mark;
+        self
+    }
+
+    pub fn with_separator(mut self, separator: &str) -> Self {
+        self.separator = Some(separator.to_owned());
+        self
+    }
+
+    /// Writes month names as given to
+    /// [`crate::ParserSettings::with_month_names`], so that the dates read
+    /// back with the same settings.
+    pub fn with_month_names(mut self, month_names: &[&str; 12]) -> Self {
+        self.month_names = month_names.iter().map(|&name| name.to_owned()).collect();
+        self
+    }
+
+    fn account_name<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if self.account_separator == ':' {
+            Cow::Borrowed(name)
+        } else {
+            Cow::Owned(name.replace(':', &self.account_separator.to_string()))
+        }
+    }
 }
 
 impl Default for SerializerSettings {
@@ -24,6 +142,14 @@ impl Default for SerializerSettings {
         Self {
             indent: "  ".to_owned(),
             eol: "\n".to_owned(),
+            precision: None,
+            rounding: RoundingMode::HalfEven,
+            notation: QuantityNotation::Decimal,
+            commodity_styles: BTreeMap::new(),
+            account_separator: ':',
+            decimal_mark: '.',
+            separator: None,
+            month_names: Vec::new(),
         }
     }
 }
@@ -36,22 +162,57 @@ pub trait Serializer {
     fn to_string_pretty(&self, settings: &SerializerSettings) -> String {
         let mut res = Vec::new();
         self.write(&mut res, settings).unwrap();
-        return std::str::from_utf8(&res).unwrap().to_owned();
+        std::str::from_utf8(&res).unwrap().to_owned()
     }
 }
 
 impl Serializer for Ledger {
+    /// Quantities after a `decimal-mark` directive are written with its
+    /// mark, so that the output reads back the same.
     fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
     where
         W: io::Write,
     {
+        let mut settings = Cow::Borrowed(settings);
         for item in &self.items {
-            item.write(writer, settings)?;
+            item.write(writer, &settings)?;
+            follow_decimal_mark(&mut settings, item);
         }
         Ok(())
     }
 }
 
+/// Switches `settings` to the decimal mark set by `item`, if it is a
+/// `decimal-mark` directive.
+pub(crate) fn follow_decimal_mark(settings: &mut Cow<SerializerSettings>, item: &LedgerItem) {
+    if let LedgerItem::DecimalMark(mark) = item {
+        settings.to_mut().decimal_mark = *mark;
+    }
+}
+
+/// Writes the text of a block as it was read, with `eol` line breaks, then
+/// its closing line.
+fn write_block<W>(
+    writer: &mut W,
+    text: &str,
+    end: &str,
+    settings: &SerializerSettings,
+) -> Result<(), io::Error>
+where
+    W: io::Write,
+{
+    for line in text.split_inclusive('\n') {
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        write!(
+            writer,
+            "{}{}",
+            line.strip_suffix('\r').unwrap_or(line),
+            settings.eol
+        )?;
+    }
+    write!(writer, "{}{}", end, settings.eol)
+}
+
 impl Serializer for LedgerItem {
     fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
     where
@@ -69,10 +230,83 @@ impl Serializer for LedgerItem {
                 write!(writer, "{}", settings.eol)?;
             }
             LedgerItem::Include(file) => write!(writer, "include {}{}", file, settings.eol)?,
+            LedgerItem::IncludeBegin(_) | LedgerItem::IncludeEnd(_) => {}
             LedgerItem::PeriodicTransaction(periodic_transactions) => {
                 periodic_transactions.write(writer, settings)?;
                 write!(writer, "{}", settings.eol)?;
             }
+            LedgerItem::AutomatedTransaction(transaction) => {
+                transaction.write(writer, settings)?;
+                write!(writer, "{}", settings.eol)?;
+            }
+            LedgerItem::AccountDeclaration(declaration) => {
+                declaration.write(writer, settings)?;
+                write!(writer, "{}", settings.eol)?;
+            }
+            LedgerItem::CommodityDeclaration(declaration) => {
+                declaration.write(writer, settings)?;
+                write!(writer, "{}", settings.eol)?;
+            }
+            LedgerItem::PayeeDeclaration(declaration) => {
+                declaration.write(writer, settings)?;
+                write!(writer, "{}", settings.eol)?;
+            }
+            LedgerItem::TagDeclaration(declaration) => {
+                declaration.write(writer, settings)?;
+                write!(writer, "{}", settings.eol)?;
+            }
+            LedgerItem::AccountAlias(alias) => {
+                alias.write(writer, settings)?;
+                write!(writer, "{}", settings.eol)?;
+            }
+            LedgerItem::AccountCapture(capture) => {
+                capture.write(writer, settings)?;
+                write!(writer, "{}", settings.eol)?;
+            }
+            LedgerItem::EndAliases => write!(writer, "end aliases{}", settings.eol)?,
+            LedgerItem::ApplyTag(tag) => write!(writer, "apply tag {}{}", tag, settings.eol)?,
+            LedgerItem::EndApplyTag => write!(writer, "end apply tag{}", settings.eol)?,
+            LedgerItem::Year(year) => write!(writer, "Y {}{}", year, settings.eol)?,
+            LedgerItem::DecimalMark(mark) => {
+                write!(writer, "decimal-mark {}{}", mark, settings.eol)?
+            }
+            LedgerItem::DefaultCommodity(format) => write!(writer, "D {}{}", format, settings.eol)?,
+            LedgerItem::NoMarket(commodity) => write!(writer, "N {}{}", commodity, settings.eol)?,
+            LedgerItem::Bucket(account) => write!(
+                writer,
+                "bucket {}{}",
+                settings.account_name(account),
+                settings.eol
+            )?,
+            LedgerItem::Definition(definition) => {
+                definition.write(writer, settings)?;
+                write!(writer, "{}", settings.eol)?;
+            }
+            LedgerItem::CommodityConversion(conversion) => {
+                conversion.write(writer, settings)?;
+                write!(writer, "{}", settings.eol)?;
+            }
+            LedgerItem::Event(event) => {
+                event.write(writer, settings)?;
+                write!(writer, "{}", settings.eol)?;
+            }
+            LedgerItem::Note(note) => write!(writer, "note {}{}", note, settings.eol)?,
+            LedgerItem::Check(expr) => write!(writer, "check {}{}", expr, settings.eol)?,
+            LedgerItem::Assert(expr) => write!(writer, "assert {}{}", expr, settings.eol)?,
+            LedgerItem::Eval(expr) => write!(writer, "eval {}{}", expr, settings.eol)?,
+            LedgerItem::Expr(expr) => write!(writer, "expr {}{}", expr, settings.eol)?,
+            LedgerItem::CommentHint(hint) => {
+                hint.write(writer, settings)?;
+                write!(writer, "{}", settings.eol)?;
+            }
+            LedgerItem::BlockComment(text) => {
+                write!(writer, "comment{}", settings.eol)?;
+                write_block(writer, text, "end comment", settings)?;
+            }
+            LedgerItem::TestBlock(text) => {
+                write!(writer, "test")?;
+                write_block(writer, text, "end test", settings)?;
+            }
         }
         Ok(())
     }
@@ -83,26 +317,15 @@ impl Serializer for PeriodicTransaction {
     where
         W: io::Write,
     {
-        write!(writer, "{}", "~")?;
-
-        match self.period {
-            Period::Daily => write!(writer, " {}", "daily")?,
-            Period::Weekly => write!(writer, " {}", "weekly")?,
-            Period::Monthly => write!(writer, " {}", "monthly")?,
-            Period::Yearly => write!(writer, " {}", "yearly")?,
-            Period::EveryNDays(interval) => write!(writer, " {} {} days", "every ", interval)?,
-            Period::EveryNWeeks(interval) => write!(writer, " {} {} weeks", "every ", interval)?,
-            Period::EveryNMonths(interval) => write!(writer, " {} {} month", "every ", interval)?,
-            Period::EveryNYears(interval) => write!(writer, " {} {} year", "every ", interval)?,
-            Period::Date(date) => write!(writer, " {}", date.format("%Y-%m-%d"))?,
-        };
+        write!(writer, "~ ")?;
+        self.period.write(writer, settings)?;
 
         if let Some(ref start_date) = self.start_date {
             write!(writer, " from {}", start_date.format("%Y-%m-%d"))?;
         }
 
         if let Some(ref end_date) = self.end_date {
-            write!(writer, " from {}", end_date.format("%Y-%m-%d"))?;
+            write!(writer, " to {}", end_date.format("%Y-%m-%d"))?;
         }
 
         if let Some(ref comment) = self.comment {
@@ -120,15 +343,162 @@ impl Serializer for PeriodicTransaction {
     }
 }
 
+impl Serializer for AutomatedTransaction {
+    fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        write!(writer, "= ")?;
+        self.query.write(writer, settings)?;
+
+        if let Some(ref comment) = self.comment {
+            for comment in comment.split('\n') {
+                write!(writer, "{}{}; {}", settings.eol, settings.indent, comment)?;
+            }
+        }
+
+        for posting in &self.postings {
+            write!(writer, "{}{}", settings.eol, settings.indent)?;
+            posting.write(writer, settings)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether an account pattern can be written without slashes.
+fn is_plain_query_word(pattern: &str) -> bool {
+    !pattern.is_empty()
+        && !["and", "or", "not", "expr"].contains(&pattern)
+        && pattern
+            .chars()
+            .all(|c| c.is_alphanumeric() || ":_-".contains(c))
+}
+
+impl Serializer for Query {
+    fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        // Operands that are themselves `and`/`or` queries need parentheses
+        let operand = |writer: &mut W, query: &Query| match query {
+            Query::And(_) | Query::Or(_) => {
+                write!(writer, "(")?;
+                query.write(writer, settings)?;
+                write!(writer, ")")
+            }
+            _ => query.write(writer, settings),
+        };
+
+        match self {
+            Query::Account(pattern) if is_plain_query_word(pattern) => {
+                write!(writer, "{}", pattern)
+            }
+            Query::Account(pattern) => write!(writer, "/{}/", pattern),
+            Query::Payee(pattern) => write!(writer, "@{}", pattern),
+            Query::Tag(name, None) => write!(writer, "%{}", name),
+            Query::Tag(name, Some(value)) => write!(writer, "%{}={}", name, value),
+            Query::Expression(expression) => write!(writer, "expr '{}'", expression),
+            Query::Not(query) => {
+                write!(writer, "not ")?;
+                operand(writer, query)
+            }
+            Query::And(queries) | Query::Or(queries) => {
+                let separator = match self {
+                    Query::And(_) => " and ",
+                    _ => " or ",
+                };
+                for (i, query) in queries.iter().enumerate() {
+                    if i > 0 {
+                        write!(writer, "{}", separator)?;
+                    }
+                    match (self, query) {
+                        (Query::Or(_), Query::And(_)) => query.write(writer, settings)?,
+                        _ => operand(writer, query)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Serializer for Period {
+    fn write<W>(&self, writer: &mut W, _settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        match self {
+            Period::Daily | Period::EveryNDays(1) => write!(writer, "daily"),
+            Period::Weekly | Period::EveryNWeeks(1) => write!(writer, "weekly"),
+            Period::Monthly | Period::EveryNMonths(1) => write!(writer, "monthly"),
+            Period::Yearly | Period::EveryNYears(1) => write!(writer, "yearly"),
+            Period::EveryNDays(interval) => write!(writer, "every {} days", interval),
+            Period::EveryNWeeks(interval) => write!(writer, "every {} weeks", interval),
+            Period::EveryNMonths(interval) => write!(writer, "every {} months", interval),
+            Period::EveryNYears(interval) => write!(writer, "every {} years", interval),
+            Period::Date(date) => write!(writer, "{}", date.format("%Y-%m-%d")),
+        }
+    }
+}
+
+fn format_date(date: NaiveDate, style: DateStyle, month_names: &[String]) -> String {
+    let format = match style {
+        DateStyle::Numeric => "%Y-%m-%d",
+        DateStyle::YearMonthNameDay => "%Y {} %d",
+        DateStyle::DayMonthNameYear => "%d {} %Y",
+        DateStyle::MonthDay => "%m/%d",
+    };
+    date.format(format)
+        .to_string()
+        .replace("{}", &month_name(date, month_names))
+}
+
+/// Name of the month of `date`: the first three letters of its name, or the
+/// whole name if another month starts with the same letters, as the parser
+/// reads them. English abbreviations without names.
+fn month_name(date: NaiveDate, month_names: &[String]) -> String {
+    let name = match month_names.get(date.month0() as usize) {
+        Some(name) => name,
+        None => return date.format("%b").to_string(),
+    };
+    let prefix = |name: &str| name.chars().take(3).collect::<String>().to_lowercase();
+    let abbreviation = prefix(name);
+    let shared = month_names
+        .iter()
+        .filter(|other| prefix(other) == abbreviation)
+        .count();
+    if shared > 1 {
+        name.clone()
+    } else {
+        name.chars().take(3).collect()
+    }
+}
+
 impl Serializer for Transaction {
     fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
     where
         W: io::Write,
     {
-        write!(writer, "{}", self.date.format("%Y-%m-%d"))?;
+        write!(
+            writer,
+            "{}",
+            format_date(self.date, self.date_style, &settings.month_names)
+        )?;
 
         if let Some(effective_date) = self.effective_date {
-            write!(writer, "={}", effective_date.format("%Y-%m-%d"))?;
+            // A partial date can only be read back in the transaction's year
+            let style = match self.date_style {
+                DateStyle::MonthDay if effective_date.year() != self.date.year() => {
+                    DateStyle::Numeric
+                }
+                style => style,
+            };
+            write!(
+                writer,
+                "={}",
+                format_date(effective_date, style, &settings.month_names)
+            )?;
         }
 
         if let Some(ref status) = self.status {
@@ -167,6 +537,7 @@ impl Serializer for TransactionStatus {
         match self {
             TransactionStatus::Pending => write!(writer, "!"),
             TransactionStatus::Cleared => write!(writer, "*"),
+            TransactionStatus::Custom(flag) => write!(writer, "{}", flag),
         }
     }
 }
@@ -182,25 +553,37 @@ impl Serializer for Posting {
         }
 
         match self.reality {
-            Reality::Real => write!(writer, "{}", self.account)?,
-            Reality::BalancedVirtual => write!(writer, "[{}]", self.account)?,
-            Reality::UnbalancedVirtual => write!(writer, "({})", self.account)?,
+            Reality::Real => write!(writer, "{}", settings.account_name(&self.account))?,
+            Reality::BalancedVirtual => {
+                write!(writer, "[{}]", settings.account_name(&self.account))?
+            }
+            Reality::UnbalancedVirtual => {
+                write!(writer, "({})", settings.account_name(&self.account))?
+            }
         }
 
-        if self.amount.is_some() || self.balance.is_some() {
-            write!(writer, "{}", settings.indent)?;
+        if self.amount.is_some() || self.amount_expr.is_some() || self.balance.is_some() {
+            let separator = settings.separator.as_ref().unwrap_or(&settings.indent);
+            write!(writer, "{}", separator)?;
         }
 
-        if let Some(ref amount) = self.amount {
+        if let Some(ref expr) = self.amount_expr {
+            write!(writer, "(")?;
+            expr.write(writer, settings)?;
+            write!(writer, ")")?;
+        } else if let Some(ref amount) = self.amount {
             amount.write(writer, settings)?;
         }
 
         if let Some(ref balance) = self.balance {
-            write!(writer, " = ")?;
+            if self.amount.is_some() || self.amount_expr.is_some() {
+                write!(writer, " ")?;
+            }
+            write!(writer, "{} ", self.balance_kind.operator())?;
             balance.write(writer, settings)?;
         }
 
-        if let Some(ref comment) = self.comment {
+        if let Some(comment) = dated_comment(self) {
             for comment in comment.split('\n') {
                 write!(writer, "{}{}; {}", settings.eol, settings.indent, comment)?;
             }
@@ -210,6 +593,74 @@ impl Serializer for Posting {
     }
 }
 
+impl AmountExpr {
+    /// Binding strength, deciding where parentheses are needed.
+    fn precedence(&self) -> u8 {
+        match self {
+            AmountExpr::Add(..) | AmountExpr::Sub(..) => 1,
+            AmountExpr::Mul(..) | AmountExpr::Div(..) => 2,
+            AmountExpr::Neg(_) => 3,
+            _ => 4,
+        }
+    }
+
+    fn write_operand<W>(
+        &self,
+        writer: &mut W,
+        settings: &SerializerSettings,
+        precedence: u8,
+    ) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        if self.precedence() < precedence {
+            write!(writer, "(")?;
+            self.write(writer, settings)?;
+            write!(writer, ")")
+        } else {
+            self.write(writer, settings)
+        }
+    }
+}
+
+impl Serializer for AmountExpr {
+    fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        let (left, operator, right) = match self {
+            AmountExpr::Amount(amount) => return amount.write(writer, settings),
+            AmountExpr::Number(quantity) => return write!(writer, "{}", quantity),
+            AmountExpr::Variable(name) => return write!(writer, "{}", name),
+            AmountExpr::Neg(expr) => {
+                write!(writer, "-")?;
+                return expr.write_operand(writer, settings, 3);
+            }
+            AmountExpr::Call(name, arguments) => {
+                write!(writer, "{}(", name)?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        write!(writer, ", ")?;
+                    }
+                    argument.write(writer, settings)?;
+                }
+                return write!(writer, ")");
+            }
+            AmountExpr::Add(left, right) => (left, "+", right),
+            AmountExpr::Sub(left, right) => (left, "-", right),
+            AmountExpr::Mul(left, right) => (left, "*", right),
+            AmountExpr::Div(left, right) => (left, "/", right),
+        };
+
+        // Operators are left-associative, so only the right operand of an
+        // equally strong operator needs parentheses
+        let precedence = self.precedence();
+        left.write_operand(writer, settings, precedence)?;
+        write!(writer, " {} ", operator)?;
+        right.write_operand(writer, settings, precedence + 1)
+    }
+}
+
 impl Serializer for PostingAmount {
     fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
     where
@@ -218,20 +669,29 @@ impl Serializer for PostingAmount {
         self.amount.write(writer, settings)?;
 
         if let Some(ref lot_price) = self.lot_price {
+            let fixed = if self.lot_price_fixed { "=" } else { "" };
             match lot_price {
                 Price::Unit(amount) => {
-                    write!(writer, " {{")?;
+                    write!(writer, " {{{}", fixed)?;
                     amount.write(writer, settings)?;
                     write!(writer, "}}")?;
                 }
                 Price::Total(amount) => {
-                    write!(writer, " {{{{")?;
+                    write!(writer, " {{{{{}", fixed)?;
                     amount.write(writer, settings)?;
                     write!(writer, "}}}}")?;
                 }
             }
         }
 
+        if let Some(lot_date) = self.lot_date {
+            write!(writer, " [{}]", lot_date.format("%Y-%m-%d"))?;
+        }
+
+        if let Some(ref lot_note) = self.lot_note {
+            write!(writer, " ({})", lot_note)?;
+        }
+
         if let Some(ref lot_price) = self.price {
             match lot_price {
                 Price::Unit(amount) => {
@@ -250,17 +710,107 @@ impl Serializer for PostingAmount {
 }
 
 impl Serializer for Amount {
-    fn write<W>(&self, writer: &mut W, _settings: &SerializerSettings) -> Result<(), io::Error>
+    fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
     where
         W: io::Write,
     {
-        match self.commodity.position {
-            CommodityPosition::Left => write!(writer, "{}{}", self.commodity.name, self.quantity),
-            CommodityPosition::Right => write!(writer, "{} {}", self.quantity, self.commodity.name),
+        let style = settings.commodity_styles.get(&self.commodity.name);
+        let quantity = match (style, settings.precision) {
+            (Some(style), _) => {
+                let mut quantity = settings.rounding.round(self.quantity, style.precision);
+                quantity.rescale(style.precision);
+                quantity
+            }
+            (None, Some(precision)) => settings.rounding.round(self.quantity, precision),
+            (None, None) => self.quantity,
+        };
+        let mut quantity = format_quantity(quantity, settings.notation);
+        let decimal_mark = style
+            .and_then(|style| style.decimal_mark)
+            .unwrap_or(settings.decimal_mark);
+        if decimal_mark != '.' {
+            quantity = quantity.replace('.', &decimal_mark.to_string());
+        }
+        let position = style.map_or(self.commodity.position, |style| style.position);
+        let name = commodity_name(&self.commodity.name, position == CommodityPosition::Right);
+        match position {
+            // Keep digits, dots and exponents from running into an unquoted
+            // name
+            CommodityPosition::Left
+                if !name.starts_with('"')
+                    && (name.contains(|c: char| c.is_ascii_digit() || c == '.')
+                        || (quantity.contains('e') && name.ends_with(char::is_alphabetic))) =>
+            {
+                write!(writer, "{} {}", name, quantity)
+            }
+            CommodityPosition::Left => write!(writer, "{}{}", name, quantity),
+            CommodityPosition::Right => write!(writer, "{} {}", quantity, name),
         }
     }
 }
 
+/// Commodity name as written, quoted if it cannot be read back unquoted.
+/// `trailing_digits` tells whether it can end with digits, which it cannot
+/// when a quantity follows.
+fn commodity_name(name: &str, trailing_digits: bool) -> Cow<'_, str> {
+    if bare_commodity_len(name, trailing_digits) == name.len() {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("\"{}\"", name.replace('"', "\\\"")))
+    }
+}
+
+fn format_quantity(quantity: Decimal, notation: QuantityNotation) -> String {
+    // Power of ten the quantity is shifted by, and the text written after
+    // it, from the decimal exponent of its leading digit
+    let shift: fn(i64) -> (i64, String) = match notation {
+        QuantityNotation::Decimal => return quantity.to_string(),
+        QuantityNotation::Scientific => |exponent| (exponent, format!("e{}", exponent)),
+        QuantityNotation::Engineering => |exponent| {
+            let shift = exponent - exponent.rem_euclid(3);
+            (shift, format!("e{}", shift))
+        },
+        QuantityNotation::SiSuffix => |exponent| {
+            let group = (exponent / 3).clamp(0, 5);
+            let prefix = ["", "k", "M", "G", "T", "P"][group as usize];
+            (group * 3, prefix.to_owned())
+        },
+    };
+    if quantity.is_zero() {
+        return quantity.to_string();
+    }
+
+    let normalized = quantity.normalize();
+    let digits = normalized.mantissa().unsigned_abs().to_string();
+    let sign = if normalized.is_sign_negative() {
+        "-"
+    } else {
+        ""
+    };
+    // Decimal exponent of the leading digit.
+    let exponent = digits.len() as i64 - 1 - i64::from(normalized.scale());
+    let digits = digits.trim_end_matches('0');
+
+    let (shift, suffix) = shift(exponent);
+
+    // Number of digits in front of the decimal mark once shifted.
+    let integer_digits = exponent - shift + 1;
+    let mantissa = if integer_digits <= 0 {
+        format!("0.{}{}", "0".repeat((-integer_digits) as usize), digits)
+    } else if integer_digits as usize >= digits.len() {
+        format!(
+            "{}{}",
+            digits,
+            "0".repeat(integer_digits as usize - digits.len())
+        )
+    } else {
+        let (integer, fraction) = digits.split_at(integer_digits as usize);
+        format!("{}.{}", integer, fraction)
+    };
+
+    format!("{}{}{}", sign, mantissa, suffix)
+}
+
 impl Serializer for Balance {
     fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
     where
@@ -278,13 +828,373 @@ impl Serializer for CommodityPrice {
     where
         W: io::Write,
     {
+        let format = if self.has_time {
+            "%Y-%m-%d %H:%M:%S"
+        } else {
+            "%Y-%m-%d"
+        };
         write!(
             writer,
             "P {} {} ",
-            self.datetime.format("%Y-%m-%d %H:%M:%S"),
-            self.commodity_name
+            self.datetime.format(format),
+            commodity_name(&self.commodity_name, true)
         )?;
         self.amount.write(writer, settings)?;
         Ok(())
     }
 }
+
+impl Serializer for AccountDeclaration {
+    fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        write!(writer, "account {}", settings.account_name(&self.name))?;
+
+        if let Some(ref comment) = self.comment {
+            write!(writer, "{}; {}", settings.indent, comment)?;
+        }
+
+        for subdirective in &self.subdirectives {
+            write!(writer, "{}{}", settings.eol, settings.indent)?;
+            match subdirective {
+                AccountSubdirective::Note(value) => write!(writer, "note {}", value)?,
+                AccountSubdirective::Alias(value) => write!(writer, "alias {}", value)?,
+                AccountSubdirective::Payee(value) => write!(writer, "payee {}", value)?,
+                AccountSubdirective::Check(value) => write!(writer, "check {}", value)?,
+                AccountSubdirective::Assert(value) => write!(writer, "assert {}", value)?,
+                AccountSubdirective::Default => write!(writer, "default")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Serializer for CommodityDeclaration {
+    fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        write!(writer, "commodity {}", self.name)?;
+
+        if let Some(ref comment) = self.comment {
+            write!(writer, "{}; {}", settings.indent, comment)?;
+        }
+
+        for subdirective in &self.subdirectives {
+            write!(writer, "{}{}", settings.eol, settings.indent)?;
+            match subdirective {
+                CommoditySubdirective::Note(value) => write!(writer, "note {}", value)?,
+                CommoditySubdirective::Format(value) => write!(writer, "format {}", value)?,
+                CommoditySubdirective::Alias(value) => write!(writer, "alias {}", value)?,
+                CommoditySubdirective::NoMarket => write!(writer, "nomarket")?,
+                CommoditySubdirective::Default => write!(writer, "default")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Serializer for PayeeDeclaration {
+    fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        write!(writer, "payee {}", self.name)?;
+
+        if let Some(ref comment) = self.comment {
+            write!(writer, "{}; {}", settings.indent, comment)?;
+        }
+
+        for subdirective in &self.subdirectives {
+            write!(writer, "{}{}", settings.eol, settings.indent)?;
+            match subdirective {
+                PayeeSubdirective::Alias(value) => write!(writer, "alias {}", value)?,
+                PayeeSubdirective::Uuid(value) => write!(writer, "uuid {}", value)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Serializer for TagDeclaration {
+    fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        write!(writer, "tag {}", self.name)?;
+
+        if let Some(ref comment) = self.comment {
+            write!(writer, "{}; {}", settings.indent, comment)?;
+        }
+
+        for subdirective in &self.subdirectives {
+            write!(writer, "{}{}", settings.eol, settings.indent)?;
+            match subdirective {
+                TagSubdirective::Check(value) => write!(writer, "check {}", value)?,
+                TagSubdirective::Assert(value) => write!(writer, "assert {}", value)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Serializer for CommodityConversion {
+    fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        write!(writer, "C ")?;
+        self.from.write(writer, settings)?;
+        write!(writer, " = ")?;
+        self.to.write(writer, settings)
+    }
+}
+
+impl Serializer for Definition {
+    fn write<W>(&self, writer: &mut W, _settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        write!(writer, "define {}={}", self.name, self.value)
+    }
+}
+
+impl Serializer for Event {
+    fn write<W>(&self, writer: &mut W, _settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        write!(
+            writer,
+            "event {} {}",
+            self.date.format("%Y-%m-%d"),
+            self.description
+        )
+    }
+}
+
+impl Serializer for CommentHint {
+    fn write<W>(&self, writer: &mut W, _settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        if self.text.is_empty() {
+            write!(writer, ";{}", self.marker)
+        } else {
+            write!(writer, ";{} {}", self.marker, self.text)
+        }
+    }
+}
+
+impl Serializer for AccountAlias {
+    fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        let alias = match self.pattern() {
+            Some(_) => Cow::Borrowed(self.alias.as_str()),
+            None => settings.account_name(&self.alias),
+        };
+        write!(
+            writer,
+            "alias {}={}",
+            alias,
+            settings.account_name(&self.account)
+        )
+    }
+}
+
+impl Serializer for AccountCapture {
+    fn write<W>(&self, writer: &mut W, settings: &SerializerSettings) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        write!(
+            writer,
+            "capture {}  {}",
+            settings.account_name(&self.account),
+            self.pattern
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd(quantity: Decimal) -> Amount {
+        Amount {
+            quantity,
+            commodity: Commodity {
+                name: "$".to_owned(),
+                position: CommodityPosition::Left,
+            },
+        }
+    }
+
+    #[test]
+    fn serialize_amount_with_precision() {
+        let settings = SerializerSettings::default().with_precision(2);
+        assert_eq!(
+            usd(Decimal::new(12345, 3)).to_string_pretty(&settings),
+            "$12.34"
+        );
+        assert_eq!(
+            usd(Decimal::new(12355, 3)).to_string_pretty(&settings),
+            "$12.36"
+        );
+        assert_eq!(
+            usd(Decimal::new(125, 1)).to_string_pretty(&settings),
+            "$12.5"
+        );
+
+        let settings = settings.with_rounding(RoundingMode::HalfUp);
+        assert_eq!(
+            usd(Decimal::new(12345, 3)).to_string_pretty(&settings),
+            "$12.35"
+        );
+        assert_eq!(
+            usd(Decimal::new(-12345, 3)).to_string_pretty(&settings),
+            "$-12.35"
+        );
+
+        let settings = settings.with_rounding(RoundingMode::Truncate);
+        assert_eq!(
+            usd(Decimal::new(12349, 3)).to_string_pretty(&settings),
+            "$12.34"
+        );
+        assert_eq!(
+            usd(Decimal::new(-12349, 3)).to_string_pretty(&settings),
+            "$-12.34"
+        );
+    }
+
+    #[test]
+    fn serialize_amount_with_notation() {
+        let btc = |quantity| Amount {
+            quantity,
+            commodity: Commodity {
+                name: "BTC".to_owned(),
+                position: CommodityPosition::Right,
+            },
+        };
+        let settings = |notation| SerializerSettings::default().with_notation(notation);
+
+        let scientific = settings(QuantityNotation::Scientific);
+        assert_eq!(
+            btc(Decimal::new(15, 5)).to_string_pretty(&scientific),
+            "1.5e-4 BTC"
+        );
+        assert_eq!(
+            usd(Decimal::new(-123450000, 2)).to_string_pretty(&scientific),
+            "$-1.2345e6"
+        );
+        assert_eq!(
+            usd(Decimal::new(3, 0)).to_string_pretty(&scientific),
+            "$3e0"
+        );
+        assert_eq!(
+            usd(Decimal::new(0, 2)).to_string_pretty(&scientific),
+            "$0.00"
+        );
+
+        let engineering = settings(QuantityNotation::Engineering);
+        assert_eq!(
+            btc(Decimal::new(15, 5)).to_string_pretty(&engineering),
+            "150e-6 BTC"
+        );
+        assert_eq!(
+            usd(Decimal::new(12345, 0)).to_string_pretty(&engineering),
+            "$12.345e3"
+        );
+        assert_eq!(
+            usd(Decimal::new(12, 1)).to_string_pretty(&engineering),
+            "$1.2e0"
+        );
+
+        let si = settings(QuantityNotation::SiSuffix);
+        assert_eq!(usd(Decimal::new(2500000, 0)).to_string_pretty(&si), "$2.5M");
+        assert_eq!(
+            btc(Decimal::new(-1500, 0)).to_string_pretty(&si),
+            "-1.5k BTC"
+        );
+        assert_eq!(usd(Decimal::new(120, 2)).to_string_pretty(&si), "$1.2");
+        assert_eq!(
+            btc(Decimal::new(15, 5)).to_string_pretty(&si),
+            "0.00015 BTC"
+        );
+
+        let stock = |name: &str, quantity| Amount {
+            quantity,
+            commodity: Commodity {
+                name: name.to_owned(),
+                position: CommodityPosition::Left,
+            },
+        };
+        assert_eq!(stock("BRK.B", Decimal::new(5, 0)).to_string(), "BRK.B 5");
+        assert_eq!(
+            stock("EUR", Decimal::new(5, 0)).to_string_pretty(&scientific),
+            "EUR 5e0"
+        );
+        for notation in [QuantityNotation::Decimal, QuantityNotation::Scientific] {
+            for amount in [
+                stock("BRK.B", Decimal::new(5, 0)),
+                stock("A1B", Decimal::new(15, 1)),
+                stock("EUR", Decimal::new(-5, 0)),
+            ] {
+                let text = amount.to_string_pretty(&settings(notation));
+                let parsed = crate::parse_amount(&text, &crate::ParserSettings::default());
+                assert_eq!(parsed.unwrap(), amount, "{}", text);
+            }
+        }
+
+        for notation in [QuantityNotation::Scientific, QuantityNotation::Engineering] {
+            for amount in [btc(Decimal::new(15, 5)), usd(Decimal::new(-123450000, 2))] {
+                let text = amount.to_string_pretty(&settings(notation));
+                let parsed = crate::parse_amount(&text, &crate::ParserSettings::default());
+                assert_eq!(parsed.unwrap(), amount, "{}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn serialize_posting_dates() {
+        let ledger = crate::parse(
+            "2023-01-02 Transfer\n  Assets:Savings  $10\n  ; date:2023/01/05\n  Assets:Checking\n",
+        )
+        .unwrap();
+        let mut transaction = ledger.transactions().next().unwrap().clone();
+        let date = |d| NaiveDate::from_ymd_opt(2023, 1, d).unwrap();
+        assert_eq!(transaction.postings[0].date, Some(date(5)));
+        assert_eq!(transaction.postings[0].effective_date, None);
+        assert_eq!(
+            transaction.to_string(),
+            "2023-01-02 Transfer\n  Assets:Savings  $10\n  ; date:2023/01/05\n  Assets:Checking"
+        );
+
+        transaction.postings[0].effective_date = Some(date(6));
+        transaction.postings[1].date = Some(date(3));
+        assert_eq!(
+            transaction.to_string(),
+            "2023-01-02 Transfer\n  Assets:Savings  $10\n  ; date:2023/01/05\n  ; date2:2023-01-06\n  Assets:Checking\n  ; date:2023-01-03"
+        );
+    }
+
+    #[test]
+    fn account_separator_round_trip() {
+        let input = "account Assets.Bank\n2024-01-02 Shop\n  Expenses.Food  $5\n  [Assets.Bank]\n";
+        let settings = crate::ParserSettings::default().with_account_separator('.');
+        let (ledger, _) = crate::parse_with_settings(input, &settings).unwrap();
+        let transaction = ledger.transactions().next().unwrap();
+        assert_eq!(transaction.postings[0].account, "Expenses:Food");
+        assert_eq!(transaction.postings[1].account, "Assets:Bank");
+
+        let settings = SerializerSettings::default().with_account_separator('.');
+        assert_eq!(ledger.to_string_pretty(&settings), input);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::model::*;
+use std::io;
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"LEDGSNAP";
+
+/// Bumped whenever the model changes in a way that affects the encoding.
+/// `snapshot_version_test` fails until it is, along with the encoding
+/// fingerprint it checks.
+const SNAPSHOT_VERSION: u32 = 3;
+
+impl Ledger {
+    /// Writes the ledger as a binary snapshot that can be loaded with
+    /// [`Ledger::read_snapshot`] without parsing the journal text again.
+    ///
+    /// The snapshot is only meant to be read back by the same version of
+    /// this crate; it is not a stable interchange format.
+    pub fn write_snapshot<W>(&self, writer: &mut W) -> Result<(), io::Error>
+    where
+        W: io::Write,
+    {
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        bincode::serialize_into(writer, self).map_err(|error| into_io_error(*error))
+    }
+
+    /// Loads a ledger written by [`Ledger::write_snapshot`].
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the data is not a snapshot
+    /// or was written by an incompatible version.
+    pub fn read_snapshot<R>(reader: &mut R) -> Result<Ledger, io::Error>
+    where
+        R: io::Read,
+    {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a ledger snapshot",
+            ));
+        }
+
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != SNAPSHOT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "unsupported snapshot version {} (expected {})",
+                    version, SNAPSHOT_VERSION
+                ),
+            ));
+        }
+
+        bincode::deserialize_from(reader).map_err(|error| into_io_error(*error))
+    }
+}
+
+fn into_io_error(error: bincode::ErrorKind) -> io::Error {
+    match error {
+        bincode::ErrorKind::Io(error) => error,
+        error => io::Error::new(io::ErrorKind::InvalidData, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn snapshot_round_trip() {
+        let ledger = parse(
+            r#"; Example
+2018-10-01=2018-10-14 ! (123) Description
+  ; Transaction comment
+  TEST:Account 123  $1.20 {5.00 PLN} @@ 6.00 PLN
+  [TEST:Account 345]  -$1.20 = $0
+
+P 2017-11-12 12:00:00 mBH 5.00 PLN
+include other.ledger
+~ monthly from 2023-01-01
+  TEST:A  1 EUR
+  TEST:B
+"#,
+        )
+        .unwrap();
+
+        let mut snapshot = Vec::new();
+        ledger.write_snapshot(&mut snapshot).unwrap();
+        let loaded = Ledger::read_snapshot(&mut snapshot.as_slice()).unwrap();
+        assert_eq!(loaded, ledger);
+
+        snapshot[8] = 0xff;
+        let error = Ledger::read_snapshot(&mut snapshot.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        let error = Ledger::read_snapshot(&mut &b"2018-10-01 Payee"[..]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn snapshot_version_test() {
+        let settings = ParserSettings::default().with_status_flags(&['?']);
+        let (ledger, _) = parse_with_settings(
+            r#"Y 2024
+decimal-mark .
+03/01=03/02 ? (7) Shop | Groceries  ; :food:
+  * Assets:Broker  10 AAPL {=$150} [2024-01-01] (first lot) @ $170 ==* 10 AAPL
+  ; date:2024-03-05
+  (Budget:Food)  ($5 * 2)
+  Assets:Cash
+P 2024-03-01 12:00:00 EUR $1.10
+alias Cash = Assets:Cash
+capture Expenses:Deductible  medical
+account Assets:Cash
+  note Wallet
+commodity $
+  format $1,000.00
+define rate = 2
+check amount > 0
+eval total
+comment
+text
+end comment
+= /Food/
+  (Budget:Food)  -1
+~ monthly from 2024-01-01
+  Expenses:Rent  $500
+  Assets:Bank
+"#,
+            &settings,
+        )
+        .unwrap();
+        let mut snapshot = Vec::new();
+        ledger.write_snapshot(&mut snapshot).unwrap();
+
+        // FNV-1a, so that the fingerprint doesn't depend on the Rust version
+        let fingerprint = snapshot.iter().fold(0xcbf29ce484222325u64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+        // When the encoding changes, bump SNAPSHOT_VERSION and update this
+        assert_eq!(
+            (super::SNAPSHOT_VERSION, fingerprint),
+            (3, 0xbd082036070c03c7),
+            "snapshot encoding changed"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use crate::model::*;
+use crate::{parse_iter, ParseError, ParserSettings};
+use std::ops::Range;
+
+///
+/// Location of a parsed element in the source.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Span {
+    /// Line of the first character, starting from 1.
+    pub line: usize,
+    /// Column of the first character in characters, starting from 1.
+    pub column: usize,
+    /// Bytes of the element, without the line break ending it.
+    pub byte_range: Range<usize>,
+}
+
+///
+/// Location of a [`LedgerItem`] and of its postings, if it has any, in the
+/// order of the postings.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ItemSpan {
+    pub span: Span,
+    pub postings: Vec<Span>,
+}
+
+/// Finds the line and column of byte offsets given in increasing order,
+/// without rescanning the input from the start.
+#[derive(Debug, Default)]
+pub(crate) struct LineIndex {
+    /// Byte offset of the start of the line of the last offset.
+    line_start: usize,
+    line: usize,
+}
+
+impl LineIndex {
+    /// Span of `input[range]`, less leading blanks when `trim_start` and any
+    /// trailing line breaks.
+    pub(crate) fn span(&mut self, input: &str, range: Range<usize>, trim_start: bool) -> Span {
+        let mut text = &input[range.clone()];
+        if trim_start {
+            text = text.trim_start_matches([' ', '\t']);
+        }
+        let start = range.end - text.len();
+        let end = start + text.trim_end_matches(['\r', '\n']).len();
+
+        let skipped = &input[self.line_start..start];
+        self.line += skipped.matches('\n').count();
+        if let Some(newline) = skipped.rfind('\n') {
+            self.line_start += newline + 1;
+        }
+        Span {
+            line: self.line + 1,
+            column: input[self.line_start..start].chars().count() + 1,
+            byte_range: start..end,
+        }
+    }
+}
+
+/// Parses ledger-cli source like [`crate::parse_with_settings`], also
+/// returning where each item is in the input, in the order of
+/// [`Ledger::items`]. Byte offsets are into `input`, or into the decoded
+/// text if [`ParserSettings::with_lossy_input`] replaced invalid bytes.
+/// Lines end with `\n`, `\r\n` or a lone `\r`.
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::*;
+///
+/// let input = "; Shop\n2018-10-01 Shop\n  Expenses:Food  $10\n  Assets:Cash\n";
+/// let (ledger, spans) = parse_with_spans(input, &ParserSettings::default()).unwrap();
+/// assert_eq!(ledger.items.len(), spans.len());
+/// let posting = &spans[1].postings[1];
+/// assert_eq!((posting.line, posting.column), (4, 3));
+/// assert_eq!(&input[posting.byte_range.clone()], "Assets:Cash");
+/// ```
+pub fn parse_with_spans(
+    input: &str,
+    settings: &ParserSettings,
+) -> Result<(Ledger, Vec<ItemSpan>), ParseError> {
+    let mut items = Vec::new();
+    let mut spans = Vec::new();
+    let mut iter = parse_iter(input, settings);
+    while let Some(result) = iter.next_with_span() {
+        let (item, span) = result?;
+        items.push(item);
+        spans.push(span);
+    }
+    Ok((Ledger { items }, spans))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn parse_with_spans_test() {
+        let input = "; ż\r\n\r\n2024-01-02 Shop  ; note\r\n    Expenses:Food  $5\r\n    ; food\r\n\tAssets:Cash\r\n= /Food/\r\n  (Budget)  -1\r\nP 2024-01-02 00:00:00 EUR $1.10";
+        let (ledger, spans) = parse_with_spans(input, &ParserSettings::default()).unwrap();
+        assert_eq!(ledger, parse(input).unwrap());
+        let text = |span: &Span| &input[span.byte_range.clone()];
+        let position = |span: &Span| (span.line, span.column);
+
+        assert_eq!(text(&spans[0].span), "; ż");
+        assert_eq!(position(&spans[1].span), (2, 1));
+        assert_eq!(text(&spans[1].span), "");
+        assert_eq!(position(&spans[2].span), (3, 1));
+        assert!(text(&spans[2].span).ends_with("\tAssets:Cash"));
+        assert_eq!(
+            spans[2].postings.iter().map(position).collect::<Vec<_>>(),
+            [(4, 5), (6, 2)]
+        );
+        assert_eq!(
+            text(&spans[2].postings[0]),
+            "Expenses:Food  $5\r\n    ; food"
+        );
+        assert_eq!(text(&spans[3].postings[0]), "(Budget)  -1");
+        assert_eq!(position(&spans[4].span), (9, 1));
+        assert!(spans[4].postings.is_empty());
+        assert_eq!(spans[4].span.byte_range.end, input.len());
+
+        let input = "\u{feff}2024-01-02 Shop\n  Expenses:Food  $5\n  Assets:Cash\n";
+        let (_, spans) = parse_with_spans(input, &ParserSettings::default()).unwrap();
+        let text = |span: &Span| &input[span.byte_range.clone()];
+        assert_eq!(text(&spans[0].span), &input[3..input.len() - 1]);
+        assert_eq!(text(&spans[0].postings[0]), "Expenses:Food  $5");
+        assert_eq!(position(&spans[0].postings[0]), (2, 3));
+
+        let input = "2024-01-02 Shop\r  Expenses:Food  $5\r  Assets:Cash\r";
+        let (_, spans) = parse_with_spans(input, &ParserSettings::default()).unwrap();
+        let text = |span: &Span| &input[span.byte_range.clone()];
+        assert_eq!(text(&spans[0].postings[0]), "Expenses:Food  $5");
+        assert_eq!(position(&spans[0].postings[1]), (3, 3));
+    }
+}
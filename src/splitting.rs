@@ -0,0 +1,144 @@
+use crate::model::*;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+impl Amount {
+    /// Splits the amount into `parts` equal parts. See
+    /// [`Amount::split_weighted`].
+    pub fn split(&self, parts: usize, decimal_places: u32) -> Option<Vec<Amount>> {
+        self.split_weighted(&vec![Decimal::ONE; parts], decimal_places)
+    }
+
+    /// Splits the amount in proportion to `weights`, e.g. for VAT or shared
+    /// expenses.
+    ///
+    /// Parts are multiples of `decimal_places` decimal places (or finer, if
+    /// the amount itself is written with more, and at most 28, the most a
+    /// [`Decimal`] holds) and always add up to the amount. The units left over after rounding every part towards zero go
+    /// to the parts with the largest remainders, earlier parts first on ties
+    /// (largest remainder method), so the result is deterministic.
+    ///
+    /// Returns `None` if there are no weights, a weight is negative, or they
+    /// sum to zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ledger_parser::*;
+    ///
+    /// let amount = Amount {
+    ///     quantity: rust_decimal::Decimal::new(10, 0),
+    ///     commodity: Commodity {
+    ///         name: "$".to_owned(),
+    ///         position: CommodityPosition::Left,
+    ///     },
+    /// };
+    /// let parts: Vec<String> = amount
+    ///     .split(3, 2)
+    ///     .unwrap()
+    ///     .iter()
+    ///     .map(Amount::to_string)
+    ///     .collect();
+    /// assert_eq!(parts, ["$3.34", "$3.33", "$3.33"]);
+    /// ```
+    pub fn split_weighted(&self, weights: &[Decimal], decimal_places: u32) -> Option<Vec<Amount>> {
+        let total_weight = weights
+            .iter()
+            .try_fold(Decimal::ZERO, |sum, weight| sum.checked_add(*weight))?;
+        if total_weight.is_zero() || weights.iter().any(Decimal::is_sign_negative) {
+            return None;
+        }
+
+        let scale = decimal_places
+            .max(self.quantity.scale())
+            .min(Decimal::MAX_SCALE);
+        let unit = Decimal::new(1, scale);
+        let total = self.quantity.abs();
+
+        let mut shares = Vec::with_capacity(weights.len());
+        let mut left = total;
+        for weight in weights {
+            let exact = total.checked_mul(*weight)?.checked_div(total_weight)?;
+            let share = exact.round_dp_with_strategy(scale, RoundingStrategy::ToZero);
+            left -= share;
+            shares.push((share, exact - share));
+        }
+
+        let mut order: Vec<usize> = (0..shares.len()).collect();
+        order.sort_by(|a, b| shares[*b].1.cmp(&shares[*a].1));
+        for index in order {
+            if left < unit {
+                break;
+            }
+            shares[index].0 += unit;
+            left -= unit;
+        }
+
+        let sign = if self.quantity.is_sign_negative() {
+            Decimal::NEGATIVE_ONE
+        } else {
+            Decimal::ONE
+        };
+        Some(
+            shares
+                .into_iter()
+                .map(|(share, _)| Amount {
+                    quantity: share * sign,
+                    commodity: self.commodity.clone(),
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::amount_from_str;
+    use crate::*;
+    use rust_decimal::Decimal;
+
+    fn parts(amounts: Option<Vec<Amount>>) -> Vec<String> {
+        amounts.unwrap().iter().map(Amount::to_string).collect()
+    }
+
+    #[test]
+    fn split_test() {
+        let amount = amount_from_str("-100.00 EUR").unwrap();
+        assert_eq!(
+            parts(amount.split(6, 2)),
+            [
+                "-16.67 EUR",
+                "-16.67 EUR",
+                "-16.67 EUR",
+                "-16.67 EUR",
+                "-16.66 EUR",
+                "-16.66 EUR"
+            ]
+        );
+        assert_eq!(
+            parts(amount.split(3, 0)),
+            ["-33.34 EUR", "-33.33 EUR", "-33.33 EUR"]
+        );
+        assert_eq!(amount.split(0, 2), None);
+
+        let weights = [Decimal::new(19, 0), Decimal::new(100, 0)];
+        let amount = amount_from_str("$10").unwrap();
+        assert_eq!(
+            parts(amount.split_weighted(&weights, 2)),
+            ["$1.60", "$8.40"]
+        );
+        assert_eq!(
+            parts(amount.split_weighted(&[Decimal::ONE, Decimal::ZERO, Decimal::ONE], 0)),
+            ["$5", "$0", "$5"]
+        );
+        assert_eq!(
+            amount.split_weighted(&[Decimal::ONE, -Decimal::ONE], 2),
+            None
+        );
+        assert_eq!(amount.split_weighted(&[Decimal::ZERO], 2), None);
+
+        let thirds = amount.split(3, u32::MAX).unwrap();
+        assert_eq!(thirds[2].quantity.scale(), Decimal::MAX_SCALE);
+        let sum: Decimal = thirds.iter().map(|part| part.quantity).sum();
+        assert_eq!(sum, amount.quantity);
+    }
+}
@@ -0,0 +1,333 @@
+use crate::model::*;
+use crate::parser::{self, Context};
+use crate::spans::LineIndex;
+use crate::{
+    catch_panic, encoding, nom_error, syntax_error, ItemSpan, ParseError, ParseWarning,
+    ParserSettings,
+};
+use nom::error::{ContextError, ErrorKind, ParseError as _, VerboseError};
+use std::borrow::Cow;
+use std::ops::Range;
+
+///
+/// Iterator over the items of ledger-cli source, parsing one item per call
+/// to `next`. Created by [`parse_iter`].
+///
+/// Items are parsed exactly as [`crate::parse_with_settings`] parses them.
+/// Iteration stops after the first error, unless
+/// [`LedgerItems::resume`] is called.
+///
+pub struct LedgerItems<'a> {
+    input: Cow<'a, str>,
+    /// Input as given, before decoding.
+    source: &'a str,
+    /// Byte order mark dropped from the start of the input when decoding.
+    bom: &'a str,
+    /// Byte offset of the input left to parse.
+    position: usize,
+    ctx: Context,
+    warnings: Vec<ParseWarning>,
+    error: Option<ParseError>,
+    done: bool,
+    lines: LineIndex,
+}
+
+/// Parses ledger-cli source lazily, yielding its items one at a time so
+/// they can be processed and dropped without building the whole
+/// [`Ledger`].
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::*;
+///
+/// let input = "2018-10-01 Shop\n  Expenses:Food  $10\n  Assets:Cash\n\n2018-10-02 Shop\n  Expenses:Food  $5\n  Assets:Cash\n";
+/// let count = parse_iter(input, &ParserSettings::default())
+///     .filter(|item| matches!(item, Ok(LedgerItem::Transaction(_))))
+///     .count();
+/// assert_eq!(count, 2);
+/// ```
+pub fn parse_iter<'a>(source: &'a str, settings: &ParserSettings) -> LedgerItems<'a> {
+    let (input, warnings, error) = match encoding::decode(source.as_bytes(), settings) {
+        Ok((input, warnings)) => (input, warnings, None),
+        Err(error) => (Cow::Borrowed(""), Vec::new(), Some(error)),
+    };
+    let bom = match source.strip_prefix('\u{feff}') {
+        Some(rest) => &source[..source.len() - rest.len()],
+        None => "",
+    };
+    let ctx = Context::new(settings.clone());
+    ctx.record_posting_spans();
+    LedgerItems {
+        input,
+        source,
+        bom,
+        position: 0,
+        ctx,
+        warnings,
+        error,
+        done: false,
+        lines: LineIndex::default(),
+    }
+}
+
+impl LedgerItems<'_> {
+    /// Takes the warnings about the items parsed so far, as
+    /// [`crate::parse_with_warnings`] reports them.
+    pub fn take_warnings(&mut self) -> Vec<ParseWarning> {
+        let mut warnings = std::mem::take(&mut self.warnings);
+        warnings.extend(self.ctx.take_warnings(&self.input));
+        warnings.sort_by_key(|warning| warning.line);
+        warnings
+    }
+
+    /// After `next` returned an error, skips the item that failed to parse,
+    /// its first line and the indented lines that follow, so that iteration
+    /// goes on with the next item. Returns the skipped lines, numbered from
+    /// 1.
+    pub fn resume(&mut self) -> Range<usize> {
+        let input: &str = &self.input;
+        let next_line = |from: usize| {
+            input[from..]
+                .find('\n')
+                .map_or(input.len(), |i| from + i + 1)
+        };
+        let start = self.position;
+        let mut end = next_line(start).min(input.len());
+        while end < input.len() {
+            let line = &input[end..next_line(end)];
+            if !line.starts_with([' ', '\t']) || line.trim().is_empty() {
+                break;
+            }
+            end = next_line(end);
+        }
+
+        let first = self.lines.span(input, start..start, false).line;
+        let count = input[start..end].lines().count();
+        self.position = end;
+        self.done = false;
+        first..first + count
+    }
+
+    /// Parses the next item, returning it with its byte range.
+    fn parse_next(&mut self) -> Option<Result<(LedgerItem, usize, usize), ParseError>> {
+        if let Some(error) = self.error.take() {
+            self.done = true;
+            return Some(Err(error));
+        }
+        if self.done || self.position == self.input.len() {
+            self.done = true;
+            return None;
+        }
+
+        let input: &str = &self.input;
+        let rest = &input[self.position..];
+        let result = catch_panic(|| match parser::parse_ledger_step(rest, &self.ctx) {
+            Ok((left, item)) => Ok((input.len() - left.len(), item)),
+            Err(nom::Err::Error(_)) => {
+                // What `parse_ledger` reports when no item parses
+                let error = VerboseError::from_error_kind(rest, ErrorKind::Eof);
+                let error =
+                    VerboseError::add_context(rest, "a transaction, directive or comment", error);
+                Err(syntax_error(input, error))
+            }
+            Err(error) => Err(nom_error(input, error)),
+        });
+        match result {
+            Ok((end, item)) => {
+                let start = std::mem::replace(&mut self.position, end);
+                Some(Ok((item, start, end)))
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+
+    /// Parses the next item like `next`, along with the bytes of the
+    /// decoded input it was read from, line break included.
+    pub(crate) fn next_with_range(
+        &mut self,
+    ) -> Option<Result<(LedgerItem, Range<usize>), ParseError>> {
+        let result = self.parse_next()?;
+        self.ctx.take_posting_spans();
+        Some(result.map(|(item, start, end)| (item, start..end)))
+    }
+
+    /// Text of the item read from `range` of the decoded input, starting
+    /// with the byte order mark of the input for the first item. It is taken
+    /// from the input as given unless decoding replaced characters, so that
+    /// lone `\r` line breaks are kept.
+    pub(crate) fn item_source(&self, range: Range<usize>) -> String {
+        let first = range.start == 0;
+        let bom = self.bom.len();
+        if bom + self.input.len() == self.source.len() {
+            // Decoding only dropped the byte order mark and turned lone `\r`
+            // into `\n`
+            let start = if first { 0 } else { range.start + bom };
+            return self.source[start..range.end + bom].to_owned();
+        }
+        let bom = if first { self.bom } else { "" };
+        format!("{}{}", bom, &self.input[range])
+    }
+
+    /// Parses the next item like `next`, along with where the item and its
+    /// postings are in the input.
+    pub fn next_with_span(&mut self) -> Option<Result<(LedgerItem, ItemSpan), ParseError>> {
+        let result = self.parse_next()?;
+        let postings = self.ctx.take_posting_spans();
+        Some(result.map(|(item, start, end)| {
+            let input: &str = &self.input;
+            // Byte ranges count the byte order mark, which is not decoded
+            let bom = self.bom.len();
+            let mut span = |range: Range<usize>, trim_start| {
+                let mut span = self.lines.span(input, range, trim_start);
+                span.byte_range = span.byte_range.start + bom..span.byte_range.end + bom;
+                span
+            };
+            let item_span = span(start..end, false);
+            let postings = postings
+                .into_iter()
+                .map(|(from, to)| span(input.len() - from..input.len() - to, true))
+                .collect();
+            (
+                item,
+                ItemSpan {
+                    span: item_span,
+                    postings,
+                },
+            )
+        }))
+    }
+}
+
+/// Error found by [`parse_with_recovery`], and the lines skipped because
+/// of it.
+#[derive(Debug)]
+pub struct ParseDiagnostic {
+    pub error: ParseError,
+    /// Lines of the item that failed to parse, numbered from 1.
+    pub lines: Range<usize>,
+}
+
+/// Parses ledger-cli source like [`crate::parse_with_settings`], but skips
+/// the items that fail to parse instead of stopping at the first one,
+/// returning an error for each.
+///
+/// An item is skipped along with the indented lines that follow its first
+/// line, so a malformed posting drops its whole transaction. Invalid UTF-8
+/// and control characters are still fatal unless
+/// [`ParserSettings::with_lossy_input`] is set, and give an empty ledger.
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::*;
+///
+/// let input = "2018-10-01 Shop\n  Expenses:Food  $1 +\n  Assets:Cash\n2018-10-02 Shop\n  Expenses:Food  $5\n  Assets:Cash\n";
+/// let (ledger, diagnostics) = parse_with_recovery(input, &ParserSettings::default());
+/// assert_eq!(ledger.transactions().count(), 1);
+/// assert_eq!(diagnostics[0].lines, 1..4);
+/// ```
+pub fn parse_with_recovery(
+    input: &str,
+    settings: &ParserSettings,
+) -> (Ledger, Vec<ParseDiagnostic>) {
+    let mut items = Vec::new();
+    let mut diagnostics = Vec::new();
+    let mut iter = parse_iter(input, settings);
+    while let Some(result) = iter.next() {
+        match result {
+            Ok(item) => items.push(item),
+            Err(error) => diagnostics.push(ParseDiagnostic {
+                error,
+                lines: iter.resume(),
+            }),
+        }
+    }
+    (Ledger { items }, diagnostics)
+}
+
+impl Iterator for LedgerItems<'_> {
+    type Item = Result<LedgerItem, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.parse_next()?;
+        self.ctx.take_posting_spans();
+        Some(result.map(|(item, _, _)| item))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn parse_iter_test() {
+        let input = r#"Y 2024
+; Groceries
+03/01 Shop
+  Expenses:Food  $1,000
+  Assets:Cash $20
+  Assets:Bank
+
+P 2024-03-01 00:00:00 EUR $1.10
+"#;
+        let settings = ParserSettings::default();
+        let (ledger, warnings) = parse_with_settings(input, &settings).unwrap();
+        let mut items = parse_iter(input, &settings);
+        assert_eq!(items.next().unwrap().unwrap(), ledger.items[0]);
+        assert!(items.take_warnings().is_empty());
+        let rest: Vec<LedgerItem> = items.by_ref().map(Result::unwrap).collect();
+        assert_eq!(rest, ledger.items[1..]);
+        assert_eq!(items.take_warnings(), warnings);
+
+        let input = "; ok\n2024-01-02 Shop\n  Expenses:Food  $5\n  Assets:Cash\nbad line\n; never reached\n";
+        let items: Vec<Result<LedgerItem, ParseError>> = parse_iter(input, &settings).collect();
+        assert_eq!(items.len(), 3);
+        assert_eq!(
+            items[2].as_ref().unwrap_err().to_string(),
+            parse(input).unwrap_err().to_string()
+        );
+
+        let mut items = parse_iter("2024-01-02 Caf\u{1b}\n", &settings);
+        assert!(matches!(
+            items.next(),
+            Some(Err(ParseError::ControlCharacter { offset: 14, .. }))
+        ));
+        assert!(items.next().is_none());
+    }
+
+    #[test]
+    fn parse_with_recovery_test() {
+        let input = r#"2024-01-01 Good
+  Expenses:Food  $5
+  Assets:Cash
+2024-01-02 Bad
+  Expenses:Food  $5 @@
+  ; comment
+
+  Assets:Cash
+what is this
+P 2024-01-03 00:00:00 EUR $1.10
+2024-01-0x Bad date
+  Expenses:Food  $5"#;
+        let (ledger, diagnostics) = parse_with_recovery(input, &ParserSettings::default());
+        assert_eq!(
+            ledger.to_string(),
+            "2024-01-01 Good\n  Expenses:Food  $5\n  Assets:Cash\n\nP 2024-01-03 00:00:00 EUR $1.10\n"
+        );
+        let lines: Vec<_> = diagnostics.iter().map(|d| d.lines.clone()).collect();
+        assert_eq!(lines, [4..7, 8..9, 9..10, 11..13]);
+        assert!(matches!(
+            diagnostics[2].error,
+            ParseError::Syntax(SyntaxError { line: 9, .. })
+        ));
+
+        let (ledger, diagnostics) =
+            parse_with_recovery("2024-01-02 Caf\u{1b}\n", &ParserSettings::default());
+        assert!(ledger.items.is_empty());
+        assert_eq!(diagnostics.len(), 1);
+    }
+}
@@ -0,0 +1,312 @@
+use crate::model::*;
+use crate::parser::{format_from_str, ParserSettings};
+use std::collections::BTreeMap;
+
+///
+/// How amounts of a commodity are displayed.
+///
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommodityStyle {
+    pub position: CommodityPosition,
+    /// Number of decimal places.
+    pub precision: u32,
+    /// Decimal mark, if not the one of the serializer settings.
+    pub decimal_mark: Option<char>,
+}
+
+#[derive(Default)]
+struct StyleVotes {
+    left: usize,
+    right: usize,
+    precision: u32,
+}
+
+impl StyleVotes {
+    fn add(&mut self, amount: &Amount) {
+        match amount.commodity.position {
+            CommodityPosition::Left => self.left += 1,
+            CommodityPosition::Right => self.right += 1,
+        }
+        self.precision = self.precision.max(amount.quantity.scale());
+    }
+
+    fn style(&self) -> CommodityStyle {
+        let position = if self.right > self.left {
+            CommodityPosition::Right
+        } else {
+            CommodityPosition::Left
+        };
+        CommodityStyle {
+            position,
+            precision: self.precision,
+            decimal_mark: None,
+        }
+    }
+}
+
+impl CommodityDeclaration {
+    /// Commodity name and display style declared by the `format`
+    /// subdirective or by the one-line form (`commodity $1,000.00`).
+    pub fn style(&self) -> Option<(String, CommodityStyle)> {
+        format_style(self.format())
+    }
+
+    /// Argument of the `format` subdirective, or else the name, which is
+    /// the format in the one-line form.
+    pub(crate) fn format(&self) -> &str {
+        self.subdirectives
+            .iter()
+            .find_map(|subdirective| match subdirective {
+                CommoditySubdirective::Format(format) => Some(format),
+                _ => None,
+            })
+            .unwrap_or(&self.name)
+    }
+}
+
+/// Commodity name and display style of an amount written as a format, such
+/// as `$1,000.00` or `1.000,00 EUR`.
+fn format_style(format: &str) -> Option<(String, CommodityStyle)> {
+    let (amount, decimal_mark) = format_from_str(format, &ParserSettings::default())?;
+    let style = CommodityStyle {
+        position: amount.commodity.position,
+        precision: amount.quantity.scale(),
+        decimal_mark,
+    };
+    Some((amount.commodity.name, style))
+}
+
+///
+/// Amount whose commodity symbol is on the other side than the commodity's
+/// style, found by [`Ledger::commodity_position_mismatches`].
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CommodityPositionMismatch {
+    /// Index of the item in [`Ledger::items`].
+    pub item: usize,
+    pub amount: Amount,
+    pub expected: CommodityPosition,
+}
+
+impl Ledger {
+    /// Calls `f` with the item index and every amount written in
+    /// transactions, periodic transactions and commodity prices.
+    fn for_each_amount<F>(&self, mut f: F)
+    where
+        F: FnMut(usize, &Amount),
+    {
+        for (index, item) in self.items.iter().enumerate() {
+            let postings = match item {
+                LedgerItem::Transaction(transaction) => &transaction.postings,
+                LedgerItem::PeriodicTransaction(transaction) => &transaction.postings,
+                LedgerItem::CommodityPrice(price) => {
+                    f(index, &price.amount);
+                    continue;
+                }
+                _ => continue,
+            };
+
+            for posting in postings {
+                if let Some(ref amount) = posting.amount {
+                    f(index, &amount.amount);
+                    for price in [&amount.lot_price, &amount.price].into_iter().flatten() {
+                        match price {
+                            Price::Unit(amount) | Price::Total(amount) => f(index, amount),
+                        }
+                    }
+                }
+                if let Some(Balance::Amount(ref amount)) = posting.balance {
+                    f(index, amount);
+                }
+            }
+        }
+    }
+
+    /// Infers each commodity's display style from how its amounts are written
+    /// throughout the journal: the majority symbol position and, like ledger,
+    /// the largest number of decimal places used. Styles declared with the
+    /// `commodity` or `D` directives take precedence.
+    ///
+    /// The result can be passed to
+    /// [`SerializerSettings::with_commodity_styles`](crate::SerializerSettings::with_commodity_styles).
+    pub fn infer_commodity_styles(&self) -> BTreeMap<String, CommodityStyle> {
+        let mut votes: BTreeMap<String, StyleVotes> = BTreeMap::new();
+        self.for_each_amount(|_, amount| {
+            votes
+                .entry(amount.commodity.name.clone())
+                .or_default()
+                .add(amount)
+        });
+
+        let mut styles: BTreeMap<String, CommodityStyle> = votes
+            .into_iter()
+            .map(|(name, votes)| (name, votes.style()))
+            .collect();
+        for item in &self.items {
+            match item {
+                LedgerItem::CommodityDeclaration(declaration) => styles.extend(declaration.style()),
+                LedgerItem::DefaultCommodity(format) => styles.extend(format_style(format)),
+                _ => {}
+            }
+        }
+        styles
+    }
+
+    /// Lists the amounts whose symbol position differs from their
+    /// commodity's style in `styles`, usually the result of
+    /// [`Ledger::infer_commodity_styles`].
+    ///
+    /// Serializing with
+    /// [`SerializerSettings::with_commodity_styles`](crate::SerializerSettings::with_commodity_styles)
+    /// fixes them. The space between symbol and quantity is not kept by the
+    /// parser, so it is always consistent.
+    pub fn commodity_position_mismatches(
+        &self,
+        styles: &BTreeMap<String, CommodityStyle>,
+    ) -> Vec<CommodityPositionMismatch> {
+        let mut mismatches = Vec::new();
+        self.for_each_amount(|item, amount| {
+            if let Some(style) = styles.get(&amount.commodity.name) {
+                if style.position != amount.commodity.position {
+                    mismatches.push(CommodityPositionMismatch {
+                        item,
+                        amount: amount.clone(),
+                        expected: style.position,
+                    });
+                }
+            }
+        });
+        mismatches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn infer_commodity_styles_test() {
+        let ledger = parse(
+            r#"2024-01-02 Shop
+  Expenses:Food  $20.00
+  Expenses:Fun  10 EUR @ $1.1
+  Assets:Cash  $-31.00 = $0
+2024-01-03 Shop
+  Expenses:Food  EUR5
+  Assets:Cash  -5.50 EUR
+"#,
+        )
+        .unwrap();
+
+        let styles = ledger.infer_commodity_styles();
+        assert_eq!(
+            styles["$"],
+            CommodityStyle {
+                position: CommodityPosition::Left,
+                precision: 2,
+                decimal_mark: None
+            }
+        );
+        assert_eq!(
+            styles["EUR"],
+            CommodityStyle {
+                position: CommodityPosition::Right,
+                precision: 2,
+                decimal_mark: None
+            }
+        );
+
+        let settings = SerializerSettings::default().with_commodity_styles(styles);
+        assert_eq!(
+            ledger.to_string_pretty(&settings),
+            r#"2024-01-02 Shop
+  Expenses:Food  $20.00
+  Expenses:Fun  10.00 EUR @ $1.10
+  Assets:Cash  $-31.00 = $0.00
+2024-01-03 Shop
+  Expenses:Food  5.00 EUR
+  Assets:Cash  -5.50 EUR
+"#
+        );
+    }
+
+    #[test]
+    fn declared_commodity_styles_test() {
+        let ledger = parse(
+            r#"commodity EUR
+  note Euro
+  format 1.000 EUR
+  nomarket
+commodity $1,000.00
+2024-01-02 Shop
+  Expenses:Food  EUR5
+  Assets:Cash  $-5
+"#,
+        )
+        .unwrap();
+
+        let styles = ledger.infer_commodity_styles();
+        assert_eq!(
+            styles["EUR"],
+            CommodityStyle {
+                position: CommodityPosition::Right,
+                precision: 3,
+                decimal_mark: None
+            }
+        );
+        assert_eq!(styles["$"].precision, 2);
+    }
+
+    #[test]
+    fn default_commodity_test() {
+        let input =
+            "D $1,000.00\n2024-01-02 Shop\n  Expenses:Food  12.5\n  Assets:Cash  -12.5 = 0\n";
+        let ledger = parse(input).unwrap();
+        let posting = &ledger.transactions().next().unwrap().postings[0];
+        assert_eq!(posting.amount.as_ref().unwrap().amount.to_string(), "$12.5");
+
+        let styles = ledger.infer_commodity_styles();
+        let settings = SerializerSettings::default().with_commodity_styles(styles);
+        assert_eq!(
+            ledger.to_string_pretty(&settings),
+            "D $1,000.00\n2024-01-02 Shop\n  Expenses:Food  $12.50\n  Assets:Cash  $-12.50 = $0.00\n"
+        );
+
+        assert!(parse("2024-01-02 Shop\n  Expenses:Food  12.5\n  Assets:Cash\n").is_err());
+    }
+
+    #[test]
+    fn commodity_position_mismatches_test() {
+        let ledger = parse(
+            r#"2024-01-02 Shop
+  Expenses:Food  $20
+  Expenses:Fun  $10
+  Assets:Cash  -30 $
+2024-01-03 Shop
+  Expenses:Food  5 EUR @ 1.1 $
+  Assets:Cash
+"#,
+        )
+        .unwrap();
+
+        let styles = ledger.infer_commodity_styles();
+        let mismatches = ledger.commodity_position_mismatches(&styles);
+        assert_eq!(
+            mismatches
+                .iter()
+                .map(|m| (m.item, m.amount.to_string()))
+                .collect::<Vec<_>>(),
+            [(0, "-30 $".to_owned()), (1, "1.1 $".to_owned())]
+        );
+        assert!(mismatches
+            .iter()
+            .all(|m| m.expected == CommodityPosition::Left));
+
+        let fixed = parse(&ledger.to_string_pretty(
+            &SerializerSettings::default().with_commodity_styles(styles.clone()),
+        ))
+        .unwrap();
+        assert_eq!(fixed.commodity_position_mismatches(&styles), vec![]);
+    }
+}
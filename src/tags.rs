@@ -0,0 +1,517 @@
+use crate::model::*;
+use chrono::NaiveDate;
+use std::borrow::Cow;
+
+/// Extracts tags from a comment, one line at a time.
+///
+/// Two forms are recognized, as in ledger and hledger:
+///
+/// - `:tag1:tag2:` words declare tags without values,
+/// - `name: value` or `name:value` pairs declare a tag with a value. As in
+///   hledger, the value runs to the next comma or the end of the line, so it
+///   can contain colons (`time: 12:30`).
+pub(crate) fn parse_tags(comment: &str) -> Vec<(String, Option<String>)> {
+    let mut tags = Vec::new();
+    for line in comment.split('\n') {
+        parse_line_tags(line, &mut tags);
+    }
+    tags
+}
+
+/// Tags of an optional comment.
+pub(crate) fn comment_tags(comment: Option<&str>) -> Vec<(String, Option<String>)> {
+    comment.map(parse_tags).unwrap_or_default()
+}
+
+/// Value of the tag `name` read as a date (`2023-01-05`, `2023/01/05` or
+/// `2023.01.05`), as used by the `date:` and `date2:` posting tags.
+pub(crate) fn tag_date(tags: &[(String, Option<String>)], name: &str) -> Option<NaiveDate> {
+    let (_, value) = tags.iter().find(|(tag, _)| tag == name)?;
+    let value = value.as_deref()?;
+    ["%Y-%m-%d", "%Y/%m/%d", "%Y.%m.%d"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+}
+
+/// Comment of the posting as it should be written: `date:` and `date2:` tags
+/// that disagree with `date` and `effective_date` are replaced by the dates.
+pub(crate) fn dated_comment(posting: &Posting) -> Option<Cow<'_, str>> {
+    let mut comment = posting.comment.as_deref().map(Cow::Borrowed);
+    for (name, date) in [("date", posting.date), ("date2", posting.effective_date)] {
+        if tag_date(&posting.tags, name) == date {
+            continue;
+        }
+        let mut owned = comment.map(Cow::into_owned);
+        remove_comment_tag(&mut owned, name);
+        if let Some(date) = date {
+            push_comment_line(&mut owned, &format!("{}:{}", name, date.format("%Y-%m-%d")));
+        }
+        comment = owned.map(Cow::Owned);
+    }
+    comment
+}
+
+fn parse_line_tags(line: &str, tags: &mut Vec<(String, Option<String>)>) {
+    let mut rest = String::new();
+    for word in line.split_whitespace() {
+        if word.len() > 2 && word.starts_with(':') && word.ends_with(':') {
+            for tag in word.split(':').filter(|tag| !tag.is_empty()) {
+                tags.push((tag.to_owned(), None));
+            }
+        } else {
+            if !rest.is_empty() {
+                rest.push(' ');
+            }
+            rest.push_str(word);
+        }
+    }
+
+    for (start, colon) in tag_starts(&rest) {
+        let end = rest[colon..]
+            .find(',')
+            .map_or(rest.len(), |comma| colon + comma);
+        let name = &rest[start..colon];
+        let value = rest[colon + 1..end].trim();
+        let value = if value.is_empty() {
+            None
+        } else {
+            Some(value.to_owned())
+        };
+        tags.push((name.to_owned(), value));
+    }
+}
+
+/// Finds `name:` occurrences, returning the byte offsets of the name and of
+/// its colon. A name starts the line or follows whitespace or a comma, but
+/// not within the value of the previous tag, which ends at a comma.
+fn tag_starts(line: &str) -> Vec<(usize, usize)> {
+    let mut starts = Vec::new();
+    let mut name_start = Some(0);
+    let mut in_value = false;
+    for (pos, c) in line.char_indices() {
+        if c == ',' {
+            in_value = false;
+            name_start = Some(pos + 1);
+        } else if in_value {
+            continue;
+        } else if c.is_whitespace() {
+            name_start = Some(pos + c.len_utf8());
+        } else if c == ':' {
+            if let Some(start) = name_start {
+                if start < pos {
+                    starts.push((start, pos));
+                    in_value = true;
+                }
+            }
+            name_start = None;
+        }
+    }
+    starts
+}
+
+/// Tag as written in a comment line: `:name:` or `name: value`.
+fn tag_text(name: &str, value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("{}: {}", name, value),
+        None => format!(":{}:", name),
+    }
+}
+
+fn push_comment_line(comment: &mut Option<String>, line: &str) {
+    *comment = Some(match comment.take() {
+        Some(comment) => format!("{}\n{}", comment, line),
+        None => line.to_owned(),
+    });
+}
+
+/// Removes every occurrence of the tag `name` from the comment, dropping
+/// lines left empty. Returns whether the comment changed.
+fn remove_comment_tag(comment: &mut Option<String>, name: &str) -> bool {
+    let text = match comment {
+        Some(text) => text,
+        None => return false,
+    };
+    let mut changed = false;
+    let mut lines = Vec::new();
+    for line in text.split('\n') {
+        match remove_line_tag(line, name) {
+            Some(line) => {
+                changed = true;
+                if !line.is_empty() {
+                    lines.push(line);
+                }
+            }
+            None => lines.push(line.to_owned()),
+        }
+    }
+    if changed {
+        *comment = if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        };
+    }
+    changed
+}
+
+/// Line without the tag `name`, or `None` if the line doesn't declare it.
+fn remove_line_tag(line: &str, name: &str) -> Option<String> {
+    let mut result = line.to_owned();
+    let mut changed = false;
+
+    // `:tag1:tag2:` words, last first so that earlier offsets stay valid
+    let words: Vec<(usize, &str)> = line
+        .split_whitespace()
+        .map(|word| (word.as_ptr() as usize - line.as_ptr() as usize, word))
+        .collect();
+    for &(start, word) in words.iter().rev() {
+        if !(word.len() > 2 && word.starts_with(':') && word.ends_with(':')) {
+            continue;
+        }
+        let names: Vec<&str> = word.split(':').filter(|tag| !tag.is_empty()).collect();
+        if !names.contains(&name) {
+            continue;
+        }
+        changed = true;
+        let rest: Vec<&str> = names.into_iter().filter(|tag| *tag != name).collect();
+        let replacement = if rest.is_empty() {
+            String::new()
+        } else {
+            format!(":{}:", rest.join(":"))
+        };
+        result.replace_range(start..start + word.len(), &replacement);
+    }
+
+    // `name: value` pairs, each running to a comma or the next tag word
+    for (start, colon) in tag_starts(&result).into_iter().rev() {
+        if &result[start..colon] != name {
+            continue;
+        }
+        changed = true;
+        let tag_word = result[colon + 1..]
+            .split_whitespace()
+            .find(|word| word.len() > 2 && word.starts_with(':') && word.ends_with(':'))
+            .map(|word| word.as_ptr() as usize - result.as_ptr() as usize);
+        let comma = result[colon..].find(',').map(|comma| colon + comma);
+        match (comma, tag_word) {
+            (Some(comma), tag_word) if tag_word.is_none_or(|word| comma < word) => {
+                result.replace_range(start..comma + 1, "");
+            }
+            (_, Some(word)) => result.replace_range(start..word, ""),
+            (_, None) => {
+                result.truncate(start);
+                let trimmed = result.trim_end().trim_end_matches(',').len();
+                result.truncate(trimmed);
+            }
+        }
+    }
+
+    if changed {
+        Some(result.split_whitespace().collect::<Vec<_>>().join(" "))
+    } else {
+        None
+    }
+}
+
+impl Transaction {
+    /// Appends a line to the comment, updating `tags`.
+    pub fn add_comment_line(&mut self, line: &str) {
+        push_comment_line(&mut self.comment, line);
+        self.tags = comment_tags(self.comment.as_deref());
+    }
+
+    /// Adds a `:name:` or `name: value` line to the comment, updating
+    /// `tags`.
+    pub fn add_tag(&mut self, name: &str, value: Option<&str>) {
+        self.add_comment_line(&tag_text(name, value));
+    }
+
+    /// Replaces any occurrence of the tag `name` by a single one with this
+    /// value.
+    pub fn set_tag(&mut self, name: &str, value: Option<&str>) {
+        self.remove_tag(name);
+        self.add_tag(name, value);
+    }
+
+    /// Removes the tag `name` from the comment and `tags`. Returns whether
+    /// the tag was present.
+    pub fn remove_tag(&mut self, name: &str) -> bool {
+        let removed = remove_comment_tag(&mut self.comment, name);
+        self.tags = comment_tags(self.comment.as_deref());
+        removed
+    }
+}
+
+impl Posting {
+    /// Appends a line to the comment, updating `tags` and the dates given by
+    /// `date:` and `date2:` tags.
+    pub fn add_comment_line(&mut self, line: &str) {
+        push_comment_line(&mut self.comment, line);
+        self.update_tags();
+    }
+
+    /// Adds a `:name:` or `name: value` line to the comment, updating
+    /// `tags` and the dates.
+    pub fn add_tag(&mut self, name: &str, value: Option<&str>) {
+        self.add_comment_line(&tag_text(name, value));
+    }
+
+    /// Replaces any occurrence of the tag `name` by a single one with this
+    /// value.
+    pub fn set_tag(&mut self, name: &str, value: Option<&str>) {
+        self.remove_tag(name);
+        self.add_tag(name, value);
+    }
+
+    /// Removes the tag `name` from the comment, `tags` and the dates.
+    /// Returns whether the tag was present.
+    pub fn remove_tag(&mut self, name: &str) -> bool {
+        let removed = remove_comment_tag(&mut self.comment, name);
+        self.update_tags();
+        removed
+    }
+
+    /// Sets the posting date, rewriting its `date:` tag.
+    pub fn set_date(&mut self, date: Option<NaiveDate>) {
+        self.set_date_tag("date", date);
+        self.date = date;
+    }
+
+    /// Sets the effective date, rewriting its `date2:` tag.
+    pub fn set_effective_date(&mut self, date: Option<NaiveDate>) {
+        self.set_date_tag("date2", date);
+        self.effective_date = date;
+    }
+
+    fn set_date_tag(&mut self, name: &str, date: Option<NaiveDate>) {
+        match date {
+            Some(date) => self.set_tag(name, Some(&date.format("%Y-%m-%d").to_string())),
+            None => {
+                self.remove_tag(name);
+            }
+        }
+    }
+
+    /// Parses `tags` again from the comment. Dates that came from a `date:`
+    /// or `date2:` tag follow it; dates set otherwise are kept unless the
+    /// comment now has the tag.
+    fn update_tags(&mut self) {
+        let tags = comment_tags(self.comment.as_deref());
+        for (date, name) in [
+            (&mut self.date, "date"),
+            (&mut self.effective_date, "date2"),
+        ] {
+            let tagged = tag_date(&tags, name);
+            if tagged.is_some() || *date == tag_date(&self.tags, name) {
+                *date = tagged;
+            }
+        }
+        self.tags = tags;
+    }
+}
+
+impl Ledger {
+    /// Adds the tags of each `apply tag` block to the transactions inside
+    /// it, then removes the `apply tag` and `end apply tag` lines so that
+    /// expanding again doesn't add the tags twice.
+    pub fn expand_applied_tags(&mut self) {
+        let mut applied: Vec<Vec<(String, Option<String>)>> = Vec::new();
+        self.items.retain_mut(|item| match item {
+            LedgerItem::ApplyTag(tag) => {
+                applied.push(parse_tags(tag));
+                false
+            }
+            LedgerItem::EndApplyTag => {
+                applied.pop();
+                false
+            }
+            LedgerItem::Transaction(transaction) => {
+                for (name, value) in applied.iter().flatten() {
+                    transaction.add_tag(name, value.as_deref());
+                }
+                true
+            }
+            _ => true,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str, value: Option<&str>) -> (String, Option<String>) {
+        (name.to_owned(), value.map(str::to_owned))
+    }
+
+    #[test]
+    fn parse_tags_test() {
+        assert_eq!(parse_tags("weekly shop"), vec![]);
+        assert_eq!(
+            parse_tags(":tag1:tag2:"),
+            vec![tag("tag1", None), tag("tag2", None)]
+        );
+        assert_eq!(parse_tags("Trip: Paris"), vec![tag("Trip", Some("Paris"))]);
+        assert_eq!(
+            parse_tags("lunch date:2023-01-05, receipt:r123"),
+            vec![
+                tag("date", Some("2023-01-05")),
+                tag("receipt", Some("r123"))
+            ]
+        );
+        assert_eq!(
+            parse_tags("Note: one, two\n:done: reviewed:"),
+            vec![
+                tag("Note", Some("one")),
+                tag("done", None),
+                tag("reviewed", None)
+            ]
+        );
+        // Values run to the next comma, colons included
+        assert_eq!(
+            parse_tags("a:1, b c:2 d:3"),
+            vec![tag("a", Some("1")), tag("c", Some("2 d:3"))]
+        );
+        assert_eq!(
+            parse_tags("time: 12:30, url: https://example.com/a?b=c:d"),
+            vec![
+                tag("time", Some("12:30")),
+                tag("url", Some("https://example.com/a?b=c:d"))
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_applied_tags_test() {
+        let mut ledger = crate::parse(
+            r#"apply tag trip: Paris
+2024-05-01 Hotel  ; booked
+  Expenses:Travel  EUR100
+  Assets:Cash
+apply tag :holiday:
+2024-05-02 Museum
+  Expenses:Fun  EUR10
+  Assets:Cash
+end apply tag
+end tag
+2024-05-03 Home
+  Expenses:Food  EUR5
+  Assets:Cash
+"#,
+        )
+        .unwrap();
+        assert_eq!(ledger.items.len(), 7);
+
+        ledger.expand_applied_tags();
+        assert_eq!(ledger.items.len(), 3);
+        let tags: Vec<_> = ledger.transactions().map(|t| t.tags.clone()).collect();
+        assert_eq!(
+            tags,
+            vec![
+                vec![tag("trip", Some("Paris"))],
+                vec![tag("trip", Some("Paris")), tag("holiday", None)],
+                vec![],
+            ]
+        );
+        assert_eq!(
+            ledger.items[0].to_string(),
+            "2024-05-01 Hotel\n  ; booked\n  ; trip: Paris\n  Expenses:Travel  EUR100\n  Assets:Cash\n"
+        );
+    }
+
+    #[test]
+    fn edit_tags_test() {
+        let mut ledger = crate::parse(
+            r#"2024-05-01 Hotel  ; booked, trip: Paris, receipt: r1
+  ; :travel:work: paid: yes
+  Expenses:Travel  EUR100  ; date: 2024-05-03
+  Assets:Cash
+"#,
+        )
+        .unwrap();
+        let transaction = match &mut ledger.items[0] {
+            LedgerItem::Transaction(transaction) => transaction,
+            _ => unreachable!(),
+        };
+
+        assert!(transaction.remove_tag("trip"));
+        assert!(transaction.remove_tag("work"));
+        assert!(!transaction.remove_tag("trip"));
+        transaction.set_tag("receipt", Some("r123"));
+        transaction.add_comment_line("checked");
+        assert_eq!(
+            transaction.comment.as_deref(),
+            Some("booked\n:travel: paid: yes\nreceipt: r123\nchecked")
+        );
+        assert_eq!(
+            transaction.tags,
+            vec![
+                tag("travel", None),
+                tag("paid", Some("yes")),
+                tag("receipt", Some("r123"))
+            ]
+        );
+
+        let posting = &mut transaction.postings[0];
+        assert!(posting.remove_tag("date"));
+        assert_eq!((posting.comment.as_deref(), posting.date), (None, None));
+        posting.set_tag("date2", Some("2024-05-04"));
+        assert_eq!(posting.effective_date, NaiveDate::from_ymd_opt(2024, 5, 4));
+        posting.date = NaiveDate::from_ymd_opt(2024, 5, 2);
+        posting.add_tag("receipt", Some("r1"));
+        assert_eq!(
+            posting.to_string(),
+            "Expenses:Travel  EUR100\n  ; date2: 2024-05-04\n  ; receipt: r1\n  ; date:2024-05-02"
+        );
+
+        let date = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        let mut transaction =
+            Transaction::new(date, "Call").with_comment("time: 12:30, url: https://x :work:");
+        assert!(transaction.remove_tag("time"));
+        assert_eq!(
+            transaction.comment.as_deref(),
+            Some("url: https://x :work:")
+        );
+        assert!(transaction.remove_tag("url"));
+        assert_eq!(transaction.comment.as_deref(), Some(":work:"));
+    }
+
+    #[test]
+    fn edit_posting_dates_test() {
+        let input = "2023-01-01 Shop\n  Expenses:Food  $5  ; paid date:2023-01-05, receipt: r1\n  ; date2: 2023-01-06\n  Assets:Cash\n";
+        let mut ledger = crate::parse(input).unwrap();
+        let transaction = match &mut ledger.items[0] {
+            LedgerItem::Transaction(transaction) => transaction,
+            _ => unreachable!(),
+        };
+
+        let posting = &mut transaction.postings[0];
+        posting.date = NaiveDate::from_ymd_opt(2023, 1, 7);
+        posting.effective_date = None;
+        assert_eq!(
+            posting.to_string(),
+            "Expenses:Food  $5\n  ; paid receipt: r1\n  ; date:2023-01-07"
+        );
+        let reparsed = crate::parse(&ledger.to_string()).unwrap();
+        let posting = &reparsed.transactions().next().unwrap().postings[0];
+        assert_eq!(posting.date, NaiveDate::from_ymd_opt(2023, 1, 7));
+        assert_eq!(posting.effective_date, None);
+
+        let mut ledger = crate::parse(input).unwrap();
+        let transaction = match &mut ledger.items[0] {
+            LedgerItem::Transaction(transaction) => transaction,
+            _ => unreachable!(),
+        };
+        let posting = &mut transaction.postings[0];
+        posting.set_date(NaiveDate::from_ymd_opt(2023, 1, 8));
+        posting.set_effective_date(None);
+        assert_eq!(
+            posting.comment.as_deref(),
+            Some("paid receipt: r1\ndate: 2023-01-08")
+        );
+        let reparsed = crate::parse(&ledger.to_string()).unwrap();
+        let posting = &reparsed.transactions().next().unwrap().postings[0];
+        assert_eq!(posting.date, NaiveDate::from_ymd_opt(2023, 1, 8));
+        assert_eq!(posting.effective_date, None);
+    }
+}
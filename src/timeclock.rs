@@ -0,0 +1,223 @@
+use crate::model::*;
+use chrono::{NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+use std::fmt;
+
+///
+/// Line of a timeclock file, as parsed by [`crate::parse_timeclock`].
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TimeclockEntry {
+    /// `i DATE TIME ACCOUNT  [DESCRIPTION]`: starts a session on `account`.
+    In {
+        datetime: NaiveDateTime,
+        account: String,
+        description: Option<String>,
+    },
+    /// `o DATE TIME [NOTE]`: ends the current session. `O` ends it and
+    /// marks it cleared.
+    Out {
+        datetime: NaiveDateTime,
+        cleared: bool,
+        note: Option<String>,
+    },
+}
+
+/// Reason timeclock entries could not be turned into transactions. Each
+/// variant holds the index of the offending entry.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TimeclockError {
+    /// Clock-out without a session to end.
+    NotClockedIn(usize),
+    /// Clock-in while a session is still open.
+    AlreadyClockedIn(usize),
+    /// Clock-in never followed by a clock-out.
+    NotClockedOut(usize),
+    /// Clock-out earlier than the clock-in it ends.
+    OutBeforeIn(usize),
+}
+
+impl fmt::Display for TimeclockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeclockError::NotClockedIn(index) => {
+                write!(f, "entry {}: clock-out without a clock-in", index)
+            }
+            TimeclockError::AlreadyClockedIn(index) => {
+                write!(f, "entry {}: clock-in while already clocked in", index)
+            }
+            TimeclockError::NotClockedOut(index) => {
+                write!(f, "entry {}: clock-in without a clock-out", index)
+            }
+            TimeclockError::OutBeforeIn(index) => {
+                write!(f, "entry {}: clock-out before its clock-in", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TimeclockError {}
+
+/// Transaction of an unbalanced virtual posting of `quantity` hours on
+/// `account`, as timeclock and timedot files are read into.
+pub(crate) fn hours_transaction(
+    date: NaiveDate,
+    status: Option<TransactionStatus>,
+    description: &str,
+    account: &str,
+    mut quantity: Decimal,
+) -> Transaction {
+    quantity.rescale(2);
+    let hours = Amount {
+        quantity,
+        commodity: Commodity {
+            name: "h".to_owned(),
+            position: CommodityPosition::Right,
+        },
+    };
+    let posting = Posting {
+        reality: Reality::UnbalancedVirtual,
+        ..Posting::new(account, Some(hours))
+    };
+    Transaction {
+        status,
+        ..Transaction::new(date, description).with_postings([posting])
+    }
+}
+
+/// Turns each clock-in and clock-out pair into a transaction dated on the
+/// clock-in day, with an unbalanced virtual posting of the hours spent on
+/// the account, rounded to two decimals, as hledger does. Sessions ended
+/// with `O` are cleared.
+///
+/// # Examples
+///
+/// ```
+/// use ledger_parser::*;
+///
+/// let entries = parse_timeclock("i 2024-01-02 09:00 Project:Client  Design\no 2024-01-02 17:30\n").unwrap();
+/// let transactions = timeclock_transactions(&entries).unwrap();
+/// assert_eq!(
+///     transactions[0].to_string(),
+///     "2024-01-02 Design\n  (Project:Client)  8.50 h"
+/// );
+/// ```
+pub fn timeclock_transactions(
+    entries: &[TimeclockEntry],
+) -> Result<Vec<Transaction>, TimeclockError> {
+    let mut transactions = Vec::new();
+    let mut open = None;
+    for (index, entry) in entries.iter().enumerate() {
+        match entry {
+            TimeclockEntry::In { .. } if open.is_some() => {
+                return Err(TimeclockError::AlreadyClockedIn(index));
+            }
+            TimeclockEntry::In { .. } => open = Some((index, entry)),
+            TimeclockEntry::Out {
+                datetime: end,
+                cleared,
+                ..
+            } => {
+                let Some((
+                    _,
+                    TimeclockEntry::In {
+                        datetime: start,
+                        account,
+                        description,
+                    },
+                )) = open.take()
+                else {
+                    return Err(TimeclockError::NotClockedIn(index));
+                };
+                if end < start {
+                    return Err(TimeclockError::OutBeforeIn(index));
+                }
+                let seconds = Decimal::from((*end - *start).num_seconds());
+                transactions.push(hours_transaction(
+                    start.date(),
+                    cleared.then_some(TransactionStatus::Cleared),
+                    description.as_deref().unwrap_or_default(),
+                    account,
+                    (seconds / Decimal::from(3600)).round_dp(2),
+                ));
+            }
+        }
+    }
+    match open {
+        Some((index, _)) => Err(TimeclockError::NotClockedOut(index)),
+        None => Ok(transactions),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn parse_timeclock_test() {
+        let entries = parse_timeclock(
+            "; work log\ni 2024-01-02 09:00 Project:Client\tKickoff  call\nO 2024-01-02 12:15:30 done\n\ni 2024-01-02 23:00:00 Support\no 2024-01-03 01:00\n",
+        )
+        .unwrap();
+        let datetime = |d, h, m, s| {
+            NaiveDate::from_ymd_opt(2024, 1, d)
+                .unwrap()
+                .and_hms_opt(h, m, s)
+                .unwrap()
+        };
+        assert_eq!(
+            entries[..2],
+            [
+                TimeclockEntry::In {
+                    datetime: datetime(2, 9, 0, 0),
+                    account: "Project:Client".to_owned(),
+                    description: Some("Kickoff  call".to_owned()),
+                },
+                TimeclockEntry::Out {
+                    datetime: datetime(2, 12, 15, 30),
+                    cleared: true,
+                    note: Some("done".to_owned()),
+                },
+            ]
+        );
+        assert!(parse_timeclock("i 2024-01-02 09:00\n").is_err());
+        assert!(parse_timeclock("o 2024-01-02 9:00\n").is_err());
+
+        let transactions: Vec<String> = timeclock_transactions(&entries)
+            .unwrap()
+            .iter()
+            .map(Transaction::to_string)
+            .collect();
+        assert_eq!(
+            transactions,
+            [
+                "2024-01-02 * Kickoff  call\n  (Project:Client)  3.26 h",
+                "2024-01-02\n  (Support)  2.00 h"
+            ]
+        );
+    }
+
+    #[test]
+    fn timeclock_transactions_test() {
+        let entries = |input| parse_timeclock(input).unwrap();
+        assert_eq!(
+            timeclock_transactions(&entries("o 2024-01-02 09:00\n")),
+            Err(TimeclockError::NotClockedIn(0))
+        );
+        assert_eq!(
+            timeclock_transactions(&entries("i 2024-01-02 09:00 A\ni 2024-01-02 10:00 B\n")),
+            Err(TimeclockError::AlreadyClockedIn(1))
+        );
+        assert_eq!(
+            timeclock_transactions(&entries(
+                "i 2024-01-02 09:00 A\no 2024-01-02 10:00\ni 2024-01-02 11:00 A\n"
+            )),
+            Err(TimeclockError::NotClockedOut(2))
+        );
+        assert_eq!(
+            timeclock_transactions(&entries("i 2024-01-02 09:00 A\no 2024-01-02 08:00\n")),
+            Err(TimeclockError::OutBeforeIn(1))
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use crate::model::*;
+use crate::parser;
+use crate::timeclock::hours_transaction;
+use crate::{ParseError, ParserSettings};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+/// Hours in one unit of a timedot quantity: `s`, `m`, `h` (the default),
+/// `d` (24 hours), `w`, `mo` (30 days) or `y` (365 days).
+pub(crate) fn unit_hours(unit: &str) -> Option<Decimal> {
+    let hours = match unit {
+        "s" => return Some(Decimal::ONE / Decimal::from(3600)),
+        "m" => return Some(Decimal::ONE / Decimal::from(60)),
+        "" | "h" => 1,
+        "d" => 24,
+        "w" => 24 * 7,
+        "mo" => 24 * 30,
+        "y" => 24 * 365,
+        _ => return None,
+    };
+    Some(Decimal::from(hours))
+}
+
+/// Transaction of one timedot line: `hours` spent on `account` on `date`.
+pub(crate) fn timedot_transaction(
+    date: NaiveDate,
+    description: &str,
+    account: &str,
+    hours: Decimal,
+) -> LedgerItem {
+    LedgerItem::Transaction(hours_transaction(
+        date,
+        None,
+        description,
+        account,
+        hours.round_dp(2),
+    ))
+}
+
+/// Parses a timedot file into a ledger with one transaction per time entry,
+/// holding an unbalanced virtual posting of the hours spent on the account.
+///
+/// A line starting with a date opens a day, and the text after the date is
+/// the description of its transactions. Each following line is an account,
+/// two spaces or a tab, and the time spent: dots worth a quarter of an hour
+/// each, spaces between them ignored, or a quantity with an optional unit
+/// (`1.5`, `1.5h`, `90m`, and `s`, `d`, `w`, `mo`, `y`). Empty lines and
+/// comment lines, including org-mode headings, are skipped.
+///
+/// # Examples
+///
+/// ```
+/// let ledger = ledger_parser::parse_timedot("2024-01-02\nproj:client  .... ..\nadmin  30m\n").unwrap();
+/// assert_eq!(
+///     ledger.to_string(),
+///     "2024-01-02\n  (proj:client)  1.50 h\n2024-01-02\n  (admin)  0.50 h\n"
+/// );
+/// ```
+pub fn parse_timedot(input: &str) -> Result<Ledger, ParseError> {
+    parser::parse_fragment(input, &ParserSettings::default(), parser::parse_timedot)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn parse_timedot_test() {
+        let ledger = parse_timedot(
+            r#"# Time log
+* January
+2024-01-02 Tuesday
+fos:haskell   .... ..
+biz:research  .
+inc:client1	2.5
+
+2024-01-03
+  inc:client1  90m
+  inc:client2  1d
+  biz:research  15s
+"#,
+        )
+        .unwrap();
+        let entries: Vec<(String, String, String)> = ledger
+            .transactions()
+            .map(|t| {
+                let posting = &t.postings[0];
+                (
+                    format!("{} {}", t.date, t.description),
+                    posting.account.clone(),
+                    posting.amount.as_ref().unwrap().amount.to_string(),
+                )
+            })
+            .collect();
+        let entry = |day: &str, account: &str, amount: &str| {
+            (day.to_owned(), account.to_owned(), amount.to_owned())
+        };
+        assert_eq!(
+            entries,
+            vec![
+                entry("2024-01-02 Tuesday", "fos:haskell", "1.50 h"),
+                entry("2024-01-02 Tuesday", "biz:research", "0.25 h"),
+                entry("2024-01-02 Tuesday", "inc:client1", "2.50 h"),
+                entry("2024-01-03 ", "inc:client1", "1.50 h"),
+                entry("2024-01-03 ", "inc:client2", "24.00 h"),
+                entry("2024-01-03 ", "biz:research", "0.00 h"),
+            ]
+        );
+        assert!(ledger
+            .transactions()
+            .all(|t| t.postings[0].reality == Reality::UnbalancedVirtual));
+
+        assert!(parse_timedot("proj  ..\n2024-01-02\n").is_err());
+        assert!(parse_timedot("2024-01-02\nproj  2x\n").is_err());
+        assert!(parse_timedot("2024-01-02\nproj ..\n").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::model::*;
+use rust_decimal::Decimal;
+
+impl Ledger {
+    /// Inserts trading account postings into every transaction that converts
+    /// between commodities. See [`Transaction::add_trading_postings`].
+    pub fn add_trading_postings(&mut self, root: &str) {
+        for item in &mut self.items {
+            if let LedgerItem::Transaction(transaction) = item {
+                transaction.add_trading_postings(root);
+            }
+        }
+    }
+}
+
+impl Transaction {
+    /// Replaces `@`/`@@` conversion prices with a pair of postings to
+    /// `ROOT:FROM:TO`, so that each commodity balances on its own.
+    ///
+    /// For example, `Assets:Eur  100 EUR @ $1.10` becomes `Assets:Eur  100 EUR`
+    /// followed by `Trading:EUR:$  -100 EUR` and `Trading:EUR:$  $110.00`.
+    ///
+    /// Postings with a lot price are left untouched, because ledger uses the
+    /// lot price as their cost once the conversion price is removed.
+    pub fn add_trading_postings(&mut self, root: &str) {
+        let mut postings = Vec::with_capacity(self.postings.len());
+
+        for mut posting in self.postings.drain(..) {
+            let conversion = match posting.amount {
+                Some(PostingAmount {
+                    ref amount,
+                    lot_price: None,
+                    lot_price_fixed: false,
+                    lot_date: None,
+                    lot_note: None,
+                    price: Some(ref price),
+                }) => trading_amounts(amount, price),
+                _ => None,
+            };
+
+            match conversion {
+                Some((from, to)) => {
+                    let account = format!("{}:{}:{}", root, from.commodity.name, to.commodity.name);
+                    if let Some(ref mut amount) = posting.amount {
+                        amount.price = None;
+                    }
+                    postings.push(posting);
+                    postings.push(Posting::new(&account, Some(from)));
+                    postings.push(Posting::new(&account, Some(to)));
+                }
+                None => postings.push(posting),
+            }
+        }
+
+        self.postings = postings;
+    }
+}
+
+/// Returns the amounts the trading account receives for a converted posting:
+/// the negated original amount and its cost in the price commodity. Returns
+/// `None` if the cost overflows.
+fn trading_amounts(amount: &Amount, price: &Price) -> Option<(Amount, Amount)> {
+    let cost = match price {
+        Price::Unit(unit) => Amount {
+            quantity: amount.quantity.checked_mul(unit.quantity)?,
+            commodity: unit.commodity.clone(),
+        },
+        Price::Total(total) => Amount {
+            quantity: if amount.quantity.is_sign_negative() {
+                -total.quantity.abs()
+            } else {
+                total.quantity.abs()
+            },
+            commodity: total.commodity.clone(),
+        },
+    };
+
+    if cost.commodity.name == amount.commodity.name {
+        return None;
+    }
+
+    Some((
+        Amount {
+            quantity: amount.quantity * Decimal::NEGATIVE_ONE,
+            commodity: amount.commodity.clone(),
+        },
+        cost,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn add_trading_postings_test() {
+        let mut ledger = parse(
+            r#"2024-01-02 Exchange
+  Assets:Eur  100 EUR @ $1.10
+  Assets:Usd
+2024-01-03 Exchange back
+  Assets:Eur  -50 EUR @@ $56
+  Assets:Usd
+2024-01-04 Buy
+  Assets:Broker  2 AAPL {$150} @ $160
+  Assets:Usd
+"#,
+        )
+        .unwrap();
+        ledger.add_trading_postings("Trading");
+
+        assert_eq!(
+            ledger.to_string(),
+            r#"2024-01-02 Exchange
+  Assets:Eur  100 EUR
+  Trading:EUR:$  -100 EUR
+  Trading:EUR:$  $110.00
+  Assets:Usd
+2024-01-03 Exchange back
+  Assets:Eur  -50 EUR
+  Trading:EUR:$  50 EUR
+  Trading:EUR:$  $-56
+  Assets:Usd
+2024-01-04 Buy
+  Assets:Broker  2 AAPL {$150} @ $160
+  Assets:Usd
+"#
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use crate::model::*;
+
+/// Tag holding the id shared by both halves of a transfer.
+pub const LINK_TAG: &str = "link";
+
+///
+/// Pair of transactions found by [`Ledger::link_transfers`].
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TransferLink {
+    /// Value of the `link:` tag added to both transactions.
+    pub id: String,
+    /// Index in `Ledger::items` of the half with the outgoing amount.
+    pub from_index: usize,
+    /// Index in `Ledger::items` of the half with the incoming amount.
+    pub to_index: usize,
+}
+
+fn is_own_account(account: &str, own_accounts: &[&str]) -> bool {
+    own_accounts.iter().any(|own| {
+        account
+            .strip_prefix(own)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(':'))
+    })
+}
+
+/// The single posting of a transaction on one of `own_accounts`, if the
+/// transaction is one half of a transfer.
+fn own_posting<'a>(transaction: &'a Transaction, own_accounts: &[&str]) -> Option<&'a Posting> {
+    let mut own = transaction
+        .postings
+        .iter()
+        .filter(|posting| is_own_account(&posting.account, own_accounts));
+    match (own.next(), own.next()) {
+        (Some(posting), None) if posting.amount.is_some() => Some(posting),
+        _ => None,
+    }
+}
+
+fn is_linked(transaction: &Transaction) -> bool {
+    transaction.tags.iter().any(|(name, _)| name == LINK_TAG)
+}
+
+impl Ledger {
+    /// Links the two halves of transfers between `own_accounts`, e.g. the
+    /// same transfer imported from the statements of both banks.
+    ///
+    /// A half is a transaction with exactly one posting on an own account
+    /// (or a sub-account). Two halves match when those postings are on
+    /// different accounts, have opposite amounts in the same commodity and
+    /// dates at most `max_days` apart; the closest date wins. Both
+    /// transactions get a `link: transfer-N` tag. Transactions that already
+    /// have a `link` tag are left alone.
+    pub fn link_transfers(&mut self, own_accounts: &[&str], max_days: u32) -> Vec<TransferLink> {
+        let halves: Vec<(usize, &Transaction, &Posting, &Amount)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, item)| match item {
+                LedgerItem::Transaction(transaction) if !is_linked(transaction) => {
+                    let posting = own_posting(transaction, own_accounts)?;
+                    let amount = &posting.amount.as_ref()?.amount;
+                    Some((index, transaction, posting, amount))
+                }
+                _ => None,
+            })
+            .collect();
+
+        let mut pairs: Vec<(usize, usize)> = Vec::new();
+        let mut used = vec![false; halves.len()];
+        for (i, &(_, transaction, posting, amount)) in halves.iter().enumerate() {
+            if used[i] {
+                continue;
+            }
+            let candidate = halves
+                .iter()
+                .enumerate()
+                .filter(|&(j, &(_, other_transaction, other_posting, other))| {
+                    !used[j]
+                        && j != i
+                        && other_posting.account != posting.account
+                        && other.commodity.name == amount.commodity.name
+                        && other.quantity == -amount.quantity
+                        && (other_transaction.date - transaction.date)
+                            .num_days()
+                            .unsigned_abs()
+                            <= u64::from(max_days)
+                })
+                .min_by_key(|&(_, &(_, other_transaction, _, _))| {
+                    (other_transaction.date - transaction.date).num_days().abs()
+                });
+            if let Some((j, _)) = candidate {
+                used[i] = true;
+                used[j] = true;
+                if amount.quantity.is_sign_negative() {
+                    pairs.push((halves[i].0, halves[j].0));
+                } else {
+                    pairs.push((halves[j].0, halves[i].0));
+                }
+            }
+        }
+
+        let first_id = 1 + self
+            .transactions()
+            .flat_map(|transaction| &transaction.tags)
+            .filter(|(name, _)| name == LINK_TAG)
+            .filter_map(|(_, value)| value.as_deref()?.strip_prefix("transfer-")?.parse().ok())
+            .max()
+            .unwrap_or(0u64);
+
+        let mut links = Vec::new();
+        for ((from_index, to_index), n) in pairs.into_iter().zip(first_id..) {
+            let id = format!("transfer-{}", n);
+            for index in [from_index, to_index] {
+                if let LedgerItem::Transaction(transaction) = &mut self.items[index] {
+                    transaction.add_tag(LINK_TAG, Some(&id));
+                }
+            }
+            links.push(TransferLink {
+                id,
+                from_index,
+                to_index,
+            });
+        }
+        links
+    }
+
+    /// Replaces each pair of linked halves by a single transfer transaction
+    /// holding both own-account postings, placed where the earlier half was.
+    /// The later half is removed. `links` must come from
+    /// [`Ledger::link_transfers`] on this ledger.
+    pub fn merge_transfers(&mut self, links: &[TransferLink], own_accounts: &[&str]) {
+        let mut removed = Vec::new();
+        for link in links {
+            let (first, second) = if link.from_index < link.to_index {
+                (link.from_index, link.to_index)
+            } else {
+                (link.to_index, link.from_index)
+            };
+            let second_posting = match &self.items[second] {
+                LedgerItem::Transaction(transaction) => {
+                    own_posting(transaction, own_accounts).cloned()
+                }
+                _ => None,
+            };
+            let merged = match (&mut self.items[first], second_posting) {
+                (LedgerItem::Transaction(transaction), Some(second_posting)) => {
+                    match own_posting(transaction, own_accounts).cloned() {
+                        Some(first_posting) => {
+                            transaction.postings = vec![first_posting, second_posting];
+                            true
+                        }
+                        None => false,
+                    }
+                }
+                _ => false,
+            };
+            if merged {
+                removed.push(second);
+            }
+        }
+
+        removed.sort_unstable();
+        for index in removed.into_iter().rev() {
+            self.items.remove(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn link_transfers_test() {
+        let input = r#"2024-01-02 Transfer to savings
+  Assets:Checking  $-500
+  Expenses:Unknown
+2024-01-03 Groceries
+  Expenses:Food  $500
+  Assets:Checking
+2024-01-04 Incoming transfer
+  Assets:Savings:Main  $500
+  Income:Unknown
+2024-03-01 Unrelated deposit
+  Assets:Savings  $500
+  Income:Unknown
+"#;
+        let mut ledger = parse(input).unwrap();
+        let links = ledger.link_transfers(&["Assets:Checking", "Assets:Savings"], 3);
+        assert_eq!(
+            links,
+            vec![TransferLink {
+                id: "transfer-1".to_owned(),
+                from_index: 0,
+                to_index: 2,
+            }]
+        );
+        let linked: Vec<bool> = ledger
+            .transactions()
+            .map(|t| {
+                t.tags
+                    .contains(&("link".to_owned(), Some("transfer-1".to_owned())))
+            })
+            .collect();
+        assert_eq!(linked, vec![true, false, true, false]);
+        assert_eq!(
+            ledger.link_transfers(&["Assets:Checking", "Assets:Savings"], 365),
+            vec![]
+        );
+
+        ledger.merge_transfers(&links, &["Assets:Checking", "Assets:Savings"]);
+        assert_eq!(ledger.items.len(), 3);
+        assert_eq!(
+            ledger.items[0].to_string(),
+            "2024-01-02 Transfer to savings\n  ; link: transfer-1\n  Assets:Checking  $-500\n  Assets:Savings:Main  $500\n"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use crate::model::*;
+
+/// Version of the view structs. It is bumped whenever a field of
+/// [`TransactionView`] or [`PostingView`] changes meaning or is removed, so
+/// exported data can be checked against the layout it was written with.
+pub const VIEW_VERSION: u32 = 1;
+
+///
+/// Flat, stable record of a transaction for exports.
+///
+/// Views only use primitive field types and don't follow changes to the
+/// model types; dates are ISO 8601 strings and quantities decimal strings.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransactionView {
+    /// Index of the transaction in `Ledger::items`.
+    pub item_index: usize,
+    pub date: String,
+    pub effective_date: Option<String>,
+    /// `*` for cleared, `!` for pending.
+    pub status: Option<String>,
+    pub code: Option<String>,
+    pub description: String,
+    pub comment: Option<String>,
+}
+
+///
+/// Flat, stable record of a transaction posting for exports.
+///
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PostingView {
+    /// Index of the transaction in `Ledger::items`.
+    pub item_index: usize,
+    /// Index of the posting in its transaction.
+    pub posting_index: usize,
+    /// Posting date, falling back to the transaction date.
+    pub date: String,
+    pub account: String,
+    /// Set for `(virtual)` and `[balanced virtual]` postings.
+    pub is_virtual: bool,
+    /// Whether the posting must balance: false only for `(virtual)` postings.
+    pub must_balance: bool,
+    pub quantity: Option<String>,
+    pub commodity: Option<String>,
+    /// `{...}` price as an amount, e.g. `$1.50`.
+    pub lot_price: Option<String>,
+    pub lot_price_is_total: bool,
+    /// Whether the lot price is fixated with `{=...}`.
+    pub lot_price_is_fixed: bool,
+    /// `[...]` lot date.
+    pub lot_date: Option<String>,
+    /// `(...)` lot note.
+    pub lot_note: Option<String>,
+    /// `@`/`@@` price as an amount, e.g. `$1.50`.
+    pub price: Option<String>,
+    pub price_is_total: bool,
+    /// Balance assertion as an amount, or `0`.
+    pub balance: Option<String>,
+    /// `=`, `==`, `=*` or `==*` when there's a balance assertion.
+    pub balance_operator: Option<String>,
+    pub status: Option<String>,
+    pub comment: Option<String>,
+}
+
+fn iso_date(date: chrono::NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+fn status(status: Option<TransactionStatus>) -> Option<String> {
+    status.map(|status| status.to_string())
+}
+
+fn price(price: Option<&Price>) -> (Option<String>, bool) {
+    match price {
+        Some(Price::Unit(amount)) => (Some(amount.to_string()), false),
+        Some(Price::Total(amount)) => (Some(amount.to_string()), true),
+        None => (None, false),
+    }
+}
+
+impl Ledger {
+    /// Flat records of the transactions, in file order.
+    pub fn transaction_views(&self) -> Vec<TransactionView> {
+        self.items
+            .iter()
+            .enumerate()
+            .filter_map(|(item_index, item)| match item {
+                LedgerItem::Transaction(transaction) => Some(TransactionView {
+                    item_index,
+                    date: iso_date(transaction.date),
+                    effective_date: transaction.effective_date.map(iso_date),
+                    status: status(transaction.status),
+                    code: transaction.code.clone(),
+                    description: transaction.description.clone(),
+                    comment: transaction.comment.clone(),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Flat records of the transaction postings, in file order.
+    pub fn posting_views(&self) -> Vec<PostingView> {
+        let mut views = Vec::new();
+        for (item_index, item) in self.items.iter().enumerate() {
+            let transaction = match item {
+                LedgerItem::Transaction(transaction) => transaction,
+                _ => continue,
+            };
+            for (posting_index, posting) in transaction.postings.iter().enumerate() {
+                let amount = posting.amount.as_ref();
+                let (lot_price, lot_price_is_total) =
+                    price(amount.and_then(|amount| amount.lot_price.as_ref()));
+                let (price, price_is_total) =
+                    price(amount.and_then(|amount| amount.price.as_ref()));
+                views.push(PostingView {
+                    item_index,
+                    posting_index,
+                    date: iso_date(posting.date.unwrap_or(transaction.date)),
+                    account: posting.account.clone(),
+                    is_virtual: posting.reality != Reality::Real,
+                    must_balance: posting.reality != Reality::UnbalancedVirtual,
+                    quantity: amount.map(|amount| amount.amount.quantity.to_string()),
+                    commodity: amount.map(|amount| amount.amount.commodity.name.clone()),
+                    lot_price,
+                    lot_price_is_total,
+                    lot_price_is_fixed: amount.is_some_and(|amount| amount.lot_price_fixed),
+                    lot_date: amount.and_then(|amount| amount.lot_date).map(iso_date),
+                    lot_note: amount.and_then(|amount| amount.lot_note.clone()),
+                    price,
+                    price_is_total,
+                    balance: posting.balance.as_ref().map(ToString::to_string),
+                    balance_operator: posting
+                        .balance
+                        .as_ref()
+                        .map(|_| posting.balance_kind.operator().to_owned()),
+                    status: status(posting.status),
+                    comment: posting.comment.clone(),
+                });
+            }
+        }
+        views
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn views_test() {
+        let ledger = parse(
+            r#"; header
+2024-01-02=2024-01-03 * (42) Exchange  ; note
+  [Assets:Eur]  100 EUR {{=$105}} [2023-12-20] (swap) @ $1.10 ==* 100 EUR
+  ! (Budget)  $-1
+  Assets:Usd
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            ledger.transaction_views(),
+            vec![TransactionView {
+                item_index: 1,
+                date: "2024-01-02".to_owned(),
+                effective_date: Some("2024-01-03".to_owned()),
+                status: Some("*".to_owned()),
+                code: Some("42".to_owned()),
+                description: "Exchange".to_owned(),
+                comment: Some("note".to_owned()),
+            }]
+        );
+
+        let postings = ledger.posting_views();
+        assert_eq!(postings.len(), 3);
+        assert_eq!(
+            postings[0],
+            PostingView {
+                item_index: 1,
+                posting_index: 0,
+                date: "2024-01-02".to_owned(),
+                account: "Assets:Eur".to_owned(),
+                is_virtual: true,
+                must_balance: true,
+                quantity: Some("100".to_owned()),
+                commodity: Some("EUR".to_owned()),
+                lot_price: Some("$105".to_owned()),
+                lot_price_is_total: true,
+                lot_price_is_fixed: true,
+                lot_date: Some("2023-12-20".to_owned()),
+                lot_note: Some("swap".to_owned()),
+                price: Some("$1.10".to_owned()),
+                price_is_total: false,
+                balance: Some("100 EUR".to_owned()),
+                balance_operator: Some("==*".to_owned()),
+                status: None,
+                comment: None,
+            }
+        );
+        assert!(!postings[1].must_balance);
+        assert_eq!(postings[1].status.as_deref(), Some("!"));
+        assert_eq!(postings[2].quantity, None);
+        assert_eq!(postings[2].balance_operator, None);
+    }
+}